@@ -0,0 +1,109 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::HashMap;
+use std::time;
+
+// The interval at which aggregated counters are written to the trace, so
+// that a long trace which is interrupted still holds recent totals.
+const FLUSH_INTERVAL: time::Duration = time::Duration::from_secs(60);
+
+// The key under which allocations are aggregated: the id of the leaf stack
+// entry of the callstack, and the async task being polled.
+pub type AggregateKey = (u64, Option<u64>);
+
+// Running counters for the allocations made with one aggregate key.
+#[derive(Default)]
+pub struct Counters {
+    // The number of bytes currently allocated.
+    pub current_bytes: u64,
+
+    // The largest value of 'current_bytes'.
+    pub peak_bytes: u64,
+
+    // The number of allocations made.
+    pub alloc_count: u64,
+
+    // The number of allocations freed.
+    pub free_count: u64,
+
+    // The id of the row in the trace holding these counters, once written.
+    pub row_id: Option<i64>,
+
+    // true if the counters have changed since last written.
+    pub dirty: bool,
+}
+
+// Aggregates allocation events into per-callstack counters, rather than
+// recording each event, so that the size of the trace is bounded by the
+// number of distinct callstacks rather than the length of the run.
+pub struct Aggregator {
+    // Counters by aggregate key.
+    pub counters: HashMap<AggregateKey, Counters>,
+
+    // The aggregate key and size of live allocations, by address, so that
+    // frees can be attributed to the callstack of their allocation.
+    live: HashMap<u64, (AggregateKey, u64)>,
+
+    // The time at which counters were last written.
+    flush_time: time::Instant,
+}
+
+impl Aggregator {
+    // Construct an aggregator with no allocations.
+    pub fn new() -> Aggregator {
+        Aggregator {
+            counters: HashMap::new(),
+            live: HashMap::new(),
+            flush_time: time::Instant::now(),
+        }
+    }
+
+    // Add an allocation to the counters for its key.
+    pub fn add_alloc(&mut self, key: AggregateKey, address: u64, size: u64) {
+        let counters = self.counters.entry(key).or_default();
+        counters.current_bytes += size;
+        counters.peak_bytes = std::cmp::max(counters.peak_bytes, counters.current_bytes);
+        counters.alloc_count += 1;
+        counters.dirty = true;
+
+        self.live.insert(address, (key, size));
+    }
+
+    // Add a free to the counters for the key of the freed allocation.
+    // Frees of blocks allocated before the trace started are ignored.
+    pub fn add_free(&mut self, address: u64) {
+        if let Some((key, size)) = self.live.remove(&address) {
+            if let Some(counters) = self.counters.get_mut(&key) {
+                counters.current_bytes -= size;
+                counters.free_count += 1;
+                counters.dirty = true;
+            }
+        }
+    }
+
+    // Returns true if it is time to write the counters to the trace.
+    pub fn is_flush_due(&self) -> bool {
+        self.flush_time.elapsed() >= FLUSH_INTERVAL
+    }
+
+    // Note that the counters have been written to the trace.
+    pub fn mark_flushed(&mut self) {
+        self.flush_time = time::Instant::now();
+    }
+}
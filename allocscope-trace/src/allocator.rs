@@ -0,0 +1,175 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::hook_spec::HookSpec;
+use crate::process_map;
+use crate::symbol_index;
+use std::collections::HashSet;
+
+// An allocator implementation found in the traced process.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Allocator {
+    Glibc,
+    Musl,
+    Jemalloc,
+    Tcmalloc,
+    Mimalloc,
+
+    // An object defining malloc which isn't a known allocator.
+    Custom,
+}
+
+// The prefixes with which jemalloc may be built, including the prefix used
+// by the Rust jemallocator crates.
+const JEMALLOC_PREFIXES: [&str; 3] = ["", "je_", "_rjem_"];
+
+// Symbols identifying allocators which may be linked alongside the C
+// library's allocator, or may replace it.
+const ALLOCATOR_MARKERS: [(&str, Allocator); 5] = [
+    ("mallocx", Allocator::Jemalloc),
+    ("je_mallocx", Allocator::Jemalloc),
+    ("_rjem_mallocx", Allocator::Jemalloc),
+    ("tc_malloc", Allocator::Tcmalloc),
+    ("mi_malloc", Allocator::Mimalloc),
+];
+
+impl Allocator {
+    // The name of the allocator, as reported to the user.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Allocator::Glibc => "glibc",
+            Allocator::Musl => "musl",
+            Allocator::Jemalloc => "jemalloc",
+            Allocator::Tcmalloc => "tcmalloc",
+            Allocator::Mimalloc => "mimalloc",
+            Allocator::Custom => "custom allocator",
+        }
+    }
+
+    // Hook specifications for the allocator's own entry points, beyond
+    // the standard allocation routines which are always hooked.
+    pub fn hook_specs(&self, free_tracking: bool) -> Vec<HookSpec> {
+        let mut specs = Vec::new();
+        match self {
+            Allocator::Jemalloc => {
+                for prefix in JEMALLOC_PREFIXES {
+                    if !prefix.is_empty() {
+                        specs.push(HookSpec::alloc(&format!("{}malloc", prefix)));
+                        specs.push(HookSpec::alloc_array(&format!("{}calloc", prefix)));
+                        specs.push(HookSpec::realloc(&format!("{}realloc", prefix)));
+                        specs.push(HookSpec::aligned_alloc(&format!("{}aligned_alloc", prefix)));
+                        specs.push(HookSpec::posix_memalign(&format!(
+                            "{}posix_memalign",
+                            prefix
+                        )));
+                        if free_tracking {
+                            specs.push(HookSpec::free(&format!("{}free", prefix)));
+                        }
+                    }
+                    specs.push(HookSpec::alloc(&format!("{}mallocx", prefix)));
+                    specs.push(HookSpec::realloc(&format!("{}rallocx", prefix)));
+                    if free_tracking {
+                        specs.push(HookSpec::free(&format!("{}dallocx", prefix)));
+                        specs.push(HookSpec::free(&format!("{}sdallocx", prefix)));
+                    }
+                }
+            }
+            Allocator::Tcmalloc => {
+                specs.push(HookSpec::alloc("tc_malloc"));
+                specs.push(HookSpec::alloc_array("tc_calloc"));
+                specs.push(HookSpec::realloc("tc_realloc"));
+                specs.push(HookSpec::aligned_alloc("tc_memalign"));
+                specs.push(HookSpec::posix_memalign("tc_posix_memalign"));
+                if free_tracking {
+                    specs.push(HookSpec::free("tc_free"));
+                }
+            }
+            Allocator::Mimalloc => {
+                specs.push(HookSpec::alloc("mi_malloc"));
+                specs.push(HookSpec::alloc("mi_zalloc"));
+                specs.push(HookSpec::alloc_array("mi_calloc"));
+                specs.push(HookSpec::realloc("mi_realloc"));
+                specs.push(HookSpec::alloc("mi_malloc_aligned"));
+                specs.push(HookSpec::alloc("mi_zalloc_aligned"));
+                if free_tracking {
+                    specs.push(HookSpec::free("mi_free"));
+                }
+            }
+            Allocator::Glibc | Allocator::Musl | Allocator::Custom => (),
+        }
+
+        specs
+    }
+}
+
+// The filenames of the objects defining a symbol.
+fn defining_objects<'a>(
+    symbol_index: &symbol_index::SymbolIndex,
+    process_map: &'a process_map::ProcessMap,
+    name: &str,
+) -> Vec<&'a str> {
+    let symbols = match symbol_index.symbols_by_name.get(name) {
+        Some(symbols) => symbols,
+        None => return Vec::new(),
+    };
+
+    symbols
+        .iter()
+        .filter_map(|symbol| process_map.entry_for_address(symbol.address))
+        .filter_map(|entry| entry.filename.as_deref())
+        .collect()
+}
+
+// Find the allocators in the traced process, with the object containing
+// each.  An object defining malloc is the C library's allocator, unless it
+// also carries the symbols of a known replacement.
+pub fn detect(
+    symbol_index: &symbol_index::SymbolIndex,
+    process_map: &process_map::ProcessMap,
+) -> Vec<(Allocator, String)> {
+    let mut detected: Vec<(Allocator, String)> = Vec::new();
+    for (marker, allocator) in ALLOCATOR_MARKERS {
+        for object in defining_objects(symbol_index, process_map, marker) {
+            detected.push((allocator, object.to_string()));
+        }
+    }
+
+    let replacements: HashSet<String> = detected.iter().map(|(_, object)| object.clone()).collect();
+    let glibc: HashSet<&str> = defining_objects(symbol_index, process_map, "__libc_malloc")
+        .into_iter()
+        .collect();
+    for object in defining_objects(symbol_index, process_map, "malloc") {
+        if replacements.contains(object) {
+            continue;
+        }
+
+        let allocator = if object.contains("musl") {
+            Allocator::Musl
+        } else if glibc.contains(object) {
+            Allocator::Glibc
+        } else {
+            Allocator::Custom
+        };
+        detected.push((allocator, object.to_string()));
+    }
+
+    detected
+        .sort_by(|(a, a_object), (b, b_object)| (a_object, a.name()).cmp(&(b_object, b.name())));
+    detected.dedup();
+    detected
+}
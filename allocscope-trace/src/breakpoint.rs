@@ -17,6 +17,7 @@
 */
 
 use crate::context;
+use crate::process_map;
 use crate::ptrace;
 use crate::symbol_index;
 use crate::trace;
@@ -46,6 +47,10 @@ pub struct Breakpoint {
     // The callback to invoke when the breakpoint is hit.
     pub callback: BreakpointCallback,
 
+    // For breakpoints on hooked allocator functions, the index of the hook
+    // specification describing the function's arguments.
+    pub hook: Option<usize>,
+
     // true if the breakpoint should remain after being encountered.
     // false for one shot breakpoints.
     pub persist: bool,
@@ -61,8 +66,15 @@ pub struct BreakpointLooseBinding {
     // Name of function at which set set the breakpoint.
     pub function_name: String,
 
+    // If set, bind to all functions with names accepted by the filter,
+    // rather than only an exact match of 'function_name'.
+    pub filter: Option<fn(&str) -> bool>,
+
     // The callback to invoke.
     pub callback: BreakpointCallback,
+
+    // The index of the hook specification for the function, if any.
+    pub hook: Option<usize>,
 }
 
 // The set of all breakpoints relevant to a traced process.
@@ -121,6 +133,7 @@ fn add_breakpoint(
     pid: u32,
     address: u64,
     callback: BreakpointCallback,
+    hook: Option<usize>,
     persist: bool,
 ) -> Result<(), Box<dyn Error>> {
     // It may be that another thread wants a one-shot breakpoint at the same
@@ -134,6 +147,7 @@ fn add_breakpoint(
             address,
             original_instruction,
             callback,
+            hook,
             persist,
             one_shot_threads: HashSet::new(),
         };
@@ -169,6 +183,28 @@ impl Breakpoint {
     }
 }
 
+// The position in the dynamic linker's search order of the object
+// containing an address, or None if the object isn't in the global scope.
+fn search_rank(
+    process_map: &process_map::ProcessMap,
+    search_order: &[String],
+    address: u64,
+) -> Option<usize> {
+    let filename = process_map.entry_for_address(address)?.filename.as_ref()?;
+
+    search_order.iter().position(|name| name == filename)
+}
+
+impl BreakpointLooseBinding {
+    // Returns true if the binding applies to a function name.
+    fn matches(&self, name: &str) -> bool {
+        match self.filter {
+            Some(filter) => filter(name),
+            None => name == self.function_name,
+        }
+    }
+}
+
 impl BreakpointSet {
     // Create a new empty set of breakpoints for a traced process.
     pub fn new() -> BreakpointSet {
@@ -187,7 +223,7 @@ impl BreakpointSet {
         address: u64,
         callback: BreakpointCallback,
     ) -> Result<(), Box<dyn Error>> {
-        add_breakpoint(&mut self.breakpoints, pid, address, callback, false)
+        add_breakpoint(&mut self.breakpoints, pid, address, callback, None, false)
     }
 
     // Disable a one shot breakpoint for a particular thread.
@@ -211,7 +247,37 @@ impl BreakpointSet {
     pub fn breakpoint_on(&mut self, function_name: &str, callback: BreakpointCallback) {
         self.bindings.push(BreakpointLooseBinding {
             function_name: function_name.to_string(),
+            filter: None,
             callback: callback,
+            hook: None,
+        });
+    }
+
+    // Break at the entry point of a hooked allocator function, described
+    // by the hook specification with a given index.
+    pub fn breakpoint_on_hook(
+        &mut self,
+        function_name: &str,
+        hook: usize,
+        callback: BreakpointCallback,
+    ) {
+        self.bindings.push(BreakpointLooseBinding {
+            function_name: function_name.to_string(),
+            filter: None,
+            callback,
+            hook: Some(hook),
+        });
+    }
+
+    // Break at the entry point of all functions with names accepted by a
+    // filter.  Useful for generic functions, where each instantiation has
+    // a distinct mangled name.
+    pub fn breakpoint_on_filter(&mut self, filter: fn(&str) -> bool, callback: BreakpointCallback) {
+        self.bindings.push(BreakpointLooseBinding {
+            function_name: String::new(),
+            filter: Some(filter),
+            callback,
+            hook: None,
         });
     }
 
@@ -230,44 +296,151 @@ impl BreakpointSet {
         Ok(())
     }
 
+    // Remove a persistent breakpoint, leaving it in place only if one-shot
+    // breakpoints for some thread share the address.
+    fn remove_persistent_breakpoint(
+        &mut self,
+        pid: u32,
+        address: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(breakpoint) = self.breakpoints.get_mut(&address) {
+            if !breakpoint.one_shot_threads.is_empty() {
+                breakpoint.persist = false;
+            } else {
+                breakpoint.remove_breakpoint_instruction(pid)?;
+                self.breakpoints.remove(&address);
+            }
+        }
+
+        Ok(())
+    }
+
     // Resolve all loosely bound breakpoint using the current process map of
     // the traced process.
+    //
+    // When a function is defined by multiple objects in the dynamic linker's
+    // global scope, such as when an allocator is preloaded, only the
+    // definition which interposes the others is used.  Definitions in
+    // objects outside the global scope are always used, since they may be
+    // statically linked or loaded in a separate namespace.
     pub fn resolve_breakpoints(
         &mut self,
         pid: u32,
         symbol_index: &symbol_index::SymbolIndex,
+        process_map: &process_map::ProcessMap,
+        search_order: &[String],
     ) -> Result<(), Box<dyn Error>> {
+        let mut interposed: Vec<u64> = Vec::new();
+
         for binding in self.bindings.iter() {
-            match symbol_index.symbols_by_name.get(&binding.function_name) {
-                Some(entry_vec) => {
-                    // For each address of the function, set a breakpoint.
-                    // Multiple addresses might be necessary, because there
-                    // might be multiple linked copies of a function with the
-                    // same name.  (Consider multiple linked copies of libc
-                    // in the same process.)
-                    for entry in entry_vec {
-                        if !self.breakpoints.contains_key(&entry.address) {
-                            add_breakpoint(
-                                &mut self.breakpoints,
-                                pid,
-                                entry.address,
-                                binding.callback,
-                                true,
-                            )?;
-                        }
+            let entry_vecs: Vec<&Vec<symbol_index::SymbolInfo>> = if binding.filter.is_some() {
+                symbol_index
+                    .symbols_by_name
+                    .iter()
+                    .filter(|(name, _)| binding.matches(name))
+                    .map(|(_, entry_vec)| entry_vec)
+                    .collect()
+            } else {
+                symbol_index
+                    .symbols_by_name
+                    .get(&binding.function_name)
+                    .into_iter()
+                    .collect()
+            };
+
+            for entry_vec in entry_vecs {
+                let first_rank = entry_vec
+                    .iter()
+                    .filter_map(|entry| search_rank(process_map, search_order, entry.address))
+                    .min();
+
+                // For each address of the function, set a breakpoint.
+                // Multiple addresses might be necessary, because there
+                // might be multiple linked copies of a function with the
+                // same name.  (Consider multiple linked copies of libc
+                // in the same process.)
+                for entry in entry_vec {
+                    let rank = search_rank(process_map, search_order, entry.address);
+                    if rank.is_some() && rank > first_rank {
+                        interposed.push(entry.address);
+                    } else if !self.breakpoints.contains_key(&entry.address) {
+                        add_breakpoint(
+                            &mut self.breakpoints,
+                            pid,
+                            entry.address,
+                            binding.callback,
+                            binding.hook,
+                            true,
+                        )?;
                     }
                 }
-                None => (),
             }
         }
 
-        // XXX: This will cause damage if a shared library is unmapped but we
-        // still have the breakpoint.
+        // Definitions may have been bound before the interposing object
+        // was loaded.
+        for address in interposed {
+            self.remove_persistent_breakpoint(pid, address)?;
+        }
+
         self.rebind_breakpoints(pid)?;
 
         Ok(())
     }
 
+    // Set breakpoints at the addresses held in function slots filled by the
+    // dynamic linker.  This catches calls through PLT stubs which have not
+    // yet been bound, and implementations selected by IFUNC resolvers,
+    // which don't carry the name of the function.  Only valid after the
+    // dynamic linker has finished relocation, since slots hold unrelocated
+    // addresses before that.
+    pub fn resolve_relocated_breakpoints(
+        &mut self,
+        pid: u32,
+        symbol_index: &symbol_index::SymbolIndex,
+        process_map: &process_map::ProcessMap,
+    ) -> Result<(), Box<dyn Error>> {
+        for slot in symbol_index.relocation_slots.iter() {
+            let binding = match self
+                .bindings
+                .iter()
+                .find(|binding| binding.matches(&slot.name))
+            {
+                Some(binding) => binding,
+                None => continue,
+            };
+
+            let address = ptrace::peektext(pid, slot.address);
+            let executable = match process_map.entry_for_address(address) {
+                Some(entry) => entry.executable,
+                None => false,
+            };
+            if executable && !self.breakpoints.contains_key(&address) {
+                add_breakpoint(
+                    &mut self.breakpoints,
+                    pid,
+                    address,
+                    binding.callback,
+                    binding.hook,
+                    true,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Forget breakpoints in a range of addresses which has been unmapped or
+    // replaced.  The breakpoint instructions are gone with the mapping, so
+    // there is nothing to restore.  One shot breakpoints still awaited by a
+    // thread are kept, as they catch the return of an allocator call in
+    // progress, and the thread will still return through them.
+    pub fn forget_breakpoints_in_range(&mut self, begin: u64, end: u64) {
+        self.breakpoints.retain(|address, breakpoint| {
+            *address < begin || *address >= end || !breakpoint.one_shot_threads.is_empty()
+        });
+    }
+
     // Remove all previously inserted breakpoints from the process.  Used
     // when deatching from a process to leave it in a runnable state when
     // not being traced.
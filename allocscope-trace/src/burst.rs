@@ -0,0 +1,174 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::{HashMap, VecDeque};
+use std::time;
+
+// The rate of allocation which counts as a burst: at least 'bytes'
+// allocated within 'window'.
+#[derive(Clone, Copy, Debug)]
+pub struct BurstThreshold {
+    // The number of bytes allocated within the window.
+    pub bytes: u64,
+
+    // The length of the window.
+    pub window: time::Duration,
+}
+
+// A completed burst of allocation.
+pub struct Burst {
+    // The time of the first allocation of the burst, in nanoseconds since
+    // the start of the trace.
+    pub time: u64,
+
+    // The time from the first allocation of the burst to the last.
+    pub duration: u64,
+
+    // The total bytes allocated during the burst.
+    pub bytes: u64,
+
+    // The id of the callstack which allocated the most bytes during the
+    // burst.
+    pub callstack: Option<u64>,
+}
+
+// An allocation within the sliding window.
+struct RecentAllocation {
+    // The time of the allocation, in nanoseconds since the start of the
+    // trace.
+    time: u64,
+
+    // The size of the allocation.
+    size: u64,
+
+    // The id of the callstack of the allocation.
+    callstack: Option<u64>,
+}
+
+// A burst which is still in progress.
+struct BurstInProgress {
+    // The time of the first allocation of the burst.
+    start: u64,
+
+    // The time of the most recent allocation of the burst.
+    end: u64,
+
+    // The bytes allocated during the burst, by callstack id.
+    bytes_by_callstack: HashMap<Option<u64>, u64>,
+}
+
+impl BurstInProgress {
+    // Summarize the burst once it has ended.
+    fn complete(self) -> Burst {
+        let bytes = self.bytes_by_callstack.values().sum();
+        let callstack = self
+            .bytes_by_callstack
+            .iter()
+            .max_by_key(|(_, bytes)| **bytes)
+            .and_then(|(callstack, _)| *callstack);
+
+        Burst {
+            time: self.start,
+            duration: self.end - self.start,
+            bytes,
+            callstack,
+        }
+    }
+}
+
+// Detects bursts by totalling the bytes allocated in a sliding window.  A
+// burst starts when the total reaches the threshold, including the
+// allocations already in the window, and ends when the total falls below
+// the threshold again.
+pub struct BurstDetector {
+    // The number of bytes in the window which starts a burst.
+    threshold_bytes: u64,
+
+    // The length of the window, in nanoseconds.
+    window: u64,
+
+    // The allocations within the window, oldest first.
+    recent: VecDeque<RecentAllocation>,
+
+    // The total size of the allocations within the window.
+    recent_bytes: u64,
+
+    // The burst in progress, if any.
+    burst: Option<BurstInProgress>,
+}
+
+impl BurstDetector {
+    // Construct a detector for bursts over a threshold.
+    pub fn new(threshold: BurstThreshold) -> BurstDetector {
+        BurstDetector {
+            threshold_bytes: threshold.bytes,
+            window: threshold.window.as_nanos() as u64,
+            recent: VecDeque::new(),
+            recent_bytes: 0,
+            burst: None,
+        }
+    }
+
+    // Note an allocation, returning the burst which it ended, if any.
+    pub fn add_alloc(&mut self, time: u64, size: u64, callstack: Option<u64>) -> Option<Burst> {
+        let cutoff = time.saturating_sub(self.window);
+        while let Some(oldest) = self.recent.front() {
+            if oldest.time >= cutoff {
+                break;
+            }
+            self.recent_bytes -= oldest.size;
+            self.recent.pop_front();
+        }
+
+        self.recent.push_back(RecentAllocation {
+            time,
+            size,
+            callstack,
+        });
+        self.recent_bytes += size;
+
+        if self.recent_bytes < self.threshold_bytes {
+            return self.finish();
+        }
+
+        match self.burst.as_mut() {
+            Some(burst) => {
+                burst.end = time;
+                *burst.bytes_by_callstack.entry(callstack).or_default() += size;
+            }
+            None => {
+                let mut bytes_by_callstack = HashMap::new();
+                for recent in self.recent.iter() {
+                    *bytes_by_callstack.entry(recent.callstack).or_default() += recent.size;
+                }
+                self.burst = Some(BurstInProgress {
+                    start: self.recent.front().map_or(time, |oldest| oldest.time),
+                    end: time,
+                    bytes_by_callstack,
+                });
+            }
+        }
+
+        None
+    }
+
+    // End the burst in progress, if any, returning it.
+    pub fn finish(&mut self) -> Option<Burst> {
+        self.burst.take().map(|burst| burst.complete())
+    }
+}
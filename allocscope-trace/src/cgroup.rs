@@ -0,0 +1,91 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::error::Error;
+use std::fs;
+
+// The memory charged to the cgroup of the traced process, in bytes.
+#[derive(Debug, Default)]
+pub struct CgroupMemory {
+    // Memory currently charged to the cgroup.
+    pub current: u64,
+
+    // The limit of the cgroup's memory, if it has one.
+    pub max: Option<u64>,
+}
+
+// Read the contents of a cgroup control file, without trailing whitespace.
+fn read_value(path: &str) -> Result<String, Box<dyn Error>> {
+    Ok(fs::read_to_string(path)?.trim().to_string())
+}
+
+// Read memory usage for a cgroup v2 hierarchy.  The limit is "max" when
+// the cgroup is unlimited.
+fn read_unified(cgroup_path: &str) -> Result<CgroupMemory, Box<dyn Error>> {
+    let directory = format!("/sys/fs/cgroup{}", cgroup_path);
+    let current = read_value(&format!("{}/memory.current", directory))?.parse()?;
+    let max = match read_value(&format!("{}/memory.max", directory))?.as_str() {
+        "max" => None,
+        max => Some(max.parse()?),
+    };
+
+    Ok(CgroupMemory { current, max })
+}
+
+// Read memory usage for a cgroup v1 memory controller.  An unlimited
+// cgroup reports a very large limit, near the largest page-aligned value.
+fn read_legacy(cgroup_path: &str) -> Result<CgroupMemory, Box<dyn Error>> {
+    let directory = format!("/sys/fs/cgroup/memory{}", cgroup_path);
+    let current = read_value(&format!("{}/memory.usage_in_bytes", directory))?.parse()?;
+    let limit: u64 = read_value(&format!("{}/memory.limit_in_bytes", directory))?.parse()?;
+    let max = if limit >= i64::MAX as u64 / 2 {
+        None
+    } else {
+        Some(limit)
+    };
+
+    Ok(CgroupMemory { current, max })
+}
+
+// Sample the memory charged to the cgroup of a process.  Each line of
+// /proc/<pid>/cgroup is "hierarchy-ID:controllers:path", with an empty
+// controller list for the cgroup v2 hierarchy.
+pub fn sample(pid: u32) -> Result<CgroupMemory, Box<dyn Error>> {
+    let cgroups = fs::read_to_string(format!("/proc/{}/cgroup", pid))?;
+
+    let mut unified_path = None;
+    for line in cgroups.lines() {
+        let mut fields = line.splitn(3, ':');
+        let (_, controllers, path) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(id), Some(controllers), Some(path)) => (id, controllers, path),
+            _ => continue,
+        };
+
+        if controllers
+            .split(',')
+            .any(|controller| controller == "memory")
+        {
+            return read_legacy(path);
+        }
+        if controllers.is_empty() {
+            unified_path = Some(path.to_string());
+        }
+    }
+
+    read_unified(&unified_path.ok_or("process has no memory cgroup")?)
+}
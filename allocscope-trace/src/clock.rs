@@ -0,0 +1,54 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+// Readings of the system clocks taken at the same moment, in nanoseconds,
+// so that trace times can be aligned with tools using other clocks.
+#[derive(Debug, Default)]
+pub struct ClockAnchor {
+    // CLOCK_MONOTONIC, as used by perf and most tracing tools.
+    pub monotonic: u64,
+
+    // CLOCK_MONOTONIC_RAW, which is not adjusted by NTP.
+    pub monotonic_raw: u64,
+
+    // CLOCK_REALTIME, the wall clock time since the Unix epoch, as used by
+    // strace timestamps.
+    pub realtime: u64,
+}
+
+// Read a clock in nanoseconds.
+fn read_clock(clock: libc::clockid_t) -> u64 {
+    let mut timespec = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe {
+        libc::clock_gettime(clock, &mut timespec);
+    }
+
+    timespec.tv_sec as u64 * 1_000_000_000 + timespec.tv_nsec as u64
+}
+
+// Read each of the clocks.
+pub fn read_anchor() -> ClockAnchor {
+    ClockAnchor {
+        monotonic: read_clock(libc::CLOCK_MONOTONIC),
+        monotonic_raw: read_clock(libc::CLOCK_MONOTONIC_RAW),
+        realtime: read_clock(libc::CLOCK_REALTIME),
+    }
+}
@@ -16,20 +16,155 @@
     with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
+use crate::burst;
 use std::error::Error;
+use std::fs;
 use std::path;
+use std::ptr;
+use std::time;
+
+// The environment variable holding default options, which are applied
+// before options given on the commandline.
+const OPTIONS_ENVIRONMENT_VARIABLE: &str = "ALLOCSCOPE_TRACE_OPTS";
+
+// The environment variable which overrides the location of the config file.
+const CONFIG_ENVIRONMENT_VARIABLE: &str = "ALLOCSCOPE_TRACE_CONFIG";
+
+// Options which control what is recorded during a trace.
+#[derive(Clone, Debug)]
+pub struct TraceOptions {
+    // If true, tag events with the async task being polled when the
+    // allocation occurred.
+    pub async_tasks: bool,
+
+    // If true, hook the CPython interpreter's allocator entry points.
+    pub python: bool,
+
+    // If true, report the time spent handling each kind of stop of the
+    // traced process when tracing completes.
+    pub profile_overhead: bool,
+
+    // Names of allocator-internal functions to omit from recorded
+    // callstacks.  A trailing '*' matches any function name with the
+    // preceding prefix.
+    pub skip_frames: Vec<String>,
+
+    // If present, append events to a spool file with this name while
+    // tracing, and convert them into the trace after the trace completes.
+    pub spool_filename: Option<String>,
+
+    // If true, re-run with elevated privileges when attaching to a process
+    // fails.
+    pub elevate: bool,
+
+    // If present, the interval at which to record the allocator's
+    // statistics, by calling mallinfo2 in the traced process.
+    pub allocator_stats_interval: Option<time::Duration>,
+
+    // If present, the interval at which to sample the breakdown of the
+    // traced process's resident memory from smaps.
+    pub resident_memory_interval: Option<time::Duration>,
+
+    // If present, the interval at which to sample the memory charged to
+    // the traced process's cgroup.
+    pub cgroup_memory_interval: Option<time::Duration>,
+
+    // If true, record the mmap, munmap and mremap calls of the traced
+    // process, with the kind of memory mapped.
+    pub mmap_tracking: bool,
+
+    // If present, mark bursts in which at least the threshold of bytes is
+    // allocated within its window.
+    pub burst_threshold: Option<burst::BurstThreshold>,
+
+    // If true, keep the traced process stopped after breakpoints are set
+    // until the user releases it.
+    pub start_paused: bool,
+
+    // If true, start with recording of events off, until it is toggled on
+    // by SIGUSR2.
+    pub recording_off: bool,
+
+    // If present, sample allocations when tracing slows the traced process
+    // by more than this factor.
+    pub max_slowdown: Option<f64>,
+
+    // If not empty, record only the events of threads with these thread
+    // ids or names.  A trailing '*' matches any name with the preceding
+    // prefix.
+    pub thread_filter: Vec<String>,
+
+    // If true, hook free, so that the trace can report leaks.
+    pub free_tracking: bool,
+
+    // If true, record running totals by callstack rather than individual
+    // allocation events.
+    pub aggregate: bool,
+
+    // If present, keep only the events within this window before the end
+    // of the trace, along with the allocations which are still live.
+    pub retain: Option<time::Duration>,
+}
+
+// Allocator-internal functions omitted from callstacks by default.
+const DEFAULT_SKIP_FRAMES: &[&str] = &[
+    "__libc_malloc",
+    "__libc_calloc",
+    "__libc_realloc",
+    "__libc_free",
+    "_int_malloc",
+    "_int_realloc",
+    "_int_free",
+    "tcache_init",
+    "malloc_hook_ini",
+    "realloc_hook_ini",
+    "__rust_alloc",
+    "__rust_alloc_zeroed",
+    "__rust_realloc",
+    "__rust_dealloc",
+    "__rdl_alloc",
+    "__rdl_alloc_zeroed",
+    "__rdl_realloc",
+    "__rdl_dealloc",
+    "_ZN5alloc5alloc*",
+];
+
+// What to do when the trace file already exists.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExistingOutput {
+    // Refuse to trace, so that an earlier trace isn't lost.
+    Refuse,
+
+    // Replace the existing file.
+    Replace,
+
+    // Rename the existing file to a numbered backup.
+    Backup,
+
+    // Add a new session to the existing file.
+    Append,
+}
 
 // Parsed commandline arguments.
 pub struct CommandLineArguments {
     // Filename to use for the trace.
     pub atrace_filename: String,
 
+    // What to do if the trace file already exists.
+    pub existing_output: ExistingOutput,
+
     // The commandline for the process to trace.
     pub command: Vec<String>,
 
     // The process-id of a running process to which to attach the trace.
     pub target_pid: Option<u32>,
 
+    // Options controlling the trace.
+    pub options: TraceOptions,
+
+    // If true, open the trace in allocscope-view once tracing completes.
+    pub view: bool,
+
     // If true, print the version of the tool and exit.
     pub report_version: bool,
 
@@ -45,6 +180,62 @@ pub fn show_help() {
     -o, --output FILE   Record trace to given filename
     -p, --pid TARGET    Attach to running process
     -v, --version       Report version
+    --aggregate         Record totals by callstack instead of each event,
+                        for long runs
+    --allocator-stats SECONDS
+                        Record allocator statistics at an interval, while
+                        the traced process has a single thread
+    --append            Add this run as a new session of an existing trace
+                        file
+    --async-tasks       Tag allocations with the polled tokio task
+    --backup            Rename an existing trace file to FILE.1, shifting
+                        older backups to FILE.2 and so on
+    --bursts SIZE/INTERVAL
+                        Mark bursts allocating at least SIZE within
+                        INTERVAL, such as 64M/100ms
+    --cgroup-memory SECONDS
+                        Sample the memory usage and limit of the traced
+                        process's cgroup at an interval
+    --elevate           Re-run with sudo or pkexec if attaching is denied
+    --force             Replace an existing trace file
+    --max-slowdown FACTOR
+                        Record only a sample of allocations while tracing
+                        slows the process by more than FACTOR
+    --mmap              Record memory mapping calls, distinguishing
+                        anonymous, file-backed and shared mappings
+    --no-free-tracking  Don't hook free, for faster tracing of allocation
+                        hot spots without leak reporting
+    --output-dir DIR    Directory for traces without an explicit filename,
+                        which are named with the time of tracing
+    --profile-overhead  Report the time spent tracing by kind of stop, as
+                        unwinding, ptrace and trace writing time
+    --python            Track CPython interpreter allocations
+    --recording-off     Start with events not recorded.  Send SIGUSR2 to
+                        toggle recording while tracing continues
+    --resident-memory SECONDS
+                        Sample resident memory by mapping kind at an
+                        interval
+    --retain DURATION   Keep only events from the last DURATION, such as
+                        10m, along with allocations still live.  Peak,
+                        block and churn totals then cover only the window
+    --skip-frames LIST  Comma separated allocator functions to omit from
+                        callstacks, replacing the default list
+    --spool FILE        Spool raw events to FILE while tracing, converting
+                        to the trace after tracing completes
+    --start-paused      Keep the process stopped once breakpoints are set
+                        until Enter is pressed or SIGUSR1 is received
+    --tid LIST          Record only events on threads with the comma
+                        separated thread ids or names, where a trailing '*'
+                        matches a name prefix
+    --view              Open the trace in allocscope-view when tracing
+                        completes, if stdout is a terminal
+
+Default options are read from ~/.config/allocscope/trace.conf (or the file
+named by ALLOCSCOPE_TRACE_CONFIG), followed by the ALLOCSCOPE_TRACE_OPTS
+environment variable, before the options given on the commandline.
+
+An existing trace file is never replaced unless --force, --backup or
+--append is given.
 "
     );
 }
@@ -54,37 +245,209 @@ pub fn report_version() {
     println!("allocscope-trace {}", env!("CARGO_PKG_VERSION"));
 }
 
-// Given a command to trace, generate an appropriate filename for the trace.
-fn get_trace_filename_from_command(command: &Vec<String>) -> Result<String, Box<dyn Error>> {
+// Given a command to trace, generate an appropriate name for the trace,
+// without the file extension.
+fn get_trace_name_from_command(command: &Vec<String>) -> Result<String, Box<dyn Error>> {
     if command.len() > 0 {
         let path = path::Path::new(&command[0]);
         if let Some(basename) = path.file_name() {
-            Ok(format!(
-                "{}.atrace",
-                basename.to_str().ok_or("invalid command name")?
-            ))
+            Ok(basename.to_str().ok_or("invalid command name")?.to_string())
         } else {
-            Ok("alloc-trace.atrace".to_string())
+            Ok("alloc-trace".to_string())
         }
     } else {
-        Ok("alloc-trace.atrace".to_string())
+        Ok("alloc-trace".to_string())
+    }
+}
+
+// The current local time, formatted for use in a filename, such as
+// 20230514-093012.
+fn get_timestamp() -> Result<String, Box<dyn Error>> {
+    unsafe {
+        let now = libc::time(ptr::null_mut());
+        let mut local = std::mem::MaybeUninit::<libc::tm>::zeroed().assume_init();
+        if libc::localtime_r(&now, &mut local).is_null() {
+            Err("can't determine the local time")?;
+        }
+
+        let mut buffer = [0u8; 32];
+        let length = libc::strftime(
+            buffer.as_mut_ptr() as *mut libc::c_char,
+            buffer.len(),
+            c"%Y%m%d-%H%M%S".as_ptr(),
+            &local,
+        );
+
+        Ok(String::from_utf8_lossy(&buffer[..length]).into_owned())
+    }
+}
+
+// Parse an interval given in seconds, which may be fractional, or with a
+// suffix of 'ms', 's', 'm', 'h' or 'd' for milliseconds, seconds, minutes,
+// hours or days.
+fn parse_interval(token: &str, description: &str) -> Result<time::Duration, Box<dyn Error>> {
+    let (number, unit_seconds) = match token.chars().last() {
+        Some('s') if token.ends_with("ms") => (&token[..token.len() - 2], 0.001),
+        Some('s') => (&token[..token.len() - 1], 1.0),
+        Some('m') => (&token[..token.len() - 1], 60.0),
+        Some('h') => (&token[..token.len() - 1], 3600.0),
+        Some('d') => (&token[..token.len() - 1], 86400.0),
+        _ => (token, 1.0),
+    };
+
+    match number.parse::<f64>() {
+        Ok(value) if value > 0.0 && value.is_finite() => {
+            Ok(time::Duration::from_secs_f64(value * unit_seconds))
+        }
+        _ => Err(format!("invalid {} interval: {}", description, token))?,
     }
 }
 
+// Parse the factor by which tracing may slow the traced process.
+fn parse_slowdown(token: &str) -> Result<f64, Box<dyn Error>> {
+    match token.parse::<f64>() {
+        Ok(value) if value > 1.0 && value.is_finite() => Ok(value),
+        _ => Err(format!("invalid slowdown factor: {}", token))?,
+    }
+}
+
+// Parse a size in bytes, with an optional suffix of 'k', 'M' or 'G' for
+// kibibytes, mebibytes or gibibytes.
+fn parse_size(token: &str, description: &str) -> Result<u64, Box<dyn Error>> {
+    let (number, unit) = match token.chars().last() {
+        Some('k') => (&token[..token.len() - 1], 1 << 10),
+        Some('M') => (&token[..token.len() - 1], 1 << 20),
+        Some('G') => (&token[..token.len() - 1], 1 << 30),
+        _ => (token, 1),
+    };
+
+    match number.parse::<u64>() {
+        Ok(value) if value > 0 => Ok(value * unit),
+        _ => Err(format!("invalid {} size: {}", description, token))?,
+    }
+}
+
+// Parse the threshold for allocation bursts, as a size and an interval
+// separated by '/', such as 64M/100ms.
+fn parse_burst_threshold(token: &str) -> Result<burst::BurstThreshold, Box<dyn Error>> {
+    let (bytes, window) = token
+        .split_once('/')
+        .ok_or(format!("invalid burst threshold: {}", token))?;
+
+    Ok(burst::BurstThreshold {
+        bytes: parse_size(bytes, "burst")?,
+        window: parse_interval(window, "burst")?,
+    })
+}
+
+// The path of the config file holding default options, if one can be found.
+fn get_config_path() -> Option<path::PathBuf> {
+    if let Ok(config_path) = std::env::var(CONFIG_ENVIRONMENT_VARIABLE) {
+        return Some(path::PathBuf::from(config_path));
+    }
+
+    let config_home = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(config_home) => path::PathBuf::from(config_home),
+        Err(_) => path::Path::new(&std::env::var("HOME").ok()?).join(".config"),
+    };
+
+    Some(config_home.join("allocscope").join("trace.conf"))
+}
+
+// Collect default option tokens from the config file and the environment.
+// Text following '#' on a line of the config file is a comment.
+fn get_default_tokens() -> Result<Vec<String>, Box<dyn Error>> {
+    let mut tokens = Vec::new();
+
+    if let Some(config_path) = get_config_path() {
+        if config_path.exists() {
+            let config = fs::read_to_string(&config_path)
+                .map_err(|err| format!("failed to read {}: {}", config_path.display(), err))?;
+            for line in config.lines() {
+                let line = line.split('#').next().unwrap_or("");
+                tokens.extend(line.split_whitespace().map(|token| token.to_string()));
+            }
+        }
+    }
+
+    if let Ok(options) = std::env::var(OPTIONS_ENVIRONMENT_VARIABLE) {
+        tokens.extend(options.split_whitespace().map(|token| token.to_string()));
+    }
+
+    Ok(tokens)
+}
+
+// The commandline arguments, with default options from the config file and
+// environment inserted ahead of the options given on the commandline.
+pub fn get_args_with_defaults() -> Result<Vec<String>, Box<dyn Error>> {
+    let default_tokens = get_default_tokens()?;
+
+    // Parse the defaults by themselves first, so that a stray token isn't
+    // mistaken for the start of the command to trace.
+    let mut defaults_only = std::iter::once(String::new()).chain(default_tokens.iter().cloned());
+    if !CommandLineArguments::parse(&mut defaults_only)?
+        .command
+        .is_empty()
+    {
+        Err("default options may not include a command")?;
+    }
+
+    let mut args = std::env::args();
+    let mut result: Vec<String> = args.next().into_iter().collect();
+    result.extend(default_tokens);
+    result.extend(args);
+
+    Ok(result)
+}
+
 impl CommandLineArguments {
     // Parse the commandline.
     pub fn parse(
         args: &mut dyn Iterator<Item = String>,
     ) -> Result<CommandLineArguments, Box<dyn Error>> {
         let mut atrace_filename: Option<String> = None;
+        let mut existing_output = ExistingOutput::Refuse;
         let mut command: Vec<String> = Vec::new();
         let mut target_pid: Option<u32> = None;
         let mut show_help = false;
         let mut command_started = false;
         let mut report_version = false;
+        let mut view = false;
+        let mut async_tasks = false;
+        let mut python = false;
+        let mut profile_overhead = false;
+        let mut elevate = false;
+        let mut skip_frames: Vec<String> = DEFAULT_SKIP_FRAMES
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
 
         let mut expect_pid = false;
         let mut expect_atrace_filename = false;
+        let mut expect_skip_frames = false;
+        let mut spool_filename: Option<String> = None;
+        let mut expect_spool_filename = false;
+        let mut output_dir: Option<String> = None;
+        let mut expect_output_dir = false;
+        let mut allocator_stats_interval: Option<time::Duration> = None;
+        let mut expect_allocator_stats_interval = false;
+        let mut free_tracking = true;
+        let mut mmap_tracking = false;
+        let mut start_paused = false;
+        let mut recording_off = false;
+        let mut max_slowdown: Option<f64> = None;
+        let mut thread_filter: Vec<String> = Vec::new();
+        let mut expect_thread_filter = false;
+        let mut expect_max_slowdown = false;
+        let mut burst_threshold: Option<burst::BurstThreshold> = None;
+        let mut expect_burst_threshold = false;
+        let mut aggregate = false;
+        let mut retain: Option<time::Duration> = None;
+        let mut expect_retain = false;
+        let mut resident_memory_interval: Option<time::Duration> = None;
+        let mut expect_resident_memory_interval = false;
+        let mut cgroup_memory_interval: Option<time::Duration> = None;
+        let mut expect_cgroup_memory_interval = false;
         for token in args.skip(1) {
             let mut consumed_token = false;
 
@@ -96,10 +459,33 @@ impl CommandLineArguments {
 
                     if token.chars().nth(1) == Some('-') {
                         match token.as_str() {
+                            "--aggregate" => aggregate = true,
+                            "--allocator-stats" => expect_allocator_stats_interval = true,
+                            "--append" => existing_output = ExistingOutput::Append,
+                            "--backup" => existing_output = ExistingOutput::Backup,
+                            "--async-tasks" => async_tasks = true,
+                            "--bursts" => expect_burst_threshold = true,
+                            "--cgroup-memory" => expect_cgroup_memory_interval = true,
+                            "--elevate" => elevate = true,
+                            "--force" => existing_output = ExistingOutput::Replace,
                             "--help" => show_help = true,
+                            "--max-slowdown" => expect_max_slowdown = true,
+                            "--mmap" => mmap_tracking = true,
+                            "--no-free-tracking" => free_tracking = false,
                             "--output" => expect_atrace_filename = true,
+                            "--output-dir" => expect_output_dir = true,
                             "--pid" => expect_pid = true,
+                            "--profile-overhead" => profile_overhead = true,
+                            "--python" => python = true,
+                            "--recording-off" => recording_off = true,
+                            "--resident-memory" => expect_resident_memory_interval = true,
+                            "--retain" => expect_retain = true,
+                            "--skip-frames" => expect_skip_frames = true,
+                            "--spool" => expect_spool_filename = true,
+                            "--start-paused" => start_paused = true,
+                            "--tid" => expect_thread_filter = true,
                             "--version" => report_version = true,
+                            "--view" => view = true,
                             _ => {
                                 eprintln!("Unrecognized argument: {}", token);
                                 show_help = true;
@@ -130,6 +516,55 @@ impl CommandLineArguments {
                     consumed_token = true;
                     expect_atrace_filename = false;
                     atrace_filename = Some(token.clone());
+                } else if expect_skip_frames {
+                    consumed_token = true;
+                    expect_skip_frames = false;
+                    skip_frames = token
+                        .split(',')
+                        .filter(|name| !name.is_empty())
+                        .map(|name| name.to_string())
+                        .collect();
+                } else if expect_spool_filename {
+                    consumed_token = true;
+                    expect_spool_filename = false;
+                    spool_filename = Some(token.clone());
+                } else if expect_output_dir {
+                    consumed_token = true;
+                    expect_output_dir = false;
+                    output_dir = Some(token.clone());
+                } else if expect_allocator_stats_interval {
+                    consumed_token = true;
+                    expect_allocator_stats_interval = false;
+                    allocator_stats_interval =
+                        Some(parse_interval(&token, "allocator statistics")?);
+                } else if expect_resident_memory_interval {
+                    consumed_token = true;
+                    expect_resident_memory_interval = false;
+                    resident_memory_interval = Some(parse_interval(&token, "resident memory")?);
+                } else if expect_cgroup_memory_interval {
+                    consumed_token = true;
+                    expect_cgroup_memory_interval = false;
+                    cgroup_memory_interval = Some(parse_interval(&token, "cgroup memory")?);
+                } else if expect_burst_threshold {
+                    consumed_token = true;
+                    expect_burst_threshold = false;
+                    burst_threshold = Some(parse_burst_threshold(&token)?);
+                } else if expect_max_slowdown {
+                    consumed_token = true;
+                    expect_max_slowdown = false;
+                    max_slowdown = Some(parse_slowdown(&token)?);
+                } else if expect_thread_filter {
+                    consumed_token = true;
+                    expect_thread_filter = false;
+                    thread_filter = token
+                        .split(',')
+                        .filter(|name| !name.is_empty())
+                        .map(|name| name.to_string())
+                        .collect();
+                } else if expect_retain {
+                    consumed_token = true;
+                    expect_retain = false;
+                    retain = Some(parse_interval(&token, "retention")?);
                 }
             }
 
@@ -139,13 +574,50 @@ impl CommandLineArguments {
             }
         }
 
+        // Aggregated counters can't be scaled by the sampling period in
+        // effect when each allocation was made.
+        if max_slowdown.is_some() && aggregate {
+            Err("--max-slowdown can't be combined with --aggregate")?;
+        }
+
         Ok(CommandLineArguments {
-            atrace_filename: match atrace_filename {
-                Some(filename) => filename,
-                None => get_trace_filename_from_command(&command)?,
+            atrace_filename: match (atrace_filename, output_dir) {
+                (Some(filename), _) => filename,
+                (None, Some(output_dir)) => path::Path::new(&output_dir)
+                    .join(format!(
+                        "{}-{}.atrace",
+                        get_trace_name_from_command(&command)?,
+                        get_timestamp()?
+                    ))
+                    .to_str()
+                    .ok_or("invalid output directory")?
+                    .to_string(),
+                (None, None) => format!("{}.atrace", get_trace_name_from_command(&command)?),
             },
+            existing_output,
             command,
             target_pid,
+            options: TraceOptions {
+                async_tasks,
+                python,
+                profile_overhead,
+                skip_frames,
+                spool_filename,
+                elevate,
+                allocator_stats_interval,
+                resident_memory_interval,
+                cgroup_memory_interval,
+                mmap_tracking,
+                burst_threshold,
+                start_paused,
+                recording_off,
+                max_slowdown,
+                thread_filter,
+                free_tracking,
+                aggregate,
+                retain,
+            },
+            view,
             report_version,
             show_help,
         })
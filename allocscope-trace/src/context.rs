@@ -16,13 +16,82 @@
     with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
+use crate::allocator;
 use crate::breakpoint;
+use crate::cgroup;
+use crate::commandline;
+use crate::crash;
+use crate::hook_spec;
+use crate::hooks;
+use crate::link_map;
+use crate::overhead;
 use crate::process_map;
 use crate::record;
+use crate::smaps;
 use crate::symbol_index;
+use crate::throttle;
 use crate::unwind;
 use std::collections::HashMap;
 use std::error::Error;
+use std::fs;
+use std::time;
+
+// The interval after which a thread's name is read again to check it
+// against the thread filter.
+const THREAD_FILTER_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+// The interval after which a thread's name is read again to check whether
+// the thread has been renamed.
+const THREAD_NAME_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+// Async task identifiers keep the generation of the task header above the
+// bits of a user space address, since a header is reused once its task is
+// freed.  The generation is limited so that identifiers remain positive
+// when stored as SQLite integers.
+const TASK_GENERATION_SHIFT: u32 = 48;
+const TASK_GENERATION_MASK: u64 = 0x7FFF;
+
+// Read the name of a thread, without its trailing newline.
+fn read_thread_name(pid: u32) -> String {
+    let name = fs::read_to_string(format!("/proc/{}/comm", pid)).unwrap_or_default();
+    name.trim_end_matches('\n').to_string()
+}
+
+// The interval after which the CPU on which a thread runs is read again.
+// Reading it costs several system calls, too many to make for every event,
+// so the CPU recorded for an event may be that of up to this long before.
+const THREAD_CPU_INTERVAL: time::Duration = time::Duration::from_millis(100);
+
+// The field of /proc/<tid>/stat holding the CPU on which the thread last
+// ran, counting fields following the command name.
+const STAT_PROCESSOR_FIELD: usize = 36;
+
+// Read the CPU on which a stopped thread last ran.
+fn read_cpu(pid: u32) -> Option<u32> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+
+    // The command name may contain spaces, so skip past its parenthesis.
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields
+        .split_whitespace()
+        .nth(STAT_PROCESSOR_FIELD)?
+        .parse()
+        .ok()
+}
+
+// Returns true if a thread matches an entry of the thread filter, either
+// by its thread id or by its name.
+fn matches_thread_filter(thread_filter: &[String], pid: u32) -> bool {
+    let tid = pid.to_string();
+    let name = read_thread_name(pid);
+
+    thread_filter
+        .iter()
+        .any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => *pattern == tid || name == pattern,
+        })
+}
 
 // Context relevant to a single thread in the traced process.
 pub struct TraceThreadContext {
@@ -31,18 +100,47 @@ pub struct TraceThreadContext {
 
     // ptrace accessors used by libunwind to access the thread.
     pub unwind_context: unwind::UPTContext,
+
+    // The async tasks currently being polled by the thread, innermost last.
+    pub task_stack: Vec<u64>,
+
+    // For an allocation in progress by a function returning the block
+    // through an out-pointer, the address of the out-pointer.
+    pub out_pointer: Option<u64>,
+
+    // While the thread is in pthread_create, the callstack of the call,
+    // to be recorded for the thread it creates.
+    pub spawn_stack: Option<Vec<unwind::StackEntry>>,
+
+    // Whether the thread matched the thread filter when last checked,
+    // and the time of the check, since a thread may be renamed.
+    pub thread_filter_match: Option<(bool, time::Instant)>,
+
+    // The name of the thread last recorded in the trace, and the time it
+    // was read.
+    pub thread_name: Option<(String, time::Instant)>,
+
+    // The CPU on which the thread last ran, and the time it was read.
+    pub cpu: Option<(Option<u32>, time::Instant)>,
 }
 
 // Context relevant to the traced process.
-pub struct TraceContext<'trace_lifetime> {
+pub struct TraceContext {
     // process-ID for the main thread of the process.
     pub pid: u32,
 
+    // Options controlling what is recorded.
+    pub options: commandline::TraceOptions,
+
     // The set of active breakpoints in the process.
     pub breakpoint_set: breakpoint::BreakpointSet,
 
-    // The SQL transaction used for recording trace data.
-    pub transaction: record::Transaction<'trace_lifetime>,
+    // Descriptions of the hooked allocator functions, indexed by the hook
+    // of their breakpoints.
+    pub hook_specs: Vec<hook_spec::HookSpec>,
+
+    // The recorder of trace data.
+    pub recorder: record::Recorder,
 
     // A representation of the binaries mmap-ed into the process's
     // address space.
@@ -57,23 +155,81 @@ pub struct TraceContext<'trace_lifetime> {
 
     // Context for individual threads of the process.
     pub thread_context: HashMap<u32, TraceThreadContext>,
+
+    // true if memory has been unmapped since the process map and symbol
+    // index were last updated.
+    pub symbols_stale: bool,
+
+    // The time at which allocator statistics were last recorded.
+    pub allocator_stats_time: Option<time::Instant>,
+
+    // true once allocator statistics have been skipped because the process
+    // has more than one thread.
+    pub allocator_stats_skipped: bool,
+
+    // The time at which resident memory was last sampled.
+    pub resident_memory_time: Option<time::Instant>,
+
+    // The time at which cgroup memory was last sampled.
+    pub cgroup_memory_time: Option<time::Instant>,
+
+    // true if events are currently being stored in the trace.  Recording
+    // is toggled by SIGUSR2 while breakpoints stay in place.
+    pub recording: bool,
+
+    // If limiting the slowdown of the traced process, the sampling of
+    // allocations.
+    pub throttle: Option<throttle::Throttle>,
+
+    // The state of the last thread to receive a crashing signal, to be
+    // recorded if the process is terminated by the signal.
+    pub crash: Option<crash::Crash>,
+
+    // If profiling the overhead of tracing, the time spent by kind of stop.
+    pub overhead: Option<overhead::OverheadProfile>,
+
+    // The allocators found in the process, with the object containing each.
+    pub allocators: Vec<(allocator::Allocator, String)>,
+
+    // The generation of each async task header which has been polled,
+    // advanced when the header's address is allocated again.
+    pub task_generations: HashMap<u64, u64>,
 }
 
-impl<'trace_lifetime> TraceContext<'trace_lifetime> {
+impl TraceContext {
     // Construct the context for tracing a new process.
     pub fn new(
         pid: u32,
+        options: commandline::TraceOptions,
         breakpoint_set: breakpoint::BreakpointSet,
-        transaction: record::Transaction,
+        hook_specs: Vec<hook_spec::HookSpec>,
+        recorder: record::Recorder,
     ) -> Result<TraceContext, Box<dyn Error>> {
         Ok(TraceContext {
             pid,
             breakpoint_set,
-            transaction,
+            hook_specs,
+            recorder,
             process_map: process_map::ProcessMap::new(pid)?,
             symbol_index: symbol_index::SymbolIndex::new(),
             unwind_address_space: unwind::AddressSpace::new_upt()?,
             thread_context: HashMap::new(),
+            symbols_stale: false,
+            allocator_stats_time: None,
+            allocator_stats_skipped: false,
+            resident_memory_time: None,
+            cgroup_memory_time: None,
+            recording: !options.recording_off,
+            throttle: options.max_slowdown.map(throttle::Throttle::new),
+            crash: None,
+            allocators: Vec::new(),
+            task_generations: HashMap::new(),
+            overhead: if options.profile_overhead {
+                Some(overhead::OverheadProfile::new())
+            } else {
+                None
+            },
+            options,
         })
     }
 
@@ -86,6 +242,12 @@ impl<'trace_lifetime> TraceContext<'trace_lifetime> {
                 TraceThreadContext {
                     in_syscall: false,
                     unwind_context: unwind::UPTContext::new(pid as i32)?,
+                    task_stack: Vec::new(),
+                    out_pointer: None,
+                    spawn_stack: None,
+                    thread_filter_match: None,
+                    thread_name: None,
+                    cpu: None,
                 },
             );
         }
@@ -110,15 +272,294 @@ impl<'trace_lifetime> TraceContext<'trace_lifetime> {
             .ok_or("missing thread context".into())
     }
 
+    // Returns true if events on a thread should be stored in the trace.
+    // Events which are not stored are still tracked while in progress, so
+    // that nested allocator calls are not mistaken for new events.  Events
+    // are stored while recording is on, for threads matching the thread
+    // filter, if any.
+    pub fn should_record(&mut self, pid: u32) -> bool {
+        if !self.recording {
+            return false;
+        }
+        if self.options.thread_filter.is_empty() {
+            return true;
+        }
+
+        let thread_context = match self.thread_context.get_mut(&pid) {
+            Some(thread_context) => thread_context,
+            None => return matches_thread_filter(&self.options.thread_filter, pid),
+        };
+        if let Some((matched, check_time)) = thread_context.thread_filter_match {
+            if check_time.elapsed() < THREAD_FILTER_INTERVAL {
+                return matched;
+            }
+        }
+
+        let matched = matches_thread_filter(&self.options.thread_filter, pid);
+        thread_context.thread_filter_match = Some((matched, time::Instant::now()));
+        matched
+    }
+
+    // Record the name of a thread if it hasn't yet been recorded or if the
+    // thread has been renamed since.  The name is only read again after an
+    // interval, to avoid reading it for every event.
+    pub fn update_thread_name(&mut self, pid: u32) -> Result<(), Box<dyn Error>> {
+        let thread_context = self.get_thread_context_mut(pid)?;
+        if let Some((_, read_time)) = &thread_context.thread_name {
+            if read_time.elapsed() < THREAD_NAME_INTERVAL {
+                return Ok(());
+            }
+        }
+
+        let name = read_thread_name(pid);
+        let renamed = match &thread_context.thread_name {
+            Some((previous, _)) => *previous != name,
+            None => true,
+        };
+        thread_context.thread_name = Some((name.clone(), time::Instant::now()));
+        if renamed && !name.is_empty() {
+            self.recorder.record_thread_name(pid, name)?;
+        }
+
+        Ok(())
+    }
+
+    // The CPU on which a thread last ran, read again only after an interval,
+    // to avoid reading it for every event.
+    pub fn thread_cpu(&mut self, pid: u32) -> Result<Option<u32>, Box<dyn Error>> {
+        let thread_context = self.get_thread_context_mut(pid)?;
+        if let Some((cpu, read_time)) = thread_context.cpu {
+            if read_time.elapsed() < THREAD_CPU_INTERVAL {
+                return Ok(cpu);
+            }
+        }
+
+        let cpu = read_cpu(pid);
+        thread_context.cpu = Some((cpu, time::Instant::now()));
+
+        Ok(cpu)
+    }
+
+    // The identifier of the async task with a given header, distinguishing
+    // tasks which reuse the header of an earlier task.
+    pub fn task_id(&mut self, header: u64) -> u64 {
+        let generation = *self.task_generations.entry(header).or_insert(0);
+        header | (generation & TASK_GENERATION_MASK) << TASK_GENERATION_SHIFT
+    }
+
+    // Note an allocation by the process, which starts a new generation of
+    // any task header previously at its address.
+    pub fn note_allocation(&mut self, address: u64) {
+        if let Some(generation) = self.task_generations.get_mut(&address) {
+            *generation += 1;
+        }
+    }
+
+    // Pause recording events if recording, or resume if paused.
+    pub fn toggle_recording(&mut self) {
+        self.recording = !self.recording;
+        if self.recording {
+            println!("Recording resumed");
+        } else {
+            println!("Recording paused");
+        }
+    }
+
     // The memory map of the process we are tracing has changed, so update
     // the process map with all current memory mappings and reindex the
     // the symbols of the process as new code may have been mapped in.
     pub fn update_process_map(&mut self, pid: u32) -> Result<(), Box<dyn Error>> {
+        self.symbols_stale = false;
         self.process_map = process_map::ProcessMap::new(pid)?;
-        self.symbol_index = symbol_index::SymbolIndex::new();
+        let previous = std::mem::replace(&mut self.symbol_index, symbol_index::SymbolIndex::new());
+        self.symbol_index = symbol_index::SymbolIndex::rebuild(previous);
         self.symbol_index.add_symbols(&self.process_map);
-        self.breakpoint_set
-            .resolve_breakpoints(pid, &self.symbol_index)?;
+        self.symbol_index.add_kernel_symbols(pid, &self.process_map);
+        self.detect_allocators();
+        let search_order = link_map::read_search_order(pid, &self.symbol_index);
+        self.breakpoint_set.resolve_breakpoints(
+            pid,
+            &self.symbol_index,
+            &self.process_map,
+            &search_order,
+        )?;
+
+        Ok(())
+    }
+
+    // Report allocators newly mapped into the process, and hook the entry
+    // points of each allocator the first time it is found.
+    fn detect_allocators(&mut self) {
+        for (allocator, object) in allocator::detect(&self.symbol_index, &self.process_map) {
+            if self.allocators.contains(&(allocator, object.clone())) {
+                continue;
+            }
+
+            println!("Detected {} in {}", allocator.name(), object);
+            if !self.allocators.iter().any(|(known, _)| *known == allocator) {
+                hooks::add_allocator_hooks(
+                    &mut self.breakpoint_set,
+                    &mut self.hook_specs,
+                    allocator.hook_specs(self.options.free_tracking),
+                );
+            }
+            self.allocators.push((allocator, object));
+        }
+    }
+
+    // A range of the process's address space has been unmapped or replaced.
+    // If the range held code, discard breakpoints and cached unwind
+    // information for it, and if that code was mapped from a file, resolve
+    // spooled frames and mark the symbol index to be rebuilt before it is
+    // next used.  Most ranges are data, such as blocks the allocator maps
+    // and unmaps, for which there is nothing to invalidate.
+    pub fn invalidate_range(&mut self, begin: u64, end: u64) -> Result<(), Box<dyn Error>> {
+        self.symbol_index.jit_symbols.remove_range(begin, end);
+
+        let overlapping_code: Vec<&process_map::ProcessMapEntry> = self
+            .process_map
+            .entries
+            .iter()
+            .filter(|entry| entry.executable && entry.begin < end && begin < entry.end)
+            .collect();
+        if overlapping_code.is_empty() {
+            return Ok(());
+        }
+        let file_backed = overlapping_code
+            .iter()
+            .any(|entry| entry.filename.is_some());
+
+        self.breakpoint_set.forget_breakpoints_in_range(begin, end);
+        self.unwind_address_space.flush_cache(begin, end);
+        if !file_backed {
+            return Ok(());
+        }
+
+        // Spooled events are resolved when the trace is finished, by which
+        // time the code they were called from may be gone, so resolve them
+        // now, while the symbols still describe it.
+        self.recorder
+            .resolve_spooled_frames(&self.process_map, &self.symbol_index);
+
+        // libunwind's ptrace accessors cache the most recently used binary,
+        // which may have been in the range, so start fresh accessors.
+        for (tid, thread_context) in self.thread_context.iter_mut() {
+            thread_context.unwind_context = unwind::UPTContext::new(*tid as i32)?;
+        }
+
+        self.symbols_stale = true;
+
+        Ok(())
+    }
+
+    // Rebuild the process map and symbol index if memory has been unmapped
+    // since they were last updated.
+    pub fn ensure_symbols_current(&mut self, pid: u32) -> Result<(), Box<dyn Error>> {
+        if self.symbols_stale {
+            self.update_process_map(pid)?;
+        }
+
+        Ok(())
+    }
+
+    // The dynamic linker has finished relocating the process, so update
+    // the process map and set breakpoints through relocated function slots.
+    pub fn update_relocated_breakpoints(&mut self, pid: u32) -> Result<(), Box<dyn Error>> {
+        self.update_process_map(pid)?;
+        self.breakpoint_set.resolve_relocated_breakpoints(
+            pid,
+            &self.symbol_index,
+            &self.process_map,
+        )?;
+
+        Ok(())
+    }
+
+    // Sample the breakdown of the process's resident memory, if the
+    // interval since the last sample has passed.  This needs no stopped
+    // thread, so samples are taken as the trace loop handles events.
+    pub fn sample_resident_memory(&mut self) -> Result<(), Box<dyn Error>> {
+        let interval = match self.options.resident_memory_interval {
+            Some(interval) => interval,
+            None => return Ok(()),
+        };
+        if let Some(sample_time) = self.resident_memory_time {
+            if sample_time.elapsed() < interval {
+                return Ok(());
+            }
+        }
+        self.resident_memory_time = Some(time::Instant::now());
+
+        // The process may be exiting, in which case there is nothing
+        // left to sample.
+        if let Ok(resident) = smaps::sample(self.pid) {
+            self.recorder.record_resident_memory(resident)?;
+        }
+
+        Ok(())
+    }
+
+    // Sample the memory usage and limit of the process's cgroup, if the
+    // interval since the last sample has passed.
+    pub fn sample_cgroup_memory(&mut self) -> Result<(), Box<dyn Error>> {
+        let interval = match self.options.cgroup_memory_interval {
+            Some(interval) => interval,
+            None => return Ok(()),
+        };
+        if let Some(sample_time) = self.cgroup_memory_time {
+            if sample_time.elapsed() < interval {
+                return Ok(());
+            }
+        }
+        self.cgroup_memory_time = Some(time::Instant::now());
+
+        // The process may have exited, or may not be in a cgroup with a
+        // memory controller.
+        if let Ok(memory) = cgroup::sample(self.pid) {
+            self.recorder.record_cgroup_memory(memory)?;
+        }
+
+        Ok(())
+    }
+
+    // Identify the kind of stop being handled, if profiling overhead.
+    pub fn note_stop_kind(&mut self, kind: &str) {
+        if let Some(profile) = self.overhead.as_mut() {
+            profile.set_kind(kind);
+        }
+    }
+
+    // Note the time spent handling a stop of the traced process, recording
+    // a change to the sampling of allocations if the slowdown requires it.
+    pub fn note_stop(&mut self, duration: time::Duration) -> Result<(), Box<dyn Error>> {
+        if let Some(profile) = self.overhead.as_mut() {
+            profile.end_stop(duration);
+        }
+
+        let throttle = match self.throttle.as_mut() {
+            Some(throttle) => throttle,
+            None => return Ok(()),
+        };
+
+        if let Some(sample_period) = throttle.add_stop(duration) {
+            println!("Recording 1 in {} allocations", sample_period);
+            self.recorder.record_sample_period(sample_period)?;
+        }
+
+        Ok(())
+    }
+
+    // A thread has created a new thread.  If the creating thread is in
+    // pthread_create, record the callstack which created the new thread.
+    pub fn on_thread_created(&mut self, parent: u32, tid: u32) -> Result<(), Box<dyn Error>> {
+        let spawn_stack = match self.thread_context.get_mut(&parent) {
+            Some(thread_context) => thread_context.spawn_stack.take(),
+            None => None,
+        };
+        if let Some(callstack) = spawn_stack {
+            self.recorder
+                .record_thread_creation(tid, parent, callstack)?;
+        }
 
         Ok(())
     }
@@ -0,0 +1,80 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::unwind;
+
+// The state of a thread of the traced process when it received a signal
+// which terminates the process by default.
+pub struct Crash {
+    // The thread id of the thread which received the signal.
+    pub tid: u32,
+
+    // The signal received.
+    pub signal: u8,
+
+    // For a fault, the address which caused it.
+    pub fault_address: Option<u64>,
+
+    // The general purpose registers of the thread, by name.
+    pub registers: Vec<(&'static str, u64)>,
+
+    // The callstack of the thread at the signal.
+    pub callstack: Vec<unwind::StackEntry>,
+}
+
+// Returns true if a signal indicates the traced process has crashed.
+pub fn is_crash_signal(signal: i32) -> bool {
+    matches!(
+        signal,
+        libc::SIGSEGV | libc::SIGBUS | libc::SIGILL | libc::SIGFPE | libc::SIGABRT
+    )
+}
+
+// Returns true if the signal is raised by a faulting instruction, such that
+// the signal information includes the address of the fault.
+pub fn is_fault_signal(signal: i32) -> bool {
+    matches!(
+        signal,
+        libc::SIGSEGV | libc::SIGBUS | libc::SIGILL | libc::SIGFPE
+    )
+}
+
+// The general purpose registers of a thread, by name, in the order they
+// are displayed.
+pub fn named_registers(regs: &libc::user_regs_struct) -> Vec<(&'static str, u64)> {
+    vec![
+        ("rip", regs.rip),
+        ("rsp", regs.rsp),
+        ("rbp", regs.rbp),
+        ("rax", regs.rax),
+        ("rbx", regs.rbx),
+        ("rcx", regs.rcx),
+        ("rdx", regs.rdx),
+        ("rsi", regs.rsi),
+        ("rdi", regs.rdi),
+        ("r8", regs.r8),
+        ("r9", regs.r9),
+        ("r10", regs.r10),
+        ("r11", regs.r11),
+        ("r12", regs.r12),
+        ("r13", regs.r13),
+        ("r14", regs.r14),
+        ("r15", regs.r15),
+        ("eflags", regs.eflags),
+    ]
+}
@@ -0,0 +1,223 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::ptrace;
+
+// A register from which a value can be captured.
+#[derive(Clone, Copy, Debug)]
+pub enum Register {
+    Rdi,
+    Rsi,
+    Rdx,
+    Rcx,
+    R8,
+    R9,
+}
+
+// Where to find an argument of a hooked function at its entry.
+#[derive(Clone, Debug)]
+pub enum ArgumentSource {
+    // The value of a register.
+    Register(Register),
+
+    // An argument passed on the stack, counting from zero for the first
+    // argument following those passed in registers.  Only valid at function
+    // entry, when the return address is at the top of the stack.
+    Stack(u64),
+}
+
+// The kind of event recorded for a hooked function.
+#[derive(Clone, Copy, Debug)]
+pub enum HookKind {
+    // An allocation of a new block.
+    Alloc,
+
+    // A reallocation of an existing block, which may move it.
+    Realloc,
+
+    // A free of an existing block.
+    Free,
+}
+
+// Where the address of a newly allocated block is found when a hooked
+// function returns.
+#[derive(Clone, Debug)]
+pub enum ResultSource {
+    // The function's return value.
+    Return,
+
+    // An out-pointer argument, captured at entry and dereferenced at return,
+    // for functions such as posix_memalign which return an error code.  The
+    // block is only read if the function returns zero.
+    OutPointer(ArgumentSource),
+}
+
+// A description of a hooked allocator function, and of the arguments to
+// capture when it is called.
+#[derive(Clone, Debug)]
+pub struct HookSpec {
+    // The name of the function.
+    pub function: String,
+
+    // The kind of event to record.
+    pub kind: HookKind,
+
+    // Arguments multiplied together for the requested size, such as the
+    // count and element size of calloc.  Unused for frees.
+    pub size: Vec<ArgumentSource>,
+
+    // The argument holding the address of the block freed or reallocated.
+    pub address: Option<ArgumentSource>,
+
+    // Where the address of the allocated block is found.  Unused for frees.
+    pub result: ResultSource,
+}
+
+// The x86_64 System V registers for integer arguments, in order.
+const ARGUMENT_REGISTERS: [Register; 6] = [
+    Register::Rdi,
+    Register::Rsi,
+    Register::Rdx,
+    Register::Rcx,
+    Register::R8,
+    Register::R9,
+];
+
+impl Register {
+    // Read the register from a thread's register contents.
+    fn read(&self, regs: &libc::user_regs_struct) -> u64 {
+        match self {
+            Register::Rdi => regs.rdi,
+            Register::Rsi => regs.rsi,
+            Register::Rdx => regs.rdx,
+            Register::Rcx => regs.rcx,
+            Register::R8 => regs.r8,
+            Register::R9 => regs.r9,
+        }
+    }
+}
+
+impl ArgumentSource {
+    // The source of the nth integer argument of a function, counting from
+    // zero, at function entry.
+    pub fn argument(index: usize) -> ArgumentSource {
+        match ARGUMENT_REGISTERS.get(index) {
+            Some(register) => ArgumentSource::Register(*register),
+            None => ArgumentSource::Stack((index - ARGUMENT_REGISTERS.len()) as u64),
+        }
+    }
+
+    // Read the value from a stopped thread.
+    pub fn read(&self, pid: u32, regs: &libc::user_regs_struct) -> u64 {
+        match self {
+            ArgumentSource::Register(register) => register.read(regs),
+
+            // Stack arguments follow the return address.
+            ArgumentSource::Stack(slot) => ptrace::peektext(pid, regs.rsp + 8 * (slot + 1)),
+        }
+    }
+}
+
+impl HookSpec {
+    // A function allocating a block of the size of its first argument,
+    // such as malloc.
+    pub fn alloc(function: &str) -> HookSpec {
+        HookSpec {
+            function: function.to_string(),
+            kind: HookKind::Alloc,
+            size: vec![ArgumentSource::argument(0)],
+            address: None,
+            result: ResultSource::Return,
+        }
+    }
+
+    // A function allocating a block of the product of its first two
+    // arguments, such as calloc.
+    pub fn alloc_array(function: &str) -> HookSpec {
+        HookSpec {
+            function: function.to_string(),
+            kind: HookKind::Alloc,
+            size: vec![ArgumentSource::argument(0), ArgumentSource::argument(1)],
+            address: None,
+            result: ResultSource::Return,
+        }
+    }
+
+    // A function allocating a block with an alignment given by its first
+    // argument and a size given by its second, such as aligned_alloc.
+    pub fn aligned_alloc(function: &str) -> HookSpec {
+        HookSpec {
+            function: function.to_string(),
+            kind: HookKind::Alloc,
+            size: vec![ArgumentSource::argument(1)],
+            address: None,
+            result: ResultSource::Return,
+        }
+    }
+
+    // posix_memalign, which stores the block through its first argument,
+    // with the size given by its third.
+    pub fn posix_memalign(function: &str) -> HookSpec {
+        HookSpec {
+            function: function.to_string(),
+            kind: HookKind::Alloc,
+            size: vec![ArgumentSource::argument(2)],
+            address: None,
+            result: ResultSource::OutPointer(ArgumentSource::argument(0)),
+        }
+    }
+
+    // A function resizing the block of its first argument to the size of
+    // its second, such as realloc.
+    pub fn realloc(function: &str) -> HookSpec {
+        HookSpec {
+            function: function.to_string(),
+            kind: HookKind::Realloc,
+            size: vec![ArgumentSource::argument(1)],
+            address: Some(ArgumentSource::argument(0)),
+            result: ResultSource::Return,
+        }
+    }
+
+    // A function freeing the block of its first argument, such as free.
+    pub fn free(function: &str) -> HookSpec {
+        HookSpec {
+            function: function.to_string(),
+            kind: HookKind::Free,
+            size: Vec::new(),
+            address: Some(ArgumentSource::argument(0)),
+            result: ResultSource::Return,
+        }
+    }
+
+    // The requested size, captured at function entry.
+    pub fn read_size(&self, pid: u32, regs: &libc::user_regs_struct) -> u64 {
+        self.size.iter().fold(1u64, |size, source| {
+            size.wrapping_mul(source.read(pid, regs))
+        })
+    }
+
+    // The address of the block freed or reallocated, captured at function
+    // entry.
+    pub fn read_address(&self, pid: u32, regs: &libc::user_regs_struct) -> u64 {
+        match &self.address {
+            Some(source) => source.read(pid, regs),
+            None => 0,
+        }
+    }
+}
@@ -17,138 +17,590 @@
 */
 
 use crate::breakpoint;
+use crate::commandline;
 use crate::context;
+use crate::hook_spec::{HookKind, HookSpec, ResultSource};
+use crate::inject;
+use crate::link_map;
+use crate::mapping;
 use crate::ptrace;
-use crate::record::EventType;
+use crate::record::{AllocatorStats, EventType};
 use crate::unwind;
 use libc;
 use std::error::Error;
+use std::time;
 
 // Collect the current stack for a stopped thread.
-fn collect_stack(
+pub fn collect_stack(
     context: &mut context::TraceContext,
     pid: u32,
 ) -> Result<Vec<unwind::StackEntry>, Box<dyn Error>> {
     context.ensure_thread_context(pid)?;
+    context.ensure_symbols_current(pid)?;
+    context
+        .symbol_index
+        .jit_symbols
+        .refresh(context.pid, &context.process_map, false);
     let thread_context = context.get_thread_context(pid)?;
 
-    unwind::collect_stack(
+    let unwind_start = time::Instant::now();
+    let stack = unwind::collect_stack(
         &context.process_map,
         &context.symbol_index,
         &context.unwind_address_space,
         &thread_context.unwind_context,
-    )
+    );
+    if let Some(profile) = context.overhead.as_mut() {
+        profile.add_unwind(unwind_start.elapsed());
+    }
+
+    stack
+}
+
+// Returns true if a function name matches an entry of the skip list.
+fn is_skipped_frame(skip_frames: &[String], name: &str) -> bool {
+    skip_frames
+        .iter()
+        .any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == pattern,
+        })
+}
+
+// Remove allocator-internal frames immediately following the hooked
+// function, so that callstacks continue directly into the code which
+// requested the allocation.  The hooked function itself is kept.
+fn prune_allocator_frames(skip_frames: &[String], stack: &mut Vec<unwind::StackEntry>) {
+    let mut end = 1;
+    while end < stack.len() && is_skipped_frame(skip_frames, &stack[end].name) {
+        end += 1;
+    }
+    if end > 1 {
+        stack.drain(1..end);
+    }
+}
+
+// Start recording an event for a thread, tagging it with the async task
+// currently being polled by the thread, if any, and the CPU on which it
+// last ran.  The stack is only unwound and the CPU only read if the event
+// will be stored in the trace.
+fn start_event(
+    context: &mut context::TraceContext,
+    pid: u32,
+    allocation: EventType,
+) -> Result<(), Box<dyn Error>> {
+    // When throttled, only sampled allocations are unwound.  Frees and
+    // reallocations are still recorded, so that the blocks they free
+    // aren't reported as leaks, but without a callstack when not sampled.
+    let mut stack = None;
+    if context.should_record(pid) {
+        context.update_thread_name(pid)?;
+        let unwind = match (&allocation, context.throttle.as_mut()) {
+            (_, None) => true,
+            (EventType::Free, Some(throttle)) => throttle.is_unthrottled(),
+            (_, Some(throttle)) => throttle.sample(),
+        };
+        if unwind {
+            let mut collected = collect_stack(context, pid)?;
+            prune_allocator_frames(&context.options.skip_frames, &mut collected);
+            stack = Some(collected);
+        } else if !matches!(allocation, EventType::Alloc(_)) {
+            stack = Some(Vec::new());
+        }
+    }
+
+    // Events without a stack aren't stored, so the CPU is skipped for them.
+    let cpu = if stack.is_some() {
+        context.thread_cpu(pid)?
+    } else {
+        None
+    };
+    let task = context.get_thread_context(pid)?.task_stack.last().copied();
+    context
+        .recorder
+        .start_event(pid, allocation, stack, task, cpu);
+
+    Ok(())
+}
+
+// The number of threads of a process, or zero if it can't be read.
+fn thread_count(pid: u32) -> usize {
+    std::fs::read_dir(format!("/proc/{}/task", pid))
+        .map(|tasks| tasks.count())
+        .unwrap_or(0)
+}
+
+// The number of fields in glibc's struct mallinfo2 and struct mallinfo.
+const MALLINFO_FIELDS: usize = 10;
+
+// Call mallinfo2 in the traced process to record the allocator's
+// statistics, if the interval since the last snapshot has passed.  Falls
+// back to mallinfo, with int fields, for glibc older than 2.33.
+fn record_allocator_stats(
+    context: &mut context::TraceContext,
+    pid: u32,
+) -> Result<(), Box<dyn Error>> {
+    let interval = match context.options.allocator_stats_interval {
+        Some(interval) => interval,
+        None => return Ok(()),
+    };
+    if let Some(stats_time) = context.allocator_stats_time {
+        if stats_time.elapsed() < interval {
+            return Ok(());
+        }
+    }
+    context.allocator_stats_time = Some(time::Instant::now());
+
+    // mallinfo2 locks every arena in turn.  Another thread may be held at
+    // one of our breakpoints or system call stops while holding an arena
+    // lock, and isn't resumed until the injected call returns, so the call
+    // could wait forever.  Statistics are only taken while the process has
+    // a single thread.
+    if thread_count(context.pid) != 1 {
+        if !context.allocator_stats_skipped {
+            println!(
+                "Allocator statistics are not recorded while the process has multiple threads"
+            );
+            context.allocator_stats_skipped = true;
+        }
+        return Ok(());
+    }
+
+    let (function, wide) = match context.symbol_index.symbols_by_name.get("mallinfo2") {
+        Some(entry_vec) => (entry_vec.first().ok_or("missing mallinfo2")?.address, true),
+        None => match context.symbol_index.symbols_by_name.get("mallinfo") {
+            Some(entry_vec) => (entry_vec.first().ok_or("missing mallinfo")?.address, false),
+            None => return Ok(()),
+        },
+    };
+
+    let words = inject::call_with_buffer(&context.breakpoint_set, pid, function, MALLINFO_FIELDS)?;
+    let fields: Vec<u64> = if wide {
+        words
+    } else {
+        words
+            .iter()
+            .flat_map(|word| [*word & 0xFFFFFFFF, *word >> 32])
+            .take(MALLINFO_FIELDS)
+            .collect()
+    };
+
+    context.recorder.record_allocator_stats(AllocatorStats {
+        arena: fields[0],
+        ordblks: fields[1],
+        smblks: fields[2],
+        hblks: fields[3],
+        hblkhd: fields[4],
+        usmblks: fields[5],
+        fsmblks: fields[6],
+        uordblks: fields[7],
+        fordblks: fields[8],
+        keepcost: fields[9],
+    })
+}
+
+// Start an allocation event at the entry of an allocation function, with a
+// breakpoint at the return address to complete the event.  This is also a
+// safe point to take allocator statistics in a single threaded process,
+// since the thread holds no allocator locks at entry.
+fn start_allocation(
+    context: &mut context::TraceContext,
+    pid: u32,
+    regs: &libc::user_regs_struct,
+    allocation: EventType,
+) -> Result<(), Box<dyn Error>> {
+    record_allocator_stats(context, pid)?;
+
+    // At function entry, the return address is at the top of the stack.
+    let return_address = ptrace::peektext(pid, regs.rsp);
+    context
+        .breakpoint_set
+        .add_one_shot_breakpoint(pid, return_address, on_malloc_return)?;
+
+    start_event(context, pid, allocation)
+}
+
+// Returns true if a system call has returned an error.
+fn is_syscall_error(result: u64) -> bool {
+    (result as i64) < 0 && (result as i64) >= -4095
+}
+
+// Record a change to the mappings of the traced process, with the
+// callstack of the system call, if mapping calls are being recorded.
+fn record_mapping(
+    context: &mut context::TraceContext,
+    pid: u32,
+    mapped: bool,
+    mapping: mapping::Mapping,
+) -> Result<(), Box<dyn Error>> {
+    if !context.options.mmap_tracking || !context.should_record(pid) {
+        return Ok(());
+    }
+
+    let stack = collect_stack(context, pid)?;
+    context.recorder.record_mapping(pid, mapped, mapping, stack)
 }
 
 // Hook for mmap, which will resolve loose breakpoint bindings when a new
-// binary is mapped into the traced process.
+// binary is mapped into the traced process.  A fixed mapping may replace
+// previously mapped code, so the new range is invalidated first.  The
+// arguments are still in their registers when the system call completes.
 fn on_mmap(
     context: &mut context::TraceContext,
     pid: u32,
     complete: bool,
 ) -> Result<(), Box<dyn Error>> {
     if complete {
+        let regs = ptrace::getregs(pid)?;
+        if !is_syscall_error(regs.rax) {
+            context.invalidate_range(regs.rax, regs.rax + regs.rsi)?;
+        }
         context.update_process_map(pid)?;
+
+        if !is_syscall_error(regs.rax) {
+            let mapping = mapping::describe(pid, regs.rax, regs.rsi, regs.r10, regs.r8);
+            record_mapping(context, pid, true, mapping)?;
+        }
     }
 
     Ok(())
 }
 
-// Hook for malloc, which will track the size of the allocation requested and
-// set a breakpoint at the return address fo malloc completion.
-fn on_malloc(context: &mut context::TraceContext, pid: u32) -> Result<(), Box<dyn Error>> {
-    let regs = ptrace::getregs(pid)?;
-    let size = regs.rdi;
+// Hook for munmap.  Symbols are resolved again lazily, because nothing new
+// needs breakpoints.
+fn on_munmap(
+    context: &mut context::TraceContext,
+    pid: u32,
+    complete: bool,
+) -> Result<(), Box<dyn Error>> {
+    if complete {
+        let regs = ptrace::getregs(pid)?;
+        if !is_syscall_error(regs.rax) {
+            context.invalidate_range(regs.rdi, regs.rdi + regs.rsi)?;
 
-    let stack = collect_stack(context, pid)?;
-    if stack.len() >= 2 {
-        context
-            .breakpoint_set
-            .add_one_shot_breakpoint(pid, stack[1].address, on_malloc_return)?;
+            let mapping = mapping::Mapping {
+                address: regs.rdi,
+                size: regs.rsi,
+                kind: None,
+                path: None,
+            };
+            record_mapping(context, pid, false, mapping)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Hook for mremap.  The old range is gone, and code may have moved to the
+// new range, so resolve breakpoints immediately.
+fn on_mremap(
+    context: &mut context::TraceContext,
+    pid: u32,
+    complete: bool,
+) -> Result<(), Box<dyn Error>> {
+    if complete {
+        let regs = ptrace::getregs(pid)?;
+        if !is_syscall_error(regs.rax) {
+            context.invalidate_range(regs.rdi, regs.rdi + regs.rsi)?;
+            context.invalidate_range(regs.rax, regs.rax + regs.rdx)?;
+            context.update_process_map(pid)?;
 
-        context
-            .transaction
-            .start_event(pid, EventType::Alloc(size), stack);
+            // The moved range keeps the kind of the old range.
+            let old_mapping = mapping::Mapping {
+                address: regs.rdi,
+                size: regs.rsi,
+                kind: None,
+                path: None,
+            };
+            record_mapping(context, pid, false, old_mapping)?;
+            let new_mapping = mapping::Mapping {
+                address: regs.rax,
+                size: regs.rdx,
+                kind: None,
+                path: None,
+            };
+            record_mapping(context, pid, true, new_mapping)?;
+        }
     }
 
     Ok(())
 }
 
-// Breakpoint callback for malloc completion.  Get the address of the
+// Hook for the dynamic linker's _dl_debug_state, which is called before and
+// after objects are loaded and relocated.  Once relocation is complete,
+// set breakpoints through the function slots the linker has filled.
+fn on_dl_debug_state(context: &mut context::TraceContext, pid: u32) -> Result<(), Box<dyn Error>> {
+    if link_map::read_state(pid, &context.symbol_index) == Some(link_map::RT_CONSISTENT) {
+        context.update_relocated_breakpoints(pid)?;
+    }
+
+    Ok(())
+}
+
+// Hook for allocator functions described by a hook specification.  The
+// arguments captured depend on the specification bound to the breakpoint.
+// Frees complete immediately, with no breakpoint on the return address,
+// since we assume free will always succeed.
+fn on_hooked_function(context: &mut context::TraceContext, pid: u32) -> Result<(), Box<dyn Error>> {
+    let regs = ptrace::getregs(pid)?;
+    let hook = context
+        .breakpoint_set
+        .breakpoints
+        .get(&regs.rip)
+        .and_then(|breakpoint| breakpoint.hook)
+        .ok_or("missing hook specification")?;
+    let spec = context
+        .hook_specs
+        .get(hook)
+        .ok_or("missing hook specification")?;
+
+    let kind = spec.kind;
+    let size = spec.read_size(pid, &regs);
+    let address = spec.read_address(pid, &regs);
+    let out_pointer = match &spec.result {
+        ResultSource::Return => None,
+        ResultSource::OutPointer(source) => Some(source.read(pid, &regs)),
+    };
+
+    context.ensure_thread_context(pid)?;
+    context.get_thread_context_mut(pid)?.out_pointer = out_pointer;
+    match kind {
+        HookKind::Alloc => start_allocation(context, pid, &regs, EventType::Alloc(size)),
+        HookKind::Realloc => {
+            start_allocation(context, pid, &regs, EventType::Realloc(address, size))
+        }
+        HookKind::Free => {
+            start_event(context, pid, EventType::Free)?;
+            context.recorder.complete_event(pid, address)
+        }
+    }
+}
+
+// Breakpoint callback for allocation completion.  Get the address of the
 // allocation and finish recording the event.
 fn on_malloc_return(context: &mut context::TraceContext, pid: u32) -> Result<(), Box<dyn Error>> {
     let regs = ptrace::getregs(pid)?;
-    let address = regs.rax;
 
-    context.transaction.complete_event(pid, address)?;
+    // Functions returning the block through an out-pointer return zero
+    // on success.
+    let address = match context.get_thread_context_mut(pid)?.out_pointer.take() {
+        Some(out_pointer) if regs.rax as u32 == 0 => ptrace::peektext(pid, out_pointer),
+        Some(_) => 0,
+        None => regs.rax,
+    };
+
+    context.note_allocation(address);
+    context.recorder.complete_event(pid, address)?;
 
     Ok(())
 }
 
-// Handle calloc similarly to malloc, but do the size calculation using
-// the size and count parameters.
-fn on_calloc(context: &mut context::TraceContext, pid: u32) -> Result<(), Box<dyn Error>> {
+// The offset of tp_basicsize in CPython's PyTypeObject, following the
+// reference count, type pointer, object size and type name.
+const PYTYPE_BASICSIZE_OFFSET: u64 = 32;
+
+// The offset of tp_itemsize in CPython's PyTypeObject.
+const PYTYPE_ITEMSIZE_OFFSET: u64 = 40;
+
+// Hook for CPython's _PyObject_New, which allocates an object of the size
+// given by the basic size of the type object passed as the first argument.
+fn on_pyobject_new(context: &mut context::TraceContext, pid: u32) -> Result<(), Box<dyn Error>> {
     let regs = ptrace::getregs(pid)?;
-    let count = regs.rdi;
-    let size = regs.rsi;
+    let size = ptrace::peektext(pid, regs.rdi + PYTYPE_BASICSIZE_OFFSET);
 
-    let stack = collect_stack(context, pid)?;
-    if stack.len() >= 2 {
-        context
-            .breakpoint_set
-            .add_one_shot_breakpoint(pid, stack[1].address, on_malloc_return)?;
+    start_allocation(context, pid, &regs, EventType::Alloc(size))
+}
+
+// Hook for CPython's _PyObject_NewVar, which allocates a variable sized
+// object with a number of items of the size given by the type object.
+fn on_pyobject_new_var(
+    context: &mut context::TraceContext,
+    pid: u32,
+) -> Result<(), Box<dyn Error>> {
+    let regs = ptrace::getregs(pid)?;
+    let basic_size = ptrace::peektext(pid, regs.rdi + PYTYPE_BASICSIZE_OFFSET);
+    let item_size = ptrace::peektext(pid, regs.rdi + PYTYPE_ITEMSIZE_OFFSET);
 
-        context
-            .transaction
-            .start_event(pid, EventType::Alloc(count * size), stack);
+    // The type object or item count may be garbage if the call is about
+    // to fail, so skip the event if the size doesn't fit.
+    match regs
+        .rsi
+        .checked_mul(item_size)
+        .and_then(|items_size| items_size.checked_add(basic_size))
+    {
+        Some(size) => start_allocation(context, pid, &regs, EventType::Alloc(size)),
+        None => Ok(()),
     }
+}
 
-    Ok(())
+// The mangled path of tokio's raw task poll function.
+const TASK_POLL_PATH: &str = "5tokio7runtime4task3raw4poll";
+
+// Returns true if a symbol name is an instantiation of tokio's raw task poll
+// function.  The function is generic, so each instantiation has a distinct
+// mangled name: a hash suffix with legacy mangling, or generic arguments
+// following the path with v0 mangling.  With v0 mangling, the path is also
+// preceded by the crate disambiguator, and a closure within the function
+// continues with a numeric identifier.
+fn is_task_poll(name: &str) -> bool {
+    if let Some(suffix) = name.strip_prefix("_ZN") {
+        return suffix
+            .strip_prefix(TASK_POLL_PATH)
+            .is_some_and(|hash| hash.starts_with("17h"));
+    }
+    if !name.starts_with("_R") {
+        return false;
+    }
+
+    name.match_indices(TASK_POLL_PATH).any(|(index, _)| {
+        !name[index + TASK_POLL_PATH.len()..].starts_with(|c: char| c.is_ascii_digit())
+    })
 }
 
-// Hook for realloc, which can be handled as malloc, but we will record as
-// EventType::Realloc so that trace will record a free on the previous
-// allocation if the reallocation is successful.
-fn on_realloc(context: &mut context::TraceContext, pid: u32) -> Result<(), Box<dyn Error>> {
+// Hook for tokio's raw task poll function.  The first argument is a pointer
+// to the task header, which identifies the task while it is alive.  Since
+// the header of a freed task may be reused, the task identifier for
+// allocations made while polling pairs it with the header's generation.
+fn on_task_poll(context: &mut context::TraceContext, pid: u32) -> Result<(), Box<dyn Error>> {
     let regs = ptrace::getregs(pid)?;
-    let address = regs.rdi;
-    let size = regs.rsi;
+    let task = context.task_id(regs.rdi);
 
-    let stack = collect_stack(context, pid)?;
-    if stack.len() >= 2 {
-        context
-            .breakpoint_set
-            .add_one_shot_breakpoint(pid, stack[1].address, on_malloc_return)?;
+    // At function entry, the return address is at the top of the stack.
+    let return_address = ptrace::peektext(pid, regs.rsp);
+    context
+        .breakpoint_set
+        .add_one_shot_breakpoint(pid, return_address, on_task_poll_return)?;
 
-        context
-            .transaction
-            .start_event(pid, EventType::Realloc(address, size), stack);
-    }
+    context.ensure_thread_context(pid)?;
+    context.get_thread_context_mut(pid)?.task_stack.push(task);
 
     Ok(())
 }
 
-// Hook for free.  No breakpoint on the return address this time, since we
-// assume free will always succeed.
-fn on_free(context: &mut context::TraceContext, pid: u32) -> Result<(), Box<dyn Error>> {
+// Breakpoint callback for the completion of a task poll.
+fn on_task_poll_return(
+    context: &mut context::TraceContext,
+    pid: u32,
+) -> Result<(), Box<dyn Error>> {
+    context.get_thread_context_mut(pid)?.task_stack.pop();
+
+    Ok(())
+}
+
+// The hook specifications for the standard allocation routines, and for
+// the allocators enabled by the trace options.
+fn allocator_hook_specs(options: &commandline::TraceOptions) -> Vec<HookSpec> {
+    let mut specs = vec![
+        HookSpec::alloc("malloc"),
+        HookSpec::alloc_array("calloc"),
+        HookSpec::realloc("realloc"),
+        HookSpec::aligned_alloc("aligned_alloc"),
+        HookSpec::aligned_alloc("memalign"),
+        HookSpec::posix_memalign("posix_memalign"),
+    ];
+    if options.free_tracking {
+        specs.push(HookSpec::free("free"));
+    }
+
+    // CPython's allocator functions share signatures with the standard
+    // routines.  Allocations which fall through to malloc are nested within
+    // these calls, so they will be recorded only once.
+    if options.python {
+        for prefix in ["PyMem", "PyObject"] {
+            specs.push(HookSpec::alloc(&format!("{}_Malloc", prefix)));
+            specs.push(HookSpec::alloc_array(&format!("{}_Calloc", prefix)));
+            specs.push(HookSpec::realloc(&format!("{}_Realloc", prefix)));
+            if options.free_tracking {
+                specs.push(HookSpec::free(&format!("{}_Free", prefix)));
+            }
+        }
+    }
+
+    specs
+}
+
+// Hook for allocscope_phase, a function with no effect which a traced
+// program can define and call with the name of each phase it enters, such as
+// 'level_load' or 'gameplay', to mark the start of the phase in the trace.
+// Phases are recorded from every thread, and while recording is off, since
+// they divide the time of the whole program.
+fn on_phase(context: &mut context::TraceContext, pid: u32) -> Result<(), Box<dyn Error>> {
+    let regs = ptrace::getregs(pid)?;
+    let name = ptrace::peekstring(pid, regs.rdi);
+
+    context.recorder.record_phase(name)
+}
+
+// Hook for pthread_create, collecting the callstack to record for the
+// thread it creates.  The stack is taken by the clone event for the new
+// thread, or discarded when pthread_create returns.
+fn on_pthread_create(context: &mut context::TraceContext, pid: u32) -> Result<(), Box<dyn Error>> {
     let regs = ptrace::getregs(pid)?;
-    let address = regs.rdi;
     let stack = collect_stack(context, pid)?;
+    context.get_thread_context_mut(pid)?.spawn_stack = Some(stack);
 
-    context.transaction.start_event(pid, EventType::Free, stack);
-    context.transaction.complete_event(pid, address)?;
+    // At function entry, the return address is at the top of the stack.
+    let return_address = ptrace::peektext(pid, regs.rsp);
+    context.breakpoint_set.add_one_shot_breakpoint(
+        pid,
+        return_address,
+        on_pthread_create_return,
+    )?;
 
     Ok(())
 }
 
-// Add breakpoints for the standard allocation routines.
-pub fn add_hooks(breakpoint_set: &mut breakpoint::BreakpointSet) -> Result<(), Box<dyn Error>> {
+// Breakpoint callback for the completion of pthread_create.
+fn on_pthread_create_return(
+    context: &mut context::TraceContext,
+    pid: u32,
+) -> Result<(), Box<dyn Error>> {
+    context.get_thread_context_mut(pid)?.spawn_stack = None;
+
+    Ok(())
+}
+
+// Add breakpoints for the entry points of an allocator found in the traced
+// process, appending their hook specifications to those already in use.
+pub fn add_allocator_hooks(
+    breakpoint_set: &mut breakpoint::BreakpointSet,
+    hook_specs: &mut Vec<HookSpec>,
+    specs: Vec<HookSpec>,
+) {
+    for spec in specs {
+        breakpoint_set.breakpoint_on_hook(&spec.function, hook_specs.len(), on_hooked_function);
+        hook_specs.push(spec);
+    }
+}
+
+// Add breakpoints for the standard allocation routines, returning the hook
+// specifications referenced by the breakpoints.
+pub fn add_hooks(
+    breakpoint_set: &mut breakpoint::BreakpointSet,
+    options: &commandline::TraceOptions,
+) -> Result<Vec<HookSpec>, Box<dyn Error>> {
     breakpoint_set.add_syscall_intercept(libc::SYS_mmap, on_mmap);
+    breakpoint_set.add_syscall_intercept(libc::SYS_munmap, on_munmap);
+    breakpoint_set.add_syscall_intercept(libc::SYS_mremap, on_mremap);
+    breakpoint_set.breakpoint_on("_dl_debug_state", on_dl_debug_state);
+    breakpoint_set.breakpoint_on("pthread_create", on_pthread_create);
+    breakpoint_set.breakpoint_on("allocscope_phase", on_phase);
 
-    breakpoint_set.breakpoint_on("malloc", on_malloc);
-    breakpoint_set.breakpoint_on("calloc", on_calloc);
-    breakpoint_set.breakpoint_on("realloc", on_realloc);
-    breakpoint_set.breakpoint_on("free", on_free);
+    let specs = allocator_hook_specs(options);
+    for (index, spec) in specs.iter().enumerate() {
+        breakpoint_set.breakpoint_on_hook(&spec.function, index, on_hooked_function);
+    }
 
-    Ok(())
+    if options.python {
+        breakpoint_set.breakpoint_on("_PyObject_New", on_pyobject_new);
+        breakpoint_set.breakpoint_on("_PyObject_NewVar", on_pyobject_new_var);
+    }
+
+    if options.async_tasks {
+        // tokio::runtime::task::raw::poll is generic, so match all
+        // instantiations by their mangled names.
+        breakpoint_set.breakpoint_on_filter(is_task_poll, on_task_poll);
+    }
+
+    Ok(specs)
 }
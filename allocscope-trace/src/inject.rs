@@ -0,0 +1,109 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::breakpoint;
+use crate::ptrace;
+use crate::trace;
+use std::error::Error;
+
+// Bytes of the thread's stack to leave untouched below the stack pointer,
+// covering the x86_64 red zone with room to spare.
+const STACK_RESERVE: u64 = 512;
+
+// A limit on the breakpoints stepped through during an injected call, in
+// case the called function loops through hooked code.
+const MAX_BREAKPOINT_STEPS: usize = 1024;
+
+// Call a function in a thread of the traced process which is stopped at
+// one of our breakpoints, passing a pointer to a scratch buffer on the
+// thread's stack as the only argument.  This fits functions returning a
+// structure by value, such as mallinfo2, since the caller passes the
+// address of the result as a hidden first argument.
+//
+// The breakpoint instruction is used as the return address, so the thread
+// traps when the function returns.  Returns the words of the buffer after
+// the call, and restores the thread's registers.
+pub fn call_with_buffer(
+    breakpoint_set: &breakpoint::BreakpointSet,
+    pid: u32,
+    function: u64,
+    buffer_words: usize,
+) -> Result<Vec<u64>, Box<dyn Error>> {
+    let saved_regs = ptrace::getregs(pid)?;
+    let return_address = saved_regs.rip;
+
+    // The buffer is 16-byte aligned, and the return address is pushed just
+    // below it, so that the stack is aligned as the ABI requires at
+    // function entry.
+    let buffer = (saved_regs.rsp - STACK_RESERVE - 8 * buffer_words as u64) & !0xF;
+    let return_slot = buffer - 8;
+    ptrace::poketext(pid, return_slot, return_address)?;
+
+    let mut regs = saved_regs;
+    regs.rip = function;
+    regs.rsp = return_slot;
+    regs.rdi = buffer;
+    regs.rax = 0;
+
+    // Prevent the kernel from restarting an interrupted system call at
+    // the function's address.
+    regs.orig_rax = u64::MAX;
+    ptrace::setregs(pid, &regs)?;
+
+    let result = run_until_return(breakpoint_set, pid, return_address, return_slot);
+    let words = (0..buffer_words)
+        .map(|ix| ptrace::peektext(pid, buffer + 8 * ix as u64))
+        .collect();
+    ptrace::setregs(pid, &saved_regs)?;
+    result?;
+
+    Ok(words)
+}
+
+// Resume a thread running an injected call until it traps at the return
+// address, stepping through any of our breakpoints the function hits.
+fn run_until_return(
+    breakpoint_set: &breakpoint::BreakpointSet,
+    pid: u32,
+    return_address: u64,
+    return_slot: u64,
+) -> Result<(), Box<dyn Error>> {
+    for _ in 0..MAX_BREAKPOINT_STEPS {
+        ptrace::cont(pid, 0)?;
+        trace::wait_for_signal(pid, libc::SIGTRAP)?;
+
+        let mut regs = ptrace::getregs(pid)?;
+        let address = regs.rip - 1;
+
+        // The stack pointer distinguishes our return from a call the
+        // function makes to the hooked function at the same address.
+        if address == return_address && regs.rsp == return_slot + 8 {
+            return Ok(());
+        }
+
+        let breakpoint = breakpoint_set
+            .breakpoints
+            .get(&address)
+            .ok_or("unexpected trap during injected call")?;
+        regs.rip = address;
+        ptrace::setregs(pid, &regs)?;
+        breakpoint.step_through(pid)?;
+    }
+
+    Err("injected call did not return")?
+}
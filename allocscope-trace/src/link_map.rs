@@ -0,0 +1,94 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::ptrace;
+use crate::symbol_index;
+use std::fs;
+
+// The offset of r_map in the dynamic linker's r_debug structure.
+const R_DEBUG_MAP_OFFSET: u64 = 8;
+
+// The offset of r_state in the dynamic linker's r_debug structure.
+const R_DEBUG_STATE_OFFSET: u64 = 24;
+
+// The offset of l_name in the dynamic linker's link_map structure.
+const LINK_MAP_NAME_OFFSET: u64 = 8;
+
+// The offset of l_next in the dynamic linker's link_map structure.
+const LINK_MAP_NEXT_OFFSET: u64 = 24;
+
+// A limit on the number of link map entries read, in case the list is
+// being modified while we read it.
+const MAX_LINK_MAP_ENTRIES: usize = 4096;
+
+// The r_state value indicating the dynamic linker has finished changing the
+// set of loaded objects.
+pub const RT_CONSISTENT: u64 = 0;
+
+// The address of the dynamic linker's r_debug structure in the traced
+// process, if the dynamic linker is mapped.
+fn get_r_debug(symbol_index: &symbol_index::SymbolIndex) -> Option<u64> {
+    Some(
+        symbol_index
+            .symbols_by_name
+            .get("_r_debug")?
+            .first()?
+            .address,
+    )
+}
+
+// Read the state of the dynamic linker from r_debug.
+pub fn read_state(pid: u32, symbol_index: &symbol_index::SymbolIndex) -> Option<u64> {
+    let r_debug = get_r_debug(symbol_index)?;
+
+    Some(ptrace::peektext(pid, r_debug + R_DEBUG_STATE_OFFSET) & 0xFFFFFFFF)
+}
+
+// Read the filenames of the objects loaded in the global scope of the
+// traced process, in the order the dynamic linker searches them for
+// symbols.  The executable comes first, followed by preloaded objects,
+// followed by the dependencies.  Filenames are canonicalized to match the
+// filenames in the process map.
+pub fn read_search_order(pid: u32, symbol_index: &symbol_index::SymbolIndex) -> Vec<String> {
+    let mut search_order = Vec::new();
+    let r_debug = match get_r_debug(symbol_index) {
+        Some(r_debug) => r_debug,
+        None => return search_order,
+    };
+
+    let mut link_map = ptrace::peektext(pid, r_debug + R_DEBUG_MAP_OFFSET);
+    while link_map != 0 && search_order.len() < MAX_LINK_MAP_ENTRIES {
+        let name_address = ptrace::peektext(pid, link_map + LINK_MAP_NAME_OFFSET);
+        let name = ptrace::peekstring(pid, name_address);
+
+        // The executable has an empty name in the link map.
+        let path = if name.is_empty() {
+            format!("/proc/{}/exe", pid)
+        } else {
+            name
+        };
+        match fs::canonicalize(&path) {
+            Ok(canonical) => search_order.push(canonical.to_string_lossy().into_owned()),
+            Err(_) => search_order.push(path),
+        }
+
+        link_map = ptrace::peektext(pid, link_map + LINK_MAP_NEXT_OFFSET);
+    }
+
+    search_order
+}
@@ -16,22 +16,58 @@
     with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
+mod aggregate;
+mod allocator;
 mod breakpoint;
+mod burst;
+mod cgroup;
+mod clock;
 mod commandline;
 mod context;
+mod crash;
+mod hook_spec;
 mod hooks;
+mod inject;
+mod link_map;
+mod mapping;
+mod overhead;
+mod permission;
 mod process_map;
 mod ptrace;
 mod record;
+mod retention;
+mod smaps;
+mod spool;
 mod symbol_index;
+mod throttle;
 mod trace;
 mod unwind;
 
 use std::error::Error;
+use std::os::unix::process::CommandExt;
+use std::process;
+
+// Replace our process with allocscope-view, viewing the trace we have
+// written.  The viewer installed alongside us is preferred over one found
+// in PATH.  Only returns on error.
+fn exec_viewer(atrace_filename: &str) -> Box<dyn Error> {
+    let sibling = std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join("allocscope-view")))
+        .filter(|viewer| viewer.is_file());
+    let mut command = match sibling {
+        Some(viewer) => process::Command::new(viewer),
+        None => process::Command::new("allocscope-view"),
+    };
+
+    command.arg(atrace_filename).exec().into()
+}
 
 // The main entry point for allocscope-trace.
 fn main() -> Result<(), Box<dyn Error>> {
-    let args = commandline::CommandLineArguments::parse(&mut std::env::args())?;
+    let args = commandline::CommandLineArguments::parse(
+        &mut commandline::get_args_with_defaults()?.into_iter(),
+    )?;
     if args.report_version {
         commandline::report_version();
         return Ok(());
@@ -42,13 +78,25 @@ fn main() -> Result<(), Box<dyn Error>> {
     }
 
     if args.target_pid.is_some() {
-        let record = record::TraceRecord::new(&args.atrace_filename)?;
-        trace::trace_pid(record, args.target_pid.unwrap())?;
+        let appending = record::prepare_output(&args.atrace_filename, args.existing_output)?;
+        trace::trace_pid(
+            &args.atrace_filename,
+            appending,
+            args.target_pid.unwrap(),
+            &args.options,
+        )?;
     } else if args.command.len() > 0 {
-        let record = record::TraceRecord::new(&args.atrace_filename)?;
-        trace::trace_command(record, &args.command)?;
+        let appending = record::prepare_output(&args.atrace_filename, args.existing_output)?;
+        let record = record::TraceRecord::new(&args.atrace_filename, appending)?;
+        trace::trace_command(record, &args.command, &args.options)?;
     } else {
         commandline::show_help();
+        return Ok(());
+    }
+
+    let is_stdout_tty = unsafe { libc::isatty(libc::STDOUT_FILENO) != 0 };
+    if args.view && is_stdout_tty {
+        Err(exec_viewer(&args.atrace_filename))?;
     }
 
     Ok(())
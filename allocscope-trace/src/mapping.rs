@@ -0,0 +1,92 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::fs;
+
+// The kind of memory backing a mapping.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MappingKind {
+    // Private memory not backed by a file, like the heap.
+    Anonymous,
+
+    // A private mapping of a file, whose pages may be in the page cache.
+    File,
+
+    // Memory shared with other mappings, whether of a file or anonymous.
+    Shared,
+}
+
+impl MappingKind {
+    // The name of the kind, as stored in the trace.
+    pub fn name(&self) -> &'static str {
+        match self {
+            MappingKind::Anonymous => "anonymous",
+            MappingKind::File => "file",
+            MappingKind::Shared => "shared",
+        }
+    }
+}
+
+// A range of the traced process's address space mapped or unmapped by a
+// system call.
+pub struct Mapping {
+    // The first address of the range.
+    pub address: u64,
+
+    // The length of the range in bytes.
+    pub size: u64,
+
+    // The kind of a new mapping.  Not present for unmapped ranges, or for
+    // ranges moved by mremap, which keep the kind of their old range.
+    pub kind: Option<MappingKind>,
+
+    // The path of the file mapped, if any.
+    pub path: Option<String>,
+}
+
+// Describe a new mapping from the flags and file descriptor passed to
+// mmap, reading the path of a mapped file through /proc.
+pub fn describe(pid: u32, address: u64, size: u64, flags: u64, fd: u64) -> Mapping {
+    let shared = matches!(
+        flags as i32 & libc::MAP_TYPE,
+        libc::MAP_SHARED | libc::MAP_SHARED_VALIDATE
+    );
+    let anonymous = flags as i32 & libc::MAP_ANONYMOUS != 0;
+
+    let path = if anonymous {
+        None
+    } else {
+        fs::read_link(format!("/proc/{}/fd/{}", pid, fd as i32))
+            .ok()
+            .map(|path| path.to_string_lossy().to_string())
+    };
+    let kind = if shared {
+        MappingKind::Shared
+    } else if anonymous {
+        MappingKind::Anonymous
+    } else {
+        MappingKind::File
+    };
+
+    Mapping {
+        address,
+        size,
+        kind: Some(kind),
+        path,
+    }
+}
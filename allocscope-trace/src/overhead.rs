@@ -0,0 +1,125 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::ptrace;
+use std::collections::HashMap;
+use std::time;
+
+// Time spent handling the stops of one kind.
+#[derive(Default)]
+struct StopOverhead {
+    // The number of stops handled.
+    count: u64,
+
+    // The total time spent handling the stops.
+    total: time::Duration,
+
+    // The time spent unwinding callstacks.
+    unwind: time::Duration,
+
+    // The time spent in ptrace requests and waiting for single steps.
+    ptrace: time::Duration,
+}
+
+// A breakdown of the time spent handling the stops of the traced process,
+// by the kind of stop, such as the hooked function, gathered with
+// --profile-overhead.
+pub struct OverheadProfile {
+    // The overhead of each kind of stop.
+    stops: HashMap<String, StopOverhead>,
+
+    // The kind of the stop currently being handled, if known yet.
+    kind: Option<String>,
+
+    // The time spent unwinding during the stop currently being handled.
+    unwind: time::Duration,
+}
+
+// Format a duration in seconds for the overhead report.
+fn format_seconds(duration: time::Duration) -> String {
+    format!("{:>9.3}s", duration.as_secs_f64())
+}
+
+impl OverheadProfile {
+    // Start profiling, accumulating the time spent in ptrace requests.
+    pub fn new() -> OverheadProfile {
+        ptrace::enable_timing();
+
+        OverheadProfile {
+            stops: HashMap::new(),
+            kind: None,
+            unwind: time::Duration::ZERO,
+        }
+    }
+
+    // Start handling a stop.  Time spent waiting for the stop is the
+    // traced process running, so it isn't counted.
+    pub fn begin_stop(&mut self) {
+        ptrace::take_elapsed();
+        self.kind = None;
+        self.unwind = time::Duration::ZERO;
+    }
+
+    // Identify the kind of the stop being handled, unless already known.
+    pub fn set_kind(&mut self, kind: &str) {
+        if self.kind.is_none() {
+            self.kind = Some(kind.to_string());
+        }
+    }
+
+    // Add time spent unwinding a callstack during the current stop.
+    pub fn add_unwind(&mut self, duration: time::Duration) {
+        self.unwind += duration;
+    }
+
+    // Finish handling a stop, which took a given total time.
+    pub fn end_stop(&mut self, total: time::Duration) {
+        let kind = self.kind.take().unwrap_or_else(|| "other".to_string());
+        let overhead = self.stops.entry(kind).or_default();
+        overhead.count += 1;
+        overhead.total += total;
+        overhead.unwind += self.unwind;
+        overhead.ptrace += ptrace::take_elapsed();
+    }
+
+    // Print the breakdown of overhead, most expensive kind of stop first,
+    // followed by the time spent writing the trace.
+    pub fn report(&self, write_time: time::Duration) {
+        let mut stops: Vec<(&String, &StopOverhead)> = self.stops.iter().collect();
+        stops.sort_by_key(|(_, overhead)| std::cmp::Reverse(overhead.total));
+
+        println!();
+        println!("Tracing overhead by stop:");
+        println!("     STOPS      TOTAL     UNWIND     PTRACE   PER STOP  Stop");
+        for (kind, overhead) in stops {
+            println!(
+                "{:>10} {} {} {} {:>8.1}us  {}",
+                overhead.count,
+                format_seconds(overhead.total),
+                format_seconds(overhead.unwind),
+                format_seconds(overhead.ptrace),
+                overhead.total.as_secs_f64() * 1e6 / overhead.count as f64,
+                kind
+            );
+        }
+        println!(
+            "Writing the trace took {}",
+            format_seconds(write_time).trim()
+        );
+    }
+}
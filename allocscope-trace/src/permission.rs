@@ -0,0 +1,181 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::error::Error;
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::process;
+
+// The capability bit for CAP_SYS_PTRACE.
+const CAP_SYS_PTRACE: u32 = 19;
+
+// The environment variables to carry across a re-exec with elevated
+// privileges, so that default options still apply.
+const PRESERVED_ENVIRONMENT: &str = "ALLOCSCOPE_TRACE_OPTS,ALLOCSCOPE_TRACE_CONFIG";
+
+// Read the Yama ptrace scope setting, or None if Yama is not enabled.
+fn read_ptrace_scope() -> Option<u32> {
+    fs::read_to_string("/proc/sys/kernel/yama/ptrace_scope")
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+// Read a field from the status file of a process.
+fn read_status_field(pid: &str, field: &str) -> Option<String> {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    for line in status.lines() {
+        if let Some(value) = line.strip_prefix(field) {
+            if let Some(value) = value.strip_prefix(':') {
+                return Some(value.trim().to_string());
+            }
+        }
+    }
+
+    None
+}
+
+// Returns true if our process has CAP_SYS_PTRACE in its effective set.
+fn has_ptrace_capability() -> bool {
+    match read_status_field("self", "CapEff") {
+        Some(caps) => match u64::from_str_radix(&caps, 16) {
+            Ok(caps) => caps & (1 << CAP_SYS_PTRACE) != 0,
+            Err(_) => false,
+        },
+        None => false,
+    }
+}
+
+// The real user id of a process, from the first entry of its Uid field.
+fn read_uid(pid: &str) -> Option<u32> {
+    read_status_field(pid, "Uid")?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+// Print an explanation of why attaching to a process may have failed, and
+// what can be changed to allow it.
+pub fn report_attach_failure(pid: u32) {
+    let target = pid.to_string();
+    let capable = has_ptrace_capability();
+
+    if read_status_field(&target, "Pid").is_none() {
+        eprintln!("Process {} does not exist.", pid);
+        return;
+    }
+
+    if let Some(tracer) = read_status_field(&target, "TracerPid") {
+        if tracer != "0" {
+            eprintln!(
+                "Process {} is already being traced by process {}, \
+                 and a process may only have one tracer.",
+                pid, tracer
+            );
+            return;
+        }
+    }
+
+    if !capable && read_uid(&target) != read_uid("self") {
+        eprintln!(
+            "Process {} belongs to another user.  Tracing it requires running \
+             as that user, or as root.",
+            pid
+        );
+        return;
+    }
+
+    match read_ptrace_scope() {
+        Some(1) if !capable => eprintln!(
+            "kernel.yama.ptrace_scope is 1, which only allows tracing of \
+             descendant processes.  To attach, either:\n\
+             \x20   - start the program under allocscope-trace instead of \
+             attaching with --pid,\n\
+             \x20   - allow attaching with 'sudo sysctl kernel.yama.ptrace_scope=0',\n\
+             \x20   - grant the capability with \
+             'sudo setcap cap_sys_ptrace+ep $(which allocscope-trace)',\n\
+             \x20   - or retry with --elevate to re-run with sudo."
+        ),
+        Some(2) if !capable => eprintln!(
+            "kernel.yama.ptrace_scope is 2, which only allows tracing by \
+             processes with CAP_SYS_PTRACE.  To attach, either:\n\
+             \x20   - grant the capability with \
+             'sudo setcap cap_sys_ptrace+ep $(which allocscope-trace)',\n\
+             \x20   - or retry with --elevate to re-run with sudo."
+        ),
+        Some(3) => eprintln!(
+            "kernel.yama.ptrace_scope is 3, which disables ptrace attach \
+             entirely until reboot.  Start the program under allocscope-trace \
+             instead of attaching with --pid."
+        ),
+        _ => (),
+    }
+}
+
+// Print an explanation of why a spawned command may have failed to start
+// under the trace.
+pub fn report_spawn_failure() {
+    if read_ptrace_scope() == Some(3) {
+        eprintln!(
+            "kernel.yama.ptrace_scope is 3, which disables ptrace entirely \
+             until reboot.  The setting must be changed in the kernel \
+             configuration or boot parameters to trace."
+        );
+    }
+}
+
+// Returns true if the error from a failed attach may be resolved by
+// re-running with elevated privileges.
+pub fn may_elevate() -> bool {
+    let is_root = unsafe { libc::geteuid() == 0 };
+
+    !is_root && read_ptrace_scope() != Some(3)
+}
+
+// Find an executable in the directories of PATH.
+fn find_in_path(name: &str) -> Option<String> {
+    let path = std::env::var("PATH").ok()?;
+    path.split(':')
+        .map(|dir| format!("{}/{}", dir, name))
+        .find(|candidate| std::path::Path::new(candidate).is_file())
+}
+
+// Replace our process by re-running ourselves with the same arguments
+// through sudo, or pkexec if sudo is not available.  Only returns on error.
+pub fn reexec_elevated() -> Box<dyn Error> {
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(err) => return err.into(),
+    };
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let mut command = if let Some(sudo) = find_in_path("sudo") {
+        let mut command = process::Command::new(sudo);
+        command.arg(format!("--preserve-env={}", PRESERVED_ENVIRONMENT));
+        command
+    } else if let Some(pkexec) = find_in_path("pkexec") {
+        process::Command::new(pkexec)
+    } else {
+        return "neither sudo nor pkexec found to elevate privileges".into();
+    };
+
+    eprintln!("Re-running with elevated privileges");
+    command.arg(exe).args(args).exec().into()
+}
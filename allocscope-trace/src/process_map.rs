@@ -31,6 +31,9 @@ pub struct ProcessMapEntry {
     // The offset within the mapped file for this mapping.
     pub offset: u64,
 
+    // True if the mapping is executable.
+    pub executable: bool,
+
     // The filename of the mapped file.
     pub filename: Option<String>,
 }
@@ -57,7 +60,8 @@ impl ProcessMap {
             let begin = u64::from_str_radix(split.next().ok_or("missing range start")?, 16)?;
             let end = u64::from_str_radix(split.next().ok_or("missing range end")?, 16)?;
 
-            let mut tokens = tokens.skip(1);
+            let permissions = tokens.next().ok_or("missing permissions")?;
+            let executable = permissions.contains('x');
             let offset = u64::from_str_radix(tokens.next().ok_or("missing mapping offset")?, 16)?;
             let mut tokens = tokens.skip(2);
 
@@ -71,6 +75,7 @@ impl ProcessMap {
                 begin,
                 end,
                 offset,
+                executable,
                 filename,
             });
         }
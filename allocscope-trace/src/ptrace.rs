@@ -20,6 +20,15 @@ use libc;
 use std::error::Error;
 use std::fmt;
 use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time;
+
+// If true, the time spent in ptrace requests and in waiting for traced
+// threads is accumulated, for profiling the overhead of tracing.
+static TIMING: AtomicBool = AtomicBool::new(false);
+
+// Nanoseconds spent in ptrace requests and waits since last taken.
+static ELAPSED_NANOS: AtomicU64 = AtomicU64::new(0);
 
 // A custom error to propagate when the tracing process receives a signal
 // to stop.  (SIGTERM, SIGINT)
@@ -62,6 +71,28 @@ fn errno_string() -> String {
     }
 }
 
+// Accumulate the time spent in ptrace requests and waits.
+pub fn enable_timing() {
+    TIMING.store(true, Ordering::Relaxed);
+}
+
+// Take the time spent in ptrace requests and waits since the last call.
+pub fn take_elapsed() -> time::Duration {
+    time::Duration::from_nanos(ELAPSED_NANOS.swap(0, Ordering::Relaxed))
+}
+
+// Perform a request, accumulating the time it takes if timing is enabled.
+fn timed<T>(request: impl FnOnce() -> T) -> T {
+    if !TIMING.load(Ordering::Relaxed) {
+        return request();
+    }
+
+    let start = time::Instant::now();
+    let result = request();
+    ELAPSED_NANOS.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    result
+}
+
 // Attach a trace to an existing process.
 pub fn attach(pid: u32) -> Result<(), Box<dyn Error>> {
     unsafe {
@@ -76,7 +107,7 @@ pub fn attach(pid: u32) -> Result<(), Box<dyn Error>> {
 // Continue a ptraced process's execution.
 pub fn cont(pid: u32, signal: u8) -> Result<(), Box<dyn Error>> {
     unsafe {
-        if libc::ptrace(libc::PTRACE_CONT, pid, 0, signal as libc::c_uint) == -1 {
+        if timed(|| libc::ptrace(libc::PTRACE_CONT, pid, 0, signal as libc::c_uint)) == -1 {
             Err(errno_string())?
         } else {
             Ok(())
@@ -98,7 +129,7 @@ pub fn detach(pid: u32, signal: u8) -> Result<(), Box<dyn Error>> {
 // Continue a ptraced process, but stop at the next system call.
 pub fn syscall(pid: u32, signal: u8) -> Result<(), Box<dyn Error>> {
     unsafe {
-        if libc::ptrace(libc::PTRACE_SYSCALL, pid, 0, signal as libc::c_uint) == -1 {
+        if timed(|| libc::ptrace(libc::PTRACE_SYSCALL, pid, 0, signal as libc::c_uint)) == -1 {
             Err(errno_string())?
         } else {
             Ok(())
@@ -111,7 +142,7 @@ pub fn getregs(pid: u32) -> Result<libc::user_regs_struct, Box<dyn Error>> {
     unsafe {
         let mut regs = std::mem::MaybeUninit::<libc::user_regs_struct>::zeroed().assume_init();
 
-        if libc::ptrace(libc::PTRACE_GETREGS, pid, 0, &mut regs) == -1 {
+        if timed(|| libc::ptrace(libc::PTRACE_GETREGS, pid, 0, &mut regs)) == -1 {
             Err(errno_string())?
         } else {
             Ok(regs)
@@ -122,7 +153,7 @@ pub fn getregs(pid: u32) -> Result<libc::user_regs_struct, Box<dyn Error>> {
 // Set the CPU register contents of a current stopped ptraced process.
 pub fn setregs(pid: u32, regs: &libc::user_regs_struct) -> Result<(), Box<dyn Error>> {
     unsafe {
-        if libc::ptrace(libc::PTRACE_SETREGS, pid, 0, regs) == -1 {
+        if timed(|| libc::ptrace(libc::PTRACE_SETREGS, pid, 0, regs)) == -1 {
             Err(errno_string())?
         } else {
             Ok(())
@@ -132,7 +163,7 @@ pub fn setregs(pid: u32, regs: &libc::user_regs_struct) -> Result<(), Box<dyn Er
 
 // Read an 8-byte word of code from a stopped ptraced process.
 pub fn peektext(pid: u32, address: u64) -> u64 {
-    unsafe { libc::ptrace(libc::PTRACE_PEEKTEXT, pid, address, 0) as u64 }
+    unsafe { timed(|| libc::ptrace(libc::PTRACE_PEEKTEXT, pid, address, 0)) as u64 }
 }
 
 // Read an individual byte of code from a stopped ptraced process.
@@ -140,10 +171,29 @@ pub fn peekbyte(pid: u32, address: u64) -> u8 {
     ((peektext(pid, address & !7) >> ((address & 7) * 8)) & 0xFF) as u8
 }
 
+// The maximum length of a string read from a ptraced process.
+const MAX_STRING_LENGTH: u64 = 4096;
+
+// Read a NUL terminated string from a stopped ptraced process.
+pub fn peekstring(pid: u32, address: u64) -> String {
+    let mut bytes: Vec<u8> = Vec::new();
+    if address != 0 {
+        for offset in 0..MAX_STRING_LENGTH {
+            let byte = peekbyte(pid, address + offset);
+            if byte == 0 {
+                break;
+            }
+            bytes.push(byte);
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
 // Write an 8-byte word of code to a stopped ptraced process.
 pub fn poketext(pid: u32, address: u64, instruction: u64) -> Result<(), Box<dyn Error>> {
     unsafe {
-        if libc::ptrace(libc::PTRACE_POKETEXT, pid, address, instruction) == -1 {
+        if timed(|| libc::ptrace(libc::PTRACE_POKETEXT, pid, address, instruction)) == -1 {
             Err(errno_string())?
         } else {
             Ok(())
@@ -154,7 +204,7 @@ pub fn poketext(pid: u32, address: u64, instruction: u64) -> Result<(), Box<dyn
 // Step through a single instruction of a stopped ptraced process.
 pub fn singlestep(pid: u32) -> Result<(), Box<dyn Error>> {
     unsafe {
-        if libc::ptrace(libc::PTRACE_SINGLESTEP, pid, 0, 0) == -1 {
+        if timed(|| libc::ptrace(libc::PTRACE_SINGLESTEP, pid, 0, 0)) == -1 {
             Err(errno_string())?
         } else {
             Ok(())
@@ -165,7 +215,7 @@ pub fn singlestep(pid: u32) -> Result<(), Box<dyn Error>> {
 // Set ptrace options on a stopped process.
 pub fn setoptions(pid: u32, options: i32) -> Result<(), Box<dyn Error>> {
     unsafe {
-        if libc::ptrace(libc::PTRACE_SETOPTIONS, pid, 0, options) == -1 {
+        if timed(|| libc::ptrace(libc::PTRACE_SETOPTIONS, pid, 0, options)) == -1 {
             Err(errno_string())?
         } else {
             Ok(())
@@ -179,7 +229,7 @@ pub fn geteventmsg(pid: u32) -> Result<u32, Box<dyn Error>> {
     let mut result: u32 = 0;
 
     unsafe {
-        if libc::ptrace(libc::PTRACE_GETEVENTMSG, pid, 0, &mut result) == -1 {
+        if timed(|| libc::ptrace(libc::PTRACE_GETEVENTMSG, pid, 0, &mut result)) == -1 {
             Err(errno_string())?
         } else {
             Ok(result)
@@ -187,6 +237,19 @@ pub fn geteventmsg(pid: u32) -> Result<u32, Box<dyn Error>> {
     }
 }
 
+// Get the information about the signal which stopped a ptraced process.
+pub fn getsiginfo(pid: u32) -> Result<libc::siginfo_t, Box<dyn Error>> {
+    unsafe {
+        let mut siginfo = std::mem::MaybeUninit::<libc::siginfo_t>::zeroed().assume_init();
+
+        if timed(|| libc::ptrace(libc::PTRACE_GETSIGINFO, pid, 0, &mut siginfo)) == -1 {
+            Err(errno_string())?
+        } else {
+            Ok(siginfo)
+        }
+    }
+}
+
 // Send a signal to a thread.
 pub fn kill(pid: u32, signal: i32) -> Result<(), Box<dyn Error>> {
     unsafe {
@@ -213,7 +276,7 @@ pub fn waitpid(
     unsafe {
         let mut status: i32 = 0;
 
-        let result = libc::waitpid(pid, &mut status, 0);
+        let result = timed(|| libc::waitpid(pid, &mut status, 0));
         if result == -1 {
             Err(errno_string())?
         } else if status >> 16 == libc::PTRACE_EVENT_CLONE {
@@ -280,6 +343,106 @@ pub fn is_term_signal_pending() -> Result<bool, Box<dyn Error>> {
     }
 }
 
+// Block the signal which toggles recording, SIGUSR2, so that it can be
+// taken between events by the trace loop.
+pub fn block_toggle_signal() -> Result<(), Box<dyn Error>> {
+    unsafe {
+        let mut sigset = std::mem::MaybeUninit::<libc::sigset_t>::zeroed().assume_init();
+
+        if libc::sigemptyset(&mut sigset) == -1 {
+            Err(errno_string())?
+        }
+        if libc::sigaddset(&mut sigset, libc::SIGUSR2) == -1 {
+            Err(errno_string())?
+        }
+        if libc::sigprocmask(libc::SIG_BLOCK, &sigset, ptr::null_mut()) == -1 {
+            Err(errno_string())?
+        }
+    }
+
+    Ok(())
+}
+
+// Take a pending signal to toggle recording, returning true if there was
+// one.
+pub fn take_toggle_signal() -> Result<bool, Box<dyn Error>> {
+    unsafe {
+        let mut sigset = std::mem::MaybeUninit::<libc::sigset_t>::zeroed().assume_init();
+
+        if libc::sigemptyset(&mut sigset) == -1 {
+            Err(errno_string())?
+        }
+        if libc::sigaddset(&mut sigset, libc::SIGUSR2) == -1 {
+            Err(errno_string())?
+        }
+
+        let no_wait = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        Ok(libc::sigtimedwait(&sigset, ptr::null_mut(), &no_wait) == libc::SIGUSR2)
+    }
+}
+
+// The interval, in milliseconds, at which a paused trace checks for the
+// signal to release it.
+const RELEASE_POLL_MS: i32 = 100;
+
+// Wait for the user to release a paused trace, by pressing Enter or by
+// sending SIGUSR1.  A pending termination signal also ends the wait, so
+// that the trace loop will stop the trace.
+pub fn wait_for_release() -> Result<(), Box<dyn Error>> {
+    unsafe {
+        let mut sigset = std::mem::MaybeUninit::<libc::sigset_t>::zeroed().assume_init();
+
+        if libc::sigemptyset(&mut sigset) == -1 {
+            Err(errno_string())?
+        }
+        if libc::sigaddset(&mut sigset, libc::SIGUSR1) == -1 {
+            Err(errno_string())?
+        }
+        if libc::sigprocmask(libc::SIG_BLOCK, &sigset, ptr::null_mut()) == -1 {
+            Err(errno_string())?
+        }
+
+        // Stop watching stdin if it is closed, such as when redirected
+        // from /dev/null, leaving only the signal to release the trace.
+        let mut watch_stdin = true;
+        loop {
+            let no_wait = libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            };
+            if libc::sigtimedwait(&sigset, ptr::null_mut(), &no_wait) == libc::SIGUSR1 {
+                return Ok(());
+            }
+            if is_term_signal_pending()? {
+                return Ok(());
+            }
+
+            let mut pollfd = libc::pollfd {
+                fd: libc::STDIN_FILENO,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let nfds = if watch_stdin { 1 } else { 0 };
+            if libc::poll(&mut pollfd, nfds, RELEASE_POLL_MS) > 0 {
+                let mut buffer = [0u8; 256];
+                let count = libc::read(
+                    libc::STDIN_FILENO,
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                );
+                if count <= 0 {
+                    watch_stdin = false;
+                } else if buffer[..count as usize].contains(&b'\n') {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
 // fork off a new child and exec a given command.  This new process will
 // be attached as a tracee prior to exec.
 //
@@ -298,7 +461,10 @@ pub fn attach_to_child_exec(command: &Vec<String>) -> Result<u32, Box<dyn Error>
     unsafe {
         pid = libc::fork();
         if pid == 0 {
-            libc::ptrace(libc::PTRACE_TRACEME, 0, 0, 0);
+            // If we can't be traced, exit rather than running untraced.
+            if libc::ptrace(libc::PTRACE_TRACEME, 0, 0, 0) == -1 {
+                libc::_exit(1);
+            }
             libc::execvp(args[0], args.as_ptr());
             libc::exit(1);
         }
@@ -16,11 +16,74 @@
     with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
+use crate::aggregate;
+use crate::burst;
+use crate::cgroup;
+use crate::clock;
+use crate::commandline;
+use crate::crash;
+use crate::mapping;
+use crate::process_map;
+use crate::retention;
+use crate::smaps;
+use crate::spool;
+use crate::symbol_index;
 use crate::unwind;
 use rusqlite;
-use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path;
+use std::sync::mpsc;
+use std::thread;
+use std::time;
+
+// The maximum number of completed events queued for the writer thread
+// before the tracing thread blocks.
+const RECORD_QUEUE_LENGTH: usize = 4096;
+
+// The interval at which the writer thread commits the events written, so
+// that a trace can be viewed while it is being recorded.
+const COMMIT_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+// The interval at which clock anchors are recorded, so that drift between
+// the clocks over a long trace can be corrected for.
+const CLOCK_ANCHOR_INTERVAL: time::Duration = time::Duration::from_secs(60);
+
+// The capability of a trace in which frees were recorded, so that leaks
+// can be reported.
+const CAPABILITY_FREE_TRACKING: &str = "free-tracking";
+
+// The capability of a trace holding per-callstack aggregates, rather than
+// individual events.
+const CAPABILITY_AGGREGATE: &str = "aggregate";
+
+// How tracing of a session ended.
+#[derive(Clone, Copy)]
+pub enum TraceEnding {
+    // The traced process exited, so blocks never freed were leaked.
+    Exit,
+
+    // We detached from the traced process while it continued to run, so
+    // blocks not yet freed may still be in use.
+    Detach,
+}
+
+impl TraceEnding {
+    // The name recorded for the ending in the trace.
+    fn name(&self) -> &'static str {
+        match self {
+            TraceEnding::Exit => "exit",
+            TraceEnding::Detach => "detach",
+        }
+    }
+}
+
+// The kind of the marker for a burst of allocation.
+const MARKER_BURST: &str = "burst";
 
 // The event type of an allocation event currently in progress on a traced
 // thread.
@@ -43,24 +106,300 @@ struct RecordInProgress {
     // The allocation event.
     allocation: EventType,
 
+    // The callstack from the start of the event, if the event is to be
+    // stored in the trace.
+    callstack: Option<Vec<unwind::StackEntry>>,
+
+    // The async task being polled when the event started, if any.
+    task: Option<u64>,
+
+    // The CPU on which the event started, if known.
+    cpu: Option<u32>,
+
+    // Nanoseconds since the start of the trace when the event started.
+    time: u64,
+}
+
+// An allocation event which has completed, ready to be written to the
+// trace.
+struct CompletedEvent {
+    // The allocation event.
+    allocation: EventType,
+
+    // The address returned or freed by the allocation function.
+    address: u64,
+
+    // The thread on which the event occurred.
+    tid: u32,
+
     // The callstack from the start of the event.
     callstack: Vec<unwind::StackEntry>,
+
+    // The hash of the callstack, identifying identical callstacks.
+    callstack_hash: u64,
+
+    // The async task being polled when the event started, if any.
+    task: Option<u64>,
+
+    // The CPU on which the event started, if known.
+    cpu: Option<u32>,
+
+    // Nanoseconds since the start of the trace when the event started.
+    time: u64,
+}
+
+// A snapshot of the statistics reported by the traced process's allocator,
+// with the fields of glibc's struct mallinfo2.
+pub struct AllocatorStats {
+    // Non-mmapped space allocated from the system.
+    pub arena: u64,
+
+    // The number of free chunks.
+    pub ordblks: u64,
+
+    // The number of free fastbin blocks.
+    pub smblks: u64,
+
+    // The number of mmapped regions.
+    pub hblks: u64,
+
+    // Space allocated in mmapped regions.
+    pub hblkhd: u64,
+
+    // Unused, always zero.
+    pub usmblks: u64,
+
+    // Space in freed fastbin blocks.
+    pub fsmblks: u64,
+
+    // Total allocated space.
+    pub uordblks: u64,
+
+    // Total free space.
+    pub fordblks: u64,
+
+    // Top-most, releasable space.
+    pub keepcost: u64,
+}
+
+// The creation of a thread by the traced process.
+struct ThreadCreation {
+    // The thread id of the new thread.
+    tid: u32,
+
+    // The thread id of the thread which created it.
+    parent: u32,
+
+    // Nanoseconds since the start of the trace when the thread was created.
+    time: u64,
+
+    // The callstack which created the thread.
+    callstack: Vec<unwind::StackEntry>,
+}
+
+// A change to the mappings of the traced process.
+struct MappingChange {
+    // The thread id of the thread making the system call.
+    tid: u32,
+
+    // If true, the range was mapped.  If false, it was unmapped.
+    mapped: bool,
+
+    // Nanoseconds since the start of the trace when the change was made.
+    time: u64,
+
+    // The range changed.
+    mapping: mapping::Mapping,
+
+    // The callstack of the system call.
+    callstack: Vec<unwind::StackEntry>,
+}
+
+// A message with data to be written to the trace.
+enum RecordMessage {
+    // A completed allocation event.
+    Event(CompletedEvent),
+
+    // An allocator statistics snapshot, taken at a time in nanoseconds
+    // since the start of the trace.
+    AllocatorStats(u64, AllocatorStats),
+
+    // A resident memory breakdown, sampled at a time in nanoseconds since
+    // the start of the trace.
+    ResidentMemory(u64, smaps::ResidentMemory),
+
+    // The memory usage and limit of the traced process's cgroup, sampled
+    // at a time in nanoseconds since the start of the trace.
+    CgroupMemory(u64, cgroup::CgroupMemory),
+
+    // The creation of a thread.
+    ThreadCreation(ThreadCreation),
+
+    // A change to the sampling period of allocations, at a time in
+    // nanoseconds since the start of the trace.
+    SamplePeriod(u64, u64),
+
+    // Readings of the system clocks, taken at a time in nanoseconds since
+    // the start of the trace.
+    ClockAnchor(u64, clock::ClockAnchor),
+
+    // A signal delivered to a thread, at a time in nanoseconds since the
+    // start of the trace.  Included are the thread id and the signal.
+    Signal(u64, u32, u8),
+
+    // The start of a named phase of the traced program, at a time in
+    // nanoseconds since the start of the trace.
+    Phase(u64, String),
+
+    // The name of a thread, as first seen or after being renamed, at a
+    // time in nanoseconds since the start of the trace.  Included are the
+    // thread id and the name.
+    ThreadName(u64, u32, String),
+
+    // A change to the process's mappings.
+    MappingChange(MappingChange),
+
+    // The state of the thread which crashed the process, at a time in
+    // nanoseconds since the start of the trace.
+    Crash(u64, crash::Crash),
+}
+
+// A spool file to which events are appended, to be converted into the trace
+// when the trace is finished.
+struct SpoolSink {
+    // The buffered spool file.
+    spool: io::BufWriter<fs::File>,
+
+    // The filename of the spool file.
+    filename: String,
+
+    // The trace to which the spool will be converted.
+    record: TraceRecord,
+
+    // Messages other than events, which are infrequent, so they are kept
+    // in memory until conversion.
+    deferred: Vec<RecordMessage>,
+
+    // The frame addresses of the events spooled in the current generation
+    // of code mappings.
+    generation_addresses: HashSet<u64>,
+
+    // The frames of each earlier generation of code mappings, resolved
+    // before the code was unmapped, by address.
+    resolved_generations: Vec<HashMap<u64, unwind::StackEntry>>,
+}
+
+// The destination of completed events.
+enum RecordSink {
+    // Messages are sent over a channel to a thread which writes them to
+    // the trace.
+    Writer(
+        mpsc::SyncSender<RecordMessage>,
+        thread::JoinHandle<Result<(TraceRecord, time::Duration), String>>,
+    ),
+
+    // Events are appended to a spool file.
+    Spool(Box<SpoolSink>),
+}
+
+// Tracks events in progress on traced threads, and hands completed events
+// off to be written to the trace without waiting for SQLite, so that the
+// traced process can be resumed as soon as possible.
+pub struct Recorder {
+    // A map from PID of traced threads to any allocations currently
+    // in progress.
+    record_in_progress: HashMap<u32, RecordInProgress>,
+
+    // The time at which the trace started.
+    start_time: time::Instant,
+
+    // The time at which a clock anchor was last recorded.
+    clock_anchor_time: time::Instant,
+
+    // Where completed events are sent.  None after the trace is finished.
+    sink: Option<RecordSink>,
+
+    // The number of events not stored in the trace, because recording was
+    // off, their thread was filtered out or their allocation wasn't
+    // sampled.
+    dropped_events: u64,
+}
+
+// Compute a hash identifying a callstack.  The function name is hashed
+// along with the address, because code may be mapped at the same address as
+// previously unmapped code.
+fn callstack_hash(callstack: &[unwind::StackEntry]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for entry in callstack {
+        entry.address.hash(&mut hasher);
+        entry.name.hash(&mut hasher);
+    }
+
+    hasher.finish()
+}
+
+// The frames of a callstack already inserted, by address and function
+// name, along with the id of its leaf stack entry.
+struct KnownCallstack {
+    // The address and function name of each frame.
+    frames: Vec<(u64, String)>,
+
+    // The id of the leaf stack entry.
+    id: Option<u64>,
+}
+
+impl KnownCallstack {
+    // True if a callstack has the same frames as the known callstack.
+    fn matches(&self, callstack: &[unwind::StackEntry]) -> bool {
+        self.frames.len() == callstack.len()
+            && self
+                .frames
+                .iter()
+                .zip(callstack)
+                .all(|((address, name), entry)| *address == entry.address && *name == entry.name)
+    }
 }
 
 // A record of a trace in progress.
 pub struct TraceRecord {
     // The SQLite connection to the database.
     connection: rusqlite::Connection,
+
+    // The id of the session being recorded.
+    session: i64,
+
+    // If true, allocations are aggregated by callstack rather than
+    // recorded as individual events.
+    aggregate: bool,
+
+    // If present, the window of time for which events are retained.
+    retain: Option<time::Duration>,
+
+    // If present, the threshold above which allocation bursts are marked.
+    burst_threshold: Option<burst::BurstThreshold>,
 }
 
 // A SQLite transaction currently in progress, used to record trace data.
-pub struct Transaction<'trace_lifetime> {
+struct Transaction<'trace_lifetime> {
     // The trace which owns this transaction.
-    pub record: &'trace_lifetime TraceRecord,
+    record: &'trace_lifetime TraceRecord,
 
-    // A map from PID of traced threads to any allocations currently
-    // in progress.
-    record_in_progress: HashMap<u32, RecordInProgress>,
+    // The callstacks already inserted, by the hash of their frames.  The
+    // frames are compared on lookup, as different callstacks may share a
+    // hash.
+    callstack_ids: HashMap<u64, Vec<KnownCallstack>>,
+
+    // If aggregating, the running counters by callstack.
+    aggregator: Option<aggregate::Aggregator>,
+
+    // If retaining a window of events, the events which may be pruned.
+    retention: Option<retention::Retention>,
+
+    // If marking allocation bursts, the detector of bursts.
+    burst_detector: Option<burst::BurstDetector>,
+
+    // The time of the last commit.
+    commit_time: time::Instant,
 
     // Prepared SQL for inserting a new location.
     location_insert_statement: rusqlite::Statement<'trace_lifetime>,
@@ -84,19 +423,72 @@ pub struct Transaction<'trace_lifetime> {
 
     // Prepared SQL for inserting a new event.
     insert_event_statement: rusqlite::Statement<'trace_lifetime>,
+
+    // Prepared SQL for inserting a failed allocation.
+    insert_failure_statement: rusqlite::Statement<'trace_lifetime>,
+
+    // Prepared SQL for inserting an allocator statistics snapshot.
+    insert_allocator_stats_statement: rusqlite::Statement<'trace_lifetime>,
+
+    // Prepared SQL for inserting a resident memory sample.
+    insert_resident_memory_statement: rusqlite::Statement<'trace_lifetime>,
+
+    // Prepared SQL for inserting a change to the sampling period.
+    insert_sample_period_statement: rusqlite::Statement<'trace_lifetime>,
+
+    // Prepared SQL for inserting a delivered signal.
+    insert_signal_statement: rusqlite::Statement<'trace_lifetime>,
+
+    // Prepared SQL for inserting the start of a phase.
+    insert_phase_statement: rusqlite::Statement<'trace_lifetime>,
+
+    // Prepared SQL for inserting the name of a thread.
+    insert_thread_name_statement: rusqlite::Statement<'trace_lifetime>,
+
+    // Prepared SQL for inserting readings of the system clocks.
+    insert_clock_anchor_statement: rusqlite::Statement<'trace_lifetime>,
+
+    // Prepared SQL for inserting a marker.
+    insert_marker_statement: rusqlite::Statement<'trace_lifetime>,
+
+    // Prepared SQL for inserting a change to the process's mappings.
+    insert_mapping_statement: rusqlite::Statement<'trace_lifetime>,
+
+    // Prepared SQL for inserting a cgroup memory sample.
+    insert_cgroup_memory_statement: rusqlite::Statement<'trace_lifetime>,
+
+    // Prepared SQL for inserting the aggregated counters of a callstack.
+    insert_aggregate_statement: rusqlite::Statement<'trace_lifetime>,
+
+    // Prepared SQL for updating previously written aggregated counters.
+    update_aggregate_statement: rusqlite::Statement<'trace_lifetime>,
+
+    // Prepared SQL for pruning an event outside the retention window.
+    delete_event_statement: rusqlite::Statement<'trace_lifetime>,
+
+    // Prepared SQL for inserting a created thread.
+    insert_thread_statement: rusqlite::Statement<'trace_lifetime>,
 }
 
 impl<'trace_lifetime> Transaction<'trace_lifetime> {
     // Start a new transaction, preparing SQL statements which we are
     // likely to need.
-    pub fn new(
+    fn new(
         record: &'trace_lifetime TraceRecord,
     ) -> Result<Transaction<'trace_lifetime>, Box<dyn Error>> {
         record.connection.execute("BEGIN TRANSACTION", [])?;
 
         Ok(Transaction {
             record,
-            record_in_progress: HashMap::new(),
+            callstack_ids: HashMap::new(),
+            aggregator: if record.aggregate {
+                Some(aggregate::Aggregator::new())
+            } else {
+                None
+            },
+            retention: record.retain.map(retention::Retention::new),
+            burst_detector: record.burst_threshold.map(burst::BurstDetector::new),
+            commit_time: time::Instant::now(),
 
             location_insert_statement: record.connection.prepare(
                 "INSERT INTO location (address, function, offset)
@@ -135,15 +527,179 @@ impl<'trace_lifetime> Transaction<'trace_lifetime> {
                 location = ? AND next IS NULL",
             )?,
             insert_event_statement: record.connection.prepare(
-                "INSERT INTO event (time, allocation, address, size, callstack)
-                    VALUES (datetime('now'), ?, ?, ?, ?)",
+                "INSERT INTO event (time, allocation, address, size, callstack, task, thread, cpu,
+                    session) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )?,
+            insert_failure_statement: record.connection.prepare(
+                "INSERT INTO failure (time, size, callstack, task, thread, session)
+                    VALUES (?, ?, ?, ?, ?, ?)",
+            )?,
+            insert_allocator_stats_statement: record.connection.prepare(
+                "INSERT INTO allocator_stats (time, arena, ordblks, smblks, hblks,
+                    hblkhd, usmblks, fsmblks, uordblks, fordblks, keepcost, session)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )?,
+            insert_resident_memory_statement: record.connection.prepare(
+                "INSERT INTO resident_memory (time, rss, anonymous, file, shmem, swap, session)
+                    VALUES (?, ?, ?, ?, ?, ?, ?)",
+            )?,
+            insert_sample_period_statement: record
+                .connection
+                .prepare("INSERT INTO sampling (time, period, session) VALUES (?, ?, ?)")?,
+            insert_signal_statement: record.connection.prepare(
+                "INSERT INTO signal (time, thread, signal, session)
+                        VALUES (?, ?, ?, ?)",
+            )?,
+            insert_phase_statement: record
+                .connection
+                .prepare("INSERT INTO phase (time, name, session) VALUES (?, ?, ?)")?,
+            insert_thread_name_statement: record.connection.prepare(
+                "INSERT INTO thread_name (time, tid, name, session) VALUES (?, ?, ?, ?)",
+            )?,
+            insert_clock_anchor_statement: record.connection.prepare(
+                "INSERT INTO clock_anchor (time, monotonic, monotonic_raw, realtime, session)
+                    VALUES (?, ?, ?, ?, ?)",
+            )?,
+            insert_marker_statement: record.connection.prepare(
+                "INSERT INTO marker (time, kind, duration, bytes, callstack, session)
+                    VALUES (?, ?, ?, ?, ?, ?)",
+            )?,
+            insert_mapping_statement: record.connection.prepare(
+                "INSERT INTO mapping (time, mapped, address, size, kind, path, callstack, thread,
+                    session) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )?,
+            insert_cgroup_memory_statement: record.connection.prepare(
+                "INSERT INTO cgroup_memory (time, current, max, session)
+                        VALUES (?, ?, ?, ?)",
+            )?,
+            insert_aggregate_statement: record.connection.prepare(
+                "INSERT INTO aggregate (callstack, task, current_bytes, peak_bytes,
+                    alloc_count, free_count, session) VALUES (?, ?, ?, ?, ?, ?, ?)",
+            )?,
+            update_aggregate_statement: record.connection.prepare(
+                "UPDATE aggregate SET current_bytes = ?, peak_bytes = ?,
+                    alloc_count = ?, free_count = ? WHERE id = ?",
+            )?,
+            delete_event_statement: record
+                .connection
+                .prepare("DELETE FROM event WHERE id = ?")?,
+            insert_thread_statement: record.connection.prepare(
+                "INSERT INTO thread (tid, parent, time, callstack, session)
+                        VALUES (?, ?, ?, ?, ?)",
             )?,
         })
     }
 
     // Commit changes in the current transaction to the database.
-    pub fn commit(&mut self) -> Result<(), Box<dyn Error>> {
+    fn commit(&mut self) -> Result<(), Box<dyn Error>> {
+        self.flush_aggregates()?;
         self.record.connection.execute("COMMIT", []).unwrap();
+        self.commit_time = time::Instant::now();
+
+        Ok(())
+    }
+
+    // Commit the changes written since the last commit if the commit
+    // interval has passed, and begin a new transaction.  Aggregated counters
+    // are left to be written on their own, longer, interval.
+    fn commit_if_due(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.commit_time.elapsed() < COMMIT_INTERVAL {
+            return Ok(());
+        }
+
+        self.record.connection.execute("COMMIT", [])?;
+        self.record.connection.execute("BEGIN TRANSACTION", [])?;
+        self.commit_time = time::Instant::now();
+
+        Ok(())
+    }
+
+    // Mark the burst still in progress at the end of the trace, if any,
+    // record the number of events pruned from the retention window, and
+    // commit.
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(retention) = self.retention.as_ref() {
+            self.record
+                .insert_metadata("pruned_events", &retention.pruned.to_string())?;
+        }
+
+        if let Some(burst) = self
+            .burst_detector
+            .as_mut()
+            .and_then(|detector| detector.finish())
+        {
+            self.insert_burst(&burst)?;
+        }
+
+        self.commit()
+    }
+
+    // Insert a marker for a burst of allocation.
+    fn insert_burst(&mut self, burst: &burst::Burst) -> Result<(), Box<dyn Error>> {
+        self.insert_marker_statement.execute(rusqlite::params![
+            burst.time,
+            MARKER_BURST,
+            burst.duration,
+            burst.bytes,
+            burst.callstack,
+            self.record.session,
+        ])?;
+
+        Ok(())
+    }
+
+    // Write the aggregated counters which have changed since last written.
+    fn flush_aggregates(&mut self) -> Result<(), Box<dyn Error>> {
+        let aggregator = match self.aggregator.as_mut() {
+            Some(aggregator) => aggregator,
+            None => return Ok(()),
+        };
+
+        for ((callstack, task), counters) in aggregator.counters.iter_mut() {
+            if !counters.dirty {
+                continue;
+            }
+            counters.dirty = false;
+
+            match counters.row_id {
+                Some(row_id) => {
+                    self.update_aggregate_statement.execute(rusqlite::params![
+                        counters.current_bytes,
+                        counters.peak_bytes,
+                        counters.alloc_count,
+                        counters.free_count,
+                        row_id,
+                    ])?;
+                }
+                None => {
+                    self.insert_aggregate_statement.execute(rusqlite::params![
+                        callstack,
+                        task,
+                        counters.current_bytes,
+                        counters.peak_bytes,
+                        counters.alloc_count,
+                        counters.free_count,
+                        self.record.session,
+                    ])?;
+                    counters.row_id = Some(self.record.connection.last_insert_rowid());
+                }
+            }
+        }
+        aggregator.mark_flushed();
+
+        Ok(())
+    }
+
+    // Write the aggregated counters if they are due, and commit them so
+    // that they survive an interrupted trace.
+    fn flush_aggregates_if_due(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.aggregator.as_ref() {
+            Some(aggregator) if aggregator.is_flush_due() => {
+                self.commit()?;
+                self.record.connection.execute("BEGIN TRANSACTION", [])?;
+            }
+            _ => (),
+        }
 
         Ok(())
     }
@@ -215,15 +771,48 @@ impl<'trace_lifetime> Transaction<'trace_lifetime> {
         Ok(last_entry_id)
     }
 
+    // Insert a callstack, unless an identical callstack has already been
+    // inserted, returning the id of the leaf stack entry.
+    fn insert_or_reuse_callstack(
+        &mut self,
+        hash: u64,
+        callstack: &Vec<unwind::StackEntry>,
+    ) -> Result<Option<u64>, Box<dyn Error>> {
+        if let Some(known) = self
+            .callstack_ids
+            .get(&hash)
+            .and_then(|known| known.iter().find(|known| known.matches(callstack)))
+        {
+            return Ok(known.id);
+        }
+
+        let locations = self.insert_locations(callstack)?;
+        let callstack_id = self.insert_callstack(&locations)?;
+        self.callstack_ids
+            .entry(hash)
+            .or_default()
+            .push(KnownCallstack {
+                frames: callstack
+                    .iter()
+                    .map(|entry| (entry.address, entry.name.clone()))
+                    .collect(),
+                id: callstack_id,
+            });
+
+        Ok(callstack_id)
+    }
+
     // Insert an entry into the allocation event table.
     fn insert_event(
         &mut self,
+        event: &CompletedEvent,
         allocation: bool,
         address: u64,
         size: Option<u64>,
         callstack_id: Option<u64>,
     ) -> Result<(), Box<dyn Error>> {
         self.insert_event_statement.execute(rusqlite::params![
+            event.time,
             allocation,
             address,
             match size {
@@ -234,102 +823,1116 @@ impl<'trace_lifetime> Transaction<'trace_lifetime> {
                 Some(_) => callstack_id.as_ref().unwrap() as &dyn rusqlite::ToSql,
                 None => &rusqlite::types::Null as &dyn rusqlite::ToSql,
             },
+            event.task,
+            event.tid,
+            event.cpu,
+            self.record.session,
         ])?;
 
+        if let Some(retention) = self.retention.as_mut() {
+            let event_id = self.record.connection.last_insert_rowid();
+            if allocation {
+                retention.add_alloc(address, event_id);
+            } else {
+                retention.add_free(event.time, address, event_id);
+            }
+        }
+
         Ok(())
     }
 
-    // Return true if a given thread currently has an event in progress.
-    pub fn is_event_in_progress(&self, pid: u32) -> bool {
-        self.record_in_progress.contains_key(&pid)
-    }
+    // Prune the events of freed allocations which have fallen out of the
+    // retention window.
+    fn prune_expired_events(&mut self, now: u64) -> Result<(), Box<dyn Error>> {
+        if let Some(retention) = self.retention.as_mut() {
+            for event_id in retention.expire(now) {
+                self.delete_event_statement
+                    .execute(rusqlite::params![event_id])?;
+            }
+        }
 
-    // Start recording an allocation event associated with a particular
-    // thread.
-    pub fn start_event(
-        &mut self,
-        pid: u32,
-        allocation: EventType,
-        callstack: Vec<unwind::StackEntry>,
-    ) {
-        self.record_in_progress.insert(
-            pid,
-            RecordInProgress {
-                allocation,
-                callstack,
-            },
-        );
+        Ok(())
     }
 
-    // Complete a previously started event with an address for the allocation.
-    pub fn complete_event(&mut self, pid: u32, address: u64) -> Result<(), Box<dyn Error>> {
-        let record_in_progress = self
-            .record_in_progress
-            .remove(&pid)
-            .ok_or("Completing event with none in-progress")?;
+    // Record an allocation or free, either by inserting it into the event
+    // table, or by adding it to the aggregated counters.
+    fn add_event(
+        &mut self,
+        event: &CompletedEvent,
+        allocation: bool,
+        address: u64,
+        size: Option<u64>,
+        callstack_id: Option<u64>,
+    ) -> Result<(), Box<dyn Error>> {
+        if let (true, Some(size), Some(detector)) = (allocation, size, self.burst_detector.as_mut())
+        {
+            if let Some(burst) = detector.add_alloc(event.time, size, callstack_id) {
+                self.insert_burst(&burst)?;
+            }
+        }
 
-        let locations = self.insert_locations(&record_in_progress.callstack)?;
-        let callstack_id = self.insert_callstack(&locations)?;
+        let aggregator = match self.aggregator.as_mut() {
+            Some(aggregator) => aggregator,
+            None => return self.insert_event(event, allocation, address, size, callstack_id),
+        };
 
-        match record_in_progress.allocation {
-            EventType::Alloc(size) => {
-                if address != 0 {
-                    self.insert_event(true, address, Some(size), callstack_id)?
-                }
-            }
-            EventType::Free => {
-                if address != 0 {
-                    self.insert_event(false, address, None, callstack_id)?
-                }
-            }
-            EventType::Realloc(original_address, size) => {
-                if original_address != 0 && (address != 0 || size == 0) {
-                    self.insert_event(false, original_address, None, callstack_id)?;
-                }
-                if address != 0 {
-                    self.insert_event(true, address, Some(size), callstack_id)?;
-                }
+        // Allocations without a callstack can't be displayed in the tree,
+        // so they aren't aggregated.
+        match (allocation, size, callstack_id) {
+            (true, Some(size), Some(callstack_id)) => {
+                aggregator.add_alloc((callstack_id, event.task), address, size)
             }
+            (false, _, _) => aggregator.add_free(address),
+            _ => (),
         }
 
         Ok(())
     }
-}
 
-impl TraceRecord {
-    // Start a new trace file with a given filename.
-    pub fn new(filename: &str) -> Result<TraceRecord, Box<dyn Error>> {
-        // First remove any existing file, so we can replace it.
-        _ = fs::remove_file(filename);
-
-        println!("Recording trace to {}", filename);
+    // Insert an entry into the failed allocation table.
+    fn insert_failure(
+        &mut self,
+        event: &CompletedEvent,
+        size: u64,
+        callstack_id: Option<u64>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.insert_failure_statement.execute(rusqlite::params![
+            event.time,
+            size,
+            callstack_id,
+            event.task,
+            event.tid,
+            self.record.session,
+        ])?;
 
-        let connection = rusqlite::Connection::open(filename)?;
+        Ok(())
+    }
 
-        // These pragmas improve write performance a bit.
-        connection.execute_batch(
-            "PRAGMA journal_mode = OFF;
-            PRAGMA synchronous = 0;
-            PRAGMA locking_mode = EXCLUSIVE;
-            PRAGMA temp_store = MEMORY;",
-        )?;
+    // Insert an allocator statistics snapshot.
+    fn insert_allocator_stats(
+        &mut self,
+        time: u64,
+        stats: &AllocatorStats,
+    ) -> Result<(), Box<dyn Error>> {
+        self.insert_allocator_stats_statement
+            .execute(rusqlite::params![
+                time,
+                stats.arena,
+                stats.ordblks,
+                stats.smblks,
+                stats.hblks,
+                stats.hblkhd,
+                stats.usmblks,
+                stats.fsmblks,
+                stats.uordblks,
+                stats.fordblks,
+                stats.keepcost,
+                self.record.session,
+            ])?;
 
-        connection.execute(
-            "CREATE TABLE IF NOT EXISTS trace (
-                version TEXT NOT NULL,
-                time TEXT NOT NULL
-            )",
-            [],
-        )?;
+        Ok(())
+    }
 
+    // Insert a resident memory sample.
+    fn insert_resident_memory(
+        &mut self,
+        time: u64,
+        resident: &smaps::ResidentMemory,
+    ) -> Result<(), Box<dyn Error>> {
+        self.insert_resident_memory_statement
+            .execute(rusqlite::params![
+                time,
+                resident.rss,
+                resident.anonymous,
+                resident.file,
+                resident.shmem,
+                resident.swap,
+                self.record.session,
+            ])?;
+
+        Ok(())
+    }
+
+    // Insert readings of the system clocks.
+    fn insert_clock_anchor(
+        &mut self,
+        time: u64,
+        anchor: &clock::ClockAnchor,
+    ) -> Result<(), Box<dyn Error>> {
+        self.insert_clock_anchor_statement
+            .execute(rusqlite::params![
+                time,
+                anchor.monotonic,
+                anchor.monotonic_raw,
+                anchor.realtime,
+                self.record.session,
+            ])?;
+
+        Ok(())
+    }
+
+    // Insert a cgroup memory sample.
+    fn insert_cgroup_memory(
+        &mut self,
+        time: u64,
+        memory: &cgroup::CgroupMemory,
+    ) -> Result<(), Box<dyn Error>> {
+        self.insert_cgroup_memory_statement
+            .execute(rusqlite::params![
+                time,
+                memory.current,
+                memory.max,
+                self.record.session
+            ])?;
+
+        Ok(())
+    }
+
+    // Insert a created thread, with the callstack which created it.
+    fn insert_thread(&mut self, creation: ThreadCreation) -> Result<(), Box<dyn Error>> {
+        let hash = callstack_hash(&creation.callstack);
+        let callstack_id = self.insert_or_reuse_callstack(hash, &creation.callstack)?;
+        self.insert_thread_statement.execute(rusqlite::params![
+            creation.tid,
+            creation.parent,
+            creation.time,
+            callstack_id,
+            self.record.session,
+        ])?;
+
+        Ok(())
+    }
+
+    // Insert a change to the process's mappings, with the callstack which
+    // made it.
+    fn insert_mapping(&mut self, change: MappingChange) -> Result<(), Box<dyn Error>> {
+        let hash = callstack_hash(&change.callstack);
+        let callstack_id = self.insert_or_reuse_callstack(hash, &change.callstack)?;
+        self.insert_mapping_statement.execute(rusqlite::params![
+            change.time,
+            change.mapped,
+            change.mapping.address,
+            change.mapping.size,
+            change.mapping.kind.map(|kind| kind.name()),
+            change.mapping.path,
+            callstack_id,
+            change.tid,
+            self.record.session,
+        ])?;
+
+        Ok(())
+    }
+
+    // Insert the state of the thread which crashed the process.  A crash
+    // happens at most once, so the statements aren't prepared in advance.
+    fn insert_crash(&mut self, time: u64, crash: crash::Crash) -> Result<(), Box<dyn Error>> {
+        let hash = callstack_hash(&crash.callstack);
+        let callstack_id = self.insert_or_reuse_callstack(hash, &crash.callstack)?;
+        self.record.connection.execute(
+            "INSERT INTO crash (time, thread, signal, fault_address, callstack, session)
+                VALUES (?, ?, ?, ?, ?, ?)",
+            rusqlite::params![
+                time,
+                crash.tid,
+                crash.signal,
+                crash.fault_address,
+                callstack_id,
+                self.record.session
+            ],
+        )?;
+
+        let crash_id = self.record.connection.last_insert_rowid();
+        for (name, value) in crash.registers {
+            self.record.connection.execute(
+                "INSERT INTO crash_register (crash, name, value) VALUES (?, ?, ?)",
+                rusqlite::params![crash_id, name, value],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    // Write a message to the trace.
+    fn write_message(&mut self, message: RecordMessage) -> Result<(), Box<dyn Error>> {
+        match message {
+            RecordMessage::Event(event) => self.write_event(event),
+            RecordMessage::AllocatorStats(time, stats) => self.insert_allocator_stats(time, &stats),
+            RecordMessage::ResidentMemory(time, resident) => {
+                self.insert_resident_memory(time, &resident)
+            }
+            RecordMessage::CgroupMemory(time, memory) => self.insert_cgroup_memory(time, &memory),
+            RecordMessage::ThreadCreation(creation) => self.insert_thread(creation),
+            RecordMessage::SamplePeriod(time, period) => {
+                self.insert_sample_period_statement
+                    .execute(rusqlite::params![time, period, self.record.session])?;
+                Ok(())
+            }
+            RecordMessage::ClockAnchor(time, anchor) => self.insert_clock_anchor(time, &anchor),
+            RecordMessage::Signal(time, tid, signal) => {
+                self.insert_signal_statement.execute(rusqlite::params![
+                    time,
+                    tid,
+                    signal,
+                    self.record.session
+                ])?;
+                Ok(())
+            }
+            RecordMessage::Phase(time, name) => {
+                self.insert_phase_statement.execute(rusqlite::params![
+                    time,
+                    name,
+                    self.record.session
+                ])?;
+                Ok(())
+            }
+            RecordMessage::ThreadName(time, tid, name) => {
+                self.insert_thread_name_statement
+                    .execute(rusqlite::params![time, tid, name, self.record.session])?;
+                Ok(())
+            }
+            RecordMessage::MappingChange(change) => self.insert_mapping(change),
+            RecordMessage::Crash(time, crash) => self.insert_crash(time, crash),
+        }
+    }
+
+    // Write a completed event to the trace.
+    fn write_event(&mut self, event: CompletedEvent) -> Result<(), Box<dyn Error>> {
+        let callstack_id =
+            self.insert_or_reuse_callstack(event.callstack_hash, &event.callstack)?;
+        let address = event.address;
+
+        match event.allocation {
+            EventType::Alloc(size) => {
+                if address != 0 {
+                    self.add_event(&event, true, address, Some(size), callstack_id)?
+                } else if size != 0 {
+                    self.insert_failure(&event, size, callstack_id)?
+                }
+            }
+            EventType::Free => {
+                if address != 0 {
+                    self.add_event(&event, false, address, None, callstack_id)?
+                }
+            }
+            EventType::Realloc(original_address, size) => {
+                if original_address != 0 && (address != 0 || size == 0) {
+                    self.add_event(&event, false, original_address, None, callstack_id)?;
+                }
+                if address != 0 {
+                    self.add_event(&event, true, address, Some(size), callstack_id)?;
+                } else if size != 0 {
+                    // A failed reallocation leaves the original block intact.
+                    self.insert_failure(&event, size, callstack_id)?;
+                }
+            }
+        }
+
+        self.prune_expired_events(event.time)?;
+        self.flush_aggregates_if_due()
+    }
+}
+
+// The body of the writer thread.  Write messages received from the tracing
+// thread until the channel is closed, and then commit the trace, returning
+// the record to the tracing thread.  Messages are committed periodically,
+// even while none are arriving, so that they can be viewed.
+fn write_messages(
+    record: TraceRecord,
+    receiver: mpsc::Receiver<RecordMessage>,
+) -> Result<(TraceRecord, time::Duration), Box<dyn Error>> {
+    let mut write_time = time::Duration::ZERO;
+    {
+        let mut transaction = Transaction::new(&record)?;
+        loop {
+            let message = match receiver.recv_timeout(COMMIT_INTERVAL) {
+                Ok(message) => Some(message),
+                Err(mpsc::RecvTimeoutError::Timeout) => None,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            };
+            let write_start = time::Instant::now();
+            if let Some(message) = message {
+                transaction.write_message(message)?;
+            }
+            transaction.commit_if_due()?;
+            write_time += write_start.elapsed();
+        }
+        let write_start = time::Instant::now();
+        transaction.finish()?;
+        write_time += write_start.elapsed();
+    }
+
+    Ok((record, write_time))
+}
+
+// Convert the events in a spool file into the trace, resolving the function
+// names of frame addresses with the symbols of the traced process.  Frames
+// of events from before code was unmapped were resolved at the time, by
+// generation.  Deferred messages are written along with the events.
+fn convert_spool(
+    record: &TraceRecord,
+    spool_filename: &str,
+    deferred: Vec<RecordMessage>,
+    resolved_generations: &[HashMap<u64, unwind::StackEntry>],
+    process_map: &process_map::ProcessMap,
+    symbol_index: &symbol_index::SymbolIndex,
+) -> Result<(), Box<dyn Error>> {
+    println!("Converting spooled events to trace");
+
+    let mut spool = io::BufReader::new(fs::File::open(spool_filename)?);
+    let mut transaction = Transaction::new(record)?;
+    for message in deferred {
+        transaction.write_message(message)?;
+    }
+
+    // The resolved frames of each callstack, by generation and frame
+    // addresses, so that the frames of a callstack are only resolved once.
+    let mut resolved: HashMap<(u32, Vec<u64>), (u64, Vec<unwind::StackEntry>)> = HashMap::new();
+    while let Some(spooled) = spool::read_record(&mut spool)? {
+        let key = (spooled.generation, spooled.frames);
+        let (hash, callstack) = match resolved.get(&key) {
+            Some((hash, callstack)) => (*hash, callstack.clone()),
+            None => {
+                let generation = resolved_generations.get(key.0 as usize);
+                let callstack: Vec<unwind::StackEntry> = key
+                    .1
+                    .iter()
+                    .map(
+                        |address| match generation.and_then(|frames| frames.get(address)) {
+                            Some(entry) => entry.clone(),
+                            None => {
+                                unwind::resolve_stack_entry(process_map, symbol_index, *address)
+                            }
+                        },
+                    )
+                    .collect();
+                let hash = callstack_hash(&callstack);
+                resolved.insert(key, (hash, callstack.clone()));
+                (hash, callstack)
+            }
+        };
+
+        transaction.write_event(CompletedEvent {
+            allocation: spooled.allocation,
+            address: spooled.address,
+            tid: spooled.tid,
+            callstack,
+            callstack_hash: hash,
+            task: spooled.task,
+            cpu: spooled.cpu,
+            time: spooled.time,
+        })?;
+    }
+    transaction.finish()?;
+
+    fs::remove_file(spool_filename)?;
+
+    Ok(())
+}
+
+impl Recorder {
+    // Start recording a trace.  If a spool filename is given, events are
+    // appended to the spool and converted when the trace is finished.
+    // Otherwise, a writer thread is started to write events to the trace.
+    pub fn new(
+        record: TraceRecord,
+        spool_filename: Option<&str>,
+    ) -> Result<Recorder, Box<dyn Error>> {
+        let sink = match spool_filename {
+            Some(spool_filename) => RecordSink::Spool(Box::new(SpoolSink {
+                spool: io::BufWriter::new(fs::File::create(spool_filename)?),
+                filename: spool_filename.to_string(),
+                record,
+                deferred: Vec::new(),
+                generation_addresses: HashSet::new(),
+                resolved_generations: Vec::new(),
+            })),
+            None => {
+                let (sender, receiver) = mpsc::sync_channel(RECORD_QUEUE_LENGTH);
+                let writer = thread::Builder::new()
+                    .name("trace-writer".to_string())
+                    .spawn(move || {
+                        write_messages(record, receiver).map_err(|err| err.to_string())
+                    })?;
+                RecordSink::Writer(sender, writer)
+            }
+        };
+
+        let start_time = time::Instant::now();
+        let mut recorder = Recorder {
+            record_in_progress: HashMap::new(),
+            start_time,
+            clock_anchor_time: start_time,
+            sink: Some(sink),
+            dropped_events: 0,
+        };
+        recorder.record_clock_anchor()?;
+
+        Ok(recorder)
+    }
+
+    // Return true if a given thread currently has an event in progress.
+    pub fn is_event_in_progress(&self, pid: u32) -> bool {
+        self.record_in_progress.contains_key(&pid)
+    }
+
+    // Start recording an allocation event associated with a particular
+    // thread.
+    pub fn start_event(
+        &mut self,
+        pid: u32,
+        allocation: EventType,
+        callstack: Option<Vec<unwind::StackEntry>>,
+        task: Option<u64>,
+        cpu: Option<u32>,
+    ) {
+        let time = self.start_time.elapsed().as_nanos() as u64;
+        self.record_in_progress.insert(
+            pid,
+            RecordInProgress {
+                allocation,
+                callstack,
+                task,
+                cpu,
+                time,
+            },
+        );
+    }
+
+    // Complete a previously started event with an address for the allocation,
+    // and hand it off to be written to the trace.
+    pub fn complete_event(&mut self, pid: u32, address: u64) -> Result<(), Box<dyn Error>> {
+        let record_in_progress = self
+            .record_in_progress
+            .remove(&pid)
+            .ok_or("Completing event with none in-progress")?;
+
+        let callstack = match record_in_progress.callstack {
+            Some(callstack) => callstack,
+            None => {
+                self.dropped_events += 1;
+                return Ok(());
+            }
+        };
+
+        let event = CompletedEvent {
+            allocation: record_in_progress.allocation,
+            address,
+            tid: pid,
+            callstack_hash: callstack_hash(&callstack),
+            callstack,
+            task: record_in_progress.task,
+            cpu: record_in_progress.cpu,
+            time: record_in_progress.time,
+        };
+
+        self.send(RecordMessage::Event(event))
+    }
+
+    // Record a snapshot of the allocator's statistics.
+    pub fn record_allocator_stats(&mut self, stats: AllocatorStats) -> Result<(), Box<dyn Error>> {
+        let time = self.start_time.elapsed().as_nanos() as u64;
+
+        self.send(RecordMessage::AllocatorStats(time, stats))
+    }
+
+    // Record a sample of where the traced process's resident memory lives.
+    pub fn record_resident_memory(
+        &mut self,
+        resident: smaps::ResidentMemory,
+    ) -> Result<(), Box<dyn Error>> {
+        let time = self.start_time.elapsed().as_nanos() as u64;
+
+        self.send(RecordMessage::ResidentMemory(time, resident))
+    }
+
+    // Record a sample of the memory usage and limit of the traced
+    // process's cgroup.
+    pub fn record_cgroup_memory(
+        &mut self,
+        memory: cgroup::CgroupMemory,
+    ) -> Result<(), Box<dyn Error>> {
+        let time = self.start_time.elapsed().as_nanos() as u64;
+
+        self.send(RecordMessage::CgroupMemory(time, memory))
+    }
+
+    // Record the creation of a thread, with the callstack which created it.
+    pub fn record_thread_creation(
+        &mut self,
+        tid: u32,
+        parent: u32,
+        callstack: Vec<unwind::StackEntry>,
+    ) -> Result<(), Box<dyn Error>> {
+        let time = self.start_time.elapsed().as_nanos() as u64;
+
+        self.send(RecordMessage::ThreadCreation(ThreadCreation {
+            tid,
+            parent,
+            time,
+            callstack,
+        }))
+    }
+
+    // Record a change to the mappings of the traced process, with the
+    // callstack of the system call which made it.
+    pub fn record_mapping(
+        &mut self,
+        tid: u32,
+        mapped: bool,
+        mapping: mapping::Mapping,
+        callstack: Vec<unwind::StackEntry>,
+    ) -> Result<(), Box<dyn Error>> {
+        let time = self.start_time.elapsed().as_nanos() as u64;
+
+        self.send(RecordMessage::MappingChange(MappingChange {
+            tid,
+            mapped,
+            time,
+            mapping,
+            callstack,
+        }))
+    }
+
+    // Record the delivery of a signal to a thread of the traced process.
+    pub fn record_signal(&mut self, tid: u32, signal: u8) -> Result<(), Box<dyn Error>> {
+        let time = self.start_time.elapsed().as_nanos() as u64;
+
+        self.send(RecordMessage::Signal(time, tid, signal))
+    }
+
+    // Record the start of a named phase of the traced program.
+    pub fn record_phase(&mut self, name: String) -> Result<(), Box<dyn Error>> {
+        let time = self.start_time.elapsed().as_nanos() as u64;
+
+        self.send(RecordMessage::Phase(time, name))
+    }
+
+    // Record the name of a thread of the traced process.
+    pub fn record_thread_name(&mut self, tid: u32, name: String) -> Result<(), Box<dyn Error>> {
+        let time = self.start_time.elapsed().as_nanos() as u64;
+
+        self.send(RecordMessage::ThreadName(time, tid, name))
+    }
+
+    // Record the state of the thread which crashed the process.
+    pub fn record_crash(&mut self, crash: crash::Crash) -> Result<(), Box<dyn Error>> {
+        let time = self.start_time.elapsed().as_nanos() as u64;
+
+        self.send(RecordMessage::Crash(time, crash))
+    }
+
+    // Record a change to the sampling period of allocations, after which
+    // one allocation in 'period' is recorded.
+    pub fn record_sample_period(&mut self, period: u64) -> Result<(), Box<dyn Error>> {
+        let time = self.start_time.elapsed().as_nanos() as u64;
+
+        self.send(RecordMessage::SamplePeriod(time, period))
+    }
+
+    // Record the readings of the system clocks at the current trace time.
+    fn record_clock_anchor(&mut self) -> Result<(), Box<dyn Error>> {
+        self.clock_anchor_time = time::Instant::now();
+        let anchor = clock::read_anchor();
+        let time = self.start_time.elapsed().as_nanos() as u64;
+
+        self.send(RecordMessage::ClockAnchor(time, anchor))
+    }
+
+    // Record the readings of the system clocks, if the interval since the
+    // last readings has passed.
+    pub fn record_clock_anchor_if_due(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.clock_anchor_time.elapsed() >= CLOCK_ANCHOR_INTERVAL {
+            self.record_clock_anchor()?;
+        }
+
+        Ok(())
+    }
+
+    // Hand a message off to be written to the trace.
+    fn send(&mut self, message: RecordMessage) -> Result<(), Box<dyn Error>> {
+        match self.sink.as_mut().ok_or("trace already finished")? {
+            RecordSink::Writer(sender, _) => {
+                sender
+                    .send(message)
+                    .map_err(|_| "trace writer has stopped")?;
+            }
+            RecordSink::Spool(spool_sink) => match message {
+                RecordMessage::Event(event) => {
+                    let frames: Vec<u64> = event
+                        .callstack
+                        .iter()
+                        .take(spool::SPOOL_MAX_FRAMES)
+                        .map(|entry| entry.address)
+                        .collect();
+                    spool_sink.generation_addresses.extend(&frames);
+                    spool::write_record(
+                        &mut spool_sink.spool,
+                        &spool::SpoolRecord {
+                            time: event.time,
+                            tid: event.tid,
+                            address: event.address,
+                            task: event.task,
+                            cpu: event.cpu,
+                            generation: spool_sink.resolved_generations.len() as u32,
+                            frames,
+                            allocation: event.allocation,
+                        },
+                    )?;
+                }
+                message => spool_sink.deferred.push(message),
+            },
+        }
+
+        Ok(())
+    }
+
+    // Resolve the frames of the events spooled since code was last
+    // unmapped, while the process map and symbols still describe that
+    // code, and start a new generation of code mappings.  Events which
+    // aren't spooled were resolved as they were recorded.
+    pub fn resolve_spooled_frames(
+        &mut self,
+        process_map: &process_map::ProcessMap,
+        symbol_index: &symbol_index::SymbolIndex,
+    ) {
+        if let Some(RecordSink::Spool(spool_sink)) = self.sink.as_mut() {
+            let resolved = spool_sink
+                .generation_addresses
+                .drain()
+                .map(|address| {
+                    let entry = unwind::resolve_stack_entry(process_map, symbol_index, address);
+                    (address, entry)
+                })
+                .collect();
+            spool_sink.resolved_generations.push(resolved);
+        }
+    }
+
+    // Finish the trace, waiting for all events to be written and committed.
+    // The symbols of the traced process are used to resolve spooled events,
+    // and the modules mapped are recorded so that addresses can be
+    // attributed to the objects containing them, along with how the trace
+    // ended.  Returns the time spent writing to the trace.
+    pub fn finish(
+        &mut self,
+        process_map: &process_map::ProcessMap,
+        symbol_index: &symbol_index::SymbolIndex,
+        ending: TraceEnding,
+    ) -> Result<time::Duration, Box<dyn Error>> {
+        if self.sink.is_some() {
+            self.record_clock_anchor()?;
+        }
+
+        let (record, write_time) = match self.sink.take() {
+            Some(RecordSink::Writer(sender, writer)) => {
+                // Closing the channel signals the writer thread to commit.
+                drop(sender);
+                writer.join().map_err(|_| "trace writer panicked")??
+            }
+            Some(RecordSink::Spool(spool_sink)) => {
+                spool_sink.spool.into_inner()?;
+                let convert_start = time::Instant::now();
+                convert_spool(
+                    &spool_sink.record,
+                    &spool_sink.filename,
+                    spool_sink.deferred,
+                    &spool_sink.resolved_generations,
+                    process_map,
+                    symbol_index,
+                )?;
+                (spool_sink.record, convert_start.elapsed())
+            }
+            None => return Ok(time::Duration::ZERO),
+        };
+        record.insert_modules(process_map)?;
+        record.insert_metadata("dropped_events", &self.dropped_events.to_string())?;
+        record.insert_ending(ending)?;
+
+        // The finished trace is returned to an ordinary journal, so that it
+        // is a single file which can be opened read-only.  This isn't
+        // possible while the trace is being viewed, in which case the
+        // write-ahead log is kept.
+        let _ = record
+            .connection
+            .execute_batch("PRAGMA journal_mode = DELETE;");
+
+        Ok(write_time)
+    }
+}
+
+// Rename an existing trace file to 'FILE.1', first shifting any earlier
+// backups up by one, so that no trace is lost.
+fn rotate_backups(filename: &str) -> Result<(), Box<dyn Error>> {
+    let mut count = 1;
+    while path::Path::new(&format!("{}.{}", filename, count)).exists() {
+        count += 1;
+    }
+    for index in (1..count).rev() {
+        fs::rename(
+            format!("{}.{}", filename, index),
+            format!("{}.{}", filename, index + 1),
+        )?;
+    }
+    fs::rename(filename, format!("{}.1", filename))?;
+    println!("Moved existing trace to {}.1", filename);
+
+    Ok(())
+}
+
+// Describe the options limiting the events recorded, as they were given
+// on the commandline, or an empty string if every event was recorded.
+fn describe_filters(options: &commandline::TraceOptions) -> String {
+    let mut filters = Vec::new();
+    if !options.thread_filter.is_empty() {
+        filters.push(format!("--tid {}", options.thread_filter.join(",")));
+    }
+    if let Some(max_slowdown) = options.max_slowdown {
+        filters.push(format!("--max-slowdown {}", max_slowdown));
+    }
+    if let Some(retain) = options.retain {
+        filters.push(format!("--retain {}s", retain.as_secs()));
+    }
+    if options.recording_off {
+        filters.push("--recording-off".to_string());
+    }
+    if !options.free_tracking {
+        filters.push("--no-free-tracking".to_string());
+    }
+    if options.aggregate {
+        filters.push("--aggregate".to_string());
+    }
+
+    filters.join(" ")
+}
+
+// Make way for a new trace file, dealing with an existing file of the same
+// name as requested by the commandline.  Returns true if the new trace
+// should be appended to the existing file.
+pub fn prepare_output(
+    filename: &str,
+    existing_output: commandline::ExistingOutput,
+) -> Result<bool, Box<dyn Error>> {
+    if !path::Path::new(filename).exists() {
+        return Ok(false);
+    }
+
+    match existing_output {
+        commandline::ExistingOutput::Refuse => Err(format!(
+            "{} already exists.  Use --force to replace it, --backup to keep it, \
+             or --append to add to it.",
+            filename
+        ))?,
+        commandline::ExistingOutput::Replace => fs::remove_file(filename)?,
+        commandline::ExistingOutput::Backup => rotate_backups(filename)?,
+        commandline::ExistingOutput::Append => return Ok(true),
+    }
+
+    Ok(false)
+}
+
+impl TraceRecord {
+    // Start a new trace file with a given filename.  If appending, a new
+    // session is added to an existing trace file instead.  An existing
+    // file should first be dealt with by prepare_output.
+    pub fn new(filename: &str, appending: bool) -> Result<TraceRecord, Box<dyn Error>> {
+        if appending {
+            println!("Appending trace to {}", filename);
+        } else {
+            println!("Recording trace to {}", filename);
+        }
+
+        let connection = rusqlite::Connection::open(filename)?;
+
+        // These pragmas improve write performance a bit.  The write-ahead
+        // log lets the trace be read, as it is periodically committed, while
+        // it is being recorded.
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            PRAGMA synchronous = 0;
+            PRAGMA temp_store = MEMORY;",
+        )?;
+
+        // Traces appended to an existing file must have room for sessions.
+        if appending
+            && (connection.prepare("SELECT session FROM event").is_err()
+                || connection.prepare("SELECT ending FROM trace").is_err())
+        {
+            Err("can't append to a trace recorded by an older version")?;
+        }
+
+        // Each run recorded to the file is a session, with a row holding
+        // the version and time of recording, and how the trace ended, which
+        // is null until the trace is finished.  The rows of other tables hold
+        // the session which recorded them.
         connection.execute(
-            "CREATE TABLE IF NOT EXISTS event (
+            "CREATE TABLE IF NOT EXISTS trace (
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
+                version TEXT NOT NULL,
                 time TEXT NOT NULL,
+                ending TEXT
+            )",
+            [],
+        )?;
+
+        // The kinds of data recorded in the trace, which depend on the
+        // options used for tracing.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS capability (
+                name TEXT NOT NULL,
+                session INTEGER
+            )",
+            [],
+        )?;
+
+        // Descriptions of the traced process and of how it was traced, by
+        // name, so that a trace file describes itself.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS metadata (
+                name TEXT NOT NULL,
+                value TEXT,
+                session INTEGER
+            )",
+            [],
+        )?;
+
+        // Threads created by the traced process, with the callstack which
+        // created each.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS thread (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                tid INTEGER NOT NULL,
+                parent INTEGER NOT NULL,
+                time INTEGER NOT NULL,
+                callstack INTEGER,
+                session INTEGER
+            )",
+            [],
+        )?;
+
+        // Running totals of allocations by callstack and task, recorded
+        // instead of events when aggregating.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS aggregate (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                callstack INTEGER NOT NULL,
+                task INTEGER,
+                current_bytes INTEGER NOT NULL,
+                peak_bytes INTEGER NOT NULL,
+                alloc_count INTEGER NOT NULL,
+                free_count INTEGER NOT NULL,
+                session INTEGER
+            )",
+            [],
+        )?;
+
+        // Event times are nanoseconds since the start of the trace.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS event (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                time INTEGER NOT NULL,
                 allocation BOOLEAN NOT NULL,
                 address INTEGER NOT NULL,
                 size INTEGER,
-                callstack INTEGER
+                callstack INTEGER,
+                task INTEGER,
+                thread INTEGER,
+                cpu INTEGER,
+                session INTEGER
+            )",
+            [],
+        )?;
+
+        // Allocations which returned NULL, with the size requested.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS failure (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                time INTEGER NOT NULL,
+                size INTEGER NOT NULL,
+                callstack INTEGER,
+                task INTEGER,
+                thread INTEGER,
+                session INTEGER
+            )",
+            [],
+        )?;
+
+        // Snapshots of the allocator's statistics, with the fields of
+        // glibc's struct mallinfo2.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS allocator_stats (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                time INTEGER NOT NULL,
+                arena INTEGER NOT NULL,
+                ordblks INTEGER NOT NULL,
+                smblks INTEGER NOT NULL,
+                hblks INTEGER NOT NULL,
+                hblkhd INTEGER NOT NULL,
+                usmblks INTEGER NOT NULL,
+                fsmblks INTEGER NOT NULL,
+                uordblks INTEGER NOT NULL,
+                fordblks INTEGER NOT NULL,
+                keepcost INTEGER NOT NULL,
+                session INTEGER
+            )",
+            [],
+        )?;
+
+        // Samples of the traced process's resident memory, by the kind of
+        // mapping holding it.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS resident_memory (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                time INTEGER NOT NULL,
+                rss INTEGER NOT NULL,
+                anonymous INTEGER NOT NULL,
+                file INTEGER NOT NULL,
+                shmem INTEGER NOT NULL,
+                swap INTEGER NOT NULL,
+                session INTEGER
+            )",
+            [],
+        )?;
+
+        // Changes to the sampling of allocations made to limit the slowdown
+        // of the traced process.  From the time of each change, one
+        // allocation in 'period' is recorded, until the next change.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS sampling (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                time INTEGER NOT NULL,
+                period INTEGER NOT NULL,
+                session INTEGER
+            )",
+            [],
+        )?;
+
+        // Signals delivered to the threads of the traced process, for
+        // correlating allocations with signal handling.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS signal (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                time INTEGER NOT NULL,
+                thread INTEGER NOT NULL,
+                signal INTEGER NOT NULL,
+                session INTEGER
+            )",
+            [],
+        )?;
+
+        // The starts of named phases of the traced program, marked by its
+        // calls to allocscope_phase, for limiting views of the trace to the
+        // span between phases.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS phase (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                time INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                session INTEGER
+            )",
+            [],
+        )?;
+
+        // The names of threads, recorded when a thread first allocates and
+        // again whenever it is found to have been renamed.  The last name
+        // recorded for a thread is its current name.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS thread_name (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                time INTEGER NOT NULL,
+                tid INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                session INTEGER
+            )",
+            [],
+        )?;
+
+        // Readings of the system clocks at trace times, recorded at the
+        // start and end of the trace and periodically in between, for
+        // aligning the trace with perf or strace captures.  Clocks are in
+        // nanoseconds.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS clock_anchor (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                time INTEGER NOT NULL,
+                monotonic INTEGER NOT NULL,
+                monotonic_raw INTEGER NOT NULL,
+                realtime INTEGER NOT NULL,
+                session INTEGER
+            )",
+            [],
+        )?;
+
+        // Points of interest in the trace, such as bursts of allocation.
+        // For a burst, the callstack is the one which allocated the most
+        // during the burst.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS marker (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                time INTEGER NOT NULL,
+                kind TEXT NOT NULL,
+                duration INTEGER,
+                bytes INTEGER,
+                callstack INTEGER,
+                session INTEGER
+            )",
+            [],
+        )?;
+
+        // Changes to the mappings of the traced process.  The kind is null
+        // for unmapped ranges, and for ranges moved by mremap, which keep
+        // the kind of the range they were moved from.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS mapping (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                time INTEGER NOT NULL,
+                mapped BOOLEAN NOT NULL,
+                address INTEGER NOT NULL,
+                size INTEGER NOT NULL,
+                kind TEXT,
+                path TEXT,
+                callstack INTEGER,
+                thread INTEGER,
+                session INTEGER
+            )",
+            [],
+        )?;
+
+        // Samples of the memory charged to the traced process's cgroup,
+        // with the cgroup's limit, which is null for an unlimited cgroup.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS cgroup_memory (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                time INTEGER NOT NULL,
+                current INTEGER NOT NULL,
+                max INTEGER,
+                session INTEGER
+            )",
+            [],
+        )?;
+
+        // The thread which crashed the traced process, with the signal
+        // which terminated it.  For a fault, the faulting address is
+        // included.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS crash (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                time INTEGER NOT NULL,
+                thread INTEGER NOT NULL,
+                signal INTEGER NOT NULL,
+                fault_address INTEGER,
+                callstack INTEGER,
+                session INTEGER
+            )",
+            [],
+        )?;
+
+        // The registers of the crashing thread at the time of the crash.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS crash_register (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                crash INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                value INTEGER NOT NULL
             )",
             [],
         )?;
@@ -353,13 +1956,37 @@ impl TraceRecord {
             [],
         )?;
 
-        connection.execute("CREATE INDEX location_address_ix ON location (address)", [])?;
+        // The executable mappings of the traced process, so that the
+        // object which contains a location can be identified.  For an
+        // allocation, the leaf location is the entry of the allocator
+        // implementation which serviced the call.  The offset within the
+        // file of each mapping locates its code in the object's debugging
+        // information.
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS module (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                address INTEGER NOT NULL,
+                size INTEGER NOT NULL,
+                filename TEXT NOT NULL,
+                offset INTEGER,
+                session INTEGER
+            )",
+            [],
+        )?;
+
+        connection.execute(
+            "CREATE INDEX IF NOT EXISTS location_address_ix ON location (address)",
+            [],
+        )?;
 
         connection.execute(
-            "CREATE INDEX stackentry_location_ix ON stackentry (location)",
+            "CREATE INDEX IF NOT EXISTS stackentry_location_ix ON stackentry (location)",
+            [],
+        )?;
+        connection.execute(
+            "CREATE INDEX IF NOT EXISTS stackentry_next_ix ON stackentry (next)",
             [],
         )?;
-        connection.execute("CREATE INDEX stackentry_next_ix ON stackentry (next)", [])?;
 
         // Store the version of the program creating the trace for future
         // compatibility checks.
@@ -369,7 +1996,134 @@ impl TraceRecord {
                 VALUES (?, datetime('now'))",
             rusqlite::params![version],
         )?;
+        let session = connection.last_insert_rowid();
 
-        Ok(TraceRecord { connection })
+        Ok(TraceRecord {
+            connection,
+            session,
+            aggregate: false,
+            retain: None,
+            burst_threshold: None,
+        })
+    }
+
+    // Record the capabilities of the trace given the options used for
+    // tracing, so the viewer can omit data which wasn't recorded.
+    pub fn insert_capabilities(
+        &self,
+        options: &commandline::TraceOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        if options.free_tracking {
+            self.connection.execute(
+                "INSERT INTO capability (name, session) VALUES (?, ?)",
+                rusqlite::params![CAPABILITY_FREE_TRACKING, self.session],
+            )?;
+        }
+        if options.aggregate {
+            self.connection.execute(
+                "INSERT INTO capability (name, session) VALUES (?, ?)",
+                rusqlite::params![CAPABILITY_AGGREGATE, self.session],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    // Record a description of the traced process or of its tracing.
+    fn insert_metadata(&self, name: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        self.connection.execute(
+            "INSERT INTO metadata (name, value, session) VALUES (?, ?, ?)",
+            rusqlite::params![name, value, self.session],
+        )?;
+
+        Ok(())
+    }
+
+    // Record the commandline, process-id and host of the traced process,
+    // along with the options limiting the events recorded.
+    pub fn insert_process_metadata(
+        &self,
+        pid: u32,
+        options: &commandline::TraceOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        // The arguments of the commandline are separated by nul characters.
+        if let Ok(cmdline) = fs::read(format!("/proc/{}/cmdline", pid)) {
+            let command = cmdline
+                .split(|byte| *byte == 0)
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect::<Vec<String>>()
+                .join(" ");
+            self.insert_metadata("command", &command)?;
+        }
+        self.insert_metadata("pid", &pid.to_string())?;
+        if let Ok(host) = fs::read_to_string("/proc/sys/kernel/hostname") {
+            self.insert_metadata("host", host.trim())?;
+        }
+        self.insert_metadata("filters", &describe_filters(options))?;
+        // The window of retained events, in seconds, so that totals can be
+        // shown as covering only the window.
+        if let Some(retain) = options.retain {
+            self.insert_metadata("retained", &retain.as_secs().to_string())?;
+        }
+
+        Ok(())
+    }
+
+    // Aggregate allocations by callstack, rather than recording each
+    // allocation event.
+    pub fn set_aggregate(&mut self, aggregate: bool) {
+        self.aggregate = aggregate;
+    }
+
+    // Keep only the events within a window of time before the end of the
+    // trace, along with the allocations still live.
+    pub fn set_retain(&mut self, retain: Option<time::Duration>) {
+        self.retain = retain;
+    }
+
+    // Mark bursts of allocation over a threshold.
+    pub fn set_burst_threshold(&mut self, burst_threshold: Option<burst::BurstThreshold>) {
+        self.burst_threshold = burst_threshold;
+    }
+
+    // Record how and when the trace of this session ended.
+    fn insert_ending(&self, ending: TraceEnding) -> Result<(), Box<dyn Error>> {
+        self.connection.execute(
+            "UPDATE trace SET ending = ? WHERE id = ?",
+            rusqlite::params![ending.name(), self.session],
+        )?;
+        self.connection.execute(
+            "INSERT INTO metadata (name, value, session)
+                VALUES ('end_time', datetime('now'), ?)",
+            rusqlite::params![self.session],
+        )?;
+
+        Ok(())
+    }
+
+    // Record the executable mappings of the traced process.
+    fn insert_modules(&self, process_map: &process_map::ProcessMap) -> Result<(), Box<dyn Error>> {
+        self.connection.execute("BEGIN TRANSACTION", [])?;
+        {
+            let mut statement = self.connection.prepare(
+                "INSERT INTO module (address, size, filename, offset, session)
+                    VALUES (?, ?, ?, ?, ?)",
+            )?;
+            for entry in process_map.entries.iter() {
+                if let (true, Some(filename)) = (entry.executable, &entry.filename) {
+                    statement.execute(rusqlite::params![
+                        entry.begin,
+                        entry.end - entry.begin,
+                        filename,
+                        entry.offset,
+                        self.session
+                    ])?;
+                }
+            }
+        }
+        self.connection.execute("COMMIT", [])?;
+
+        Ok(())
     }
 }
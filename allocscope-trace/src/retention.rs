@@ -0,0 +1,98 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::{HashMap, VecDeque};
+use std::time;
+
+// The events of an allocation which has been freed, which can be pruned
+// from the trace once the free is older than the retention window.
+struct FreedAllocation {
+    // The time of the free, in nanoseconds since the start of the trace.
+    time: u64,
+
+    // The row id of the allocation event, if it was recorded.
+    alloc_event: Option<i64>,
+
+    // The row id of the free event.
+    free_event: i64,
+}
+
+// Bookkeeping for keeping only the most recent events in the trace.  An
+// allocation is kept for as long as it is live, regardless of its age, so
+// that leaks are still reported correctly.
+pub struct Retention {
+    // The length of the window of events to retain, in nanoseconds.
+    window: u64,
+
+    // The row id of the allocation event for live allocations, by address.
+    live: HashMap<u64, i64>,
+
+    // Freed allocations, oldest first.
+    freed: VecDeque<FreedAllocation>,
+
+    // The number of events which have fallen out of the window.
+    pub pruned: u64,
+}
+
+impl Retention {
+    // Construct the bookkeeping for a retention window.
+    pub fn new(window: time::Duration) -> Retention {
+        Retention {
+            window: window.as_nanos() as u64,
+            live: HashMap::new(),
+            freed: VecDeque::new(),
+            pruned: 0,
+        }
+    }
+
+    // Note an allocation event written to the trace.
+    pub fn add_alloc(&mut self, address: u64, event_id: i64) {
+        self.live.insert(address, event_id);
+    }
+
+    // Note a free event written to the trace, pairing it with the event
+    // of the allocation it frees.
+    pub fn add_free(&mut self, time: u64, address: u64, event_id: i64) {
+        self.freed.push_back(FreedAllocation {
+            time,
+            alloc_event: self.live.remove(&address),
+            free_event: event_id,
+        });
+    }
+
+    // Remove the freed allocations which have fallen out of the window
+    // ending at a given time, returning the row ids of their events.
+    pub fn expire(&mut self, now: u64) -> Vec<i64> {
+        let cutoff = now.saturating_sub(self.window);
+
+        let mut expired = Vec::new();
+        while let Some(freed) = self.freed.front() {
+            if freed.time >= cutoff {
+                break;
+            }
+            if let Some(alloc_event) = freed.alloc_event {
+                expired.push(alloc_event);
+            }
+            expired.push(freed.free_event);
+            self.freed.pop_front();
+        }
+        self.pruned += expired.len() as u64;
+
+        expired
+    }
+}
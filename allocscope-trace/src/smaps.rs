@@ -0,0 +1,138 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::error::Error;
+use std::fs;
+
+// A breakdown of where the resident memory of the traced process lives,
+// in bytes.
+#[derive(Debug, Default)]
+pub struct ResidentMemory {
+    // Total resident memory.
+    pub rss: u64,
+
+    // Resident anonymous memory, which includes the heap.
+    pub anonymous: u64,
+
+    // Resident memory backed by files, such as code and mapped data.  Pages
+    // shared with other processes are counted proportionally.
+    pub file: u64,
+
+    // Resident shared memory, including tmpfs and memfd mappings, counted
+    // proportionally.
+    pub shmem: u64,
+
+    // Memory swapped out.
+    pub swap: u64,
+}
+
+// Parse the value in kilobytes of a "Field:  1234 kB" line as bytes.
+fn parse_kilobytes(value: &str) -> Result<u64, Box<dyn Error>> {
+    let kilobytes = value
+        .split_whitespace()
+        .next()
+        .ok_or("missing smaps value")?
+        .parse::<u64>()?;
+
+    Ok(kilobytes * 1024)
+}
+
+// Read the breakdown from smaps_rollup, which the kernel totals for us.
+// Returns None if the rollup is unavailable, or predates the file and
+// shmem fields.
+fn read_rollup(pid: u32) -> Result<Option<ResidentMemory>, Box<dyn Error>> {
+    let rollup = match fs::read_to_string(format!("/proc/{}/smaps_rollup", pid)) {
+        Ok(rollup) => rollup,
+        Err(_) => return Ok(None),
+    };
+
+    let mut resident = ResidentMemory::default();
+    let mut has_categories = false;
+    for line in rollup.lines() {
+        if let Some((field, value)) = line.split_once(':') {
+            match field {
+                "Rss" => resident.rss = parse_kilobytes(value)?,
+                "Anonymous" => resident.anonymous = parse_kilobytes(value)?,
+                "Swap" => resident.swap = parse_kilobytes(value)?,
+                "Pss_File" => {
+                    resident.file = parse_kilobytes(value)?;
+                    has_categories = true;
+                }
+                "Pss_Shmem" => resident.shmem = parse_kilobytes(value)?,
+                _ => (),
+            }
+        }
+    }
+
+    Ok(if has_categories { Some(resident) } else { None })
+}
+
+// Returns true if a mapping's pathname refers to shared memory rather than
+// an ordinary file.
+fn is_shmem_mapping(pathname: &str) -> bool {
+    pathname.starts_with("/dev/shm/")
+        || pathname.starts_with("/memfd:")
+        || pathname.starts_with("/SYSV")
+}
+
+// Total the breakdown over each mapping in smaps, categorizing the
+// proportional size of the non-anonymous pages of each mapping by its
+// pathname, to match the categories of smaps_rollup.
+fn read_mappings(pid: u32) -> Result<ResidentMemory, Box<dyn Error>> {
+    let smaps = fs::read_to_string(format!("/proc/{}/smaps", pid))?;
+
+    let mut resident = ResidentMemory::default();
+    let mut shmem_mapping = false;
+    let mut mapping_pss = 0;
+    for line in smaps.lines() {
+        match line.split_once(':') {
+            // Mapping headers have spaces before the colon of the device
+            // field, unlike the field lines for the mapping.
+            Some((field, value)) if !field.contains(' ') => match field {
+                "Rss" => resident.rss += parse_kilobytes(value)?,
+                "Pss" => mapping_pss = parse_kilobytes(value)?,
+                "Anonymous" => {
+                    let anonymous = parse_kilobytes(value)?;
+                    resident.anonymous += anonymous;
+                    if shmem_mapping {
+                        resident.shmem += mapping_pss.saturating_sub(anonymous);
+                    } else {
+                        resident.file += mapping_pss.saturating_sub(anonymous);
+                    }
+                }
+                "Swap" => resident.swap += parse_kilobytes(value)?,
+                _ => (),
+            },
+            _ => {
+                let pathname = line.split_whitespace().nth(5).unwrap_or("");
+                shmem_mapping = is_shmem_mapping(pathname);
+                mapping_pss = 0;
+            }
+        }
+    }
+
+    Ok(resident)
+}
+
+// Sample the resident memory breakdown of a process.
+pub fn sample(pid: u32) -> Result<ResidentMemory, Box<dyn Error>> {
+    match read_rollup(pid)? {
+        Some(resident) => Ok(resident),
+        None => read_mappings(pid),
+    }
+}
@@ -0,0 +1,164 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::record::EventType;
+use std::error::Error;
+use std::io::{Read, Write};
+
+// The maximum number of frames stored for a spooled event.  Frames beyond
+// this, nearest the root of the callstack, are dropped.
+pub const SPOOL_MAX_FRAMES: usize = 64;
+
+// The size in bytes of each record in the spool file.
+const SPOOL_RECORD_SIZE: usize = 64 + 8 * SPOOL_MAX_FRAMES;
+
+// Values for the kind field of a spool record.
+const KIND_ALLOC: u32 = 0;
+const KIND_REALLOC: u32 = 1;
+const KIND_FREE: u32 = 2;
+
+// The value of the cpu field of a spool record when the CPU is unknown.
+const CPU_UNKNOWN: u32 = u32::MAX;
+
+// An event as stored in the spool file.  Only frame addresses are stored,
+// so that spooling doesn't need to write strings.  Function names are
+// resolved when the spool is converted to a trace, with the symbols of the
+// generation of code mappings in which the event occurred.
+pub struct SpoolRecord {
+    // Nanoseconds since the start of the trace when the event started.
+    pub time: u64,
+
+    // The thread on which the event occurred.
+    pub tid: u32,
+
+    // The allocation event.
+    pub allocation: EventType,
+
+    // The address returned or freed by the allocation function.
+    pub address: u64,
+
+    // The async task being polled when the event started, if any.
+    pub task: Option<u64>,
+
+    // The CPU on which the event started, if known.
+    pub cpu: Option<u32>,
+
+    // The generation of the process's code mappings when the event
+    // occurred, which increases each time code mapped from a file is
+    // unmapped.
+    pub generation: u32,
+
+    // The instruction addresses of the callstack, starting with the leaf.
+    pub frames: Vec<u64>,
+}
+
+// Append a little-endian u64 to a record buffer.
+fn put_u64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+// Append a little-endian u32 to a record buffer.
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+// Read a little-endian u64 from a record buffer.
+fn get_u64(buffer: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buffer[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+// Read a little-endian u32 from a record buffer.
+fn get_u32(buffer: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&buffer[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+// Write a fixed-size record to the spool.
+pub fn write_record(spool: &mut impl Write, record: &SpoolRecord) -> Result<(), Box<dyn Error>> {
+    let (kind, size, original_address) = match record.allocation {
+        EventType::Alloc(size) => (KIND_ALLOC, size, 0),
+        EventType::Realloc(original_address, size) => (KIND_REALLOC, size, original_address),
+        EventType::Free => (KIND_FREE, 0, 0),
+    };
+    let frame_count = std::cmp::min(record.frames.len(), SPOOL_MAX_FRAMES);
+
+    let mut buffer: Vec<u8> = Vec::with_capacity(SPOOL_RECORD_SIZE);
+    put_u64(&mut buffer, record.time);
+    put_u32(&mut buffer, record.tid);
+    put_u32(&mut buffer, kind);
+    put_u64(&mut buffer, size);
+    put_u64(&mut buffer, record.address);
+    put_u64(&mut buffer, original_address);
+    put_u64(&mut buffer, record.task.unwrap_or(0));
+    put_u64(&mut buffer, record.generation as u64);
+    put_u32(&mut buffer, frame_count as u32);
+    put_u32(&mut buffer, record.cpu.unwrap_or(CPU_UNKNOWN));
+    for ix in 0..SPOOL_MAX_FRAMES {
+        put_u64(&mut buffer, *record.frames.get(ix).unwrap_or(&0));
+    }
+
+    spool.write_all(&buffer)?;
+
+    Ok(())
+}
+
+// Read the next record from the spool, returning None at the end of the
+// spool.
+pub fn read_record(spool: &mut impl Read) -> Result<Option<SpoolRecord>, Box<dyn Error>> {
+    let mut buffer = [0u8; SPOOL_RECORD_SIZE];
+    match spool.read_exact(&mut buffer) {
+        Ok(()) => (),
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => Err(err)?,
+    }
+
+    let size = get_u64(&buffer, 16);
+    let original_address = get_u64(&buffer, 32);
+    let allocation = match get_u32(&buffer, 12) {
+        KIND_ALLOC => EventType::Alloc(size),
+        KIND_REALLOC => EventType::Realloc(original_address, size),
+        KIND_FREE => EventType::Free,
+        kind => Err(format!("invalid spool record kind: {}", kind))?,
+    };
+    let task = match get_u64(&buffer, 40) {
+        0 => None,
+        task => Some(task),
+    };
+    let cpu = match get_u32(&buffer, 60) {
+        CPU_UNKNOWN => None,
+        cpu => Some(cpu),
+    };
+    let frame_count = std::cmp::min(get_u32(&buffer, 56) as usize, SPOOL_MAX_FRAMES);
+    let frames = (0..frame_count)
+        .map(|ix| get_u64(&buffer, 64 + 8 * ix))
+        .collect();
+
+    Ok(Some(SpoolRecord {
+        time: get_u64(&buffer, 0),
+        tid: get_u32(&buffer, 8),
+        allocation,
+        address: get_u64(&buffer, 24),
+        task,
+        cpu,
+        generation: get_u64(&buffer, 48) as u32,
+        frames,
+    }))
+}
@@ -17,8 +17,29 @@
 */
 
 use crate::process_map;
-use object::{Object, ObjectSegment, ObjectSymbol};
+use object::{elf, Object, ObjectSegment, ObjectSymbol, ObjectSymbolTable};
 use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Seek};
+use std::os::unix::fs::FileExt;
+use std::time;
+
+// The minimum interval between checks for new JIT symbols.
+const JIT_REFRESH_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+// The magic number at the start of a jitdump file.
+const JITDUMP_MAGIC: u32 = 0x4A695444;
+
+// The functions of the legacy vsyscall page, at fixed offsets within it.
+// The page usually can't be read, so its contents aren't parsed.
+const VSYSCALL_FUNCTIONS: [(&str, u64); 3] =
+    [("gettimeofday", 0x000), ("time", 0x400), ("getcpu", 0x800)];
+
+// The spacing of the functions of the vsyscall page.
+const VSYSCALL_FUNCTION_SIZE: u64 = 0x400;
+
+// jitdump record types.
+const JIT_CODE_LOAD: u32 = 0;
+const JIT_CODE_MOVE: u32 = 1;
 
 // A reference to a function mapped into the traced process.
 #[derive(Debug, Clone)]
@@ -33,6 +54,37 @@ pub struct SymbolInfo {
     pub size: u64,
 }
 
+// A slot in a global offset table which the dynamic linker fills with the
+// address of a function.  Calls through the PLT jump to the address in the
+// slot, which is either a lazy binding stub or the resolved function.
+#[derive(Debug, Clone)]
+pub struct RelocationSlot {
+    // The name of the function, or the name of the resolver function for
+    // an IFUNC relocation.
+    pub name: String,
+
+    // The address of the slot in the traced process's address space.
+    pub address: u64,
+}
+
+// Symbols for code generated at runtime, read from the perf map and jitdump
+// files written by JIT compilers.  The files are only appended to, so we
+// track how much of each has been read.
+#[derive(Debug, Default)]
+pub struct JitSymbols {
+    // A map from the start address of generated code to symbol info.
+    symbols: BTreeMap<u64, SymbolInfo>,
+
+    // The number of bytes of the perf map which have been read.
+    perf_map_offset: u64,
+
+    // The number of bytes of the jitdump which have been read.
+    jitdump_offset: u64,
+
+    // The time at which the files were last read.
+    refresh_time: Option<time::Instant>,
+}
+
 // An index of symbol names and addresses to which those symbols resolve.
 #[derive(Debug)]
 pub struct SymbolIndex {
@@ -41,6 +93,13 @@ pub struct SymbolIndex {
 
     // A map from address to symbol info.
     pub symbols_by_address: BTreeMap<u64, SymbolInfo>,
+
+    // Function slots filled by the dynamic linker.
+    pub relocation_slots: Vec<RelocationSlot>,
+
+    // Symbols for JIT generated code.  These aren't part of any mapped
+    // file, so they are kept when the index is rebuilt.
+    pub jit_symbols: JitSymbols,
 }
 
 impl SymbolIndex {
@@ -49,7 +108,31 @@ impl SymbolIndex {
         SymbolIndex {
             symbols_by_name: HashMap::new(),
             symbols_by_address: BTreeMap::new(),
+            relocation_slots: Vec::new(),
+            jit_symbols: JitSymbols::default(),
+        }
+    }
+
+    // Start a new index with no mapped symbols, keeping the JIT symbols
+    // of a previous index.
+    pub fn rebuild(previous: SymbolIndex) -> SymbolIndex {
+        let mut symbol_index = SymbolIndex::new();
+        symbol_index.jit_symbols = previous.jit_symbols;
+
+        symbol_index
+    }
+
+    // Insert a symbol into the index by name and by address.
+    fn insert_symbol(&mut self, symbol_info: SymbolInfo) {
+        if !self.symbols_by_name.contains_key(&symbol_info.name) {
+            self.symbols_by_name
+                .insert(symbol_info.name.clone(), Vec::new());
         }
+        let entry = self.symbols_by_name.get_mut(&symbol_info.name).unwrap();
+        entry.push(symbol_info.clone());
+
+        self.symbols_by_address
+            .insert(symbol_info.address, symbol_info);
     }
 
     // Check whether a particular symbol falls within the address range
@@ -70,19 +153,11 @@ impl SymbolIndex {
                     && sym_address < entry.offset + (entry.end - entry.begin)
                 {
                     let address = entry.begin + sym_address - entry.offset;
-                    let symbol_info = SymbolInfo {
+                    self.insert_symbol(SymbolInfo {
                         name: name.to_owned(),
                         address,
                         size,
-                    };
-
-                    if !self.symbols_by_name.contains_key(name) {
-                        self.symbols_by_name.insert(name.to_owned(), Vec::new());
-                    }
-                    let entry = self.symbols_by_name.get_mut(name).unwrap();
-                    entry.push(symbol_info.clone());
-
-                    self.symbols_by_address.insert(address, symbol_info);
+                    });
                 }
             }
             Err(_) => (),
@@ -118,6 +193,76 @@ impl SymbolIndex {
         }
     }
 
+    // Add the function slots filled by the dynamic linker for an object
+    // file which fall within the range of a mapping.  The slots are usually
+    // in a writable data segment, which may not start at a page boundary,
+    // so find the segment containing the mapped file offset.
+    fn add_elf_relocations(&mut self, entry: &process_map::ProcessMapEntry, elf: &object::File) {
+        let mut address_offset: Option<i64> = None;
+        for segment in elf.segments() {
+            let (file_offset, file_size) = segment.file_range();
+            if entry.offset >= file_offset & !0xFFF && entry.offset < file_offset + file_size {
+                address_offset = Some(segment.address() as i64 - file_offset as i64);
+                break;
+            }
+        }
+        let address_offset = match address_offset {
+            Some(address_offset) => address_offset,
+            None => return,
+        };
+
+        let relocations = match elf.dynamic_relocations() {
+            Some(relocations) => relocations,
+            None => return,
+        };
+        let dynamic_symbols = elf.dynamic_symbol_table();
+
+        // IFUNC relocations name no symbol, only the address of the
+        // resolver function, so look up resolvers by address.
+        let mut resolvers: Option<HashMap<u64, String>> = None;
+
+        for (offset, relocation) in relocations {
+            let slot_address = (offset as i64 - address_offset) as u64;
+            if slot_address < entry.offset
+                || slot_address >= entry.offset + (entry.end - entry.begin)
+            {
+                continue;
+            }
+
+            let name = match relocation.kind() {
+                object::RelocationKind::Elf(elf::R_X86_64_GLOB_DAT)
+                | object::RelocationKind::Elf(elf::R_X86_64_JUMP_SLOT) => {
+                    match (relocation.target(), &dynamic_symbols) {
+                        (object::RelocationTarget::Symbol(index), Some(symbols)) => symbols
+                            .symbol_by_index(index)
+                            .ok()
+                            .and_then(|symbol| symbol.name().ok().map(|name| name.to_string())),
+                        _ => None,
+                    }
+                }
+                object::RelocationKind::Elf(elf::R_X86_64_IRELATIVE) => resolvers
+                    .get_or_insert_with(|| {
+                        elf.symbols()
+                            .chain(elf.dynamic_symbols())
+                            .filter_map(|symbol| {
+                                Some((symbol.address(), symbol.name().ok()?.to_string()))
+                            })
+                            .collect()
+                    })
+                    .get(&(relocation.addend() as u64))
+                    .cloned(),
+                _ => None,
+            };
+
+            if let Some(name) = name {
+                self.relocation_slots.push(RelocationSlot {
+                    name,
+                    address: entry.begin + slot_address - entry.offset,
+                });
+            }
+        }
+    }
+
     // Add all the symbols for a particluar mmaped range of an executable
     // which has been mapped into a traced process.
     pub fn add_entry_symbols(&mut self, entry: &process_map::ProcessMapEntry) {
@@ -126,6 +271,7 @@ impl SymbolIndex {
                 Ok(elf_data) => match object::File::parse(&*elf_data) {
                     Ok(elf) => {
                         self.add_elf_symbols(entry, &elf);
+                        self.add_elf_relocations(entry, &elf);
                     }
                     Err(_) => (),
                 },
@@ -143,6 +289,37 @@ impl SymbolIndex {
         }
     }
 
+    // Add symbols for the code the kernel maps into the traced process.
+    // The vDSO is a shared object without a file, so its image is read from
+    // the process's memory.  The vsyscall page holds functions at fixed
+    // offsets.
+    pub fn add_kernel_symbols(&mut self, pid: u32, process_map: &process_map::ProcessMap) {
+        for entry in &process_map.entries {
+            match entry.filename.as_deref() {
+                Some("[vdso]") => {
+                    let mut image = vec![0u8; (entry.end - entry.begin) as usize];
+                    let read = std::fs::File::open(format!("/proc/{}/mem", pid))
+                        .and_then(|mem| mem.read_exact_at(&mut image, entry.begin));
+                    if read.is_ok() {
+                        if let Ok(elf) = object::File::parse(&*image) {
+                            self.add_elf_symbols(entry, &elf);
+                        }
+                    }
+                }
+                Some("[vsyscall]") => {
+                    for (name, offset) in VSYSCALL_FUNCTIONS {
+                        self.insert_symbol(SymbolInfo {
+                            name: name.to_string(),
+                            address: entry.begin + offset,
+                            size: VSYSCALL_FUNCTION_SIZE,
+                        });
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
     // Get function name by address.  We'll try a few symbols which start
     // proir to the address we are checking, as glibc likes to leave GLIBC
     // symbols near the function name.
@@ -160,6 +337,168 @@ impl SymbolIndex {
             }
         }
 
-        return None;
+        self.jit_symbols.get_function_by_address(address)
+    }
+}
+
+// Read the bytes of a file following an offset.
+fn read_from_offset(filename: &str, offset: u64) -> Option<Vec<u8>> {
+    let mut file = std::fs::File::open(filename).ok()?;
+    file.seek(std::io::SeekFrom::Start(offset)).ok()?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).ok()?;
+
+    Some(data)
+}
+
+// Read a little-endian u32 from a jitdump buffer.
+fn jitdump_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+// Read a little-endian u64 from a jitdump buffer.
+fn jitdump_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+impl JitSymbols {
+    // Add a symbol for generated code.
+    fn add_symbol(&mut self, name: String, address: u64, size: u64) {
+        self.symbols.insert(
+            address,
+            SymbolInfo {
+                name,
+                address,
+                size,
+            },
+        );
+    }
+
+    // Remove symbols for generated code in an address range which has
+    // been unmapped.
+    pub fn remove_range(&mut self, begin: u64, end: u64) {
+        self.symbols
+            .retain(|address, _| *address < begin || *address >= end);
+    }
+
+    // Find the generated code containing an address.
+    fn get_function_by_address(&self, address: u64) -> Option<SymbolInfo> {
+        let (_, info) = self.symbols.range(..address + 1).next_back()?;
+        if address - info.address < info.size {
+            Some(info.clone())
+        } else {
+            None
+        }
+    }
+
+    // Read new lines of /tmp/perf-<pid>.map, each of which has a hex start
+    // address, hex size and symbol name.  An incomplete last line is left
+    // to be read by a later refresh.
+    fn read_perf_map(&mut self, pid: u32) {
+        let filename = format!("/tmp/perf-{}.map", pid);
+        let data = match read_from_offset(&filename, self.perf_map_offset) {
+            Some(data) => data,
+            None => return,
+        };
+        let complete = match data.iter().rposition(|byte| *byte == b'\n') {
+            Some(newline) => newline + 1,
+            None => return,
+        };
+        self.perf_map_offset += complete as u64;
+
+        for line in String::from_utf8_lossy(&data[..complete]).lines() {
+            let mut fields = line.splitn(3, ' ');
+            let address = fields.next().map(|field| field.trim_start_matches("0x"));
+            let size = fields.next().map(|field| field.trim_start_matches("0x"));
+            if let (Some(address), Some(size), Some(name)) = (address, size, fields.next()) {
+                if let (Ok(address), Ok(size)) = (
+                    u64::from_str_radix(address, 16),
+                    u64::from_str_radix(size, 16),
+                ) {
+                    self.add_symbol(name.to_string(), address, size);
+                }
+            }
+        }
+    }
+
+    // Read new records from a jitdump file.  JIT compilers map the jitdump
+    // file into their process to announce it, so we find it in the
+    // process map.
+    fn read_jitdump(&mut self, pid: u32, process_map: &process_map::ProcessMap) {
+        let suffix = format!("/jit-{}.dump", pid);
+        let filename = match process_map.entries.iter().find_map(|entry| {
+            entry
+                .filename
+                .as_ref()
+                .filter(|filename| filename.ends_with(&suffix))
+        }) {
+            Some(filename) => filename,
+            None => return,
+        };
+        let data = match read_from_offset(filename, self.jitdump_offset) {
+            Some(data) => data,
+            None => return,
+        };
+
+        let mut offset = 0;
+        if self.jitdump_offset == 0 {
+            // Skip the file header, whose size is given by the header.
+            if data.len() < 12 || jitdump_u32(&data, 0) != JITDUMP_MAGIC {
+                return;
+            }
+            offset = jitdump_u32(&data, 8) as usize;
+        }
+
+        // Each record starts with its type and total size, and is only
+        // read when complete.
+        while offset + 16 <= data.len() {
+            let id = jitdump_u32(&data, offset);
+            let total_size = jitdump_u32(&data, offset + 4) as usize;
+            if total_size < 16 || offset + total_size > data.len() {
+                break;
+            }
+            let record = &data[offset..offset + total_size];
+
+            if id == JIT_CODE_LOAD && record.len() > 56 {
+                let address = jitdump_u64(record, 32);
+                let size = jitdump_u64(record, 40);
+                let name_bytes = &record[56..];
+                let name_length = name_bytes
+                    .iter()
+                    .position(|byte| *byte == 0)
+                    .unwrap_or(name_bytes.len());
+                let name = String::from_utf8_lossy(&name_bytes[..name_length]).into_owned();
+                self.add_symbol(name, address, size);
+            } else if id == JIT_CODE_MOVE && record.len() >= 56 {
+                let old_address = jitdump_u64(record, 32);
+                let new_address = jitdump_u64(record, 40);
+                let size = jitdump_u64(record, 48);
+                if let Some(info) = self.symbols.remove(&old_address) {
+                    self.add_symbol(info.name, new_address, size);
+                }
+            }
+
+            offset += total_size;
+        }
+        self.jitdump_offset += offset as u64;
+    }
+
+    // Read symbols added to the perf map or jitdump since the last refresh.
+    // Unless forced, this is limited to once per refresh interval, since
+    // JIT compilers may add symbols at any time.
+    pub fn refresh(&mut self, pid: u32, process_map: &process_map::ProcessMap, force: bool) {
+        if let Some(refresh_time) = self.refresh_time {
+            if !force && refresh_time.elapsed() < JIT_REFRESH_INTERVAL {
+                return;
+            }
+        }
+        self.refresh_time = Some(time::Instant::now());
+
+        self.read_perf_map(pid);
+        self.read_jitdump(pid, process_map);
     }
 }
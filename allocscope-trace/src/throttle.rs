@@ -0,0 +1,109 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::time;
+
+// The length of the windows over which the slowdown of the traced process
+// is measured.
+const WINDOW: time::Duration = time::Duration::from_secs(1);
+
+// The largest sampling period, beyond which the trace would hold too few
+// allocations to be useful.
+const MAX_SAMPLE_PERIOD: u64 = 1 << 16;
+
+// Samples allocations when tracing slows the traced process by more than a
+// factor.  The slowdown is estimated from the time the process spends
+// stopped while the tracer handles its breakpoints, which overestimates the
+// slowdown of a process with threads running while others are stopped.
+pub struct Throttle {
+    // The slowdown above which allocations are sampled.
+    max_slowdown: f64,
+
+    // The start of the current measurement window.
+    window_start: time::Instant,
+
+    // The time spent handling stops during the current window.
+    stopped: time::Duration,
+
+    // One allocation in this many is recorded.
+    sample_period: u64,
+
+    // The number of allocations to skip before the next one recorded.
+    countdown: u64,
+}
+
+impl Throttle {
+    // Construct a throttle for a maximum slowdown factor.
+    pub fn new(max_slowdown: f64) -> Throttle {
+        Throttle {
+            max_slowdown,
+            window_start: time::Instant::now(),
+            stopped: time::Duration::ZERO,
+            sample_period: 1,
+            countdown: 0,
+        }
+    }
+
+    // Returns true if every allocation is being recorded.
+    pub fn is_unthrottled(&self) -> bool {
+        self.sample_period == 1
+    }
+
+    // Returns true if the next allocation should be recorded.
+    pub fn sample(&mut self) -> bool {
+        if self.countdown == 0 {
+            self.countdown = self.sample_period - 1;
+            true
+        } else {
+            self.countdown -= 1;
+            false
+        }
+    }
+
+    // Note the time spent handling a stop of the traced process.  At the
+    // end of each window, the sampling period is doubled if the process
+    // slowed by more than the maximum, or halved if it slowed by less than
+    // half the maximum.  Returns the new sampling period when it changes.
+    pub fn add_stop(&mut self, duration: time::Duration) -> Option<u64> {
+        self.stopped += duration;
+
+        let elapsed = self.window_start.elapsed();
+        if elapsed < WINDOW {
+            return None;
+        }
+
+        let running = elapsed.saturating_sub(self.stopped).as_secs_f64();
+        let slowdown = elapsed.as_secs_f64() / f64::max(running, f64::MIN_POSITIVE);
+        self.window_start = time::Instant::now();
+        self.stopped = time::Duration::ZERO;
+
+        let previous_period = self.sample_period;
+        if slowdown > self.max_slowdown {
+            self.sample_period = u64::min(self.sample_period * 2, MAX_SAMPLE_PERIOD);
+        } else if slowdown < self.max_slowdown / 2.0 {
+            self.sample_period = u64::max(self.sample_period / 2, 1);
+        }
+        self.countdown = u64::min(self.countdown, self.sample_period - 1);
+
+        if self.sample_period != previous_period {
+            Some(self.sample_period)
+        } else {
+            None
+        }
+    }
+}
@@ -17,11 +17,15 @@
 */
 
 use crate::breakpoint;
+use crate::commandline;
 use crate::context;
+use crate::crash;
 use crate::hooks;
+use crate::permission;
 use crate::ptrace;
 use crate::record;
 use std::error::Error;
+use std::time;
 
 // A breakpoint has been hit on one of our traced threads.  Now what?
 // Determine what to do by checking for breakpoints and system call callbacks.
@@ -30,6 +34,19 @@ fn on_breakpoint(pid: u32, context: &mut context::TraceContext) -> Result<(), Bo
     let mut regs = ptrace::getregs(pid)?;
 
     let address = regs.rip - 1;
+
+    // Identify the kind of stop, if profiling the overhead of tracing.
+    if context.overhead.is_some() {
+        let kind = match context.breakpoint_set.breakpoints.get(&address) {
+            Some(breakpoint) => match breakpoint.hook {
+                Some(hook) => context.hook_specs[hook].function.clone(),
+                None if breakpoint.persist => "other breakpoints".to_string(),
+                None => "returns".to_string(),
+            },
+            None => "system calls".to_string(),
+        };
+        context.note_stop_kind(&kind);
+    }
     let mut callback: Option<breakpoint::BreakpointCallback> = None;
     let mut intercept: Option<breakpoint::SyscallCallback> = None;
     let mut one_shot = false;
@@ -44,7 +61,7 @@ fn on_breakpoint(pid: u32, context: &mut context::TraceContext) -> Result<(), Bo
             // If an event is already in progress, avoid invoking the callback
             // because some implementations of allocators may nest calls to
             // allocation functions.
-            if breakpoint.persist && !context.transaction.is_event_in_progress(pid) {
+            if breakpoint.persist && !context.recorder.is_event_in_progress(pid) {
                 callback = Some(breakpoint.callback);
             }
 
@@ -117,6 +134,48 @@ fn on_breakpoint(pid: u32, context: &mut context::TraceContext) -> Result<(), Bo
     Ok(())
 }
 
+// Record the delivery of a signal to a traced thread, if recording the
+// thread's events.
+fn record_signal(
+    pid: u32,
+    signal: u8,
+    context: &mut context::TraceContext,
+) -> Result<(), Box<dyn Error>> {
+    if context.should_record(pid) {
+        context.recorder.record_signal(pid, signal)?;
+    }
+
+    Ok(())
+}
+
+// A traced thread has received a signal which crashes the process unless
+// the process handles it.  Capture the thread's registers and callstack
+// before the signal is delivered, to be recorded if the process is
+// terminated by it.
+fn on_crash_signal(
+    pid: u32,
+    signal: u8,
+    context: &mut context::TraceContext,
+) -> Result<(), Box<dyn Error>> {
+    let regs = ptrace::getregs(pid)?;
+    let fault_address = if crash::is_fault_signal(signal as i32) {
+        Some(unsafe { ptrace::getsiginfo(pid)?.si_addr() } as u64)
+    } else {
+        None
+    };
+    let callstack = hooks::collect_stack(context, pid)?;
+
+    context.crash = Some(crash::Crash {
+        tid: pid,
+        signal,
+        fault_address,
+        registers: crash::named_registers(&regs),
+        callstack,
+    });
+
+    Ok(())
+}
+
 // Wait for a particular signal to be received by one of our traced threads.
 pub fn wait_for_signal(pid: u32, wait_signal: i32) -> Result<(), Box<dyn Error>> {
     loop {
@@ -143,8 +202,20 @@ pub fn wait_for_signal(pid: u32, wait_signal: i32) -> Result<(), Box<dyn Error>>
 fn trace_loop(context: &mut context::TraceContext, pid: u32) -> Result<(), Box<dyn Error>> {
     loop {
         let (status_pid, status) = ptrace::waitpid(-1, true)?;
+        let stop_time = time::Instant::now();
+        if let Some(profile) = context.overhead.as_mut() {
+            profile.begin_stop();
+        }
+        if ptrace::take_toggle_signal()? {
+            context.toggle_recording();
+        }
+        context.sample_resident_memory()?;
+        context.sample_cgroup_memory()?;
+        context.recorder.record_clock_anchor_if_due()?;
+
         match status {
-            // One of our traced threads has stopped.
+            // One of our traced threads has stopped.  Any signal other than
+            // SIGTRAP is about to be delivered to it.
             ptrace::WaitPidResult::Stopped(signal) => match signal as i32 {
                 // SIGTRAP indicates a traced thread hit a breakpoint.
                 libc::SIGTRAP => {
@@ -154,13 +225,46 @@ fn trace_loop(context: &mut context::TraceContext, pid: u32) -> Result<(), Box<d
                     ptrace::syscall(status_pid, 0)?;
                 }
 
+                // Capture the state of a crashing thread, then deliver the
+                // signal.
+                crash_signal if crash::is_crash_signal(crash_signal) => {
+                    context.note_stop_kind("signals");
+                    record_signal(status_pid, signal, context)?;
+                    if let Err(err) = on_crash_signal(status_pid, signal, context) {
+                        eprintln!("Error capturing crash: {:?}", err);
+                    }
+
+                    ptrace::syscall(status_pid, signal)?;
+                }
+
                 // Pass along other signals to the traced thread.
-                _ => ptrace::syscall(status_pid, signal)?,
+                _ => {
+                    context.note_stop_kind("signals");
+                    record_signal(status_pid, signal, context)?;
+                    ptrace::syscall(status_pid, signal)?;
+                }
             },
 
+            // A traced thread has been terminated by a signal.  If it is the
+            // signal which crashed a thread, record the crash.
+            ptrace::WaitPidResult::Signaled(signal) => {
+                if let Some(crash) = context.crash.take() {
+                    if crash.signal == signal {
+                        println!("Process {} crashed in thread {}", pid, crash.tid);
+                        context.recorder.record_crash(crash)?;
+                    }
+                }
+
+                if status_pid == pid {
+                    return Ok(());
+                }
+            }
+
             // A traced thread has spawned a new thread via clone.
             ptrace::WaitPidResult::EventClone => {
+                context.note_stop_kind("thread creation");
                 let new_thread = ptrace::geteventmsg(status_pid)?;
+                context.on_thread_created(status_pid, new_thread)?;
 
                 wait_for_signal(new_thread, libc::SIGSTOP)?;
 
@@ -184,6 +288,8 @@ fn trace_loop(context: &mut context::TraceContext, pid: u32) -> Result<(), Box<d
                 }
             }
         }
+
+        context.note_stop(stop_time.elapsed())?;
     }
 }
 
@@ -204,21 +310,47 @@ fn detach_from_tracee(context: &mut context::TraceContext) -> Result<(), Box<dyn
 
 // Start a new trace of a given process-id.  This path is common between
 // both processes we spawn and pre-existing processes to which we are
-// attaching.
-fn trace_attached_pid(record: record::TraceRecord, pid: u32) -> Result<(), Box<dyn Error>> {
+// attaching.  'relocated' is true if the dynamic linker has already
+// finished relocating the process.
+fn trace_attached_pid(
+    mut record: record::TraceRecord,
+    pid: u32,
+    options: &commandline::TraceOptions,
+    relocated: bool,
+) -> Result<(), Box<dyn Error>> {
     let mut breakpoint_set = breakpoint::BreakpointSet::new();
-    hooks::add_hooks(&mut breakpoint_set)?;
+    let hook_specs = hooks::add_hooks(&mut breakpoint_set, options)?;
+
+    record.insert_capabilities(options)?;
+    record.insert_process_metadata(pid, options)?;
+    record.set_aggregate(options.aggregate);
+    record.set_retain(options.retain);
+    record.set_burst_threshold(options.burst_threshold);
+    let recorder = record::Recorder::new(record, options.spool_filename.as_deref())?;
+    let mut context =
+        context::TraceContext::new(pid, options.clone(), breakpoint_set, hook_specs, recorder)?;
+    if relocated {
+        context.update_relocated_breakpoints(pid)?;
+    } else {
+        context.update_process_map(pid)?;
+    }
 
-    let transaction = record::Transaction::new(&record)?;
-    let mut context = context::TraceContext::new(pid, breakpoint_set, transaction)?;
-    context.update_process_map(pid)?;
+    ptrace::block_term_signals()?;
+    ptrace::block_toggle_signal()?;
+    if options.start_paused {
+        println!(
+            "Process {} is paused.  Press Enter, or send SIGUSR1 to process {}, to start.",
+            pid,
+            std::process::id()
+        );
+        ptrace::wait_for_release()?;
+    }
 
     // Now that we have set breakpoints, resume execution.
     ptrace::setoptions(pid, libc::PTRACE_O_TRACECLONE)?;
     ptrace::syscall(pid, 0)?;
 
-    ptrace::block_term_signals()?;
-    match trace_loop(&mut context, pid) {
+    let ending = match trace_loop(&mut context, pid) {
         Err(err) => {
             // If we have received SIGTERM or SIGINT while tracing, cleanly
             // detach and complete the trace file.
@@ -226,33 +358,71 @@ fn trace_attached_pid(record: record::TraceRecord, pid: u32) -> Result<(), Box<d
                 println!("Trace terminated by signal");
                 detach_from_tracee(&mut context)?;
 
-                ()
+                record::TraceEnding::Detach
             } else {
                 Err(err)?
             }
         }
-        Ok(()) => (),
+        Ok(()) => record::TraceEnding::Exit,
+    };
+    if context.allocators.is_empty() {
+        eprintln!(
+            "No allocator was found in process {}, so no allocations were recorded.  \
+             A statically linked allocator must have symbols to be traced.",
+            pid
+        );
+    }
+
+    // Spooled events are resolved with the final symbols, so ensure any
+    // recently generated code is named.
+    context
+        .symbol_index
+        .jit_symbols
+        .refresh(context.pid, &context.process_map, true);
+    let write_time =
+        context
+            .recorder
+            .finish(&context.process_map, &context.symbol_index, ending)?;
+    if let Some(profile) = &context.overhead {
+        profile.report(write_time);
     }
-    context.transaction.commit()?;
 
     Ok(())
 }
 
-// Attach to an existing process and trace it.
-pub fn trace_pid(record: record::TraceRecord, pid: u32) -> Result<(), Box<dyn Error>> {
-    ptrace::attach(pid)?;
+// Attach to an existing process and trace it.  The trace file is created
+// only once attached, so that if we re-run with elevated privileges, the
+// re-run doesn't find a trace file left by this run.
+pub fn trace_pid(
+    atrace_filename: &str,
+    appending: bool,
+    pid: u32,
+    options: &commandline::TraceOptions,
+) -> Result<(), Box<dyn Error>> {
+    if let Err(err) = ptrace::attach(pid) {
+        permission::report_attach_failure(pid);
+        if options.elevate && permission::may_elevate() {
+            Err(permission::reexec_elevated())?;
+        }
+        Err(format!("failed to attach to process {}: {}", pid, err))?;
+    }
+    let record = record::TraceRecord::new(atrace_filename, appending)?;
     wait_for_signal(pid, libc::SIGSTOP)?;
 
-    return trace_attached_pid(record, pid);
+    return trace_attached_pid(record, pid, options, true);
 }
 
 // Spawn a new process from a given commandline and trace it.
 pub fn trace_command(
     record: record::TraceRecord,
     command: &Vec<String>,
+    options: &commandline::TraceOptions,
 ) -> Result<(), Box<dyn Error>> {
     let pid = ptrace::attach_to_child_exec(&command)?;
-    wait_for_signal(pid, libc::SIGTRAP)?;
+    if let Err(err) = wait_for_signal(pid, libc::SIGTRAP) {
+        permission::report_spawn_failure();
+        Err(err)?;
+    }
 
-    return trace_attached_pid(record, pid);
+    return trace_attached_pid(record, pid, options, false);
 }
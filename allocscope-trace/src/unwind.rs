@@ -39,7 +39,7 @@ const UNWIND_ACCESSORS: libunwind_sys::unw_accessors_t = libunwind_sys::unw_acce
 };
 
 // An entry representing a stack frame in a stack backtrace.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct StackEntry {
     // The instruction address for this frame.
     pub address: u64,
@@ -81,6 +81,13 @@ impl AddressSpace {
     pub fn new_upt() -> Result<AddressSpace, Box<dyn Error>> {
         AddressSpace::new(&UNWIND_ACCESSORS, 0)
     }
+
+    // Discard unwind information libunwind has cached for an address range.
+    pub fn flush_cache(&self, begin: u64, end: u64) {
+        unsafe {
+            libunwind_sys::unw_flush_cache(self.handle, begin, end);
+        }
+    }
 }
 
 impl Drop for AddressSpace {
@@ -190,6 +197,22 @@ fn get_function_by_address(
     (name, offset)
 }
 
+// Resolve the function name and offset for an instruction address in the
+// traced process.
+pub fn resolve_stack_entry(
+    process_map: &process_map::ProcessMap,
+    symbol_index: &symbol_index::SymbolIndex,
+    address: u64,
+) -> StackEntry {
+    let (name, offset) = get_function_by_address(process_map, symbol_index, address);
+
+    StackEntry {
+        address,
+        name,
+        offset,
+    }
+}
+
 // Collect the stack from the traced process.  Assumes we have exclusive
 // access to the global CRAWL_CONTEXT.
 unsafe fn collect_stack_non_threadsafe(
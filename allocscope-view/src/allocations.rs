@@ -0,0 +1,721 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::summary;
+use crate::trace;
+use crate::transform;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+// A block allocated beneath a stack entry of the call tree.
+#[derive(Clone, Debug)]
+pub struct Allocation {
+    // The time of the allocation, in nanoseconds since the start of the
+    // trace.
+    pub time: u64,
+
+    // The size of the block in bytes.
+    pub size: u64,
+
+    // The address of the block.
+    pub address: u64,
+
+    // The time at which the block was freed, if it was freed within the
+    // span of the trace summarized.
+    pub freed: Option<u64>,
+
+    // The leaf stack entry of the recorded callstack of the free, if the
+    // block was freed and the free's callstack was recorded.
+    pub freed_by: Option<trace::StackEntryId>,
+}
+
+// The individual allocations made beneath a stack entry of the call tree,
+// in the order they were made.
+pub struct AllocationList {
+    // The name of the function of the stack entry.
+    pub function: String,
+
+    // The allocations made by the stack entry and its descendents.
+    pub allocations: Vec<Allocation>,
+}
+
+// Gather the ids of a stack entry and all of its descendents.
+fn gather_subtree(
+    transaction: &mut trace::Transaction,
+    stackentry: trace::StackEntryId,
+) -> Result<HashSet<trace::StackEntryId>, Box<dyn Error>> {
+    let mut subtree = HashSet::new();
+    let mut pending = vec![stackentry];
+    while let Some(id) = pending.pop() {
+        subtree.insert(id);
+        for child in transaction.get_stackentry_children(id)? {
+            pending.push(child.id);
+        }
+    }
+
+    Ok(subtree)
+}
+
+impl AllocationList {
+    // List the allocations within a span of the trace made beneath a stack
+    // entry of the call tree built by 'tree_builder'.  Events are mapped to
+    // the tree as they were when summarized, and frees are matched to
+    // allocations by address.
+    pub fn new(
+        trace: &trace::Trace,
+        tree_builder: &mut transform::TreeBuilder,
+        stackentry: trace::StackEntryId,
+        function: &str,
+        time_range: summary::TimeRange,
+    ) -> Result<AllocationList, Box<dyn Error>> {
+        if trace.has_capability(trace::CAPABILITY_AGGREGATE) {
+            Err("traces made with --aggregate don't record individual allocations")?;
+        }
+
+        let mut transaction = trace::Transaction::new(trace)?;
+        let subtree = gather_subtree(&mut transaction, stackentry)?;
+
+        let mut allocations: Vec<Allocation> = Vec::new();
+        let mut live: HashMap<u64, usize> = HashMap::new();
+        for event_id in 1..=trace.max_event_id()? {
+            let event = match transaction.event(event_id) {
+                Some(event) => event,
+                None => continue,
+            };
+            if event.time > time_range.end {
+                break;
+            }
+            if !time_range.contains(event.time) {
+                continue;
+            }
+
+            // A block allocated at an address which is still live was freed
+            // without its free being recorded.
+            let previous = live.remove(&event.address);
+            if !event.allocation {
+                if let Some(index) = previous {
+                    allocations[index].freed = Some(event.time);
+                    allocations[index].freed_by = event.callstack;
+                }
+                continue;
+            }
+
+            let mapped = tree_builder.map_callstack(&mut transaction, &event)?;
+            if mapped.is_some_and(|id| subtree.contains(&id)) {
+                live.insert(event.address, allocations.len());
+                allocations.push(Allocation {
+                    time: event.time,
+                    size: event.size.unwrap_or(0),
+                    address: event.address,
+                    freed: None,
+                    freed_by: None,
+                });
+            }
+        }
+
+        Ok(AllocationList {
+            function: function.to_string(),
+            allocations,
+        })
+    }
+}
+
+// A distinct callstack which freed blocks allocated beneath a stack entry.
+pub struct FreeCallstack {
+    // The leaf stack entry of the recorded callstack, or None for frees
+    // recorded without one.
+    pub callstack: Option<trace::StackEntryId>,
+
+    // The number of blocks freed by the callstack.
+    pub blocks: u64,
+
+    // The bytes of the blocks freed by the callstack.
+    pub bytes: u64,
+}
+
+// The callstacks which freed the blocks allocated beneath a stack entry,
+// showing which code takes ownership of them.
+pub struct FreedBy {
+    // The name of the function of the stack entry.
+    pub function: String,
+
+    // The number of allocations made beneath the stack entry.
+    pub count: usize,
+
+    // The number of blocks not freed within the span of the trace.
+    pub unfreed: usize,
+
+    // The callstacks which freed blocks, most blocks first.
+    pub callstacks: Vec<FreeCallstack>,
+}
+
+impl FreedBy {
+    // Group the freed allocations of a list by the callstacks of their
+    // frees.
+    pub fn new(list: &AllocationList) -> FreedBy {
+        let mut callstacks: HashMap<Option<trace::StackEntryId>, FreeCallstack> = HashMap::new();
+        let mut unfreed = 0;
+        for allocation in &list.allocations {
+            if allocation.freed.is_none() {
+                unfreed += 1;
+                continue;
+            }
+            let callstack = callstacks
+                .entry(allocation.freed_by)
+                .or_insert(FreeCallstack {
+                    callstack: allocation.freed_by,
+                    blocks: 0,
+                    bytes: 0,
+                });
+            callstack.blocks += 1;
+            callstack.bytes += allocation.size;
+        }
+
+        let mut callstacks: Vec<FreeCallstack> = callstacks.into_values().collect();
+        callstacks.sort_by(|a, b| {
+            b.blocks
+                .cmp(&a.blocks)
+                .then(b.bytes.cmp(&a.bytes))
+                .then(a.callstack.cmp(&b.callstack))
+        });
+
+        FreedBy {
+            function: list.function.clone(),
+            count: list.allocations.len(),
+            unfreed,
+            callstacks,
+        }
+    }
+}
+
+// A sequence of sizes through which blocks grew or shrank by reallocation,
+// along with the number of blocks which went through it.
+pub struct GrowthSequence {
+    // The size of the first allocation, followed by the size of each
+    // reallocation.
+    pub sizes: Vec<u64>,
+
+    // The number of blocks reallocated through the sequence of sizes.
+    pub chains: u64,
+}
+
+// The chains of reallocations of the blocks allocated beneath a stack
+// entry, such as those of a growing vector or string.
+pub struct ReallocChains {
+    // The name of the function of the stack entry.
+    pub function: String,
+
+    // The number of blocks allocated beneath the stack entry, counting each
+    // chain of reallocations as one block.
+    pub blocks: u64,
+
+    // The number of blocks reallocated at least once.
+    pub reallocated: u64,
+
+    // The total number of reallocations.
+    pub reallocations: u64,
+
+    // The bytes which reallocations may have copied, if they moved their
+    // blocks.
+    pub copied_bytes: u64,
+
+    // The distinct sequences of sizes of reallocated blocks, most chains
+    // first.
+    pub sequences: Vec<GrowthSequence>,
+}
+
+impl ReallocChains {
+    // Reconstruct the chains of reallocations within a span of the trace
+    // of blocks allocated or reallocated beneath a stack entry of the call
+    // tree built by 'tree_builder'.  A reallocation is recorded as the free
+    // of the original block followed by the allocation of its replacement,
+    // with the same time, thread and callstack.
+    pub fn new(
+        trace: &trace::Trace,
+        tree_builder: &mut transform::TreeBuilder,
+        stackentry: trace::StackEntryId,
+        function: &str,
+        time_range: summary::TimeRange,
+    ) -> Result<ReallocChains, Box<dyn Error>> {
+        if trace.has_capability(trace::CAPABILITY_AGGREGATE) {
+            Err("traces made with --aggregate don't record individual allocations")?;
+        }
+
+        let mut transaction = trace::Transaction::new(trace)?;
+        let subtree = gather_subtree(&mut transaction, stackentry)?;
+        let max_event_id = trace.max_event_id()?;
+
+        // The sizes of each chain, and whether any of its allocations were
+        // made beneath the stack entry.
+        let mut chains: Vec<(Vec<u64>, bool)> = Vec::new();
+        let mut live: HashMap<u64, usize> = HashMap::new();
+        let mut last_free: Option<(trace::Event, Option<usize>)> = None;
+        let mut after_id = 0;
+        'events: loop {
+            let events = transaction.events(after_id, max_event_id, summary::EVENT_CHUNK_SIZE)?;
+            let last = match events.last() {
+                Some(last) => last.id,
+                None => break,
+            };
+
+            for event in events {
+                if event.time > time_range.end {
+                    break 'events;
+                }
+                if !time_range.contains(event.time) {
+                    continue;
+                }
+
+                let previous = live.remove(&event.address);
+                if !event.allocation {
+                    last_free = Some((event, previous));
+                    continue;
+                }
+                let reallocated = match last_free.take() {
+                    Some((free, Some(chain)))
+                        if free.time == event.time
+                            && free.thread == event.thread
+                            && free.callstack == event.callstack =>
+                    {
+                        Some(chain)
+                    }
+                    _ => None,
+                };
+
+                let size = event.size.unwrap_or(0);
+                let mapped = tree_builder.map_callstack(&mut transaction, &event)?;
+                let in_subtree = mapped.is_some_and(|id| subtree.contains(&id));
+                let chain = match reallocated {
+                    Some(chain) => {
+                        chains[chain].0.push(size);
+                        chains[chain].1 |= in_subtree;
+                        chain
+                    }
+                    None => {
+                        chains.push((vec![size], in_subtree));
+                        chains.len() - 1
+                    }
+                };
+                live.insert(event.address, chain);
+            }
+            after_id = last;
+        }
+
+        let mut realloc_chains = ReallocChains {
+            function: function.to_string(),
+            blocks: 0,
+            reallocated: 0,
+            reallocations: 0,
+            copied_bytes: 0,
+            sequences: Vec::new(),
+        };
+        let mut sequences: HashMap<Vec<u64>, u64> = HashMap::new();
+        for (sizes, in_subtree) in chains {
+            if !in_subtree {
+                continue;
+            }
+            realloc_chains.blocks += 1;
+            if sizes.len() < 2 {
+                continue;
+            }
+            realloc_chains.reallocated += 1;
+            realloc_chains.reallocations += sizes.len() as u64 - 1;
+            realloc_chains.copied_bytes += sizes
+                .windows(2)
+                .map(|pair| std::cmp::min(pair[0], pair[1]))
+                .sum::<u64>();
+            *sequences.entry(sizes).or_default() += 1;
+        }
+        realloc_chains.sequences = sequences
+            .into_iter()
+            .map(|(sizes, chains)| GrowthSequence { sizes, chains })
+            .collect();
+        realloc_chains.sequences.sort_by(|a, b| {
+            b.chains
+                .cmp(&a.chains)
+                .then(b.sizes.len().cmp(&a.sizes.len()))
+                .then(a.sizes.cmp(&b.sizes))
+        });
+
+        Ok(realloc_chains)
+    }
+}
+
+// The allocation or free of a block at an inspected address.
+#[derive(Clone, Debug)]
+pub struct BlockEvent {
+    // The time of the event, in nanoseconds since the start of the trace.
+    pub time: u64,
+
+    // The thread id of the thread which generated the event.
+    pub thread: Option<u64>,
+
+    // The leaf stack entry of the recorded callstack of the event.
+    pub callstack: Option<trace::StackEntryId>,
+}
+
+// A block allocated over an inspected address.
+#[derive(Clone, Debug)]
+pub struct AddressBlock {
+    // The address of the start of the block.
+    pub address: u64,
+
+    // The size of the block in bytes.
+    pub size: u64,
+
+    // The allocation of the block.
+    pub allocated: BlockEvent,
+
+    // The free of the block, if it was freed within the trace.
+    pub freed: Option<BlockEvent>,
+}
+
+// Everything recorded of the blocks allocated over an address, such as an
+// address printed by a sanitizer, a debugger or a log.
+pub struct AddressHistory {
+    // The inspected address.
+    pub address: u64,
+
+    // The blocks allocated over the address, in the order they were
+    // allocated.
+    pub blocks: Vec<AddressBlock>,
+}
+
+// Parse an address, in hexadecimal with a prefix of '0x', or in decimal.
+pub fn parse_address(token: &str) -> Result<u64, Box<dyn Error>> {
+    let token = token.trim();
+    let address = match token
+        .strip_prefix("0x")
+        .or_else(|| token.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => token.parse(),
+    };
+
+    Ok(address.map_err(|_| format!("invalid address: {}", token))?)
+}
+
+impl AddressHistory {
+    // Find the blocks allocated over an address in the session of the trace
+    // being viewed, and the frees matched to them by address.
+    pub fn new(trace: &trace::Trace, address: u64) -> Result<AddressHistory, Box<dyn Error>> {
+        if trace.has_capability(trace::CAPABILITY_AGGREGATE) {
+            Err("traces made with --aggregate don't record individual allocations")?;
+        }
+
+        let mut transaction = trace::Transaction::new(trace)?;
+        let max_event_id = trace.max_event_id()?;
+        let mut blocks: Vec<AddressBlock> = Vec::new();
+        let mut live: HashMap<u64, usize> = HashMap::new();
+        let mut after_id = 0;
+        loop {
+            let events = transaction.events(after_id, max_event_id, summary::EVENT_CHUNK_SIZE)?;
+            let last = match events.last() {
+                Some(last) => last.id,
+                None => break,
+            };
+
+            for event in events {
+                // A block allocated at an address which is still live was
+                // freed without its free being recorded.
+                let previous = live.remove(&event.address);
+                let block_event = BlockEvent {
+                    time: event.time,
+                    thread: event.thread,
+                    callstack: event.callstack,
+                };
+                if !event.allocation {
+                    if let Some(index) = previous {
+                        blocks[index].freed = Some(block_event);
+                    }
+                    continue;
+                }
+
+                let size = event.size.unwrap_or(0);
+                let contains = if size == 0 {
+                    event.address == address
+                } else {
+                    event.address <= address && address - event.address < size
+                };
+                if contains {
+                    live.insert(event.address, blocks.len());
+                    blocks.push(AddressBlock {
+                        address: event.address,
+                        size,
+                        allocated: block_event,
+                        freed: None,
+                    });
+                }
+            }
+            after_id = last;
+        }
+
+        Ok(AddressHistory { address, blocks })
+    }
+}
+
+// The number of buckets into which allocations are counted by lifetime.
+pub const LIFETIME_BUCKETS: usize = 5;
+
+// The labels of the buckets of lifetimes: blocks freed within a
+// millisecond, a second and a minute, freed later, and never freed.
+pub const LIFETIME_LABELS: [&str; LIFETIME_BUCKETS] = ["<1ms", "<1s", "<1min", "later", "never"];
+
+// The exclusive upper bounds, in nanoseconds, of the lifetimes of freed
+// blocks counted in each of the first buckets.
+const LIFETIME_BOUNDS: [u64; 3] = [1_000_000, 1_000_000_000, 60_000_000_000];
+
+// The allocations of a site counted by how long their blocks lived, to
+// tell transient churn from persistent memory.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LifetimeCounts {
+    // The number of blocks in each bucket of 'LIFETIME_LABELS'.
+    pub blocks: [u64; LIFETIME_BUCKETS],
+
+    // The bytes of the blocks in each bucket.
+    pub bytes: [u64; LIFETIME_BUCKETS],
+}
+
+impl LifetimeCounts {
+    // Count a block of 'size' bytes, freed after 'lifetime' nanoseconds, or
+    // never freed if there is no lifetime.
+    pub fn add(&mut self, size: u64, lifetime: Option<u64>) {
+        let bucket = match lifetime {
+            Some(lifetime) => LIFETIME_BOUNDS
+                .iter()
+                .position(|bound| lifetime < *bound)
+                .unwrap_or(LIFETIME_BOUNDS.len()),
+            None => LIFETIME_BUCKETS - 1,
+        };
+        self.blocks[bucket] += 1;
+        self.bytes[bucket] += size;
+    }
+
+    // Add the counts of another site, such as a callee.
+    pub fn add_counts(&mut self, other: &LifetimeCounts) {
+        for bucket in 0..LIFETIME_BUCKETS {
+            self.blocks[bucket] += other.blocks[bucket];
+            self.bytes[bucket] += other.bytes[bucket];
+        }
+    }
+
+    // Remove the counts of another site, such as a callee.
+    pub fn remove_counts(&mut self, other: &LifetimeCounts) {
+        for bucket in 0..LIFETIME_BUCKETS {
+            self.blocks[bucket] = self.blocks[bucket].saturating_sub(other.blocks[bucket]);
+            self.bytes[bucket] = self.bytes[bucket].saturating_sub(other.bytes[bucket]);
+        }
+    }
+}
+
+// The allocations made beneath a stack entry, counted by lifetime.
+pub struct LifetimeHistogram {
+    // The name of the function of the stack entry.
+    pub function: String,
+
+    // The number of allocations counted.
+    pub count: usize,
+
+    // The allocations counted into buckets by lifetime.
+    pub counts: LifetimeCounts,
+}
+
+impl LifetimeHistogram {
+    // Count the allocations of a list into buckets by lifetime.
+    pub fn new(list: &AllocationList) -> LifetimeHistogram {
+        let mut counts = LifetimeCounts::default();
+        for allocation in &list.allocations {
+            let lifetime = allocation.freed.map(|freed| freed - allocation.time);
+            counts.add(allocation.size, lifetime);
+        }
+
+        LifetimeHistogram {
+            function: list.function.clone(),
+            count: list.allocations.len(),
+            counts,
+        }
+    }
+}
+
+// Count the allocations within a span of the trace by lifetime, for each
+// stack entry of the call tree built by 'tree_builder', including the
+// allocations of its descendents.  Blocks not freed within the span count
+// as never freed.
+pub fn count_lifetimes(
+    trace: &trace::Trace,
+    tree_builder: &mut transform::TreeBuilder,
+    time_range: summary::TimeRange,
+) -> Result<HashMap<trace::StackEntryId, LifetimeCounts>, Box<dyn Error>> {
+    if trace.has_capability(trace::CAPABILITY_AGGREGATE) {
+        Err("traces made with --aggregate don't record individual allocations")?;
+    }
+
+    let mut transaction = trace::Transaction::new(trace)?;
+    let max_event_id = trace.max_event_id()?;
+
+    // Blocks are counted by the stack entry which allocated them, and
+    // then added to the counts of each of its ancestors.
+    let mut leaf_counts: HashMap<trace::StackEntryId, LifetimeCounts> = HashMap::new();
+    let mut live: HashMap<u64, (trace::StackEntryId, u64, u64)> = HashMap::new();
+    let mut after_id = 0;
+    'events: loop {
+        let events = transaction.events(after_id, max_event_id, summary::EVENT_CHUNK_SIZE)?;
+        let last = match events.last() {
+            Some(last) => last.id,
+            None => break,
+        };
+
+        for event in events {
+            if event.time > time_range.end {
+                break 'events;
+            }
+            if !time_range.contains(event.time) {
+                continue;
+            }
+
+            // A block allocated at an address which is still live was freed
+            // without its free being recorded, so its lifetime isn't known.
+            let previous = live.remove(&event.address);
+            if !event.allocation {
+                if let Some((stackentry, time, size)) = previous {
+                    leaf_counts
+                        .entry(stackentry)
+                        .or_default()
+                        .add(size, Some(event.time - time));
+                }
+                continue;
+            }
+            if let Some((stackentry, _, size)) = previous {
+                leaf_counts.entry(stackentry).or_default().add(size, None);
+            }
+
+            if let Some(stackentry) = tree_builder.map_callstack(&mut transaction, &event)? {
+                live.insert(
+                    event.address,
+                    (stackentry, event.time, event.size.unwrap_or(0)),
+                );
+            }
+        }
+        after_id = last;
+    }
+    for (stackentry, _, size) in live.into_values() {
+        leaf_counts.entry(stackentry).or_default().add(size, None);
+    }
+
+    let mut counts: HashMap<trace::StackEntryId, LifetimeCounts> = HashMap::new();
+    for (leaf, leaf_count) in leaf_counts {
+        let mut id = Some(leaf);
+        while let Some(entry_id) = id {
+            counts.entry(entry_id).or_default().add_counts(&leaf_count);
+            id = transaction
+                .stackentry(entry_id)
+                .and_then(|entry| entry.next);
+        }
+    }
+
+    Ok(counts)
+}
+
+// A range of block sizes, and the allocations made with a size within it.
+pub struct SizeBucket {
+    // The smallest block size in the range.
+    pub low: u64,
+
+    // The largest block size in the range.
+    pub high: u64,
+
+    // The number of allocations with a size within the range.
+    pub count: usize,
+
+    // The total bytes of the allocations with a size within the range.
+    pub bytes: u64,
+}
+
+// The distribution of block sizes among the allocations made beneath a
+// stack entry.
+pub struct SizeHistogram {
+    // The name of the function of the stack entry.
+    pub function: String,
+
+    // The number of allocations counted.
+    pub count: usize,
+
+    // The number of distinct block sizes allocated.
+    pub distinct_sizes: usize,
+
+    // The most commonly allocated block size, and the number of times it
+    // was allocated.
+    pub common_size: Option<(u64, usize)>,
+
+    // A bucket for each power of two between the smallest and largest
+    // block sizes, in order of size.
+    pub buckets: Vec<SizeBucket>,
+}
+
+// The index of the power of two bucket holding blocks of 'size' bytes.
+// Zero sized blocks get a bucket of their own.
+fn size_bucket_index(size: u64) -> u32 {
+    64 - size.leading_zeros()
+}
+
+impl SizeHistogram {
+    // Count the allocations of a list into buckets by block size.
+    pub fn new(list: &AllocationList) -> SizeHistogram {
+        let mut size_counts: HashMap<u64, usize> = HashMap::new();
+        for allocation in &list.allocations {
+            *size_counts.entry(allocation.size).or_insert(0) += 1;
+        }
+        let common_size = size_counts
+            .iter()
+            .max_by_key(|(size, count)| (**count, std::cmp::Reverse(**size)))
+            .map(|(size, count)| (*size, *count));
+
+        let mut buckets = Vec::new();
+        let sizes = list.allocations.iter().map(|allocation| allocation.size);
+        if let (Some(min), Some(max)) = (sizes.clone().min(), sizes.max()) {
+            for index in size_bucket_index(min)..=size_bucket_index(max) {
+                let (low, high) = match index {
+                    0 => (0, 0),
+                    64 => (1 << 63, u64::MAX),
+                    _ => (1 << (index - 1), (1 << index) - 1),
+                };
+                buckets.push(SizeBucket {
+                    low,
+                    high,
+                    count: 0,
+                    bytes: 0,
+                });
+            }
+
+            let first = size_bucket_index(min);
+            for allocation in &list.allocations {
+                let bucket = &mut buckets[(size_bucket_index(allocation.size) - first) as usize];
+                bucket.count += 1;
+                bucket.bytes += allocation.size;
+            }
+        }
+
+        SizeHistogram {
+            function: list.function.clone(),
+            count: list.allocations.len(),
+            distinct_sizes: size_counts.len(),
+            common_size,
+            buckets,
+        }
+    }
+}
@@ -0,0 +1,264 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::report;
+use crate::trace;
+use std::error::Error;
+
+// A total of the whole trace which can be asserted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Metric {
+    // The maximum concurrent bytes allocated.
+    PeakBytes,
+
+    // The bytes never freed.
+    LeakedBytes,
+
+    // The number of blocks never freed.
+    LeakedBlocks,
+
+    // The number of blocks allocated.
+    Blocks,
+
+    // The bytes allocated, including those freed.
+    TotalBytes,
+}
+
+// The comparison by which a metric is held to its limit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+}
+
+// An assertion of a budget for a trace, such as 'leaked_bytes<1MB'.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Assertion {
+    // The assertion as given on the commandline.
+    text: String,
+
+    // The total asserted.
+    metric: Metric,
+
+    // The comparison the total must satisfy.
+    comparison: Comparison,
+
+    // The value to which the total is compared.
+    limit: u64,
+}
+
+impl Metric {
+    // Parse a metric from its name.
+    fn parse(name: &str) -> Result<Metric, Box<dyn Error>> {
+        match name {
+            "blocks" => Ok(Metric::Blocks),
+            "leaked_blocks" => Ok(Metric::LeakedBlocks),
+            "leaked_bytes" => Ok(Metric::LeakedBytes),
+            "peak_bytes" => Ok(Metric::PeakBytes),
+            "total_bytes" => Ok(Metric::TotalBytes),
+            _ => Err(format!("unknown metric: {}", name).into()),
+        }
+    }
+
+    // True if the metric counts blocks rather than bytes.
+    fn is_count(&self) -> bool {
+        matches!(self, Metric::Blocks | Metric::LeakedBlocks)
+    }
+
+    // The value of the metric for the totals of the roots of the call tree.
+    fn value(&self, summary: &trace::StackEntrySummary) -> u64 {
+        match self {
+            Metric::PeakBytes => summary.maximum_total,
+            Metric::LeakedBytes => summary.current_total,
+            Metric::LeakedBlocks => summary.alloc_count - summary.free_count,
+            Metric::Blocks => summary.alloc_count,
+            Metric::TotalBytes => summary.total_bytes,
+        }
+    }
+}
+
+impl Comparison {
+    // Parse the comparison at the start of some text, returning it with the
+    // length of its operator.
+    fn parse_prefix(text: &str) -> Option<(Comparison, usize)> {
+        if text.starts_with("<=") {
+            Some((Comparison::LessOrEqual, 2))
+        } else if text.starts_with(">=") {
+            Some((Comparison::GreaterOrEqual, 2))
+        } else if text.starts_with("==") {
+            Some((Comparison::Equal, 2))
+        } else if text.starts_with('<') {
+            Some((Comparison::Less, 1))
+        } else if text.starts_with('>') {
+            Some((Comparison::Greater, 1))
+        } else if text.starts_with('=') {
+            Some((Comparison::Equal, 1))
+        } else {
+            None
+        }
+    }
+
+    // True if a value satisfies the comparison with a limit.
+    fn holds(&self, value: u64, limit: u64) -> bool {
+        match self {
+            Comparison::Less => value < limit,
+            Comparison::LessOrEqual => value <= limit,
+            Comparison::Greater => value > limit,
+            Comparison::GreaterOrEqual => value >= limit,
+            Comparison::Equal => value == limit,
+        }
+    }
+}
+
+// Parse the limit of an assertion, which may have a suffix of 'k', 'M', 'G'
+// or 'T', optionally followed by 'B' or 'iB'.  Suffixes of sizes are powers
+// of 1024, as in the reports, and those of counts powers of 1000.
+fn parse_limit(token: &str, is_count: bool) -> Result<u64, Box<dyn Error>> {
+    let base: f64 = if is_count { 1000.0 } else { 1024.0 };
+    let number = token
+        .strip_suffix("iB")
+        .or_else(|| token.strip_suffix('B'))
+        .unwrap_or(token);
+    let (number, multiplier) = match number.chars().last() {
+        Some('k') | Some('K') => (&number[..number.len() - 1], base),
+        Some('M') => (&number[..number.len() - 1], base.powi(2)),
+        Some('G') => (&number[..number.len() - 1], base.powi(3)),
+        Some('T') => (&number[..number.len() - 1], base.powi(4)),
+        _ => (number, 1.0),
+    };
+
+    match number.parse::<f64>() {
+        Ok(value) if value >= 0.0 && value.is_finite() => Ok((value * multiplier) as u64),
+        _ => Err(format!("invalid limit: {}", token))?,
+    }
+}
+
+impl Assertion {
+    // Parse an assertion of a metric compared with a limit, such as
+    // 'peak_bytes<200MB'.
+    pub fn parse(text: &str) -> Result<Assertion, Box<dyn Error>> {
+        let position = text
+            .find(['<', '>', '='])
+            .ok_or(format!("assertion has no comparison: {}", text))?;
+        let (comparison, length) = Comparison::parse_prefix(&text[position..])
+            .ok_or(format!("assertion has no comparison: {}", text))?;
+        let metric = Metric::parse(text[..position].trim())?;
+        let limit = parse_limit(text[position + length..].trim(), metric.is_count())?;
+
+        Ok(Assertion {
+            text: text.to_string(),
+            metric,
+            comparison,
+            limit,
+        })
+    }
+}
+
+// The totals of the whole of the summarized call tree.
+fn trace_totals(
+    transaction: &mut trace::Transaction,
+) -> Result<trace::StackEntrySummary, Box<dyn Error>> {
+    let mut totals = trace::StackEntrySummary {
+        stackentry: 0,
+        current_total: 0,
+        maximum_total: 0,
+        alloc_count: 0,
+        free_count: 0,
+        total_bytes: 0,
+        first_time: None,
+        last_time: None,
+    };
+    for root in transaction.root_stackentries()? {
+        if let Some(summary) = transaction.summary(root.id) {
+            totals.current_total += summary.current_total;
+            totals.maximum_total += summary.maximum_total;
+            totals.alloc_count += summary.alloc_count;
+            totals.free_count += summary.free_count;
+            totals.total_bytes += summary.total_bytes;
+        }
+    }
+
+    Ok(totals)
+}
+
+// Format a value of a metric in full, followed by the abbreviated value
+// used by the reports if it differs.
+fn format_metric_value(metric: Metric, value: u64) -> String {
+    let divisor = if metric.is_count() { 1000 } else { 1024 };
+    let abbreviated = report::format_table_value(value, divisor);
+    if abbreviated.trim() == value.to_string() {
+        report::separate_thousands(value)
+    } else {
+        format!(
+            "{} ({})",
+            report::separate_thousands(value),
+            abbreviated.trim()
+        )
+    }
+}
+
+// Check assertions against the totals of a summarized trace, writing the
+// result of each to stdout, and the limit and value of each failing
+// assertion as a diff.  Returns true if every assertion holds.
+pub fn check_assertions(
+    trace: &trace::Trace,
+    assertions: &[Assertion],
+) -> Result<bool, Box<dyn Error>> {
+    for assertion in assertions {
+        match assertion.metric {
+            Metric::LeakedBytes | Metric::LeakedBlocks
+                if !trace.has_capability(trace::CAPABILITY_FREE_TRACKING) =>
+            {
+                Err("leaks aren't recorded by traces made with --no-free-tracking")?
+            }
+            Metric::TotalBytes if trace.has_capability(trace::CAPABILITY_AGGREGATE) => {
+                Err("total bytes aren't recorded by traces made with --aggregate")?
+            }
+            _ => {}
+        }
+    }
+
+    let mut transaction = trace::Transaction::new(trace)?;
+    let totals = trace_totals(&mut transaction)?;
+    let mut failures = 0;
+    for assertion in assertions {
+        let value = assertion.metric.value(&totals);
+        if assertion.comparison.holds(value, assertion.limit) {
+            println!("ok    {}", assertion.text);
+        } else {
+            failures += 1;
+            println!("FAIL  {}", assertion.text);
+            println!(
+                "  - limit  {}",
+                format_metric_value(assertion.metric, assertion.limit)
+            );
+            println!(
+                "  + actual {}",
+                format_metric_value(assertion.metric, value)
+            );
+        }
+    }
+    if failures > 0 {
+        println!("{} of {} assertions failed", failures, assertions.len());
+    }
+
+    Ok(failures == 0)
+}
@@ -0,0 +1,176 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::report;
+use crate::trace;
+use rusqlite;
+use std::cmp::Ordering;
+use std::error::Error;
+use std::sync;
+
+// A column of the call tree defined by the user, computed by an SQL
+// expression over the summary of each entry.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ComputedColumn {
+    // The name of the column, shown in its header.
+    pub name: String,
+
+    // The SQL expression computing the values of the column.
+    pub expression: String,
+}
+
+// The columns of the summary of an entry over which expressions are
+// computed, named as in the summary table, along with the names of the
+// built-in columns.  Values are bound as reals, so that division isn't
+// truncated.
+const SUMMARY_COLUMNS: &str = "SELECT
+    ?1 AS maximum_total, ?2 AS current_total, ?3 AS alloc_count,
+    ?4 AS free_count, ?5 AS total_bytes, ?6 AS first_time, ?7 AS last_time,
+    ?1 AS bytes, ?2 AS held, ?3 AS blocks, ?3 - ?4 AS leaks, ?5 AS churn";
+
+// The query computing an expression over the columns of a summary.
+fn column_query(expression: &str) -> String {
+    format!("SELECT ({}) FROM ({})", expression, SUMMARY_COLUMNS)
+}
+
+impl ComputedColumn {
+    // Parse a column from its definition, as 'NAME = EXPRESSION', such as
+    // 'AVG = total_bytes / alloc_count'.  The expression is checked to be
+    // valid SQL over the columns of a summary.
+    pub fn parse(definition: &str) -> Result<ComputedColumn, Box<dyn Error>> {
+        let (name, expression) = definition
+            .split_once('=')
+            .map(|(name, expression)| (name.trim(), expression.trim()))
+            .filter(|(name, expression)| {
+                !name.is_empty() && !name.contains(char::is_whitespace) && !expression.is_empty()
+            })
+            .ok_or_else(|| format!("invalid column definition: {}", definition))?;
+        rusqlite::Connection::open_in_memory()?
+            .prepare(&column_query(expression))
+            .map_err(|err| format!("invalid expression for column {}: {}", name, err))?;
+
+        Ok(ComputedColumn {
+            name: name.to_string(),
+            expression: expression.to_string(),
+        })
+    }
+
+    // The width of the column, wide enough for its header and for values
+    // with two decimal places.
+    pub fn width(&self) -> usize {
+        std::cmp::max(self.name.chars().count(), report::table_value_width() + 2)
+    }
+
+    // Format the header of the column, aligned with its values.
+    pub fn header(&self) -> String {
+        format!("{:>width$}", self.name, width = self.width())
+    }
+
+    // Format a value of the column, or a placeholder if the expression had
+    // no value, as when dividing by zero.  Whole values are abbreviated as
+    // counts when too wide, and other values are rounded to two decimal
+    // places.
+    pub fn value(&self, value: Option<f64>) -> String {
+        let width = self.width();
+        let text = match value {
+            None => "-".to_string(),
+            Some(value) if value.fract() != 0.0 && format!("{:.2}", value).len() <= width => {
+                format!("{:.2}", value)
+            }
+            Some(value) => {
+                let whole = format!("{:.0}", value);
+                if whole.len() <= width {
+                    whole
+                } else {
+                    let sign = if value < 0.0 { "-" } else { "" };
+                    let abbreviated = report::format_table_value(value.abs() as u64, 1000);
+                    format!("{}{}", sign, abbreviated.trim_start())
+                }
+            }
+        };
+
+        format!("{:>width$}", text)
+    }
+}
+
+// The computed columns, along with a connection on which their expressions
+// are evaluated.
+struct ColumnEvaluator {
+    // The columns, in the order shown.
+    columns: Vec<ComputedColumn>,
+
+    // An in-memory database, caching the prepared query of each column.
+    connection: sync::Mutex<rusqlite::Connection>,
+}
+
+// The computed columns, set once when the viewer starts.
+static COMPUTED_COLUMNS: sync::OnceLock<ColumnEvaluator> = sync::OnceLock::new();
+
+// Set the computed columns shown for the remainder of the run.
+pub fn set_computed_columns(columns: Vec<ComputedColumn>) -> Result<(), Box<dyn Error>> {
+    let evaluator = ColumnEvaluator {
+        columns,
+        connection: sync::Mutex::new(rusqlite::Connection::open_in_memory()?),
+    };
+    let _ = COMPUTED_COLUMNS.set(evaluator);
+
+    Ok(())
+}
+
+// The computed columns shown, in order.
+pub fn computed_columns() -> &'static [ComputedColumn] {
+    match COMPUTED_COLUMNS.get() {
+        Some(evaluator) => &evaluator.columns,
+        None => &[],
+    }
+}
+
+// Compute the value of a column for the summary of an entry, or None if the
+// expression has no value or isn't numeric.
+pub fn evaluate(index: usize, summary: &trace::StackEntrySummary) -> Option<f64> {
+    let evaluator = COMPUTED_COLUMNS.get()?;
+    let column = evaluator.columns.get(index)?;
+    let connection = evaluator.connection.lock().ok()?;
+    let mut statement = connection
+        .prepare_cached(&column_query(&column.expression))
+        .ok()?;
+
+    statement
+        .query_row(
+            rusqlite::params![
+                summary.maximum_total as f64,
+                summary.current_total as f64,
+                summary.alloc_count as f64,
+                summary.free_count as f64,
+                summary.total_bytes as f64,
+                summary.first_time.map(|time| time as f64),
+                summary.last_time.map(|time| time as f64),
+            ],
+            |row| row.get::<_, Option<f64>>(0),
+        )
+        .ok()
+        .flatten()
+}
+
+// Order the values of a column, with missing values before all others.
+pub fn compare_values(value: Option<f64>, other: Option<f64>) -> Ordering {
+    match (value, other) {
+        (Some(value), Some(other)) => value.total_cmp(&other),
+        (value, other) => value.is_some().cmp(&other.is_some()),
+    }
+}
@@ -16,6 +16,17 @@
     with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
+use crate::allocations;
+use crate::budget;
+use crate::columns;
+use crate::export;
+use crate::report;
+use crate::rows;
+use crate::source;
+use crate::summary;
+use crate::theme;
+use crate::transform;
+use regex::Regex;
 use std::error::Error;
 
 // Parsed commandline arguments.
@@ -23,9 +34,170 @@ pub struct CommandLineArguments {
     // Filename from which to read the trace.
     pub atrace_filename: Option<String>,
 
+    // With --diff, the trace compared against the baseline trace read from
+    // 'atrace_filename'.
+    pub diff_filename: Option<String>,
+
+    // With --baseline, the trace against which the call sites of the trace
+    // read from 'atrace_filename' are checked for regressions.
+    pub baseline_filename: Option<String>,
+
+    // With --fail-on-regression, the growth of a call site since the
+    // baseline, as a fraction, beyond which the check fails with a non-zero
+    // exit status.
+    pub regression_threshold: Option<f64>,
+
+    // Budgets checked against the totals of the trace, failing with a
+    // non-zero exit status if any is exceeded.
+    pub assertions: Vec<budget::Assertion>,
+
+    // The format to which the call tree should be exported, if any.
+    pub export_format: Option<export::ExportFormat>,
+
+    // The file to which an export is written, or stdout if none.
+    pub export_filename: Option<String>,
+
+    // The value by which exported callstacks are weighted.
+    pub export_weight: export::Weight,
+
+    // The grouping applied to the call tree.
+    pub group_mode: transform::GroupMode,
+
+    // If true, invert the call tree, with allocating functions as roots.
+    pub inverted: bool,
+
+    // If set, only subtrees with a function matching the filter are shown.
+    pub filter: Option<Regex>,
+
+    // Frames with a function matching any of these rules are folded into
+    // their callers.
+    pub fold_rules: Vec<Regex>,
+
+    // The options with which function names are demangled.
+    pub demangle: rows::DemangleOptions,
+
+    // The options with which values are written.
+    pub numbers: report::NumberOptions,
+
+    // The columns of the call tree computed from SQL expressions over the
+    // summary of each entry.
+    pub computed_columns: Vec<columns::ComputedColumn>,
+
+    // Rules replacing a prefix of the paths of source files, so that
+    // sources built elsewhere can be found.
+    pub source_map: Vec<(String, String)>,
+
+    // If set, entries of the call tree contributing less than this fraction
+    // of the total are hidden.
+    pub prune: Option<f64>,
+
+    // If set, a report shows at most this many children of each entry of
+    // the call tree.
+    pub top: Option<usize>,
+
+    // If set, only this many levels of the call tree are shown, with deeper
+    // entries rolled up into them.
+    pub max_depth: Option<usize>,
+
+    // The color scheme of the curses UI.
+    pub theme: theme::Theme,
+
+    // If true, the UI periodically reads events newly written to a trace
+    // which is still being recorded.
+    pub follow: bool,
+
+    // If true, roll consecutive frames in the same shared object into a
+    // single frame.
+    pub group_modules: bool,
+
+    // If true, remove the frames of recursive calls, leaving the outermost
+    // call of each function.
+    pub collapse_recursion: bool,
+
+    // If set, report the blocks allocated over this address rather than the
+    // call tree.
+    pub address: Option<u64>,
+
+    // If true, report failed allocations rather than the call tree.
+    pub failure_report: bool,
+
+    // If true, report the totals of each function wherever it is called,
+    // rather than the call tree.
+    pub flat_report: bool,
+
+    // If true, describe the trace and the traced process rather than
+    // reporting the call tree.
+    pub info_report: bool,
+
+    // If true, report the blocks allocated by each entry of the call tree
+    // counted by how long they lived.
+    pub lifetime_report: bool,
+
+    // If true, report the blocks still allocated at the end of the trace
+    // rather than the call tree.
+    pub live_report: bool,
+
+    // If true, report the blocks never freed with the full callstack of
+    // each, rather than the call tree.
+    pub leak_report: bool,
+
+    // If true, report mapped memory by kind and file rather than the call
+    // tree.
+    pub mapping_report: bool,
+
+    // If true, report the allocations of each module of the traced process
+    // rather than the call tree.
+    pub module_report: bool,
+
+    // The session of the trace to view, if not the most recent.
+    pub session: Option<u64>,
+
+    // The span of the trace to summarize.
+    pub time_range: summary::TimeRange,
+
+    // The names of the phases between which the trace is summarized, in
+    // place of the time range, if given.
+    pub between: Option<(String, String)>,
+
+    // If true, show the rates of allocation in the call tree.
+    pub rates: bool,
+
+    // If true, show when each entry of the call tree first allocated and
+    // was last active.
+    pub times: bool,
+
+    // If true, show the total bytes allocated by each entry of the call
+    // tree, including those freed.
+    pub churn: bool,
+
+    // If true, show the instruction address of each entry of the call tree
+    // and the module containing it.
+    pub addresses: bool,
+
+    // If true, show a bar in each row of the call tree as long as its share
+    // of the total of the sorted column.
+    pub bars: bool,
+
+    // If set, the column by which the call tree is sorted, rather than
+    // peak bytes.
+    pub sort_mode: Option<rows::SortMode>,
+
     // If true, we should generate a text (non-ncurses) report.
     pub report_mode: bool,
 
+    // The format of the report.
+    pub report_format: report::ReportFormat,
+
+    // The style in which the values of the call tree are written.
+    pub value_style: report::ValueStyle,
+
+    // If true, reports are written identically for the same trace, without
+    // the version of the viewer and with values in full.
+    pub stable: bool,
+
+    // If set, the address at which the call tree is served over HTTP.
+    pub serve: Option<String>,
+
     // If true, we should show performance statistics i nthe ncurses UI.
     pub report_perf: bool,
 
@@ -40,13 +212,297 @@ pub struct CommandLineArguments {
 pub fn show_help() {
     println!(
         "Usage: allocscope-view [OPTIONS] [ATRACE-FILENAME]
+       allocscope-view --diff BEFORE-ATRACE AFTER-ATRACE
+       allocscope-view --baseline BEFORE-ATRACE [--fail-on-regression PERCENT]
+                       ATRACE-FILENAME
+
+ATRACE-FILENAME may be '-' to read the trace from stdin, as in
+'ssh host cat trace.atrace | allocscope-view -', or an http:// URL from
+which the trace is fetched.
 
-    -r, --report    Generate text report to stdout
-    -v, --version   Report version
+    --address ADDRESS   Report the blocks allocated over ADDRESS, such as an
+                        address printed by a sanitizer or debugger, with
+                        their sizes and the times, threads and callstacks
+                        of their allocations and frees.  Also available in
+                        the UI with 'A'
+    --addresses         Show the instruction address of each entry of the
+                        call tree and the module containing it, to match
+                        entries with disassembly or profiler output, or
+                        frames without symbols.  Toggled in the UI with 'X'
+    --assert BUDGET     Check BUDGET against the totals of the trace, such
+                        as 'leaked_bytes<1MB' or 'peak_bytes<=200MB', and
+                        exit with status 1 if it isn't met.  The totals are
+                        'peak_bytes', 'leaked_bytes', 'total_bytes',
+                        'blocks' and 'leaked_blocks'.  May be given more
+                        than once
+    --bars              Show a bar in each row of the call tree as long as
+                        its share of the total of the sorted column.
+                        Toggled in the UI with 'b'
+    --between START END Summarize only events between the start of the
+                        phase named START and the start of the following
+                        phase named END, as marked by the traced program's
+                        calls to allocscope_phase(const char *name).
+                        Phases are also chosen in the UI with 'P'
+    --baseline FILE     Report the call sites whose peak bytes or leaked
+                        blocks grew since the trace FILE
+    --churn             Show the total bytes allocated by each entry of the
+                        call tree, including those freed, to find blocks
+                        repeatedly allocated and freed.  Toggled in the UI
+                        with 'C'
+    --collapse-recursion
+                        Remove the frames of recursive calls, leaving the
+                        outermost call of each function, so that
+                        allocations are counted once in the totals of each
+                        function calling them, rather than once per level
+                        of recursion.  Toggled in the UI with 'u'
+    --column NAME=EXPRESSION
+                        Add a column NAME to the call tree, computed by the
+                        SQL EXPRESSION over the summary of each entry, such
+                        as 'AVG=total_bytes/alloc_count'.  The summary has
+                        'bytes', 'held', 'blocks', 'leaks', 'churn',
+                        'current_total', 'maximum_total', 'alloc_count',
+                        'free_count', 'total_bytes', 'first_time' and
+                        'last_time'.  May be given more than once.  Columns
+                        are also read, one per line, from
+                        ~/.config/allocscope/columns
+    --demangle OPTIONS  Demangle function names with OPTIONS, separated by
+                        commas: 'no-hash' to strip the hashes of Rust
+                        names, 'no-templates' to strip the template
+                        arguments of C++ names, or 'none' to show names as
+                        mangled.  Options are also read from
+                        ~/.config/allocscope/demangle
+    -d, --diff          Report the changes at each call site between two
+                        traces, largest regressions first
+    --exact             Write byte and block counts in full, rather than
+                        abbreviated, widening columns as needed
+    -e, --export FORMAT Export the call tree in FORMAT ('flamegraph' for
+                        folded stacks, 'html' for a self-contained page
+                        with a searchable tree), or the timeline of live
+                        bytes and large allocations ('chrome' for
+                        trace-event JSON viewable in Perfetto)
+    --fail-on-regression PERCENT
+                        With --baseline, report only growth of more than
+                        PERCENT, such as '5%', and exit with status 1 if any
+                        call site grew by more
+    -f, --failures      Report the call sites of failed allocations
+    --filter REGEX      Show only the parts of the call tree with a function
+                        matching REGEX
+    --fold REGEX        Fold frames with a function matching REGEX into
+                        their callers, such as '^core::' or '^alloc::'.
+                        May be given more than once.  Rules are also read,
+                        one per line, from ~/.config/allocscope/fold
+    --follow            Watch a trace still being recorded, adding newly
+                        written events to the call tree every few seconds
+    --format FORMAT     Report in FORMAT ('text', 'jsonl' for a JSON object
+                        per line for each entry of the call tree, or
+                        'markdown' for tables to post in pull requests)
+    --from TIME         Summarize only events at or after TIME since the
+                        start of the trace, in seconds or with a suffix of
+                        'ms' or 's'
+    -g, --group MODE    Group the call tree by MODE ('task', 'spawner' for
+                        the callstack creating each thread, 'cpu', or
+                        'none')
+    --info              Describe the trace: the traced command, process and
+                        host, when it was traced, the tracer version, and
+                        the events recorded and dropped
+    -i, --invert        Invert the call tree, with the functions making
+                        allocations as roots and their callers beneath
+    -l, --live          Report the blocks still allocated at the end of the
+                        trace, by the callstack which allocated them
+    -m, --mappings      Report mapped memory by kind and by file
+    --max-depth DEPTH   Show only the first DEPTH levels of the call tree,
+                        with deeper entries rolled up into them
+    --no-color          Draw the UI without colors, as with --theme mono or
+                        when NO_COLOR is set
+    --modules           Roll consecutive frames in the same shared object
+                        into a single entry named for the object
+    --numbers OPTIONS   Write values with OPTIONS, separated by commas:
+                        'si' for bytes in powers of 1000 (kB, MB), 'iec'
+                        for bytes in powers of 1024 (KiB, MiB), 'compact'
+                        for bytes in powers of 1024 with the suffixes of
+                        counts (k, M), 'precision=N' for N decimal places
+                        of abbreviated values, or 'separator=NAME' to
+                        separate thousands with a 'comma', 'period',
+                        'space', 'underscore' or 'apostrophe'.  Options
+                        are also read from ~/.config/allocscope/numbers
+    -o, --output FILE   Write an export to FILE rather than stdout
+    --prune PERCENT     Hide entries of the call tree with less than PERCENT
+                        of the total peak bytes and blocks, such as '0.5%',
+                        replacing them with a single entry
+    --rates             Show allocations and bytes allocated per second
+    -r, --report [flat|leaks|lifetimes|modules]
+                        Generate text report to stdout, or with 'flat',
+                        the totals of each function wherever it is called,
+                        including and excluding its callees, or with
+                        'leaks', list the blocks never freed by callstack,
+                        largest first, with full callstacks and source
+                        lines, or with 'lifetimes', count the blocks of
+                        each entry of the call tree by how long they lived,
+                        or with 'modules', total the peak, allocated and
+                        held bytes of each shared object, attributing each
+                        block to the object which called the allocator
+    --separators        Write byte and block counts in full, with thousands
+                        separated by commas
+    --serve ADDRESS     Serve the call tree and a flamegraph over HTTP at
+                        ADDRESS, such as '127.0.0.1:8080', to browse from
+                        another machine
+    -s, --session ID    View the session numbered ID of a trace recorded
+                        with --append, rather than the most recent
+    --sort COLUMN       Sort the call tree by COLUMN ('bytes', 'held',
+                        'blocks', 'leaks', 'churn', 'function' or the name
+                        of a computed column), rather than by peak bytes
+    --source-map RULE   Read source files for the source pane from another
+                        directory, with RULE as 'FROM=TO' replacing the
+                        prefix FROM of their paths.  May be given more than
+                        once.  Rules are also read, one per line, from
+                        ~/.config/allocscope/source-map
+    --stable            Write reports which are identical for the same
+                        trace, for comparing with golden files or between
+                        runs, omitting the version header, writing values
+                        in full and ordering entries with equal values by
+                        function name
+    --theme NAME        Draw the UI with the color theme NAME ('default',
+                        'light', 'dark' using 256 colors where available,
+                        or 'mono')
+    --times             Show when each entry first allocated, and when it
+                        last allocated or freed, since the start of the
+                        trace
+    --to TIME           Summarize only events at or before TIME since the
+                        start of the trace
+    --top N             Report only the N largest children of each entry of
+                        the call tree, replacing the rest with a single
+                        entry
+    -v, --version       Report version
+    -w, --weight KIND   Weight exported callstacks by KIND ('peak' bytes,
+                        'total' bytes, or 'leaked' bytes)
+
+The state of the call tree in the UI is saved to ATRACE-FILENAME.view when
+the UI exits, and restored when the trace is viewed again.  Summaries of the
+whole trace are saved within ATRACE-FILENAME, if it is writable, so that
+they needn't be computed again.
 "
     );
 }
 
+// Parse a time since the start of the trace, given in seconds, which may be
+// fractional, or with a suffix of 'ms' or 's', as nanoseconds.
+fn parse_time(token: &str) -> Result<u64, Box<dyn Error>> {
+    let (number, unit_nanoseconds) = match token.strip_suffix("ms") {
+        Some(number) => (number, 1e6),
+        None => (token.strip_suffix('s').unwrap_or(token), 1e9),
+    };
+
+    match number.parse::<f64>() {
+        Ok(value) if value >= 0.0 && value.is_finite() => Ok((value * unit_nanoseconds) as u64),
+        _ => Err(format!("invalid time: {}", token))?,
+    }
+}
+
+// Parse a percentage, with or without a '%' suffix, as a fraction.
+fn parse_percentage(token: &str) -> Result<f64, Box<dyn Error>> {
+    match token.strip_suffix('%').unwrap_or(token).parse::<f64>() {
+        Ok(value) if (0.0..=100.0).contains(&value) => Ok(value / 100.0),
+        _ => Err(format!("invalid percentage: {}", token))?,
+    }
+}
+
+// Parse a rule for folding frames.
+fn parse_fold_rule(rule: &str) -> Result<Regex, Box<dyn Error>> {
+    Ok(Regex::new(rule).map_err(|err| format!("invalid fold rule: {}", err))?)
+}
+
+// Read a file of the user's configuration, returning its filename and its
+// lines other than blank lines and lines starting with '#'.  A missing file
+// has no lines.
+fn read_config_lines(name: &str) -> (String, Vec<String>) {
+    let config_dir = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => dir,
+        _ => match std::env::var("HOME") {
+            Ok(home) => format!("{}/.config", home),
+            Err(_) => return (name.to_string(), Vec::new()),
+        },
+    };
+    let filename = format!("{}/allocscope/{}", config_dir, name);
+    let contents = std::fs::read_to_string(&filename).unwrap_or_default();
+    let lines = contents
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect();
+
+    (filename, lines)
+}
+
+// Read the rules for folding frames from the user's configuration, which
+// has one rule per line.
+fn read_fold_rules() -> Result<Vec<Regex>, Box<dyn Error>> {
+    let (filename, lines) = read_config_lines("fold");
+    let mut rules = Vec::new();
+    for line in lines {
+        rules.push(parse_fold_rule(&line).map_err(|err| format!("{}: {}", filename, err))?);
+    }
+
+    Ok(rules)
+}
+
+// Read the rules for remapping the paths of source files from the user's
+// configuration, which has one rule per line.
+fn read_source_map() -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let (filename, lines) = read_config_lines("source-map");
+    let mut rules = Vec::new();
+    for line in lines {
+        rules.push(
+            source::parse_source_map_rule(&line).map_err(|err| format!("{}: {}", filename, err))?,
+        );
+    }
+
+    Ok(rules)
+}
+
+// Read the options for demangling names from the user's configuration,
+// which lists them one per line or separated by commas.
+fn read_demangle_options() -> Result<rows::DemangleOptions, Box<dyn Error>> {
+    let (filename, lines) = read_config_lines("demangle");
+    let mut options = rows::DemangleOptions::default();
+    for line in lines {
+        options
+            .apply(&line)
+            .map_err(|err| format!("{}: {}", filename, err))?;
+    }
+
+    Ok(options)
+}
+
+// Read the computed columns from the user's configuration, which has one
+// definition per line.
+fn read_computed_columns() -> Result<Vec<columns::ComputedColumn>, Box<dyn Error>> {
+    let (filename, lines) = read_config_lines("columns");
+    let mut computed_columns = Vec::new();
+    for line in lines {
+        computed_columns.push(
+            columns::ComputedColumn::parse(&line)
+                .map_err(|err| format!("{}: {}", filename, err))?,
+        );
+    }
+
+    Ok(computed_columns)
+}
+
+// Read the options for writing values from the user's configuration, which
+// lists them one per line or separated by commas.
+fn read_number_options() -> Result<report::NumberOptions, Box<dyn Error>> {
+    let (filename, lines) = read_config_lines("numbers");
+    let mut options = report::NumberOptions::default();
+    for line in lines {
+        options
+            .apply(&line)
+            .map_err(|err| format!("{}: {}", filename, err))?;
+    }
+
+    Ok(options)
+}
+
 // Print the version of the build.
 pub fn report_version() {
     println!("allocscope-view {}", env!("CARGO_PKG_VERSION"));
@@ -58,19 +514,244 @@ impl CommandLineArguments {
         args: &mut dyn Iterator<Item = String>,
     ) -> Result<CommandLineArguments, Box<dyn Error>> {
         let mut atrace_filename: Option<String> = None;
+        let mut diff = false;
+        let mut diff_filename: Option<String> = None;
+        let mut baseline_filename: Option<String> = None;
+        let mut regression_threshold: Option<f64> = None;
+        let mut assertions = Vec::new();
+        let mut export_format: Option<export::ExportFormat> = None;
+        let mut export_filename: Option<String> = None;
+        let mut export_weight = export::Weight::Peak;
+        let mut group_mode = transform::GroupMode::None;
+        let mut inverted = false;
+        let mut session: Option<u64> = None;
+        let mut time_range = summary::TimeRange::all();
+        let mut between: Option<(String, String)> = None;
+        let mut between_start: Option<String> = None;
+        let mut rates = false;
+        let mut times = false;
+        let mut churn = false;
+        let mut addresses = false;
+        let mut bars = false;
+        let mut sort_name: Option<String> = None;
         let mut report_mode = false;
+        let mut report_format = report::ReportFormat::Text;
+        let mut value_style = report::ValueStyle::Abbreviated;
+        let mut stable = false;
+        let mut serve: Option<String> = None;
+        let mut filter: Option<Regex> = None;
+        let mut fold_rules = read_fold_rules()?;
+        let mut demangle = read_demangle_options()?;
+        let mut numbers = read_number_options()?;
+        let mut computed_columns = read_computed_columns()?;
+        let mut source_map = read_source_map()?;
+        let mut group_modules = false;
+        let mut collapse_recursion = false;
+        let mut prune: Option<f64> = None;
+        let mut top: Option<usize> = None;
+        let mut max_depth: Option<usize> = None;
+        let mut follow = false;
+        let mut theme = match std::env::var("NO_COLOR") {
+            Ok(value) if !value.is_empty() => theme::Theme::Mono,
+            _ => theme::Theme::Default,
+        };
+        let mut address: Option<u64> = None;
+        let mut failure_report = false;
+        let mut flat_report = false;
+        let mut lifetime_report = false;
+        let mut live_report = false;
+        let mut leak_report = false;
+        let mut mapping_report = false;
+        let mut module_report = false;
+        let mut info_report = false;
         let mut report_perf = false;
         let mut report_version = false;
         let mut show_help = false;
 
+        let mut expect_address = false;
+        let mut expect_assertion = false;
+        let mut expect_baseline = false;
+        let mut expect_demangle = false;
+        let mut expect_numbers = false;
+        let mut expect_column = false;
+        let mut expect_export_format = false;
+        let mut expect_export_filename = false;
+        let mut expect_export_weight = false;
+        let mut expect_filter = false;
+        let mut expect_fold_rule = false;
+        let mut expect_group_mode = false;
+        let mut expect_max_depth = false;
+        let mut expect_prune = false;
+        let mut expect_regression_threshold = false;
+        let mut expect_report_format = false;
+        let mut allow_report_kind = false;
+        let mut expect_theme = false;
+        let mut expect_top = false;
+        let mut expect_serve = false;
+        let mut expect_session = false;
+        let mut expect_sort_mode = false;
+        let mut expect_source_map = false;
+        let mut expect_time_start = false;
+        let mut expect_time_end = false;
+        let mut expect_between_start = false;
+        let mut expect_between_end = false;
         for token in args.skip(1) {
-            if token.chars().next() == Some('-') {
+            // The kind of report may follow --report, which otherwise
+            // needs no argument.
+            if allow_report_kind {
+                allow_report_kind = false;
+                if token == "flat" {
+                    flat_report = true;
+                    continue;
+                }
+                if token == "leaks" {
+                    leak_report = true;
+                    continue;
+                }
+                if token == "lifetimes" {
+                    lifetime_report = true;
+                    continue;
+                }
+                if token == "modules" {
+                    module_report = true;
+                    continue;
+                }
+            }
+
+            if expect_address {
+                expect_address = false;
+                address = Some(allocations::parse_address(&token)?);
+            } else if expect_assertion {
+                expect_assertion = false;
+                assertions.push(budget::Assertion::parse(&token)?);
+            } else if expect_baseline {
+                expect_baseline = false;
+                baseline_filename = Some(token);
+            } else if expect_demangle {
+                expect_demangle = false;
+                demangle.apply(&token)?;
+            } else if expect_numbers {
+                expect_numbers = false;
+                numbers.apply(&token)?;
+            } else if expect_column {
+                expect_column = false;
+                computed_columns.push(columns::ComputedColumn::parse(&token)?);
+            } else if expect_export_format {
+                expect_export_format = false;
+                export_format = Some(export::ExportFormat::parse(&token)?);
+            } else if expect_export_filename {
+                expect_export_filename = false;
+                export_filename = Some(token);
+            } else if expect_export_weight {
+                expect_export_weight = false;
+                export_weight = export::Weight::parse(&token)?;
+            } else if expect_filter {
+                expect_filter = false;
+                filter =
+                    Some(Regex::new(&token).map_err(|err| format!("invalid filter: {}", err))?);
+            } else if expect_serve {
+                expect_serve = false;
+                serve = Some(token);
+            } else if expect_sort_mode {
+                expect_sort_mode = false;
+                sort_name = Some(token);
+            } else if expect_source_map {
+                expect_source_map = false;
+                source_map.push(source::parse_source_map_rule(&token)?);
+            } else if expect_fold_rule {
+                expect_fold_rule = false;
+                fold_rules.push(parse_fold_rule(&token)?);
+            } else if expect_group_mode {
+                expect_group_mode = false;
+                group_mode = transform::GroupMode::parse(&token)?;
+            } else if expect_max_depth {
+                expect_max_depth = false;
+                max_depth = Some(token.parse().map_err(|_| "invalid depth")?);
+            } else if expect_prune {
+                expect_prune = false;
+                prune = Some(parse_percentage(&token)?);
+            } else if expect_regression_threshold {
+                expect_regression_threshold = false;
+                regression_threshold = Some(parse_percentage(&token)?);
+            } else if expect_report_format {
+                expect_report_format = false;
+                report_format = report::ReportFormat::parse(&token)?;
+                report_mode = true;
+            } else if expect_theme {
+                expect_theme = false;
+                theme = theme::Theme::parse(&token)?;
+            } else if expect_top {
+                expect_top = false;
+                top = Some(token.parse().map_err(|_| "invalid count of entries")?);
+            } else if expect_time_start {
+                expect_time_start = false;
+                time_range.start = parse_time(&token)?;
+            } else if expect_time_end {
+                expect_time_end = false;
+                time_range.end = parse_time(&token)?;
+            } else if expect_between_start {
+                expect_between_start = false;
+                expect_between_end = true;
+                between_start = Some(token);
+            } else if expect_between_end {
+                expect_between_end = false;
+                between = between_start.take().map(|start| (start, token));
+            } else if expect_session {
+                expect_session = false;
+                session = Some(token.parse().map_err(|_| "invalid session")?);
+            } else if token.chars().next() == Some('-') && token != "-" {
                 if token.chars().nth(1) == Some('-') {
                     match token.as_str() {
+                        "--address" => expect_address = true,
+                        "--addresses" => addresses = true,
+                        "--assert" => expect_assertion = true,
+                        "--bars" => bars = true,
+                        "--between" => expect_between_start = true,
+                        "--baseline" => expect_baseline = true,
+                        "--churn" => churn = true,
+                        "--collapse-recursion" => collapse_recursion = true,
+                        "--column" => expect_column = true,
+                        "--demangle" => expect_demangle = true,
+                        "--diff" => diff = true,
+                        "--exact" => value_style = report::ValueStyle::Exact,
+                        "--export" => expect_export_format = true,
+                        "--fail-on-regression" => expect_regression_threshold = true,
+                        "--failures" => failure_report = true,
+                        "--filter" => expect_filter = true,
+                        "--fold" => expect_fold_rule = true,
+                        "--follow" => follow = true,
+                        "--format" => expect_report_format = true,
+                        "--from" => expect_time_start = true,
+                        "--group" => expect_group_mode = true,
                         "--help" => show_help = true,
+                        "--info" => info_report = true,
+                        "--invert" => inverted = true,
+                        "--live" => live_report = true,
+                        "--mappings" => mapping_report = true,
+                        "--max-depth" => expect_max_depth = true,
+                        "--modules" => group_modules = true,
+                        "--no-color" => theme = theme::Theme::Mono,
+                        "--numbers" => expect_numbers = true,
+                        "--output" => expect_export_filename = true,
                         "--perf" => report_perf = true, // Undocumented command for development.
-                        "--report" => report_mode = true,
+                        "--prune" => expect_prune = true,
+                        "--rates" => rates = true,
+                        "--report" => {
+                            report_mode = true;
+                            allow_report_kind = true;
+                        }
+                        "--separators" => value_style = report::ValueStyle::Separated,
+                        "--serve" => expect_serve = true,
+                        "--session" => expect_session = true,
+                        "--sort" => expect_sort_mode = true,
+                        "--source-map" => expect_source_map = true,
+                        "--stable" => stable = true,
+                        "--theme" => expect_theme = true,
+                        "--times" => times = true,
+                        "--to" => expect_time_end = true,
+                        "--top" => expect_top = true,
                         "--version" => report_version = true,
+                        "--weight" => expect_export_weight = true,
                         _ => {
                             eprintln!("Unrecognized argument: {}", token);
                             show_help = true;
@@ -79,9 +760,22 @@ impl CommandLineArguments {
                 } else {
                     for char in token.chars().skip(1) {
                         match char {
+                            'd' => diff = true,
+                            'e' => expect_export_format = true,
+                            'f' => failure_report = true,
+                            'g' => expect_group_mode = true,
                             'h' => show_help = true,
-                            'r' => report_mode = true,
+                            'i' => inverted = true,
+                            'l' => live_report = true,
+                            'm' => mapping_report = true,
+                            'o' => expect_export_filename = true,
+                            'r' => {
+                                report_mode = true;
+                                allow_report_kind = true;
+                            }
+                            's' => expect_session = true,
                             'v' => report_version = true,
+                            'w' => expect_export_weight = true,
                             _ => {
                                 eprintln!("Unrecognized flag: {}", char);
                                 show_help = true;
@@ -91,15 +785,95 @@ impl CommandLineArguments {
                 }
             } else if atrace_filename.is_none() && atrace_filename.is_none() {
                 atrace_filename = Some(token);
+            } else if diff && diff_filename.is_none() {
+                diff_filename = Some(token);
             } else {
                 eprintln!("Spurious argument: {}", token);
                 show_help = true;
             }
         }
 
+        if time_range.start > time_range.end {
+            Err("--from is later than --to")?;
+        }
+        if expect_between_start || expect_between_end {
+            Err("--between requires the names of two phases")?;
+        }
+        if between.is_some() && time_range != summary::TimeRange::all() {
+            Err("--between can't be used with --from or --to")?;
+        }
+        // Computed columns may be sorted by, once all are defined.
+        let sort_mode = match sort_name {
+            Some(name) => Some(rows::SortMode::parse(&name, &computed_columns)?),
+            None => None,
+        };
+        // Values are written in full in stable reports, as abbreviations
+        // may hide differences.
+        if stable {
+            value_style = report::ValueStyle::Exact;
+        }
+        // The column sorted by is always shown.
+        if sort_mode == Some(rows::SortMode::Churn) {
+            churn = true;
+        }
+        if flat_report && inverted {
+            Err("--report flat can't be used with --invert")?;
+        }
+        if regression_threshold.is_some() && baseline_filename.is_none() {
+            Err("--fail-on-regression requires --baseline")?;
+        }
+        if diff && diff_filename.is_none() {
+            eprintln!("--diff requires two trace files");
+            show_help = true;
+        }
+
         Ok(CommandLineArguments {
             atrace_filename: atrace_filename,
+            diff_filename,
+            baseline_filename,
+            regression_threshold,
+            assertions,
+            export_format,
+            export_filename,
+            export_weight,
+            group_mode,
+            inverted,
+            filter,
+            fold_rules,
+            demangle,
+            numbers,
+            computed_columns,
+            source_map,
+            prune,
+            top,
+            max_depth,
+            theme,
+            follow,
+            group_modules,
+            collapse_recursion,
+            address,
+            failure_report,
+            flat_report,
+            info_report,
+            lifetime_report,
+            live_report,
+            leak_report,
+            mapping_report,
+            module_report,
+            session,
+            time_range,
+            between,
+            rates,
+            times,
+            churn,
+            addresses,
+            bars,
+            sort_mode,
             report_mode,
+            report_format,
+            value_style,
+            stable,
+            serve,
             report_perf,
             report_version,
             show_help,
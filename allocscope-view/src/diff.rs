@@ -0,0 +1,324 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::report;
+use crate::rows;
+use crate::summary;
+use crate::trace;
+use crate::transform;
+use std::collections::HashMap;
+use std::error::Error;
+
+// The totals of a call site, aligned across traces by the function names of
+// its callstack.
+#[derive(Clone, Copy, Default)]
+struct SiteTotals {
+    // The maximum concurrent bytes allocated by the site and its callees.
+    maximum_size: u64,
+
+    // The number of blocks allocated by the site and its callees.
+    total_blocks: u64,
+
+    // The number of blocks allocated by the site and its callees which were
+    // never freed.
+    unfreed_blocks: u64,
+}
+
+// A metric of a call site which has regressed since a baseline trace.
+struct Regression {
+    // The name of the metric, as printed in the report.
+    metric: &'static str,
+
+    // The function names of the callstack, outermost first.
+    callstack: Vec<String>,
+
+    // The value of the metric in the baseline trace.
+    before: u64,
+
+    // The value of the metric in the trace being viewed.
+    after: u64,
+
+    // The divisor used to abbreviate the values of the metric.
+    divisor: u64,
+}
+
+// The change in the totals of a call site between two traces.
+struct SiteDelta {
+    // The function names of the callstack, outermost first.
+    callstack: Vec<String>,
+
+    // The change in maximum concurrent bytes.
+    maximum_size: i64,
+
+    // The change in blocks allocated.
+    total_blocks: i64,
+
+    // The change in blocks never freed.
+    unfreed_blocks: i64,
+}
+
+// The name of a function in a callstack used to align call sites, across
+// traces or across callstacks.  Offsets are omitted, since they change when
+// code is rebuilt.
+pub fn site_frame_name(transaction: &mut trace::Transaction, entry: &trace::StackEntry) -> String {
+    match transaction.location(entry.location) {
+        Some(trace::Location {
+            function: Some(function),
+            ..
+        }) if !function.is_empty() => rows::demangle_function_name(&function),
+        Some(location) => format!("0x{:x}", location.address),
+        None => "[unknown]".to_string(),
+    }
+}
+
+// Collect the totals of every call site in the summarized call tree of a
+// trace.  Sites which differ only by offset within a function are merged.
+fn collect_sites(trace: &trace::Trace) -> Result<HashMap<Vec<String>, SiteTotals>, Box<dyn Error>> {
+    let mut transaction = trace::Transaction::new(trace)?;
+    let mut sites: HashMap<Vec<String>, SiteTotals> = HashMap::new();
+
+    let mut entries: Vec<(trace::StackEntry, Vec<String>)> = Vec::new();
+    for root in transaction.root_stackentries()? {
+        let callstack = vec![site_frame_name(&mut transaction, &root)];
+        entries.push((root, callstack));
+    }
+
+    while let Some((entry, callstack)) = entries.pop() {
+        let summary = match transaction.summary(entry.id) {
+            Some(summary) => summary,
+            None => continue,
+        };
+
+        for child in transaction.get_stackentry_children(entry.id)? {
+            let mut child_callstack = callstack.clone();
+            child_callstack.push(site_frame_name(&mut transaction, &child));
+            entries.push((child, child_callstack));
+        }
+
+        let site = sites.entry(callstack).or_default();
+        site.maximum_size += summary.maximum_total;
+        site.total_blocks += summary.alloc_count;
+        site.unfreed_blocks += summary.alloc_count - summary.free_count;
+    }
+
+    Ok(sites)
+}
+
+// Open and summarize a trace compared against the trace being viewed, using
+// its most recent session.
+fn summarize_compared_trace(
+    filename: &str,
+    scratch_filename: &str,
+    group_mode: transform::GroupMode,
+    time_range: summary::TimeRange,
+) -> Result<trace::Trace, Box<dyn Error>> {
+    let mut trace = trace::Trace::new(filename, scratch_filename)?;
+    trace.select_session(None)?;
+
+    let mut tree_builder = transform::TreeBuilder::new(group_mode);
+    if group_mode == transform::GroupMode::Spawner {
+        tree_builder.set_thread_creators(trace.thread_creators()?);
+    }
+    summary::summarize_allocations(&mut trace, &mut tree_builder, time_range, false)?;
+
+    Ok(trace)
+}
+
+// Collect the totals of every call site of another trace, summarized over
+// the same span of time as the trace being viewed.
+fn collect_compared_sites(
+    filename: &str,
+    group_mode: transform::GroupMode,
+    time_range: summary::TimeRange,
+) -> Result<HashMap<Vec<String>, SiteTotals>, Box<dyn Error>> {
+    let scratch_filename = format!("/tmp/trace-view-{}-diff.scratch", std::process::id());
+    let compared = summarize_compared_trace(filename, &scratch_filename, group_mode, time_range);
+    let compared_sites = compared.and_then(|compared| collect_sites(&compared));
+    if let Err(err) = std::fs::remove_file(&scratch_filename) {
+        eprintln!("Can't remove scratch file: {:?}", err);
+    }
+
+    compared_sites
+}
+
+// Format a signed change for printing in a column one wider than the values
+// of 'format_table_value', using their suffixes.
+fn format_delta_value(value: i64, divisor: u64) -> String {
+    let sign = if value > 0 {
+        "+"
+    } else if value < 0 {
+        "-"
+    } else {
+        ""
+    };
+    let magnitude = report::format_table_value(value.unsigned_abs(), divisor);
+
+    format!(
+        "{:>width$}",
+        format!("{}{}", sign, magnitude.trim_start()),
+        width = report::table_value_width() + 1
+    )
+}
+
+// Generate a report to stdout of the changes at each call site between a
+// summarized baseline trace and a later trace, largest regressions in peak
+// bytes first.  The later trace is summarized over the same span of time as
+// the baseline.
+pub fn generate_diff_report(
+    baseline: &trace::Trace,
+    filename: &str,
+    group_mode: transform::GroupMode,
+    time_range: summary::TimeRange,
+) -> Result<(), Box<dyn Error>> {
+    let compared_sites = collect_compared_sites(filename, group_mode, time_range)?;
+    let baseline_sites = collect_sites(baseline)?;
+
+    let mut deltas = Vec::new();
+    let mut callstacks: Vec<&Vec<String>> =
+        baseline_sites.keys().chain(compared_sites.keys()).collect();
+    callstacks.sort();
+    callstacks.dedup();
+    for callstack in callstacks {
+        let before = baseline_sites.get(callstack).copied().unwrap_or_default();
+        let after = compared_sites.get(callstack).copied().unwrap_or_default();
+        let delta = SiteDelta {
+            callstack: callstack.clone(),
+            maximum_size: after.maximum_size as i64 - before.maximum_size as i64,
+            total_blocks: after.total_blocks as i64 - before.total_blocks as i64,
+            unfreed_blocks: after.unfreed_blocks as i64 - before.unfreed_blocks as i64,
+        };
+        if delta.maximum_size != 0 || delta.total_blocks != 0 || delta.unfreed_blocks != 0 {
+            deltas.push(delta);
+        }
+    }
+    deltas.sort_by(|a, b| {
+        b.maximum_size
+            .cmp(&a.maximum_size)
+            .then(b.total_blocks.cmp(&a.total_blocks))
+            .then(b.unfreed_blocks.cmp(&a.unfreed_blocks))
+            .then(a.callstack.cmp(&b.callstack))
+    });
+
+    report::print_title("memory diff");
+    if deltas.is_empty() {
+        println!("No changes in allocations");
+        return Ok(());
+    }
+
+    let width = report::table_value_width() + 1;
+    println!(
+        "{:>width$} {:>width$} {:>width$}   Callstack",
+        "BYTES", "BLOCK", "LEAKS"
+    );
+    for delta in deltas {
+        println!(
+            "{} {} {}   {}",
+            format_delta_value(delta.maximum_size, 1024),
+            format_delta_value(delta.total_blocks, 1000),
+            format_delta_value(delta.unfreed_blocks, 1000),
+            delta.callstack.join(" > "),
+        );
+    }
+
+    Ok(())
+}
+
+// Format the relative growth of a regressed metric for printing in a six
+// column space, or 'new' for a site absent from the baseline.
+fn format_growth(before: u64, after: u64) -> String {
+    if before == 0 {
+        return format!("{:>6}", "new");
+    }
+
+    let percent = (after - before) as f64 * 100.0 / before as f64;
+    format!("{:>6}", format!("+{:.0}%", percent))
+}
+
+// Compare the peak bytes and the leaked blocks of each call site of a
+// summarized trace against those of a baseline trace, and report to stdout
+// the sites which grew by more than 'threshold', as a fraction of the
+// baseline value.  Sites absent from the baseline have regressed if they
+// have any peak bytes or leaks.  Returns true if no site regressed.
+pub fn check_regressions(
+    trace: &trace::Trace,
+    baseline_filename: &str,
+    group_mode: transform::GroupMode,
+    time_range: summary::TimeRange,
+    threshold: f64,
+) -> Result<bool, Box<dyn Error>> {
+    let baseline_sites = collect_compared_sites(baseline_filename, group_mode, time_range)?;
+    let sites = collect_sites(trace)?;
+
+    let mut regressions = Vec::new();
+    let mut callstacks: Vec<&Vec<String>> = sites.keys().collect();
+    callstacks.sort();
+    for callstack in callstacks {
+        let before = baseline_sites.get(callstack).copied().unwrap_or_default();
+        let after = sites[callstack];
+        let metrics = [
+            ("peak", before.maximum_size, after.maximum_size, 1024),
+            ("leaks", before.unfreed_blocks, after.unfreed_blocks, 1000),
+        ];
+        for (metric, before, after, divisor) in metrics {
+            if after > before && after as f64 > before as f64 * (1.0 + threshold) {
+                regressions.push(Regression {
+                    metric,
+                    callstack: callstack.clone(),
+                    before,
+                    after,
+                    divisor,
+                });
+            }
+        }
+    }
+    regressions.sort_by(|a, b| {
+        b.metric
+            .cmp(a.metric)
+            .then((b.after - b.before).cmp(&(a.after - a.before)))
+            .then(a.callstack.cmp(&b.callstack))
+    });
+
+    report::print_title("regression check");
+    println!("Baseline: {}", baseline_filename);
+    println!("Threshold: {}%", threshold * 100.0);
+    println!();
+    if regressions.is_empty() {
+        println!("No call sites regressed");
+        return Ok(true);
+    }
+
+    let width = std::cmp::max(report::table_value_width() + 1, 6);
+    println!(
+        "METRIC {:>width$} {:>width$} CHANGE   Callstack",
+        "BEFORE", "AFTER"
+    );
+    for regression in &regressions {
+        println!(
+            "{:>6} {:>width$} {:>width$} {}   {}",
+            regression.metric,
+            report::format_table_value(regression.before, regression.divisor),
+            report::format_table_value(regression.after, regression.divisor),
+            format_growth(regression.before, regression.after),
+            regression.callstack.join(" > "),
+        );
+    }
+    println!();
+    println!("{} regressions", regressions.len());
+
+    Ok(false)
+}
@@ -0,0 +1,490 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::report;
+use crate::rows;
+use crate::summary;
+use crate::trace;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::io::Write;
+
+// A format to which the call tree can be exported.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    // Folded stacks, one line per callstack, as read by the FlameGraph
+    // scripts.
+    Flamegraph,
+
+    // Chrome trace-event JSON, as read by Perfetto and chrome://tracing,
+    // with a counter of live bytes over time.
+    Chrome,
+
+    // A self-contained HTML page with the call tree, which can be expanded,
+    // sorted and searched without a server.
+    Html,
+}
+
+// Allocations of at least this many bytes are marked with an instant event
+// in Chrome trace-event output.
+const LARGE_ALLOCATION_SIZE: u64 = 1024 * 1024;
+
+// The process id used for events in Chrome trace-event output.
+const CHROME_PID: u64 = 1;
+
+// The page of an HTML export, into which the call tree is written.
+const HTML_PAGE: &str = include_str!("export.html");
+
+// The placeholder in the HTML page replaced by the call tree.
+const HTML_DATA_PLACEHOLDER: &str = "/*ALLOCSCOPE_DATA*/null";
+
+// The value by which exported callstacks are weighted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Weight {
+    // The maximum concurrent bytes allocated.
+    Peak,
+
+    // The total bytes allocated, including those freed.
+    Total,
+
+    // The bytes never freed.
+    Leaked,
+}
+
+impl ExportFormat {
+    // Parse an export format from its commandline name.
+    pub fn parse(name: &str) -> Result<ExportFormat, Box<dyn Error>> {
+        match name {
+            "chrome" => Ok(ExportFormat::Chrome),
+            "flamegraph" => Ok(ExportFormat::Flamegraph),
+            "html" => Ok(ExportFormat::Html),
+            _ => Err(format!("unknown export format: {}", name).into()),
+        }
+    }
+}
+
+impl Weight {
+    // Parse a weight from its commandline name.
+    pub fn parse(name: &str) -> Result<Weight, Box<dyn Error>> {
+        match name {
+            "peak" => Ok(Weight::Peak),
+            "total" => Ok(Weight::Total),
+            "leaked" => Ok(Weight::Leaked),
+            _ => Err(format!("unknown weight: {}", name).into()),
+        }
+    }
+
+    // The value of this weight for a summarized stack entry, including its
+    // descendents.
+    fn value(&self, summary: &trace::StackEntrySummary) -> u64 {
+        match self {
+            Weight::Peak => summary.maximum_total,
+            Weight::Total => summary.total_bytes,
+            Weight::Leaked => summary.current_total,
+        }
+    }
+}
+
+// The name of the frame for a stack entry in folded output, which can't
+// contain the ';' separating frames.
+fn folded_frame_name(transaction: &mut trace::Transaction, entry: &trace::StackEntry) -> String {
+    let location = transaction.location(entry.location);
+    let name = match location {
+        Some(trace::Location {
+            function: Some(function),
+            ..
+        }) => rows::demangle_function_name(&function),
+        Some(location) => format!("0x{:x}", location.address),
+        None => "[unknown]".to_string(),
+    };
+
+    name.replace(';', ":")
+}
+
+// Write the call tree as folded stacks.  Each stack entry contributes a
+// line weighted by its own value, excluding its children, so that the
+// FlameGraph scripts sum the lines back to the value of each entry.  Peaks
+// of children may occur at different times, so the peak of an entry can
+// be less than the sum of its childrens' peaks, in which case the entry
+// contributes nothing of its own.
+fn write_folded(
+    transaction: &mut trace::Transaction,
+    weight: Weight,
+    output: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+    let mut entries: Vec<(trace::StackEntry, String)> = Vec::new();
+    for root in transaction.root_stackentries()? {
+        let name = folded_frame_name(transaction, &root);
+        entries.push((root, name));
+    }
+
+    while let Some((entry, path)) = entries.pop() {
+        let value = match transaction.summary(entry.id) {
+            Some(summary) => weight.value(&summary),
+            None => continue,
+        };
+
+        let mut children_value = 0;
+        for child in transaction.get_stackentry_children(entry.id)? {
+            if let Some(summary) = transaction.summary(child.id) {
+                children_value += weight.value(&summary);
+            }
+            let child_path = format!("{};{}", path, folded_frame_name(transaction, &child));
+            entries.push((child, child_path));
+        }
+
+        if value > children_value {
+            writeln!(output, "{} {}", path, value - children_value)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Quote a string for JSON output.
+pub fn json_string(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for char in value.chars() {
+        match char {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            char if (char as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", char as u32)),
+            char => quoted.push(char),
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+// The totals of a stack entry, or of the whole call tree.
+#[derive(Clone, Copy, Debug, Default)]
+struct Totals {
+    // The maximum concurrent bytes allocated.
+    peak_bytes: u64,
+
+    // The bytes still allocated at the end of the trace.
+    held_bytes: u64,
+
+    // The number of blocks allocated.
+    blocks: u64,
+
+    // The bytes allocated, including those freed.
+    total_bytes: u64,
+
+    // The number of blocks never freed.
+    unfreed_blocks: u64,
+}
+
+impl Totals {
+    // The totals of a stack entry, if it has a summary.
+    fn of(transaction: &mut trace::Transaction, id: trace::StackEntryId) -> Option<Totals> {
+        let summary = transaction.summary(id)?;
+
+        Some(Totals {
+            peak_bytes: summary.maximum_total,
+            held_bytes: summary.current_total,
+            blocks: summary.alloc_count,
+            total_bytes: summary.total_bytes,
+            unfreed_blocks: summary.alloc_count - summary.free_count,
+        })
+    }
+
+    // Add the totals of another entry.
+    fn add(&mut self, other: &Totals) {
+        self.peak_bytes += other.peak_bytes;
+        self.held_bytes += other.held_bytes;
+        self.blocks += other.blocks;
+        self.total_bytes += other.total_bytes;
+        self.unfreed_blocks += other.unfreed_blocks;
+    }
+}
+
+// The name of the function of a stack entry, or its address if unknown.
+fn frame_name(transaction: &mut trace::Transaction, entry: &trace::StackEntry) -> String {
+    match transaction.location(entry.location) {
+        Some(trace::Location {
+            function: Some(function),
+            ..
+        }) if !function.is_empty() => rows::demangle_function_name(&function),
+        Some(location) => format!("0x{:x}", location.address),
+        None => "[unknown]".to_string(),
+    }
+}
+
+// The entries with summaries among the children of a stack entry, or among
+// the roots of the call tree, largest peak first.
+fn sorted_children(
+    transaction: &mut trace::Transaction,
+    id: Option<trace::StackEntryId>,
+) -> Result<Vec<trace::StackEntry>, Box<dyn Error>> {
+    let children = match id {
+        Some(id) => transaction.get_stackentry_children(id)?,
+        None => transaction.root_stackentries()?,
+    };
+
+    rows::sort_stackentries(
+        transaction,
+        &mut children.into_iter(),
+        rows::SortOrder {
+            mode: rows::SortMode::Bytes,
+            reversed: false,
+        },
+    )
+}
+
+// Write a stack entry, or the whole call tree, as a JSON object with its
+// descendents to a number of levels.  Descendents with a peak below
+// 'min_bytes' are left out, and counted as omitted.
+pub fn write_tree_json(
+    transaction: &mut trace::Transaction,
+    entry: Option<&trace::StackEntry>,
+    depth: usize,
+    min_bytes: u64,
+    json: &mut String,
+) -> Result<(), Box<dyn Error>> {
+    let id = entry.map(|entry| entry.id);
+    let children = sorted_children(transaction, id)?;
+    let (function, totals) = match entry {
+        Some(entry) => (
+            frame_name(transaction, entry),
+            Totals::of(transaction, entry.id).unwrap_or_default(),
+        ),
+        None => {
+            let mut totals = Totals::default();
+            for child in children.iter() {
+                if let Some(child_totals) = Totals::of(transaction, child.id) {
+                    totals.add(&child_totals);
+                }
+            }
+            ("all".to_string(), totals)
+        }
+    };
+
+    json.push_str(&format!(
+        "{{\"id\":{},\"function\":{},\"peak_bytes\":{},\"held_bytes\":{},\"blocks\":{},\
+        \"total_bytes\":{},\"unfreed_blocks\":{},\"has_children\":{}",
+        id.map_or("null".to_string(), |id| id.to_string()),
+        json_string(&function),
+        totals.peak_bytes,
+        totals.held_bytes,
+        totals.blocks,
+        totals.total_bytes,
+        totals.unfreed_blocks,
+        !children.is_empty()
+    ));
+    if depth > 0 {
+        let mut omitted = 0;
+        json.push_str(",\"children\":[");
+        let mut first = true;
+        for child in children.iter() {
+            let peak = Totals::of(transaction, child.id)
+                .unwrap_or_default()
+                .peak_bytes;
+            if peak < min_bytes {
+                omitted += 1;
+                continue;
+            }
+            if !first {
+                json.push(',');
+            }
+            first = false;
+            write_tree_json(transaction, Some(child), depth - 1, min_bytes, json)?;
+        }
+        json.push_str(&format!("],\"omitted\":{}", omitted));
+    }
+    json.push('}');
+
+    Ok(())
+}
+
+// The JSON fields describing a trace, which accompany an exported or
+// served call tree.
+pub fn trace_json_fields(trace: &trace::Trace, trace_name: &str) -> String {
+    let numbers = report::number_options();
+    format!(
+        "\"trace\":{},\"version\":{},\"free_tracking\":{},\"ended_by_detach\":{},\
+         \"numbers\":{{\"units\":{},\"precision\":{}}}",
+        json_string(trace_name),
+        json_string(env!("CARGO_PKG_VERSION")),
+        trace.has_capability(trace::CAPABILITY_FREE_TRACKING),
+        trace.ended_by_detach(),
+        json_string(numbers.units_name()),
+        numbers.precision
+    )
+}
+
+// Format a time in nanoseconds as the microseconds used by trace events.
+fn chrome_timestamp(time: u64) -> String {
+    format!("{}.{:03}", time / 1000, time % 1000)
+}
+
+// The function names of a recorded callstack, innermost first, as a JSON
+// array.
+fn chrome_callstack(transaction: &mut trace::Transaction, callstack: Option<u64>) -> String {
+    let mut frames = Vec::new();
+    let mut id = callstack;
+    while let Some(entry_id) = id {
+        let entry = match transaction.original_stackentry(entry_id) {
+            Some(entry) => entry,
+            None => break,
+        };
+        let frame = match transaction.original_location(entry.location) {
+            Some(trace::Location {
+                function: Some(function),
+                ..
+            }) => rows::demangle_function_name(&function),
+            Some(location) => format!("0x{:x}", location.address),
+            None => "[unknown]".to_string(),
+        };
+        frames.push(json_string(&frame));
+        id = entry.next;
+    }
+
+    format!("[{}]", frames.join(","))
+}
+
+// Write the allocation timeline as Chrome trace-event JSON.  Live bytes are
+// written as a counter track updated by each event, and large allocations
+// are marked with instant events on the thread which made them.
+fn write_chrome(
+    trace: &trace::Trace,
+    transaction: &mut trace::Transaction,
+    output: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+    if trace.has_capability(trace::CAPABILITY_AGGREGATE) {
+        Err("traces made with --aggregate have no timeline to export")?;
+    }
+    let sample_periods = trace.sample_periods()?;
+    let max_event_id = trace.max_event_id()?;
+
+    writeln!(output, "{{\"displayTimeUnit\":\"ns\",\"traceEvents\":[")?;
+    write!(
+        output,
+        "{{\"name\":\"process_name\",\"ph\":\"M\",\"pid\":{},\"args\":{{\"name\":\"allocscope\"}}}}",
+        CHROME_PID
+    )?;
+
+    // The size of each live block, scaled for sampling, by address.
+    let mut live_blocks: HashMap<u64, u64> = HashMap::new();
+    let mut live_bytes: u64 = 0;
+    for event_id in 1..=max_event_id {
+        let event = match transaction.event(event_id) {
+            Some(event) => event,
+            None => continue,
+        };
+
+        if event.allocation {
+            let size = match event.size {
+                Some(size) => size,
+                None => continue,
+            };
+            let weighted_size = size * summary::sample_period_at(&sample_periods, event.time);
+            live_blocks.insert(event.address, weighted_size);
+            live_bytes += weighted_size;
+
+            if size >= LARGE_ALLOCATION_SIZE {
+                write!(
+                    output,
+                    ",\n{{\"name\":\"large allocation\",\"ph\":\"i\",\"s\":\"t\",\
+                        \"ts\":{},\"pid\":{},\"tid\":{},\
+                        \"args\":{{\"size\":{},\"address\":\"0x{:x}\",\"callstack\":{}}}}}",
+                    chrome_timestamp(event.time),
+                    CHROME_PID,
+                    event.thread.unwrap_or(0),
+                    size,
+                    event.address,
+                    chrome_callstack(transaction, event.callstack),
+                )?;
+            }
+        } else {
+            match live_blocks.remove(&event.address) {
+                Some(size) => live_bytes -= size,
+                None => continue,
+            }
+        }
+
+        write!(
+            output,
+            ",\n{{\"name\":\"live bytes\",\"ph\":\"C\",\"ts\":{},\"pid\":{},\
+                \"args\":{{\"bytes\":{}}}}}",
+            chrome_timestamp(event.time),
+            CHROME_PID,
+            live_bytes,
+        )?;
+    }
+    writeln!(output, "\n]}}")?;
+
+    Ok(())
+}
+
+// Write the whole call tree into the HTML page.  Any '<' is escaped so that
+// function names can't close the script element.
+fn write_html(
+    trace: &trace::Trace,
+    trace_name: &str,
+    transaction: &mut trace::Transaction,
+    output: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+    let mut json = format!("{{{},\"tree\":", trace_json_fields(trace, trace_name));
+    write_tree_json(transaction, None, usize::MAX, 0, &mut json)?;
+    json.push('}');
+
+    let (before, after) = HTML_PAGE
+        .split_once(HTML_DATA_PLACEHOLDER)
+        .ok_or("missing data placeholder in HTML page")?;
+    output.write_all(before.as_bytes())?;
+    output.write_all(json.replace('<', "\\u003c").as_bytes())?;
+    output.write_all(after.as_bytes())?;
+
+    Ok(())
+}
+
+// Export the summarized call tree, or the timeline, of a trace to a file,
+// or to stdout if no filename is given.
+pub fn export(
+    trace: &trace::Trace,
+    trace_name: &str,
+    format: ExportFormat,
+    weight: Weight,
+    filename: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    if weight == Weight::Total && trace.has_capability(trace::CAPABILITY_AGGREGATE) {
+        Err("total bytes aren't recorded by traces made with --aggregate")?;
+    }
+    if weight == Weight::Leaked && !trace.has_capability(trace::CAPABILITY_FREE_TRACKING) {
+        Err("leaks aren't recorded by traces made with --no-free-tracking")?;
+    }
+
+    let mut output: Box<dyn Write> = match filename {
+        Some(filename) => Box::new(io::BufWriter::new(fs::File::create(filename)?)),
+        None => Box::new(io::BufWriter::new(io::stdout())),
+    };
+    let mut transaction = trace::Transaction::new(trace)?;
+    match format {
+        ExportFormat::Flamegraph => write_folded(&mut transaction, weight, &mut output)?,
+        ExportFormat::Chrome => write_chrome(trace, &mut transaction, &mut output)?,
+        ExportFormat::Html => write_html(trace, trace_name, &mut transaction, &mut output)?,
+    }
+    output.flush()?;
+
+    Ok(())
+}
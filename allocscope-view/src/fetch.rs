@@ -0,0 +1,188 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::io::{BufRead, Write};
+use std::net;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+
+// The name given in place of a trace's filename to read it from stdin.
+const STDIN_NAME: &str = "-";
+
+// The port of HTTP URLs which don't name one.
+const DEFAULT_HTTP_PORT: u16 = 80;
+
+// True if a trace named on the commandline isn't a local file, but is read
+// from stdin or fetched from a URL, and so must be copied to a local file
+// before it is viewed.
+pub fn is_remote(name: &str) -> bool {
+    name == STDIN_NAME || name.starts_with("http://") || name.starts_with("https://")
+}
+
+// The local copy of a trace read from stdin or fetched from a URL, which
+// is removed when dropped, so that a large copy isn't left behind when
+// viewing the trace fails.
+pub struct FetchedTrace {
+    // The name of the local file.
+    pub filename: String,
+}
+
+impl Drop for FetchedTrace {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.filename) {
+            eprintln!("Can't remove fetched trace: {:?}", err);
+        }
+    }
+}
+
+// Copy a trace read from stdin or fetched from a URL to a local file, as
+// the trace is an SQLite database, which can't be read as a stream.  The
+// local file is removed if the trace can't be copied whole.  The file must
+// not already exist, so that another user can't substitute a file, or a
+// link to one, at a predictable name.
+pub fn fetch_trace(name: &str, local_filename: &str) -> Result<FetchedTrace, Box<dyn Error>> {
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(local_filename)
+        .map_err(|err| format!("can't create {}: {}", local_filename, err))?;
+    let mut file = io::BufWriter::new(file);
+    let fetched = FetchedTrace {
+        filename: local_filename.to_string(),
+    };
+    if name == STDIN_NAME {
+        read_stdin(&mut file)?;
+    } else {
+        fetch_url(name, &mut file)?;
+    }
+    file.flush()?;
+
+    Ok(fetched)
+}
+
+// Copy the trace piped to stdin.  Afterward, stdin is replaced with the
+// terminal, if there is one, so that the UI can read keys from it.
+fn read_stdin(file: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    if unsafe { libc::isatty(libc::STDIN_FILENO) != 0 } {
+        Err("no trace piped to stdin")?;
+    }
+    io::copy(&mut io::stdin().lock(), file)?;
+
+    if let Ok(terminal) = fs::File::open("/dev/tty") {
+        unsafe {
+            libc::dup2(terminal.as_raw_fd(), libc::STDIN_FILENO);
+        }
+    }
+
+    Ok(())
+}
+
+// Fetch a trace over HTTP.  HTTP/1.0 is requested, so that the body is
+// sent whole, until the connection is closed.  If the server gives the
+// length of the body, a body cut short by a dropped connection is an
+// error, rather than a truncated trace.
+fn fetch_url(url: &str, file: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let rest = match url.strip_prefix("http://") {
+        Some(rest) => rest,
+        None => Err(format!(
+            "can't fetch {}: only http:// URLs are supported, so fetch the trace with \
+            another tool and pipe it to 'allocscope-view -'",
+            url
+        ))?,
+    };
+    let (host, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    };
+    let address = match host.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() => host.to_string(),
+        _ => format!("{}:{}", host, DEFAULT_HTTP_PORT),
+    };
+
+    let mut stream = net::TcpStream::connect(&address)
+        .map_err(|err| format!("can't connect to {}: {}", host, err))?;
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: allocscope-view/{}\r\n\r\n",
+        path,
+        host,
+        env!("CARGO_PKG_VERSION")
+    )?;
+    stream.flush()?;
+
+    let mut reader = io::BufReader::new(stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or("")
+        .to_string();
+
+    // The headers end with an empty line, followed by the body.
+    let mut content_length: Option<u64> = None;
+    let mut location: Option<String> = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("can't fetch {}: invalid Content-Length", url))?,
+                );
+            } else if name.eq_ignore_ascii_case("location") {
+                location = Some(value.to_string());
+            }
+        }
+    }
+
+    // Redirects aren't followed, as the new location may not be http://.
+    if status.starts_with('3') {
+        match location {
+            Some(location) => Err(format!(
+                "can't fetch {}: redirected to {}, which must be given instead",
+                url, location
+            ))?,
+            None => Err(format!("can't fetch {}: {}", url, status_line.trim_end()))?,
+        }
+    }
+    if status != "200" {
+        Err(format!("can't fetch {}: {}", url, status_line.trim_end()))?;
+    }
+
+    let copied = io::copy(&mut reader, file)?;
+    if let Some(content_length) = content_length {
+        if copied != content_length {
+            Err(format!(
+                "can't fetch {}: connection closed after {} of {} bytes",
+                url, copied, content_length
+            ))?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,182 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::diff;
+use crate::trace;
+use std::error::Error;
+
+// A box of the flamegraph, standing for a stack entry, as wide as the
+// entry's share of the peak bytes of its parent.
+pub struct FlameBox {
+    // The stack entry drawn by the box.
+    pub id: trace::StackEntryId,
+
+    // The function of the stack entry.
+    pub function: String,
+
+    // The maximum concurrent bytes allocated beneath the entry.
+    pub bytes: u64,
+
+    // The first column of the box.
+    pub column: usize,
+
+    // The number of columns spanned by the box.
+    pub width: usize,
+
+    // The index of the box of the parent entry in the level above, or None
+    // for boxes of the top level.
+    pub parent: Option<usize>,
+}
+
+// The call tree laid out as an icicle graph, with the roots in the top
+// level, and the children of each entry beneath it in the next level.
+pub struct Flamegraph {
+    // The entries zoomed into, outermost first.  The innermost spans the
+    // whole width of the graph, and when there are none, the roots of the
+    // call tree share it.
+    pub zoom: Vec<trace::StackEntryId>,
+
+    // The peak bytes of the top level, in proportion to which boxes are
+    // sized.
+    pub total: u64,
+
+    // The number of columns across which the graph is laid out.
+    pub columns: usize,
+
+    // The boxes of each level, in order of their columns.
+    pub levels: Vec<Vec<FlameBox>>,
+}
+
+impl Flamegraph {
+    // Lay out the summarized call tree across a number of columns, from the
+    // innermost entry zoomed into, or from the roots.  Children are ordered
+    // largest first.  Since the peaks of children may have come at
+    // different times, and so can sum to more than the peak of their
+    // parent, they are shrunk to fit beneath it when they do.  Entries too
+    // small to span a column are left out, along with their descendents.
+    pub fn new(
+        transaction: &mut trace::Transaction,
+        zoom: Vec<trace::StackEntryId>,
+        columns: usize,
+    ) -> Result<Flamegraph, Box<dyn Error>> {
+        let top = match zoom.last() {
+            Some(id) => transaction.stackentry(*id).into_iter().collect(),
+            None => transaction.root_stackentries()?,
+        };
+        let top = sized_entries(transaction, top);
+        let total = top.iter().map(|(_, bytes)| bytes).sum();
+
+        let mut levels = vec![place_entries(transaction, top, None, 0, columns, total)];
+        loop {
+            let mut level = Vec::new();
+            for (index, parent) in levels[levels.len() - 1].iter().enumerate() {
+                let children = transaction.get_stackentry_children(parent.id)?;
+                let children = sized_entries(transaction, children);
+                let children_bytes: u64 = children.iter().map(|(_, bytes)| bytes).sum();
+                level.extend(place_entries(
+                    transaction,
+                    children,
+                    Some(index),
+                    parent.column,
+                    parent.width,
+                    std::cmp::max(parent.bytes, children_bytes),
+                ));
+            }
+            if level.is_empty() {
+                break;
+            }
+            levels.push(level);
+        }
+
+        Ok(Flamegraph {
+            zoom,
+            total,
+            columns,
+            levels,
+        })
+    }
+
+    // The index of the widest child of a box in the level beneath it.
+    pub fn first_child(&self, level: usize, index: usize) -> Option<usize> {
+        self.levels
+            .get(level + 1)?
+            .iter()
+            .position(|child| child.parent == Some(index))
+    }
+
+    // The index of the box of a level spanning a column.
+    pub fn box_at(&self, level: usize, column: usize) -> Option<usize> {
+        self.levels.get(level)?.iter().position(|flame_box| {
+            flame_box.column <= column && column < flame_box.column + flame_box.width
+        })
+    }
+}
+
+// The summarized entries among some stack entries, with their peak bytes,
+// largest first.
+fn sized_entries(
+    transaction: &mut trace::Transaction,
+    entries: Vec<trace::StackEntry>,
+) -> Vec<(trace::StackEntry, u64)> {
+    let mut sized: Vec<(trace::StackEntry, u64)> = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let summary = transaction.summary(entry.id)?;
+            Some((entry, summary.maximum_total))
+        })
+        .collect();
+    sized.sort_by(|(a, a_bytes), (b, b_bytes)| b_bytes.cmp(a_bytes).then(a.id.cmp(&b.id)));
+
+    sized
+}
+
+// Place boxes for entries side by side across the columns of their parent,
+// each in proportion to its share of 'scale' bytes.
+fn place_entries(
+    transaction: &mut trace::Transaction,
+    entries: Vec<(trace::StackEntry, u64)>,
+    parent: Option<usize>,
+    column: usize,
+    width: usize,
+    scale: u64,
+) -> Vec<FlameBox> {
+    let mut boxes = Vec::new();
+    if scale == 0 {
+        return boxes;
+    }
+
+    let offset = |bytes: u64| (width as u128 * bytes as u128 / scale as u128) as usize;
+    let mut placed_bytes = 0;
+    for (entry, bytes) in entries {
+        let start = offset(placed_bytes);
+        placed_bytes += bytes;
+        let end = offset(placed_bytes);
+        if end > start {
+            boxes.push(FlameBox {
+                id: entry.id,
+                function: diff::site_frame_name(transaction, &entry),
+                bytes,
+                column: column + start,
+                width: end - start,
+                parent,
+            });
+        }
+    }
+
+    boxes
+}
@@ -0,0 +1,170 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::diff;
+use crate::report;
+use crate::text;
+use crate::trace;
+use std::collections::HashMap;
+use std::error::Error;
+
+// The totals of a function across every callstack in which it appears.
+#[derive(Default)]
+struct FunctionTotals {
+    // The bytes allocated by the function and its callees.
+    total_bytes: u64,
+
+    // The bytes allocated by the function itself.
+    self_bytes: u64,
+
+    // The blocks allocated by the function and its callees.
+    total_blocks: u64,
+
+    // The blocks allocated by the function itself.
+    self_blocks: u64,
+
+    // The blocks allocated by the function and its callees which were never
+    // freed.
+    unfreed_blocks: u64,
+
+    // The blocks allocated by the function itself which were never freed.
+    self_unfreed_blocks: u64,
+}
+
+// Collect the totals of each function in the summarized call tree.  An
+// entry's totals are added to its function once, at the outermost frame of
+// each recursion, and less the totals of its children to the function's own.
+fn collect_functions(
+    trace: &trace::Trace,
+) -> Result<HashMap<String, FunctionTotals>, Box<dyn Error>> {
+    let mut transaction = trace::Transaction::new(trace)?;
+    let mut functions: HashMap<String, FunctionTotals> = HashMap::new();
+
+    let mut entries: Vec<(trace::StackEntry, Vec<String>)> = Vec::new();
+    for root in transaction.root_stackentries()? {
+        entries.push((root, Vec::new()));
+    }
+
+    while let Some((entry, callers)) = entries.pop() {
+        let summary = match transaction.summary(entry.id) {
+            Some(summary) => summary,
+            None => continue,
+        };
+        let function = diff::site_frame_name(&mut transaction, &entry);
+
+        let mut self_bytes = summary.total_bytes;
+        let mut self_blocks = summary.alloc_count;
+        let mut self_unfreed_blocks = summary.alloc_count - summary.free_count;
+        let mut callstack = callers.clone();
+        callstack.push(function.clone());
+        for child in transaction.get_stackentry_children(entry.id)? {
+            if let Some(child_summary) = transaction.summary(child.id) {
+                self_bytes = self_bytes.saturating_sub(child_summary.total_bytes);
+                self_blocks = self_blocks.saturating_sub(child_summary.alloc_count);
+                self_unfreed_blocks = self_unfreed_blocks
+                    .saturating_sub(child_summary.alloc_count - child_summary.free_count);
+            }
+            entries.push((child, callstack.clone()));
+        }
+
+        let totals = functions.entry(function.clone()).or_default();
+        if !callers.contains(&function) {
+            totals.total_bytes += summary.total_bytes;
+            totals.total_blocks += summary.alloc_count;
+            totals.unfreed_blocks += summary.alloc_count - summary.free_count;
+        }
+        totals.self_bytes += self_bytes;
+        totals.self_blocks += self_blocks;
+        totals.self_unfreed_blocks += self_unfreed_blocks;
+    }
+
+    Ok(functions)
+}
+
+// Generate a flat report to stdout of the allocations of each function,
+// wherever it appears in the call tree, both including its callees and of
+// its own, with the most bytes allocated first.
+pub fn generate_flat_report(
+    trace: &trace::Trace,
+    value_style: report::ValueStyle,
+) -> Result<(), Box<dyn Error>> {
+    let free_tracking = trace.has_capability(trace::CAPABILITY_FREE_TRACKING);
+    let functions = collect_functions(trace)?;
+
+    let mut functions: Vec<(String, FunctionTotals)> = functions.into_iter().collect();
+    functions.sort_by(|(a_name, a), (b_name, b)| {
+        b.total_bytes
+            .cmp(&a.total_bytes)
+            .then(b.self_bytes.cmp(&a.self_bytes))
+            .then(b.total_blocks.cmp(&a.total_blocks))
+            .then(a_name.cmp(b_name))
+    });
+    let largest = functions
+        .iter()
+        .map(|(_, totals)| totals.total_bytes)
+        .max()
+        .unwrap_or(0);
+    let value_format = report::ValueFormat::new(value_style, largest);
+
+    report::print_title("flat report");
+    if functions.is_empty() {
+        println!("No allocations");
+        return Ok(());
+    }
+
+    println!("Allocations by function, including callees, and by the function itself");
+    println!("(SELF), wherever it is called.");
+    println!();
+    println!(
+        "{} {} {} {} {} {}   Function",
+        value_format.header("BYTES"),
+        value_format.header("SELF"),
+        value_format.header("BLOCK"),
+        value_format.header("SELF"),
+        value_format.header("LEAKS"),
+        value_format.header("SELF"),
+    );
+    let leaks_value = |value: u64| {
+        if free_tracking {
+            value_format.value(value, 1000)
+        } else {
+            value_format.header("-")
+        }
+    };
+    // Rows are truncated to fit a terminal, as in the report of the call
+    // tree.
+    let line_width = text::terminal_width();
+    for (function, totals) in functions {
+        let line = format!(
+            "{} {} {} {} {} {}   {}",
+            value_format.value(totals.total_bytes, 1024),
+            value_format.value(totals.self_bytes, 1024),
+            value_format.value(totals.total_blocks, 1000),
+            value_format.value(totals.self_blocks, 1000),
+            leaks_value(totals.unfreed_blocks),
+            leaks_value(totals.self_unfreed_blocks),
+            function,
+        );
+        match line_width {
+            Some(width) => println!("{}", text::truncate(&line, width)),
+            None => println!("{}", line),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,187 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::rows;
+use crate::trace;
+use std::collections::HashMap;
+use std::error::Error;
+
+// Allocation totals attributed to a function, or to a caller or callee of
+// a focused function.
+#[derive(Clone, Copy, Default)]
+pub struct FocusTotals {
+    // The maximum concurrent bytes allocated.
+    pub maximum_size: u64,
+
+    // The bytes still allocated at the end of the trace.
+    pub held_size: u64,
+
+    // The number of blocks allocated.
+    pub total_blocks: u64,
+
+    // The total bytes allocated, including those freed.
+    pub total_bytes: u64,
+
+    // The number of blocks allocated which were never freed.
+    pub unfreed_blocks: u64,
+
+    // The time of the first allocation, if known.
+    pub first_time: Option<u64>,
+
+    // The time of the last allocation or free, if known.
+    pub last_time: Option<u64>,
+}
+
+// The callers and callees of a function, aggregated across every path
+// through the call tree on which the function appears.
+pub struct FocusView {
+    // The name of the focused function.
+    pub function: String,
+
+    // The totals of allocations made through the function.
+    pub totals: FocusTotals,
+
+    // The functions calling the focused function, with the totals of the
+    // allocations made through each call, largest first.
+    pub callers: Vec<(String, FocusTotals)>,
+
+    // The functions called by the focused function, with the totals of the
+    // allocations made through each, largest first.
+    pub callees: Vec<(String, FocusTotals)>,
+}
+
+impl FocusTotals {
+    // Add the summary of a stack entry to these totals.
+    pub fn add(&mut self, summary: &trace::StackEntrySummary) {
+        self.maximum_size += summary.maximum_total;
+        self.held_size += summary.current_total;
+        self.total_blocks += summary.alloc_count;
+        self.total_bytes += summary.total_bytes;
+        self.unfreed_blocks += summary.alloc_count - summary.free_count;
+        self.first_time = rows::earliest_time(self.first_time, summary.first_time);
+        self.last_time = std::cmp::max(self.last_time, summary.last_time);
+    }
+
+    // These totals as a summary, from which computed columns are computed.
+    pub fn summary(&self) -> trace::StackEntrySummary {
+        trace::StackEntrySummary {
+            stackentry: 0,
+            current_total: self.held_size,
+            maximum_total: self.maximum_size,
+            alloc_count: self.total_blocks,
+            free_count: self.total_blocks - self.unfreed_blocks,
+            total_bytes: self.total_bytes,
+            first_time: self.first_time,
+            last_time: self.last_time,
+        }
+    }
+}
+
+// The function of a stack entry, or an empty string if unknown.
+pub fn entry_function(transaction: &mut trace::Transaction, entry: &trace::StackEntry) -> String {
+    match transaction.location(entry.location) {
+        Some(trace::Location {
+            function: Some(function),
+            ..
+        }) => rows::demangle_function_name(&function),
+        _ => String::new(),
+    }
+}
+
+// Sort the totals of functions, largest first.
+fn sorted_totals(totals: HashMap<String, FocusTotals>) -> Vec<(String, FocusTotals)> {
+    let mut totals: Vec<(String, FocusTotals)> = totals.into_iter().collect();
+    totals.sort_by(|(name_a, a), (name_b, b)| {
+        b.maximum_size
+            .cmp(&a.maximum_size)
+            .then(b.total_blocks.cmp(&a.total_blocks))
+            .then(name_a.cmp(name_b))
+    });
+
+    totals
+}
+
+impl FocusView {
+    // Gather the callers and callees of a function from the summarized call
+    // tree.  In an inverted tree, the parents of an entry are its callees
+    // rather than its callers.  Recursive calls are counted once, at the
+    // outermost appearance of the function on a path.
+    pub fn new(
+        transaction: &mut trace::Transaction,
+        function: &str,
+        inverted: bool,
+    ) -> Result<FocusView, Box<dyn Error>> {
+        let mut totals = FocusTotals::default();
+        let mut parents: HashMap<String, FocusTotals> = HashMap::new();
+        let mut children: HashMap<String, FocusTotals> = HashMap::new();
+
+        // Entries to visit, with the function of their parent, and whether
+        // the focused function appears above them.
+        let mut entries: Vec<(trace::StackEntry, Option<String>, bool)> = transaction
+            .root_stackentries()?
+            .into_iter()
+            .map(|root| (root, None, false))
+            .collect();
+        while let Some((entry, parent_function, below_focus)) = entries.pop() {
+            let summary = match transaction.summary(entry.id) {
+                Some(summary) => summary,
+                None => continue,
+            };
+            let name = entry_function(transaction, &entry);
+            let focused = name == function;
+            let child_entries = transaction.get_stackentry_children(entry.id)?;
+
+            if focused && !below_focus {
+                totals.add(&summary);
+                if let Some(parent_function) = parent_function {
+                    parents.entry(parent_function).or_default().add(&summary);
+                }
+                for child in &child_entries {
+                    if let Some(child_summary) = transaction.summary(child.id) {
+                        let child_function = entry_function(transaction, child);
+                        if child_function != function {
+                            children
+                                .entry(child_function)
+                                .or_default()
+                                .add(&child_summary);
+                        }
+                    }
+                }
+            }
+
+            for child in child_entries {
+                entries.push((child, Some(name.clone()), below_focus || focused));
+            }
+        }
+        parents.remove("");
+        children.remove("");
+
+        let (callers, callees) = if inverted {
+            (children, parents)
+        } else {
+            (parents, children)
+        };
+
+        Ok(FocusView {
+            function: function.to_string(),
+            totals,
+            callers: sorted_totals(callers),
+            callees: sorted_totals(callees),
+        })
+    }
+}
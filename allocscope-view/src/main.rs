@@ -16,19 +16,47 @@
     with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
+mod allocations;
+mod budget;
+mod columns;
 mod commandline;
+mod diff;
+mod export;
+mod fetch;
+mod flamegraph;
+mod flat;
+mod focus;
+mod mapping;
+mod modules;
 mod report;
 mod rows;
+mod search;
+mod serve;
+mod signals;
+mod source;
 mod summary;
+mod text;
+mod theme;
+mod timeline;
 mod trace;
+mod transform;
 mod ui;
+mod viewstate;
 
 use libc;
+use regex::Regex;
 use std::error::Error;
 
+// Remove the scratch database of summaries.
+fn remove_scratch_file(scratch_filename: &str) {
+    if let Err(err) = std::fs::remove_file(scratch_filename) {
+        eprintln!("Can't remove scratch file: {:?}", err);
+    }
+}
+
 // The main entry point for allocscope-view.
 fn main() -> Result<(), Box<dyn Error>> {
-    let args = commandline::CommandLineArguments::parse(&mut std::env::args())?;
+    let mut args = commandline::CommandLineArguments::parse(&mut std::env::args())?;
     if args.report_version {
         commandline::report_version();
         return Ok(());
@@ -37,22 +65,248 @@ fn main() -> Result<(), Box<dyn Error>> {
         commandline::show_help();
         return Ok(());
     }
+    rows::set_demangle_options(args.demangle);
+    report::set_number_options(args.numbers);
+    report::set_stable_output(args.stable);
+    columns::set_computed_columns(args.computed_columns.clone())?;
 
     let is_stdout_tty = unsafe { libc::isatty(libc::STDOUT_FILENO) != 0 };
-    let report_mode = args.report_mode || !is_stdout_tty;
+    let report_mode = args.report_mode
+        || args.address.is_some()
+        || args.export_format.is_some()
+        || args.diff_filename.is_some()
+        || args.baseline_filename.is_some()
+        || !args.assertions.is_empty()
+        || args.serve.is_some()
+        || !is_stdout_tty;
 
     let scratch_filename = format!("/tmp/trace-view-{}.scratch", std::process::id());
-    let mut trace = trace::Trace::new(&args.atrace_filename.unwrap(), &scratch_filename)?;
-    summary::summarize_allocations(&mut trace, !report_mode)?;
+    let trace_name = args.atrace_filename.clone().unwrap();
+    // The local copy of a trace read from stdin or fetched from a URL is
+    // removed when dropped, including when returning an error.
+    let fetched = if fetch::is_remote(&trace_name) {
+        let fetched_filename = format!("/tmp/trace-view-{}.atrace", std::process::id());
+        Some(fetch::fetch_trace(&trace_name, &fetched_filename)?)
+    } else {
+        None
+    };
+    let atrace_filename = match &fetched {
+        Some(fetched) => fetched.filename.clone(),
+        None => trace_name,
+    };
+    let mut trace = trace::Trace::new(&atrace_filename, &scratch_filename)?;
+    trace.select_session(args.session)?;
+    if let Some((start, end)) = &args.between {
+        args.time_range = summary::phase_range(&trace.phases()?, start, end)?;
+    }
 
-    if report_mode {
-        report::generate_report(trace)?;
+    // Cleared if an assertion or a regression check against the trace fails.
+    let mut checks_passed = true;
+    if args.failure_report {
+        report::generate_failure_report(&trace)?;
+    } else if let Some(address) = args.address {
+        report::generate_address_report(&trace, address, args.source_map.clone())?;
+    } else if args.live_report {
+        report::generate_live_report(&trace, args.time_range)?;
+    } else if args.leak_report {
+        report::generate_leak_report(&trace, args.time_range, args.source_map.clone())?;
+    } else if args.mapping_report {
+        report::generate_mapping_report(&trace)?;
+    } else if args.module_report {
+        modules::generate_module_report(&trace, args.time_range, args.value_style)?;
+    } else if args.info_report {
+        report::generate_info_report(
+            &trace,
+            args.filter.as_ref().map(|filter| filter.as_str()),
+            args.time_range,
+        )?;
     } else {
-        ui::main_loop(trace, args.report_perf);
+        let mut tree_builder = transform::TreeBuilder::new(args.group_mode);
+        if args.group_mode == transform::GroupMode::Spawner {
+            tree_builder.set_thread_creators(trace.thread_creators()?);
+        }
+        tree_builder.set_inverted(args.inverted);
+        // The UI restores its state from when the trace was last viewed,
+        // including the filter, unless a filter is given.  The state of
+        // traces which aren't local files isn't kept.
+        let view_state = if report_mode || fetched.is_some() {
+            None
+        } else {
+            viewstate::ViewState::load(&atrace_filename)
+        };
+        let saved_filter = view_state
+            .as_ref()
+            .and_then(|state| state.filter.as_ref())
+            .and_then(|filter| Regex::new(filter).ok());
+        tree_builder.set_filter(args.filter.clone().or(saved_filter));
+        tree_builder.set_fold_rules(args.fold_rules.clone());
+        tree_builder.set_collapse_recursion(args.collapse_recursion);
+        if args.group_modules {
+            tree_builder.set_modules(Some(trace.modules()?));
+        }
+        // Summaries of the whole of the trace's own callstacks are saved to
+        // the trace, so that they needn't be computed again when it is next
+        // viewed.  The totals of traces made with --aggregate are quickly
+        // summarized, and may still be changing.  Local copies of traces
+        // are removed on exit, so their summaries aren't saved, nor are
+        // those of traces still being recorded, which the tracer is writing.
+        let saveable = tree_builder.is_identity()
+            && fetched.is_none()
+            && !args.follow
+            && args.time_range == summary::TimeRange::all()
+            && !trace.has_capability(trace::CAPABILITY_AGGREGATE);
+        let saved_event_id = if saveable {
+            trace.load_saved_summary()?
+        } else {
+            None
+        };
+        let max_event_id = trace.max_event_id()?;
+        let summary_position = match saved_event_id {
+            Some(event_id) if event_id == max_event_id => summary::SummaryPosition {
+                event_id,
+                stackentry_id: trace.max_stackentry_id()?,
+            },
+            _ => {
+                // Summarization of a large trace can be stopped with Ctrl-C,
+                // saving the summary so far to be resumed when the trace is
+                // next viewed.
+                if saveable && !report_mode {
+                    match saved_event_id {
+                        Some(event_id) => println!(
+                            "Resuming summary from event {} of {}  (Ctrl-C to stop)",
+                            event_id, max_event_id
+                        ),
+                        None => println!("Summarizing trace  (Ctrl-C to stop)"),
+                    }
+                }
+                signals::block_term_signals()?;
+                let position = summary::extend_summary(
+                    &mut trace,
+                    &mut tree_builder,
+                    args.time_range,
+                    summary::SummaryPosition {
+                        event_id: saved_event_id.unwrap_or(0),
+                        stackentry_id: 0,
+                    },
+                    !report_mode,
+                )?;
+                // Saving is best effort, as the trace may not be writable.
+                let saved = saveable
+                    && trace
+                        .save_summary(&atrace_filename, position.event_id)
+                        .is_ok();
+                if signals::is_term_signal_pending() {
+                    if position.event_id < max_event_id {
+                        if saved {
+                            eprintln!(
+                                "Summary stopped at event {} of {}, to be resumed when {} is next viewed",
+                                position.event_id, max_event_id, atrace_filename
+                            );
+                        } else {
+                            eprintln!("Summary stopped");
+                        }
+                    }
+                    remove_scratch_file(&scratch_filename);
+                    drop(fetched);
+                    std::process::exit(130);
+                }
+                signals::unblock_term_signals()?;
+                position
+            }
+        };
+
+        if !args.assertions.is_empty() {
+            checks_passed = budget::check_assertions(&trace, &args.assertions)?;
+        } else if let Some(diff_filename) = &args.diff_filename {
+            diff::generate_diff_report(&trace, diff_filename, args.group_mode, args.time_range)?;
+        } else if let Some(baseline_filename) = &args.baseline_filename {
+            let no_regressions = diff::check_regressions(
+                &trace,
+                baseline_filename,
+                args.group_mode,
+                args.time_range,
+                args.regression_threshold.unwrap_or(0.0),
+            )?;
+            checks_passed = no_regressions || args.regression_threshold.is_none();
+        } else if let Some(export_format) = args.export_format {
+            export::export(
+                &trace,
+                &atrace_filename,
+                export_format,
+                args.export_weight,
+                args.export_filename.as_deref(),
+            )?;
+        } else if args.flat_report {
+            flat::generate_flat_report(&trace, args.value_style)?;
+        } else if args.lifetime_report {
+            report::generate_lifetime_report(
+                &trace,
+                &mut tree_builder,
+                args.time_range,
+                rows::RowLimits {
+                    prune: args.prune,
+                    top: args.top,
+                    max_depth: args.max_depth,
+                },
+                args.value_style,
+            )?;
+        } else if let Some(address) = &args.serve {
+            serve::serve(&trace, &atrace_filename, address)?;
+        } else if report_mode {
+            let row_limits = rows::RowLimits {
+                prune: args.prune,
+                top: args.top,
+                max_depth: args.max_depth,
+            };
+            let sort_order = rows::SortOrder {
+                mode: args.sort_mode.unwrap_or(rows::SortMode::Bytes),
+                reversed: false,
+            };
+            match args.report_format {
+                report::ReportFormat::Jsonl => report::generate_jsonl_report(
+                    &trace,
+                    args.time_range,
+                    args.rates,
+                    args.times,
+                    row_limits,
+                    sort_order,
+                )?,
+                report::ReportFormat::Markdown => report::generate_markdown_report(
+                    &trace,
+                    args.time_range,
+                    report::Columns {
+                        rates: args.rates,
+                        times: args.times,
+                        churn: args.churn,
+                        addresses: args.addresses,
+                    },
+                    row_limits,
+                    args.value_style,
+                    sort_order,
+                )?,
+                report::ReportFormat::Text => report::generate_report(
+                    trace,
+                    args.time_range,
+                    report::Columns {
+                        rates: args.rates,
+                        times: args.times,
+                        churn: args.churn,
+                        addresses: args.addresses,
+                    },
+                    row_limits,
+                    args.value_style,
+                    sort_order,
+                )?,
+            }
+        } else {
+            ui::main_loop(trace, tree_builder, &args, summary_position, view_state);
+        }
     }
 
-    if let Err(err) = std::fs::remove_file(&scratch_filename) {
-        eprintln!("Can't remove scratch file: {:?}", err);
+    remove_scratch_file(&scratch_filename);
+    drop(fetched);
+    if !checks_passed {
+        std::process::exit(1);
     }
 
     Ok(())
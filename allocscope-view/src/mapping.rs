@@ -0,0 +1,168 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::trace;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+
+// A range of the traced process's address space which is mapped.
+struct MappedRange {
+    // The address following the end of the range.
+    end: u64,
+
+    // The kind of memory backing the range.
+    kind: String,
+
+    // The path of the file mapped, if any.
+    path: Option<String>,
+}
+
+// Totals of mapped memory of a single kind, or from a single file.
+#[derive(Clone, Debug, Default)]
+pub struct MappedTotal {
+    // The number of bytes mapped at the end of the trace.
+    pub current: u64,
+
+    // The largest number of bytes mapped at once.
+    pub peak: u64,
+}
+
+// Mapped memory at the end of the trace, by kind and by file.
+#[derive(Debug, Default)]
+pub struct MappingSummary {
+    // Totals by the kind of memory mapped.
+    pub by_kind: BTreeMap<String, MappedTotal>,
+
+    // Totals by the path of the file mapped.
+    pub by_path: HashMap<String, MappedTotal>,
+}
+
+// Replays the mapping changes of a trace to track which ranges are mapped.
+struct MappingReplay {
+    // The mapped ranges, by their first address.
+    ranges: BTreeMap<u64, MappedRange>,
+
+    // The range most recently unmapped, if the following change hasn't yet
+    // been applied.
+    last_unmapped: Option<MappedRange>,
+
+    // The running totals.
+    summary: MappingSummary,
+}
+
+impl MappingReplay {
+    // Adjust the totals for a range by a number of bytes.
+    fn adjust(&mut self, range: &MappedRange, bytes: u64, add: bool) {
+        let mut totals = vec![self.summary.by_kind.entry(range.kind.clone()).or_default()];
+        if let Some(path) = &range.path {
+            totals.push(self.summary.by_path.entry(path.clone()).or_default());
+        }
+
+        for total in totals {
+            if add {
+                total.current += bytes;
+                total.peak = std::cmp::max(total.peak, total.current);
+            } else {
+                total.current = total.current.saturating_sub(bytes);
+            }
+        }
+    }
+
+    // Remove a span of addresses from the mapped ranges, keeping the parts
+    // of ranges outside of the span.  Returns the last range removed from,
+    // so that a range moved by mremap can keep its kind.
+    fn unmap(&mut self, begin: u64, end: u64) -> Option<MappedRange> {
+        let overlapping: Vec<u64> = self
+            .ranges
+            .range(..end)
+            .rev()
+            .take_while(|(_, range)| range.end > begin)
+            .map(|(start, _)| *start)
+            .collect();
+
+        let mut last_removed = None;
+        for start in overlapping {
+            let range = self.ranges.remove(&start).unwrap();
+            let removed_begin = std::cmp::max(start, begin);
+            let removed_end = std::cmp::min(range.end, end);
+            self.adjust(&range, removed_end - removed_begin, false);
+
+            if start < begin {
+                self.ranges.insert(
+                    start,
+                    MappedRange {
+                        end: begin,
+                        kind: range.kind.clone(),
+                        path: range.path.clone(),
+                    },
+                );
+            }
+            if range.end > end {
+                self.ranges.insert(
+                    end,
+                    MappedRange {
+                        end: range.end,
+                        kind: range.kind.clone(),
+                        path: range.path.clone(),
+                    },
+                );
+            }
+            last_removed = Some(range);
+        }
+
+        last_removed
+    }
+
+    // Apply a single change to the mapped ranges.  A new mapping replaces
+    // any ranges it overlaps.  A range moved by mremap is recorded as
+    // unmapping the old range immediately before mapping the new one, so a
+    // new mapping without a kind takes the kind of the last range unmapped.
+    fn apply(&mut self, change: &trace::MappingChange) {
+        let end = change.address.saturating_add(change.size);
+        let removed = self.unmap(change.address, end);
+        if !change.mapped {
+            self.last_unmapped = removed;
+            return;
+        }
+
+        let (kind, path) = match (&change.kind, self.last_unmapped.take()) {
+            (Some(kind), _) => (kind.clone(), change.path.clone()),
+            (None, Some(moved)) => (moved.kind, moved.path),
+            (None, None) => ("unknown".to_string(), None),
+        };
+        let range = MappedRange { end, kind, path };
+        self.adjust(&range, change.size, true);
+        self.ranges.insert(change.address, range);
+    }
+}
+
+// Summarize the memory mapped by the traced process from its mapping
+// changes, in the order they were made.
+pub fn summarize(changes: &[trace::MappingChange]) -> MappingSummary {
+    let mut replay = MappingReplay {
+        ranges: BTreeMap::new(),
+        last_unmapped: None,
+        summary: MappingSummary::default(),
+    };
+
+    for change in changes {
+        replay.apply(change);
+    }
+
+    replay.summary
+}
@@ -0,0 +1,197 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::report;
+use crate::summary;
+use crate::text;
+use crate::trace;
+use std::collections::HashMap;
+use std::error::Error;
+
+// The allocations attributed to a single module.
+#[derive(Default)]
+struct ModuleTotals {
+    // The bytes currently allocated, while scanning the events.
+    current: u64,
+
+    // The most bytes allocated at once.
+    peak: u64,
+
+    // The bytes allocated over the span of the trace.
+    total_bytes: u64,
+
+    // The blocks allocated over the span of the trace.
+    blocks: u64,
+}
+
+// The name of the module containing the innermost frame of a callstack, or
+// a label if the frame's address isn't in a known module or there is no
+// callstack.
+fn callstack_module(
+    transaction: &mut trace::Transaction,
+    modules: &[trace::Module],
+    callstack: Option<trace::StackEntryId>,
+) -> Result<String, Box<dyn Error>> {
+    let callstack = match callstack {
+        Some(callstack) => callstack,
+        None => return Ok("[no callstack]".to_string()),
+    };
+    let stackentry = transaction
+        .original_stackentry(callstack)
+        .ok_or("missing stackentry")?;
+    let location = transaction
+        .original_location(stackentry.location)
+        .ok_or("missing location")?;
+
+    Ok(match trace::module_at(modules, location.address) {
+        Some(module) => module
+            .filename
+            .rsplit('/')
+            .next()
+            .unwrap_or(&module.filename)
+            .to_string(),
+        None => "[unknown]".to_string(),
+    })
+}
+
+// Total the allocations of each module over a span of the trace.  Each
+// allocation is attributed to the module containing the innermost frame of
+// its callstack, the code which called the allocator.
+fn collect_modules(
+    trace: &trace::Trace,
+    time_range: summary::TimeRange,
+) -> Result<HashMap<String, ModuleTotals>, Box<dyn Error>> {
+    if trace.has_capability(trace::CAPABILITY_AGGREGATE) {
+        Err("modules aren't reported for traces made with --aggregate")?;
+    }
+    let mut transaction = trace::Transaction::new(trace)?;
+    let modules = trace.modules()?;
+    let sample_periods = trace.sample_periods()?;
+    let max_event_id = trace.max_event_id()?;
+
+    let mut totals: HashMap<String, ModuleTotals> = HashMap::new();
+    let mut callstack_modules: HashMap<Option<trace::StackEntryId>, String> = HashMap::new();
+
+    // The weighted size and module of each allocated block, by address.
+    let mut blocks: HashMap<u64, (u64, String)> = HashMap::new();
+    for event_id in 1..=max_event_id {
+        let event = match transaction.event(event_id) {
+            Some(event) => event,
+            None => continue,
+        };
+        if event.time > time_range.end {
+            break;
+        }
+        if !time_range.contains(event.time) {
+            continue;
+        }
+
+        if event.allocation {
+            let module = match callstack_modules.get(&event.callstack) {
+                Some(module) => module.clone(),
+                None => {
+                    let module = callstack_module(&mut transaction, &modules, event.callstack)?;
+                    callstack_modules.insert(event.callstack, module.clone());
+                    module
+                }
+            };
+            let weight = summary::sample_period_at(&sample_periods, event.time);
+            let size = event.size.unwrap_or(0) * weight;
+
+            let module_totals = totals.entry(module.clone()).or_default();
+            module_totals.current += size;
+            module_totals.peak = std::cmp::max(module_totals.peak, module_totals.current);
+            module_totals.total_bytes += size;
+            module_totals.blocks += weight;
+            blocks.insert(event.address, (size, module));
+        } else if let Some((size, module)) = blocks.remove(&event.address) {
+            if let Some(module_totals) = totals.get_mut(&module) {
+                module_totals.current = module_totals.current.saturating_sub(size);
+            }
+        }
+    }
+
+    Ok(totals)
+}
+
+// Generate a report to stdout of the allocations of each module of the
+// traced process, with the most bytes allocated at once first.
+pub fn generate_module_report(
+    trace: &trace::Trace,
+    time_range: summary::TimeRange,
+    value_style: report::ValueStyle,
+) -> Result<(), Box<dyn Error>> {
+    let free_tracking = trace.has_capability(trace::CAPABILITY_FREE_TRACKING);
+    let modules = collect_modules(trace, time_range)?;
+
+    let mut modules: Vec<(String, ModuleTotals)> = modules.into_iter().collect();
+    modules.sort_by(|(a_name, a), (b_name, b)| {
+        b.peak
+            .cmp(&a.peak)
+            .then(b.total_bytes.cmp(&a.total_bytes))
+            .then(a_name.cmp(b_name))
+    });
+    let largest = modules
+        .iter()
+        .map(|(_, totals)| totals.total_bytes)
+        .max()
+        .unwrap_or(0);
+    let value_format = report::ValueFormat::new(value_style, largest);
+
+    report::print_title("module report");
+    if modules.is_empty() {
+        println!("No allocations");
+        return Ok(());
+    }
+
+    println!("Allocations by the module containing the code which called the");
+    println!("allocator, with the most bytes allocated at once (PEAK), the bytes");
+    println!("allocated over the trace (BYTES), and the bytes never freed (HELD).");
+    println!();
+    println!(
+        "{} {} {} {}   Module",
+        value_format.header("PEAK"),
+        value_format.header("BYTES"),
+        value_format.header("BLOCK"),
+        value_format.header("HELD"),
+    );
+    // Rows are truncated to fit a terminal, as in the report of the call
+    // tree.
+    let line_width = text::terminal_width();
+    for (module, totals) in modules {
+        let held = if free_tracking {
+            value_format.value(totals.current, 1024)
+        } else {
+            value_format.header("-")
+        };
+        let line = format!(
+            "{} {} {} {}   {}",
+            value_format.value(totals.peak, 1024),
+            value_format.value(totals.total_bytes, 1024),
+            value_format.value(totals.blocks, 1000),
+            held,
+            module,
+        );
+        match line_width {
+            Some(width) => println!("{}", text::truncate(&line, width)),
+            None => println!("{}", line),
+        }
+    }
+
+    Ok(())
+}
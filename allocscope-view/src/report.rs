@@ -16,33 +16,510 @@
     with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
+use crate::allocations;
+use crate::columns;
+use crate::export;
+use crate::mapping;
 use crate::rows;
+use crate::source;
+use crate::summary;
+use crate::text;
 use crate::trace;
+use crate::transform;
 use std::collections;
 use std::error::Error;
+use std::io::Write;
+use std::sync;
+use std::time;
 
-// Format a large value for printing in a five column space, using
-// an appropriate suffix.
+// The format of the report of the call tree.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReportFormat {
+    // Text, with the call tree drawn in ASCII beneath a description of the
+    // trace.
+    Text,
+
+    // A JSON object on a line of its own for each entry of the call tree,
+    // with entries in the order of the text report.
+    Jsonl,
+
+    // Markdown, with a table of the call tree beneath each root in a
+    // collapsible section, as rendered by GitHub.
+    Markdown,
+}
+
+impl ReportFormat {
+    // Parse a report format from its commandline name.
+    pub fn parse(name: &str) -> Result<ReportFormat, Box<dyn Error>> {
+        match name {
+            "jsonl" => Ok(ReportFormat::Jsonl),
+            "markdown" => Ok(ReportFormat::Markdown),
+            "text" => Ok(ReportFormat::Text),
+            _ => Err(format!("unknown report format: {}", name).into()),
+        }
+    }
+}
+
+// The style in which the values of the call tree's columns are written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ValueStyle {
+    // Abbreviated with a suffix, to fit 'table_value_width' characters.
+    Abbreviated,
+
+    // Written in full.
+    Exact,
+
+    // Written in full, with thousands separated.
+    Separated,
+}
+
+// The style and width in which the values of the call tree's columns are
+// written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ValueFormat {
+    // The style of the values.
+    pub style: ValueStyle,
+
+    // The width of each column, wide enough for the largest value.
+    pub width: usize,
+}
+
+impl ValueFormat {
+    // Values written in 'style', in columns wide enough for values as large
+    // as 'largest' and for their headers.
+    pub fn new(style: ValueStyle, largest: u64) -> ValueFormat {
+        let mut format = ValueFormat { style, width: 5 };
+        format.width = std::cmp::max(format.value(largest, 1024).len(), 5);
+
+        format
+    }
+
+    // Format a value, abbreviated with a suffix for powers of 'divisor' if
+    // values aren't exact.
+    pub fn value(&self, value: u64, divisor: u64) -> String {
+        match self.style {
+            ValueStyle::Abbreviated => format_table_value(value, divisor),
+            ValueStyle::Exact => format!("{:>width$}", value, width = self.width),
+            ValueStyle::Separated => {
+                format!("{:>width$}", separate_thousands(value), width = self.width)
+            }
+        }
+    }
+
+    // Format the header of a column, aligned with its values.
+    pub fn header(&self, name: &str) -> String {
+        format!("{:>width$}", name, width = self.width)
+    }
+
+    // The width of the four columns of values, with the spaces between
+    // them.
+    pub fn columns_width(&self) -> usize {
+        self.width * 4 + 3
+    }
+}
+
+// The units in which abbreviated byte counts are written.  Counts of
+// blocks are always abbreviated in powers of 1000.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ByteUnits {
+    // Powers of 1024, with the same suffixes as counts, as in '12k'.
+    Compact,
+
+    // Powers of 1000, as in '12kB'.
+    Si,
+
+    // Powers of 1024, as in '12KiB'.
+    Iec,
+}
+
+// The options with which values are written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NumberOptions {
+    // The units of abbreviated byte counts.
+    pub units: ByteUnits,
+
+    // The number of decimal places of abbreviated values.
+    pub precision: usize,
+
+    // The character separating thousands in values written in full.
+    pub separator: char,
+}
+
+impl Default for NumberOptions {
+    fn default() -> NumberOptions {
+        NumberOptions {
+            units: ByteUnits::Compact,
+            precision: 0,
+            separator: ',',
+        }
+    }
+}
+
+// The most decimal places of abbreviated values.
+const MAX_PRECISION: usize = 3;
+
+impl NumberOptions {
+    // Apply a comma separated list of options, such as 'iec' or
+    // 'precision=1'.
+    pub fn apply(&mut self, spec: &str) -> Result<(), Box<dyn Error>> {
+        for option in spec.split(',').map(|option| option.trim()) {
+            match option.split_once('=') {
+                None => match option {
+                    "" => {}
+                    "compact" => self.units = ByteUnits::Compact,
+                    "iec" => self.units = ByteUnits::Iec,
+                    "si" => self.units = ByteUnits::Si,
+                    _ => Err(format!("invalid number option: {}", option))?,
+                },
+                Some(("precision", digits)) => match digits.parse() {
+                    Ok(precision) if precision <= MAX_PRECISION => self.precision = precision,
+                    _ => Err(format!("invalid precision: {}", digits))?,
+                },
+                Some(("separator", name)) => {
+                    self.separator = match name {
+                        "apostrophe" => '\'',
+                        "comma" => ',',
+                        "period" => '.',
+                        "space" => ' ',
+                        "underscore" => '_',
+                        _ => Err(format!("invalid separator: {}", name))?,
+                    }
+                }
+                Some(_) => Err(format!("invalid number option: {}", option))?,
+            }
+        }
+
+        Ok(())
+    }
+
+    // The name of the units of byte counts, as given in the options.
+    pub fn units_name(&self) -> &'static str {
+        match self.units {
+            ByteUnits::Compact => "compact",
+            ByteUnits::Si => "si",
+            ByteUnits::Iec => "iec",
+        }
+    }
+}
+
+// The options with which values are written, set once when the viewer
+// starts.
+static NUMBER_OPTIONS: sync::OnceLock<NumberOptions> = sync::OnceLock::new();
+
+// Set the options with which values are written for the remainder of the
+// run.  Values written before this use the default options.
+pub fn set_number_options(options: NumberOptions) {
+    let _ = NUMBER_OPTIONS.set(options);
+}
+
+// The options with which values are written.
+pub fn number_options() -> NumberOptions {
+    NUMBER_OPTIONS.get().copied().unwrap_or_default()
+}
+
+// If set, reports are written identically for the same trace.
+static STABLE_OUTPUT: sync::OnceLock<bool> = sync::OnceLock::new();
+
+// Write reports identically for the same trace for the remainder of the
+// run, without the version of the viewer and with values in full, so that
+// they can be compared with golden files or between runs.
+pub fn set_stable_output(stable: bool) {
+    let _ = STABLE_OUTPUT.set(stable);
+}
+
+// True if reports are written identically for the same trace.
+pub fn stable_output() -> bool {
+    STABLE_OUTPUT.get().copied().unwrap_or(false)
+}
+
+// Print the title of a report, with the version of the viewer, unless
+// writing stable reports, which omit it as it differs between builds.
+pub fn print_title(title: &str) {
+    if stable_output() {
+        return;
+    }
+
+    println!("allocscope {} {}", env!("CARGO_PKG_VERSION"), title);
+    println!("https://allocscope.com/support");
+    println!();
+}
+
+// The suffixes of abbreviated counts, for each power of 1000.
+const COUNT_SUFFIXES: [&str; 5] = ["k", "M", "G", "T", "P"];
+
+// The suffixes of abbreviated byte counts in SI units.
+const SI_SUFFIXES: [&str; 5] = ["kB", "MB", "GB", "TB", "PB"];
+
+// The suffixes of abbreviated byte counts in IEC units.
+const IEC_SUFFIXES: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+
+// The width of the values written by 'format_table_value', which is five
+// columns with the default options.
+pub fn table_value_width() -> usize {
+    let options = number_options();
+    let suffix_width = match options.units {
+        ByteUnits::Compact => 1,
+        ByteUnits::Si => 2,
+        ByteUnits::Iec => 3,
+    };
+    let decimals_width = if options.precision > 0 {
+        options.precision + 1
+    } else {
+        0
+    };
+
+    4 + decimals_width + suffix_width
+}
+
+// Format the header of a column of values written by 'format_table_value'.
+pub fn table_header(name: &str) -> String {
+    format!("{:>width$}", name, width = table_value_width())
+}
+
+// Format the values of the computed columns for a summary, each with a
+// leading space.
+pub fn format_computed_values(summary: &trace::StackEntrySummary) -> String {
+    columns::computed_columns()
+        .iter()
+        .enumerate()
+        .map(|(index, column)| format!(" {}", column.value(columns::evaluate(index, summary))))
+        .collect()
+}
+
+// The optional columns of the call tree shown.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Columns {
+    // If true, show the rates of allocation.
+    pub rates: bool,
+
+    // If true, show when each entry first allocated and was last active.
+    pub times: bool,
+
+    // If true, show the total bytes allocated, including those freed.
+    pub churn: bool,
+
+    // If true, show the instruction address of each entry and the module
+    // containing it.
+    pub addresses: bool,
+}
+
+// Format a value with its thousands separated, by commas unless the
+// number options choose another separator.
+pub fn separate_thousands(value: u64) -> String {
+    let digits = value.to_string();
+    let mut separated = String::new();
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(3) {
+            separated.push(number_options().separator);
+        }
+        separated.push(digit);
+    }
+
+    separated
+}
+
+// Format a large value for printing in a column of 'table_value_width'
+// columns, abbreviated with a suffix for powers of 'divisor'.  A divisor of
+// 1024 marks a count of bytes, written in the units of the number options.
+// Stable reports write values in full, widening the column as needed.
 pub fn format_table_value(value: u64, divisor: u64) -> String {
-    if value < 99999 {
-        format!("{:5}", value)
-    } else if value / divisor < 9999 {
-        format!("{:4}k", value / divisor)
-    } else if value / divisor / divisor < 9999 {
-        format!("{:4}M", value / divisor / divisor)
-    } else if value / divisor / divisor / divisor < 9999 {
-        format!("{:4}G", value / divisor / divisor / divisor)
-    } else if value / divisor / divisor / divisor / divisor < 9999 {
-        format!("{:4}T", value / divisor / divisor / divisor / divisor)
+    let options = number_options();
+    let width = table_value_width();
+    if stable_output() || value < 10u64.pow(width as u32) - 1 {
+        return format!("{:>width$}", value);
+    }
+
+    let (divisor, suffixes) = match options.units {
+        ByteUnits::Si if divisor == 1024 => (1000, SI_SUFFIXES),
+        ByteUnits::Iec if divisor == 1024 => (1024, IEC_SUFFIXES),
+        _ => (divisor, COUNT_SUFFIXES),
+    };
+    // Values are scaled in fixed point, truncated to the precision shown.
+    let scale = 10u128.pow(options.precision as u32);
+    let mut power = divisor as u128;
+    let mut index = 0;
+    while value as u128 / power >= 9999 && index < suffixes.len() - 1 {
+        power *= divisor as u128;
+        index += 1;
+    }
+    let scaled = value as u128 * scale / power;
+    let mantissa = if options.precision > 0 {
+        format!(
+            "{}.{:0precision$}",
+            scaled / scale,
+            scaled % scale,
+            precision = options.precision
+        )
+    } else {
+        scaled.to_string()
+    };
+
+    format!("{:>width$}", mantissa + suffixes[index])
+}
+
+// Format a span of the trace for display.
+pub fn format_time_range(time_range: summary::TimeRange) -> String {
+    let start = time::Duration::from_nanos(time_range.start);
+    if time_range.end == u64::MAX {
+        format!("from {:.2?}", start)
     } else {
         format!(
-            "{:4}P",
-            value / divisor / divisor / divisor / divisor / divisor
+            "from {:.2?} to {:.2?}",
+            start,
+            time::Duration::from_nanos(time_range.end)
         )
     }
 }
 
+// Format the bytes still allocated at the end of the trace for a row, or a
+// placeholder if the trace didn't record frees.
+pub fn format_held_value(
+    entry: &rows::StackEntryRow,
+    free_tracking: bool,
+    value_format: ValueFormat,
+) -> String {
+    if free_tracking {
+        value_format.value(entry.held_size, 1024)
+    } else {
+        value_format.header("-")
+    }
+}
+
+// Format the rates of allocation, in blocks and bytes per second, over a
+// span of the trace given in nanoseconds.  Placeholders are shown if events
+// weren't timed.
+pub fn format_rate_values(total_blocks: u64, total_bytes: u64, span: u64) -> String {
+    if span == 0 {
+        return format!("{} {}", table_header("-"), table_header("-"));
+    }
+
+    let per_second = |value: u64| (value as u128 * 1_000_000_000 / span as u128) as u64;
+    format!(
+        "{} {}",
+        format_table_value(per_second(total_blocks), 1000),
+        format_table_value(per_second(total_bytes), 1024)
+    )
+}
+
+// Format a time since the start of the trace in six columns, in seconds,
+// or in minutes for long traces.  A placeholder is shown if the time isn't
+// known.
+pub fn format_time_value(time: Option<u64>) -> String {
+    let millis = match time {
+        Some(time) => time / 1_000_000,
+        None => return format!("{:>6}", "-"),
+    };
+
+    let formatted = if millis < 10_000 {
+        format!("{}.{:03}s", millis / 1000, millis % 1000)
+    } else if millis < 100_000 {
+        format!("{}.{:02}s", millis / 1000, millis % 1000 / 10)
+    } else if millis < 1_000_000 {
+        format!("{}.{}s", millis / 1000, millis % 1000 / 100)
+    } else if millis < 100_000_000 {
+        format!("{}s", millis / 1000)
+    } else {
+        format!("{}m", millis / 60_000)
+    };
+    format!("{:>6}", formatted)
+}
+
+// Format the times of the first allocation and the last allocation or
+// free of a row.
+pub fn format_time_values(first_time: Option<u64>, last_time: Option<u64>) -> String {
+    format!(
+        "{} {}",
+        format_time_value(first_time),
+        format_time_value(last_time)
+    )
+}
+
+// The number of columns of the bar showing a row's share of the total.
+pub const BAR_WIDTH: usize = 10;
+
+// Format a bar of block characters, in eighths of a column, as long as a
+// value's share of a total.
+pub fn format_bar(value: u64, total: u64) -> String {
+    const PARTIAL_BLOCKS: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+
+    let eighths = if total > 0 {
+        (std::cmp::min(value, total) as u128 * (BAR_WIDTH * 8) as u128 / total as u128) as usize
+    } else {
+        0
+    };
+    let mut bar = "█".repeat(eighths / 8);
+    if eighths % 8 > 0 {
+        bar.push(PARTIAL_BLOCKS[eighths % 8]);
+    }
+
+    format!("{:<BAR_WIDTH$}", bar)
+}
+
+// Format the count of unfreed blocks for a row, or a placeholder if the
+// trace didn't record frees.
+pub fn format_leaks_value(
+    entry: &rows::StackEntryRow,
+    free_tracking: bool,
+    value_format: ValueFormat,
+) -> String {
+    if free_tracking {
+        value_format.value(entry.unfreed_blocks, 1000)
+    } else {
+        value_format.header("-")
+    }
+}
+
+// The width of the address column, enough for any user space address.
+const ADDRESS_WIDTH: usize = 14;
+
+// The width of the module column, beyond which module names are cut off.
+const MODULE_WIDTH: usize = 20;
+
+// The width of the address and module columns, with the space between
+// them.
+pub const ADDRESS_COLUMNS_WIDTH: usize = ADDRESS_WIDTH + 1 + MODULE_WIDTH;
+
+// The header of the address and module columns.
+pub fn address_columns_header() -> String {
+    format!(
+        "{:>ADDRESS_WIDTH$} {}",
+        "ADDRESS",
+        text::fit("MODULE", MODULE_WIDTH)
+    )
+}
+
+// Format the instruction address of an entry and the file name of the
+// module containing it.  Rows standing for pruned entries have neither.
+pub fn format_address_values(entry: &rows::StackEntryRow, modules: &[trace::Module]) -> String {
+    if entry.pruned > 0 {
+        return " ".repeat(ADDRESS_COLUMNS_WIDTH);
+    }
+
+    let module = trace::module_at(modules, entry.address)
+        .and_then(|module| module.filename.rsplit('/').next())
+        .unwrap_or("unknown");
+    format!(
+        "{:>ADDRESS_WIDTH$} {}",
+        format!("0x{:x}", entry.address),
+        text::fit(module, MODULE_WIDTH)
+    )
+}
+
 // Format the name of a function, using ASCII to indicate the call tree.
+// Format the function of a row, with its offset, or its address if the
+// function isn't known.
+fn format_function_name(entry: &rows::StackEntryRow) -> String {
+    if entry.function.len() > 0 {
+        if entry.offset > 0 {
+            format!("{} + 0x{:x}", entry.function, entry.offset)
+        } else {
+            entry.function.clone()
+        }
+    } else {
+        format!("0x{:x}", entry.address)
+    }
+}
+
 pub fn format_function_tree_row(
     collapsed: Option<&collections::HashSet<trace::StackEntryId>>,
     entry: &rows::StackEntryRow,
@@ -61,15 +538,7 @@ pub fn format_function_tree_row(
             };
     }
 
-    let function_name = if entry.function.len() > 0 {
-        if entry.offset > 0 {
-            format!("{} + 0x{:x}", entry.function, entry.offset)
-        } else {
-            entry.function.clone()
-        }
-    } else {
-        format!("0x{:x}", entry.address)
-    };
+    let function_name = format_function_name(entry);
 
     format!(
         "{}{} {}",
@@ -80,7 +549,7 @@ pub fn format_function_tree_row(
                 None => false,
             };
 
-            if entry_collapsed {
+            if entry_collapsed || entry.rolled_up {
                 "#"
             } else {
                 "|"
@@ -92,29 +561,1421 @@ pub fn format_function_tree_row(
     )
 }
 
+// Format the name of a function at a location, falling back to the address
+// when the function is unknown.
+fn format_location(location: &trace::Location) -> String {
+    match &location.function {
+        Some(function) if !function.is_empty() => match location.offset {
+            Some(offset) if offset > 0 => format!("{} + 0x{:x}", function, offset),
+            _ => function.clone(),
+        },
+        _ => format!("0x{:x}", location.address),
+    }
+}
+
+// Generate a report of failed allocations to stdout, listing the callstack
+// of each call site at which allocations failed.
+pub fn generate_failure_report(trace: &trace::Trace) -> Result<(), Box<dyn Error>> {
+    let failures = trace.failure_summaries()?;
+    let mut transaction = trace::Transaction::new(trace)?;
+
+    print_title("allocation failure report");
+    if failures.is_empty() {
+        println!("No failed allocations");
+        return Ok(());
+    }
+
+    // In stable reports, failures at equal counts are ordered by their
+    // callstacks, rather than by the order in which they were recorded.
+    let mut failures: Vec<(Vec<String>, trace::FailureSummary)> = failures
+        .into_iter()
+        .map(|failure| {
+            let labels = stable_callstack_labels(&mut transaction, failure.callstack)?;
+            Ok((labels, failure))
+        })
+        .collect::<Result<_, Box<dyn Error>>>()?;
+    failures.sort_by(|(a_labels, a), (b_labels, b)| {
+        b.count
+            .cmp(&a.count)
+            .then(b.bytes.cmp(&a.bytes))
+            .then(a_labels.cmp(b_labels))
+    });
+
+    println!(
+        "{} {}   Callstack",
+        table_header("COUNT"),
+        table_header("BYTES")
+    );
+    for (_, failure) in failures {
+        let prefix = format!(
+            "{} {}  ",
+            format_table_value(failure.count, 1000),
+            format_table_value(failure.bytes, 1024)
+        );
+
+        print_callstack(&mut transaction, &prefix, failure.callstack)?;
+        println!();
+    }
+
+    Ok(())
+}
+
+// Blocks still allocated from a single callstack.
+struct LiveAllocations {
+    // The leaf stack entry of the callstack.
+    callstack: Option<trace::StackEntryId>,
+
+    // The number of blocks.
+    count: u64,
+
+    // The total size of the blocks, in bytes.
+    bytes: u64,
+
+    // The time the oldest of the blocks was allocated, in nanoseconds since
+    // the start of the trace.
+    oldest: u64,
+}
+
+// The blocks still allocated at the end of the trace, or at the end of a
+// span of it, grouped by the callstack which allocated them, with the
+// callstacks holding the most bytes first.  Sampled blocks are counted as
+// the number of blocks they stand in for.
+fn live_allocations(
+    trace: &trace::Trace,
+    transaction: &mut trace::Transaction,
+    time_range: summary::TimeRange,
+) -> Result<Vec<LiveAllocations>, Box<dyn Error>> {
+    if !trace.has_capability(trace::CAPABILITY_FREE_TRACKING) {
+        Err("live blocks aren't known for traces made with --no-free-tracking")?;
+    }
+    if trace.has_capability(trace::CAPABILITY_AGGREGATE) {
+        Err("live blocks aren't listed for traces made with --aggregate")?;
+    }
+    let sample_periods = trace.sample_periods()?;
+    let max_event_id = trace.max_event_id()?;
+
+    // The allocating event of each live block, by address.
+    let mut live_blocks: collections::HashMap<u64, trace::Event> = collections::HashMap::new();
+    for event_id in 1..=max_event_id {
+        let event = match transaction.event(event_id) {
+            Some(event) => event,
+            None => continue,
+        };
+        if event.time > time_range.end {
+            break;
+        }
+        if !time_range.contains(event.time) {
+            continue;
+        }
+
+        if event.allocation {
+            live_blocks.insert(event.address, event);
+        } else {
+            live_blocks.remove(&event.address);
+        }
+    }
+
+    let mut by_callstack: collections::HashMap<Option<trace::StackEntryId>, LiveAllocations> =
+        collections::HashMap::new();
+    for event in live_blocks.values() {
+        let live = by_callstack
+            .entry(event.callstack)
+            .or_insert(LiveAllocations {
+                callstack: event.callstack,
+                count: 0,
+                bytes: 0,
+                oldest: event.time,
+            });
+        let weight = summary::sample_period_at(&sample_periods, event.time);
+        live.count += weight;
+        live.bytes += event.size.unwrap_or(0) * weight;
+        live.oldest = std::cmp::min(live.oldest, event.time);
+    }
+    let mut by_callstack: Vec<(Vec<String>, LiveAllocations)> = by_callstack
+        .into_values()
+        .map(|live| {
+            let labels = stable_callstack_labels(transaction, live.callstack)?;
+            Ok((labels, live))
+        })
+        .collect::<Result<_, Box<dyn Error>>>()?;
+    by_callstack.sort_by(|(a_labels, a), (b_labels, b)| {
+        b.bytes
+            .cmp(&a.bytes)
+            .then(b.count.cmp(&a.count))
+            .then(a_labels.cmp(b_labels))
+            .then(a.callstack.cmp(&b.callstack))
+    });
+
+    Ok(by_callstack.into_iter().map(|(_, live)| live).collect())
+}
+
+// Print what the blocks of a live or leak report are, given the span of the
+// trace reported.
+fn print_live_description(trace: &trace::Trace, time_range: summary::TimeRange) {
+    if time_range.end != u64::MAX {
+        println!(
+            "Blocks still allocated at {:.2?}",
+            time::Duration::from_nanos(time_range.end)
+        );
+    } else if trace.ended_by_detach() {
+        println!("Blocks still allocated when the trace detached");
+    } else {
+        println!("Blocks never freed before the process exited");
+    }
+    println!();
+}
+
+// Generate a report of the blocks still allocated at the end of the trace,
+// or at the end of a span of it, grouped by the callstack which allocated
+// them, with the callstacks holding the most bytes first.
+pub fn generate_live_report(
+    trace: &trace::Trace,
+    time_range: summary::TimeRange,
+) -> Result<(), Box<dyn Error>> {
+    let mut transaction = trace::Transaction::new(trace)?;
+    let by_callstack = live_allocations(trace, &mut transaction, time_range)?;
+
+    print_title("live allocation report");
+    print_live_description(trace, time_range);
+    if by_callstack.is_empty() {
+        println!("No live allocations");
+        return Ok(());
+    }
+
+    println!(
+        "{} {} {:>10}   Callstack",
+        table_header("COUNT"),
+        table_header("BYTES"),
+        "OLDEST"
+    );
+    for live in by_callstack {
+        let prefix = format!(
+            "{} {} {:>10}  ",
+            format_table_value(live.count, 1000),
+            format_table_value(live.bytes, 1024),
+            format!("{:.2?}", time::Duration::from_nanos(live.oldest)),
+        );
+
+        print_callstack(&mut transaction, &prefix, live.callstack)?;
+        println!();
+    }
+
+    Ok(())
+}
+
+// Format a count of blocks for a sentence, with thousands separated.
+fn format_block_count(count: u64) -> String {
+    if count == 1 {
+        "1 block".to_string()
+    } else {
+        format!("{} blocks", separate_thousands(count))
+    }
+}
+
+// Format a recorded callstack in full, one frame per line with its address
+// and source line, in the style of valgrind's records.
+fn format_full_callstack(
+    transaction: &mut trace::Transaction,
+    modules: &[trace::Module],
+    source_cache: &mut source::SourceCache,
+    callstack: Option<trace::StackEntryId>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut locations = Vec::new();
+    let mut id = callstack;
+    while let Some(entry_id) = id {
+        let stackentry = transaction
+            .original_stackentry(entry_id)
+            .ok_or("missing stackentry")?;
+        locations.push(
+            transaction
+                .original_location(stackentry.location)
+                .ok_or("missing location")?,
+        );
+        id = stackentry.next;
+    }
+    // Frames other than the innermost are return addresses, which may
+    // follow the last instruction of the line making the call.
+    let addresses: Vec<(u64, bool)> = locations
+        .iter()
+        .enumerate()
+        .map(|(index, location)| (location.address, index > 0))
+        .collect();
+    let source_locations = source_cache.locations(modules, &addresses);
+
+    let mut lines = Vec::new();
+    for (index, (location, source_location)) in locations.iter().zip(source_locations).enumerate() {
+        let source = match source_location {
+            Ok(source_location) => {
+                format!(" ({}:{})", source_location.filename, source_location.line)
+            }
+            Err(_) => String::new(),
+        };
+        lines.push(format!(
+            "   {} 0x{:x}: {}{}",
+            if index == 0 { "at" } else { "by" },
+            location.address,
+            format_location(location),
+            source
+        ));
+    }
+    if locations.is_empty() {
+        lines.push("   at [unknown]".to_string());
+    }
+
+    Ok(lines)
+}
+
+// Generate a report of the blocks never freed, grouped by the callstack
+// which allocated them, with the most bytes first.  Each callstack is
+// listed in full with the source lines of its frames, in the style of
+// valgrind's leak records, for pasting into bug reports.
+pub fn generate_leak_report(
+    trace: &trace::Trace,
+    time_range: summary::TimeRange,
+    source_map: Vec<(String, String)>,
+) -> Result<(), Box<dyn Error>> {
+    let mut transaction = trace::Transaction::new(trace)?;
+    let leaks = live_allocations(trace, &mut transaction, time_range)?;
+    let modules = trace.modules().unwrap_or_default();
+    let mut source_cache = source::SourceCache::new(source_map);
+
+    print_title("leak report");
+    print_live_description(trace, time_range);
+    if leaks.is_empty() {
+        println!("No leaked allocations");
+        return Ok(());
+    }
+
+    let total_bytes: u64 = leaks.iter().map(|live| live.bytes).sum();
+    let total_count: u64 = leaks.iter().map(|live| live.count).sum();
+    println!(
+        "{} bytes in {} from {} callstacks",
+        separate_thousands(total_bytes),
+        format_block_count(total_count),
+        leaks.len()
+    );
+
+    let record_count = leaks.len();
+    for (index, live) in leaks.into_iter().enumerate() {
+        println!();
+        println!(
+            "{} bytes in {}, first allocated at {:.2?}, leak {} of {}",
+            separate_thousands(live.bytes),
+            format_block_count(live.count),
+            time::Duration::from_nanos(live.oldest),
+            index + 1,
+            record_count
+        );
+        for line in format_full_callstack(
+            &mut transaction,
+            &modules,
+            &mut source_cache,
+            live.callstack,
+        )? {
+            println!("{}", line);
+        }
+    }
+
+    Ok(())
+}
+
+// Format the time and thread of the allocation or free of a block at an
+// inspected address.
+fn format_block_event(verb: &str, event: &allocations::BlockEvent) -> String {
+    let thread = match event.thread {
+        Some(thread) => format!(" by thread {}", thread),
+        None => String::new(),
+    };
+
+    format!(
+        "{} at {:.2?}{}",
+        verb,
+        time::Duration::from_nanos(event.time),
+        thread
+    )
+}
+
+// Format everything recorded of the blocks allocated over an address, as
+// lines of text: their sizes, and the times, threads and full callstacks of
+// their allocations and frees.
+pub fn format_address_history(
+    trace: &trace::Trace,
+    history: &allocations::AddressHistory,
+    source_cache: &mut source::SourceCache,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut transaction = trace::Transaction::new(trace)?;
+    let modules = trace.modules().unwrap_or_default();
+
+    let mut lines = Vec::new();
+    if history.blocks.is_empty() {
+        lines.push(format!(
+            "No block was recorded as allocated over 0x{:x}",
+            history.address
+        ));
+        return Ok(lines);
+    }
+
+    lines.push(format!(
+        "0x{:x} was within {}",
+        history.address,
+        format_block_count(history.blocks.len() as u64)
+    ));
+    let count = history.blocks.len();
+    for (index, block) in history.blocks.iter().enumerate() {
+        lines.push(String::new());
+        lines.push(format!(
+            "{} bytes at 0x{:x}, with the address at offset {}, block {} of {}",
+            separate_thousands(block.size),
+            block.address,
+            history.address - block.address,
+            index + 1,
+            count
+        ));
+        lines.push(format_block_event("Allocated", &block.allocated));
+        lines.append(&mut format_full_callstack(
+            &mut transaction,
+            &modules,
+            source_cache,
+            block.allocated.callstack,
+        )?);
+        match &block.freed {
+            Some(freed) => {
+                let lifetime = time::Duration::from_nanos(freed.time - block.allocated.time);
+                lines.push(format!(
+                    "{}, after {:.2?}",
+                    format_block_event("Freed", freed),
+                    lifetime
+                ));
+                if freed.callstack.is_some() {
+                    lines.append(&mut format_full_callstack(
+                        &mut transaction,
+                        &modules,
+                        source_cache,
+                        freed.callstack,
+                    )?);
+                }
+            }
+            None if trace.ended_by_detach() => {
+                lines.push("Still allocated when the trace ended".to_string())
+            }
+            None => lines.push("Never freed".to_string()),
+        }
+    }
+
+    Ok(lines)
+}
+
+// Describe the callstacks which freed the blocks allocated beneath a stack
+// entry, each with the blocks and bytes it freed, for the lines of a view in
+// place of the call tree.
+pub fn format_freed_by(
+    trace: &trace::Trace,
+    freed_by: &allocations::FreedBy,
+    source_cache: &mut source::SourceCache,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    if !trace.has_capability(trace::CAPABILITY_FREE_TRACKING) {
+        Err("frees weren't recorded in this trace")?;
+    }
+    let mut transaction = trace::Transaction::new(trace)?;
+    let modules = trace.modules().unwrap_or_default();
+
+    let freed = freed_by.count - freed_by.unfreed;
+    let mut lines = vec![format!(
+        "{} of {} allocated by {} freed from {} callstacks",
+        separate_thousands(freed as u64),
+        format_block_count(freed_by.count as u64),
+        freed_by.function,
+        freed_by.callstacks.len()
+    )];
+    if freed_by.unfreed > 0 {
+        lines.push(String::new());
+        lines.push(format!(
+            "{} {}",
+            format_block_count(freed_by.unfreed as u64),
+            if trace.ended_by_detach() {
+                "still allocated when the trace ended"
+            } else {
+                "never freed"
+            }
+        ));
+    }
+    for callstack in &freed_by.callstacks {
+        lines.push(String::new());
+        lines.push(format!(
+            "{} ({:.1}%), {} bytes, freed",
+            format_block_count(callstack.blocks),
+            callstack.blocks as f64 * 100.0 / freed as f64,
+            separate_thousands(callstack.bytes)
+        ));
+        if callstack.callstack.is_some() {
+            lines.append(&mut format_full_callstack(
+                &mut transaction,
+                &modules,
+                source_cache,
+                callstack.callstack,
+            )?);
+        } else {
+            lines.push("   without a recorded callstack".to_string());
+        }
+    }
+
+    Ok(lines)
+}
+
+// Describe the chains of reallocations of the blocks allocated beneath a
+// stack entry, with the sequences of sizes through which they grew, for the
+// lines of a view in place of the call tree.
+pub fn format_realloc_chains(chains: &allocations::ReallocChains) -> Vec<String> {
+    if chains.reallocated == 0 {
+        return vec![format!(
+            "None of {} allocated by {} were reallocated",
+            format_block_count(chains.blocks),
+            chains.function
+        )];
+    }
+
+    let mut lines = vec![format!(
+        "{} of {} allocated by {} reallocated {} times",
+        separate_thousands(chains.reallocated),
+        format_block_count(chains.blocks),
+        chains.function,
+        separate_thousands(chains.reallocations)
+    )];
+    lines.push(String::new());
+    lines.push(format!(
+        "Reallocating may have copied up to {} bytes.  Reserving the final size",
+        separate_thousands(chains.copied_bytes)
+    ));
+    lines.push("up front, as with Vec::with_capacity or String::with_capacity, would".to_string());
+    lines.push("avoid the copies.".to_string());
+    lines.push(String::new());
+    lines.push("  CHAINS   GROWTH".to_string());
+    for sequence in &chains.sequences {
+        let sizes: Vec<String> = sequence
+            .sizes
+            .iter()
+            .map(|size| format_table_value(*size, 1024).trim_start().to_string())
+            .collect();
+        lines.push(format!(
+            "{:>8}   {}",
+            separate_thousands(sequence.chains),
+            sizes.join(" → ")
+        ));
+    }
+
+    lines
+}
+
+// Generate a report of the blocks allocated over an address, with the full
+// callstacks of their allocations and frees, for correlating with
+// addresses printed by sanitizers, debuggers or logs.
+pub fn generate_address_report(
+    trace: &trace::Trace,
+    address: u64,
+    source_map: Vec<(String, String)>,
+) -> Result<(), Box<dyn Error>> {
+    let history = allocations::AddressHistory::new(trace, address)?;
+    let mut source_cache = source::SourceCache::new(source_map);
+
+    print_title("address report");
+    if let Some((session, count)) = trace.session_of()? {
+        println!("Session {} of {}", session, count);
+        println!();
+    }
+    for line in format_address_history(trace, &history, &mut source_cache)? {
+        println!("{}", line);
+    }
+
+    Ok(())
+}
+
+// Format the description of the session of the trace being viewed, as
+// lines of text, along with the filter and span of the trace applied by the
+// viewer.
+pub fn format_trace_info(
+    trace: &trace::Trace,
+    filter: Option<&str>,
+    time_range: summary::TimeRange,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let info = trace.info()?;
+    let unknown = "not recorded".to_string();
+
+    let mut lines = Vec::new();
+    lines.push(match trace.session_of()? {
+        Some((session, count)) => format!("Session {} of {} of the trace", session, count),
+        None => "Trace".to_string(),
+    });
+    lines.push(format!(
+        "Command       {}",
+        info.command.as_ref().unwrap_or(&unknown)
+    ));
+    lines.push(format!(
+        "Process       {}",
+        info.pid.as_ref().unwrap_or(&unknown)
+    ));
+    lines.push(format!(
+        "Host          {}",
+        info.host.as_ref().unwrap_or(&unknown)
+    ));
+    lines.push(format!(
+        "Started       {}",
+        info.start_time
+            .as_ref()
+            .map_or(unknown.clone(), |time| format!("{} UTC", time))
+    ));
+    let ending = match info.ending.as_deref() {
+        Some("exit") => ", when the process exited",
+        Some("detach") => ", detaching from the running process",
+        _ => "",
+    };
+    lines.push(format!(
+        "Ended         {}{}",
+        info.end_time
+            .as_ref()
+            .map_or(unknown.clone(), |time| format!("{} UTC", time)),
+        ending
+    ));
+    lines.push(format!(
+        "Duration      {:.2?}",
+        time::Duration::from_nanos(trace.duration()?)
+    ));
+    lines.push(format!(
+        "Tracer        {}",
+        info.version
+            .as_ref()
+            .map_or(unknown.clone(), |version| format!(
+                "allocscope-trace {}",
+                version
+            ))
+    ));
+    lines.push(format!(
+        "Events        {}",
+        separate_thousands(info.event_count)
+    ));
+    lines.push(format!(
+        "Dropped       {}",
+        info.dropped_events
+            .map_or(unknown.clone(), separate_thousands)
+    ));
+    if let Some(retained) = info.retained {
+        lines.push(format!(
+            "Retained      last {:?}, {} events pruned",
+            time::Duration::from_secs(retained),
+            info.pruned_events
+                .map_or(unknown.clone(), separate_thousands)
+        ));
+    }
+    lines.push(format!(
+        "Filters       {}",
+        match info.filters.as_deref() {
+            Some("") => "none",
+            Some(filters) => filters,
+            None => unknown.as_str(),
+        }
+    ));
+
+    let mut viewed = Vec::new();
+    if let Some(filter) = filter {
+        viewed.push(format!("functions matching '{}'", filter));
+    }
+    if time_range != summary::TimeRange::all() {
+        viewed.push(format!("events {}", format_time_range(time_range)));
+    }
+    if viewed.is_empty() {
+        viewed.push("everything".to_string());
+    }
+    lines.push(format!("Viewing       {}", viewed.join(", ")));
+
+    Ok(lines)
+}
+
+// Generate a report describing the trace: the traced process, when and how
+// it was traced, and the events recorded.
+pub fn generate_info_report(
+    trace: &trace::Trace,
+    filter: Option<&str>,
+    time_range: summary::TimeRange,
+) -> Result<(), Box<dyn Error>> {
+    print_title("trace information");
+    for line in format_trace_info(trace, filter, time_range)? {
+        println!("{}", line);
+    }
+
+    Ok(())
+}
+
+// Generate a report of the memory mapped by the traced process, separating
+// anonymous memory from file-backed and shared mappings, with the files
+// mapped listed by the bytes they still have mapped.
+pub fn generate_mapping_report(trace: &trace::Trace) -> Result<(), Box<dyn Error>> {
+    let changes = trace.mapping_changes()?;
+
+    print_title("mapped memory report");
+    if changes.is_empty() {
+        println!("No mappings recorded (trace with --mmap to record them)");
+        return Ok(());
+    }
+
+    let summary = mapping::summarize(&changes);
+    println!("{} {}   Kind", table_header("BYTES"), table_header("PEAK"));
+    for (kind, total) in summary.by_kind.iter() {
+        println!(
+            "{} {}   {}",
+            format_table_value(total.current, 1024),
+            format_table_value(total.peak, 1024),
+            kind
+        );
+    }
+
+    let mut by_path: Vec<(&String, &mapping::MappedTotal)> = summary.by_path.iter().collect();
+    by_path.sort_by(|a, b| {
+        b.1.current
+            .cmp(&a.1.current)
+            .then(b.1.peak.cmp(&a.1.peak))
+            .then(a.0.cmp(b.0))
+    });
+    if !by_path.is_empty() {
+        println!();
+        println!("{} {}   File", table_header("BYTES"), table_header("PEAK"));
+        for (path, total) in by_path {
+            println!(
+                "{} {}   {}",
+                format_table_value(total.current, 1024),
+                format_table_value(total.peak, 1024),
+                path
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// The frames of a recorded callstack as printed, innermost first, by which
+// stable reports order callstacks with equal values, as the ids of
+// callstacks depend on the order in which they were recorded.  Empty unless
+// reporting stable output.
+fn stable_callstack_labels(
+    transaction: &mut trace::Transaction,
+    callstack: Option<trace::StackEntryId>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut labels = Vec::new();
+    if !stable_output() {
+        return Ok(labels);
+    }
+
+    let mut id = callstack;
+    while let Some(entry_id) = id {
+        let stackentry = transaction
+            .original_stackentry(entry_id)
+            .ok_or("missing stackentry")?;
+        let location = transaction
+            .original_location(stackentry.location)
+            .ok_or("missing location")?;
+        labels.push(format_location(&location));
+        id = stackentry.next;
+    }
+
+    Ok(labels)
+}
+
+// Print a recorded callstack, one frame per line, with a prefix before
+// the first frame and the following frames aligned beneath it.
+fn print_callstack(
+    transaction: &mut trace::Transaction,
+    prefix: &str,
+    callstack: Option<trace::StackEntryId>,
+) -> Result<(), Box<dyn Error>> {
+    let mut prefix = prefix.to_string();
+    let mut id = callstack;
+    while let Some(entry_id) = id {
+        let stackentry = transaction
+            .original_stackentry(entry_id)
+            .ok_or("missing stackentry")?;
+        let location = transaction
+            .original_location(stackentry.location)
+            .ok_or("missing location")?;
+        println!("{}{}", prefix, format_location(&location));
+
+        prefix = " ".repeat(prefix.len());
+        id = stackentry.next;
+    }
+
+    Ok(())
+}
+
+// The number of the largest allocation bursts listed in the report.
+const REPORT_BURSTS: u64 = 10;
+
+// Print the largest bursts of allocation marked by the tracer, in the
+// order they occurred, with the callstack which allocated the most during
+// each burst.
+fn print_bursts(
+    trace: &trace::Trace,
+    transaction: &mut trace::Transaction,
+) -> Result<(), Box<dyn Error>> {
+    let bursts = trace.largest_bursts(REPORT_BURSTS)?;
+    if bursts.is_empty() {
+        return Ok(());
+    }
+
+    println!(
+        "    TIME   LENGTH {}  Allocation bursts",
+        table_header("BYTES")
+    );
+    for burst in bursts {
+        let prefix = format!(
+            "{:>7.3}s {:>6.1}ms {}  ",
+            burst.time as f64 / 1e9,
+            burst.duration as f64 / 1e6,
+            format_table_value(burst.bytes, 1024)
+        );
+        print_callstack(transaction, &prefix, burst.callstack)?;
+        println!();
+    }
+
+    Ok(())
+}
+
+// The number of registers printed on each line of a crash report.
+const CRASH_REGISTERS_PER_LINE: usize = 3;
+
+// Print where the traced process crashed, with the registers and callstack
+// of the crashing thread.
+fn print_crash(
+    trace: &trace::Trace,
+    transaction: &mut trace::Transaction,
+) -> Result<(), Box<dyn Error>> {
+    let crash = match trace.crash()? {
+        Some(crash) => crash,
+        None => return Ok(()),
+    };
+
+    let signal_name = unsafe { std::ffi::CStr::from_ptr(libc::strsignal(crash.signal)) };
+    println!(
+        "Process crashed here: {} in thread {} after {:.1}s",
+        signal_name.to_string_lossy(),
+        crash.thread,
+        crash.time as f64 / 1e9
+    );
+    if let Some(fault_address) = crash.fault_address {
+        println!("Fault address: 0x{:016x}", fault_address);
+    }
+    println!();
+    for line in crash.registers.chunks(CRASH_REGISTERS_PER_LINE) {
+        let registers: Vec<String> = line
+            .iter()
+            .map(|(name, value)| format!("{:>6} 0x{:016x}", name, value))
+            .collect();
+        println!("{}", registers.join("  "));
+    }
+    println!();
+    print_callstack(transaction, "  ", crash.callstack)?;
+    println!();
+
+    Ok(())
+}
+
+// The number of callstacks listed as growing at the cgroup memory peak.
+const CGROUP_PEAK_CALLSTACKS: u64 = 5;
+
+// Print the peak of the memory charged to the traced process's cgroup,
+// with the callstacks which allocated the most in the sampling interval
+// leading up to the peak.
+fn print_cgroup_memory_peak(
+    trace: &trace::Trace,
+    transaction: &mut trace::Transaction,
+) -> Result<(), Box<dyn Error>> {
+    let peak = match trace.cgroup_memory_peak()? {
+        Some(peak) => peak,
+        None => return Ok(()),
+    };
+
+    let limit = match peak.max {
+        Some(max) if max > 0 => format!(
+            "of {} limit ({}%)",
+            format_table_value(max, 1024).trim(),
+            peak.current * 100 / max
+        ),
+        _ => "with no limit".to_string(),
+    };
+    println!(
+        "Cgroup memory peaked at {} {} after {:.1}s",
+        format_table_value(peak.current, 1024).trim(),
+        limit,
+        peak.time as f64 / 1e9
+    );
+
+    let growth = trace.growth_between(peak.previous_time, peak.time, CGROUP_PEAK_CALLSTACKS)?;
+    if !growth.is_empty() {
+        println!();
+        println!(
+            "{} {}   Callstacks allocating before the peak",
+            table_header("COUNT"),
+            table_header("BYTES")
+        );
+        for entry in growth {
+            let prefix = format!(
+                "{} {}  ",
+                format_table_value(entry.count, 1000),
+                format_table_value(entry.bytes, 1024)
+            );
+            print_callstack(transaction, &prefix, entry.callstack)?;
+            println!();
+        }
+    }
+    println!();
+
+    Ok(())
+}
+
 // Generate a report of allocations to stdout, in a text format suitable for
 // redirecting to a text file or being piped to another command.
-pub fn generate_report(trace: trace::Trace) -> Result<(), Box<dyn Error>> {
+pub fn generate_report(
+    trace: trace::Trace,
+    time_range: summary::TimeRange,
+    columns: Columns,
+    row_limits: rows::RowLimits,
+    value_style: ValueStyle,
+    sort_order: rows::SortOrder,
+) -> Result<(), Box<dyn Error>> {
+    let free_tracking = trace.has_capability(trace::CAPABILITY_FREE_TRACKING);
     let mut transaction = trace::Transaction::new(&trace)?;
+    let value_format = ValueFormat::new(value_style, rows::largest_value(&mut transaction)?);
+    let churn_format = ValueFormat::new(value_style, rows::largest_churn(&mut transaction)?);
 
-    let row_count = rows::count_rows(&mut transaction, None)?;
+    let mut cache = rows::TreeCache::new(sort_order);
+    let row_count = rows::count_rows(&mut transaction, &mut cache, None, row_limits)?;
     let rows =
-        rows::iter_stackentry_rows(&mut transaction, rows::SortMode::Bytes, None, 0, row_count)?;
+        rows::iter_stackentry_rows(&mut transaction, &mut cache, None, row_limits, 0, row_count)?;
 
-    println!("allocscope {} memory report", env!("CARGO_PKG_VERSION"));
-    println!("https://allocscope.com/support");
-    println!("");
-    println!("BYTES BLOCK LEAKS   Function");
+    print_title("memory report");
+    if let Some((session, count)) = trace.session_of()? {
+        println!("Session {} of {}", session, count);
+        println!();
+    }
+    if time_range != summary::TimeRange::all() {
+        println!("Events {}", format_time_range(time_range));
+        println!();
+    }
+    let max_sample_period = trace
+        .sample_periods()?
+        .iter()
+        .map(|change| change.period)
+        .max()
+        .unwrap_or(1);
+    if max_sample_period > 1 {
+        println!(
+            "Allocations were sampled, as few as 1 in {}, to limit tracing overhead.",
+            max_sample_period
+        );
+        println!("Totals are scaled estimates.");
+        println!();
+    }
+    if let Some(retained) = trace.retained_window() {
+        println!(
+            "Only events from the last {:?} were kept, along with blocks still live.",
+            time::Duration::from_secs(retained)
+        );
+        println!("Peak, block and churn totals cover only that window.");
+        println!();
+    }
+    print_crash(&trace, &mut transaction)?;
+    print_cgroup_memory_peak(&trace, &mut transaction)?;
+    print_bursts(&trace, &mut transaction)?;
+    let modules = if columns.addresses {
+        trace.modules()?
+    } else {
+        Vec::new()
+    };
+    if trace.ended_by_detach() {
+        println!("The trace ended by detaching from a running process, so blocks");
+        println!("not yet freed are reported as LIVE rather than as leaks.");
+        println!();
+    }
+    print!(
+        "{} {} {} {}",
+        value_format.header("BYTES"),
+        value_format.header("HELD"),
+        value_format.header("BLOCK"),
+        value_format.header(if trace.ended_by_detach() {
+            "LIVE"
+        } else {
+            "LEAKS"
+        })
+    );
+    if columns.churn {
+        print!(" {}", churn_format.header("CHURN"));
+    }
+    for column in columns::computed_columns() {
+        print!(" {}", column.header());
+    }
+    if columns.rates {
+        print!(" {} {}", table_header("ALC/s"), table_header("B/s"));
+    }
+    if columns.times {
+        print!("  FIRST   LAST");
+    }
+    if columns.addresses {
+        print!(" {}", address_columns_header());
+    }
+    println!("   Function");
+    let span = time_range.span(trace.duration()?);
+    // Rows are truncated to fit a terminal, rather than wrapping, but are
+    // written whole to a file or pipe.
+    let line_width = text::terminal_width();
     for entry in rows {
         let function = format_function_tree_row(None, &entry);
-        println!(
-            "{} {} {} {}",
-            format_table_value(entry.maximum_size, 1024),
-            format_table_value(entry.total_blocks, 1000),
-            format_table_value(entry.unfreed_blocks, 1000),
+        let churn_value = if columns.churn {
+            format!(" {}", churn_format.value(entry.total_bytes, 1024))
+        } else {
+            String::new()
+        };
+        let computed_values = format_computed_values(&entry.summary());
+        let rate_values = if columns.rates {
+            format!(
+                " {}",
+                format_rate_values(entry.total_blocks, entry.total_bytes, span)
+            )
+        } else {
+            String::new()
+        };
+        let time_values = if columns.times {
+            format!(" {}", format_time_values(entry.first_time, entry.last_time))
+        } else {
+            String::new()
+        };
+        let address_values = if columns.addresses {
+            format!(" {}", format_address_values(&entry, &modules))
+        } else {
+            String::new()
+        };
+        let line = format!(
+            "{} {} {} {}{}{}{}{}{} {}",
+            value_format.value(entry.maximum_size, 1024),
+            format_held_value(&entry, free_tracking, value_format),
+            value_format.value(entry.total_blocks, 1000),
+            format_leaks_value(&entry, free_tracking, value_format),
+            churn_value,
+            computed_values,
+            rate_values,
+            time_values,
+            address_values,
             function,
         );
+        match line_width {
+            Some(width) => println!("{}", text::truncate(&line, width)),
+            None => println!("{}", line),
+        }
+    }
+
+    Ok(())
+}
+
+// Generate a report to stdout of the call tree with the blocks allocated by
+// each entry counted by how long they lived before being freed, telling
+// transient churn from persistent memory.
+pub fn generate_lifetime_report(
+    trace: &trace::Trace,
+    tree_builder: &mut transform::TreeBuilder,
+    time_range: summary::TimeRange,
+    row_limits: rows::RowLimits,
+    value_style: ValueStyle,
+) -> Result<(), Box<dyn Error>> {
+    let counts = allocations::count_lifetimes(trace, tree_builder, time_range)?;
+    let mut transaction = trace::Transaction::new(trace)?;
+    let largest = counts
+        .values()
+        .flat_map(|entry_counts| entry_counts.blocks)
+        .max()
+        .unwrap_or(0);
+    let value_format = ValueFormat::new(value_style, largest);
+
+    let mut cache = rows::TreeCache::new(rows::SortOrder {
+        mode: rows::SortMode::Bytes,
+        reversed: false,
+    });
+    let row_count = rows::count_rows(&mut transaction, &mut cache, None, row_limits)?;
+    let rows =
+        rows::iter_stackentry_rows(&mut transaction, &mut cache, None, row_limits, 0, row_count)?;
+
+    print_title("lifetime report");
+    if time_range != summary::TimeRange::all() {
+        println!("Events {}", format_time_range(time_range));
+        println!();
+    }
+    println!("Blocks by how long they lived before being freed, with blocks not");
+    println!("freed by the end of the span summarized counted as never freed.");
+    println!();
+    let max_sample_period = trace
+        .sample_periods()?
+        .iter()
+        .map(|change| change.period)
+        .max()
+        .unwrap_or(1);
+    if max_sample_period > 1 {
+        println!(
+            "Allocations were sampled, as few as 1 in {}, and only those recorded",
+            max_sample_period
+        );
+        println!("are counted.");
+        println!();
+    }
+
+    let headers: Vec<String> = allocations::LIFETIME_LABELS
+        .iter()
+        .map(|label| value_format.header(label))
+        .collect();
+    println!("{}   Function", headers.join(" "));
+    // The counts of the row standing for pruned entries are those of their
+    // parent less those of the siblings shown before it.  Pruned roots
+    // stand for the rest of the counts of all roots.
+    let mut roots_total = allocations::LifetimeCounts::default();
+    for root in transaction.root_stackentries()? {
+        if let Some(root_counts) = counts.get(&root.id) {
+            roots_total.add_counts(root_counts);
+        }
+    }
+    let mut unshown = vec![roots_total];
+    let line_width = text::terminal_width();
+    for entry in rows {
+        unshown.truncate(entry.depth + 1);
+        let entry_counts = if entry.pruned > 0 {
+            unshown[entry.depth]
+        } else {
+            counts.get(&entry.id).copied().unwrap_or_default()
+        };
+        unshown[entry.depth].remove_counts(&entry_counts);
+        unshown.push(entry_counts);
+
+        let values: Vec<String> = entry_counts
+            .blocks
+            .iter()
+            .map(|blocks| value_format.value(*blocks, 1000))
+            .collect();
+        let line = format!(
+            "{} {}",
+            values.join(" "),
+            format_function_tree_row(None, &entry)
+        );
+        match line_width {
+            Some(width) => println!("{}", text::truncate(&line, width)),
+            None => println!("{}", line),
+        }
+    }
+
+    Ok(())
+}
+
+// Format a value for a JSON report, or null if the trace didn't record it.
+fn json_value(value: u64, recorded: bool) -> String {
+    if recorded {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+// Generate a report of the call tree as JSON lines, writing each entry as
+// it is generated so that large trees needn't be held in memory.  Entries
+// are written parents first, so the parent of an entry is the closest
+// preceding entry with a depth one less.
+pub fn generate_jsonl_report(
+    trace: &trace::Trace,
+    time_range: summary::TimeRange,
+    rates: bool,
+    times: bool,
+    row_limits: rows::RowLimits,
+    sort_order: rows::SortOrder,
+) -> Result<(), Box<dyn Error>> {
+    let free_tracking = trace.has_capability(trace::CAPABILITY_FREE_TRACKING);
+    let span = time_range.span(trace.duration()?);
+    let mut transaction = trace::Transaction::new(trace)?;
+    let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+
+    rows::for_each_stackentry_row(
+        &mut transaction,
+        &mut rows::TreeCache::new(sort_order),
+        None,
+        row_limits,
+        0,
+        usize::MAX,
+        |entry| {
+            let mut line = format!(
+                "{{\"id\":{},\"depth\":{},\"function\":{},\"address\":{},\"offset\":{},\
+                \"peak_bytes\":{},\"held_bytes\":{},\"blocks\":{},\"total_bytes\":{},\
+                \"unfreed_blocks\":{},\"pruned\":{}",
+                entry.id,
+                entry.depth,
+                export::json_string(&entry.function),
+                entry.address,
+                entry.offset,
+                entry.maximum_size,
+                json_value(entry.held_size, free_tracking),
+                entry.total_blocks,
+                entry.total_bytes,
+                json_value(entry.unfreed_blocks, free_tracking),
+                entry.pruned
+            );
+            if rates {
+                let per_second = |value: u64| match span {
+                    0 => "null".to_string(),
+                    _ => ((value as u128 * 1_000_000_000 / span as u128) as u64).to_string(),
+                };
+                line += &format!(
+                    ",\"blocks_per_second\":{},\"bytes_per_second\":{}",
+                    per_second(entry.total_blocks),
+                    per_second(entry.total_bytes)
+                );
+            }
+            if !columns::computed_columns().is_empty() {
+                let summary = entry.summary();
+                let values: Vec<String> = columns::computed_columns()
+                    .iter()
+                    .enumerate()
+                    .map(|(index, column)| {
+                        let value = match columns::evaluate(index, &summary) {
+                            Some(value) if value.is_finite() => value.to_string(),
+                            _ => "null".to_string(),
+                        };
+                        format!("{}:{}", export::json_string(&column.name), value)
+                    })
+                    .collect();
+                line += &format!(",\"computed\":{{{}}}", values.join(","));
+            }
+            if times {
+                let nanoseconds = |time: Option<u64>| match time {
+                    Some(time) => time.to_string(),
+                    None => "null".to_string(),
+                };
+                line += &format!(
+                    ",\"first_time_ns\":{},\"last_time_ns\":{}",
+                    nanoseconds(entry.first_time),
+                    nanoseconds(entry.last_time)
+                );
+            }
+            writeln!(stdout, "{}}}", line)?;
+
+            Ok(())
+        },
+    )?;
+    stdout.flush()?;
+
+    Ok(())
+}
+
+// Escape the characters of text which HTML would otherwise interpret.
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// Format text as a Markdown code span within a table cell.  The delimiter is
+// lengthened when the text contains backticks, and pipes are escaped so as
+// not to end the cell.
+fn markdown_code(text: &str) -> String {
+    let text = text.replace('|', "\\|");
+    if text.contains('`') {
+        let padding = if text.starts_with('`') || text.ends_with('`') {
+            " "
+        } else {
+            ""
+        };
+        format!("``{}{}{}``", padding, text, padding)
+    } else {
+        format!("`{}`", text)
+    }
+}
+
+// Generate a report to stdout in Markdown, for posting memory summaries to
+// pull requests and the like.  Each root of the call tree is given a
+// collapsible section, with a summary line of its totals, holding a table of
+// its entries.
+pub fn generate_markdown_report(
+    trace: &trace::Trace,
+    time_range: summary::TimeRange,
+    columns: Columns,
+    row_limits: rows::RowLimits,
+    value_style: ValueStyle,
+    sort_order: rows::SortOrder,
+) -> Result<(), Box<dyn Error>> {
+    let free_tracking = trace.has_capability(trace::CAPABILITY_FREE_TRACKING);
+    let span = time_range.span(trace.duration()?);
+    let mut transaction = trace::Transaction::new(trace)?;
+    let value_format = ValueFormat::new(value_style, rows::largest_value(&mut transaction)?);
+    let churn_format = ValueFormat::new(value_style, rows::largest_churn(&mut transaction)?);
+    let modules = if columns.addresses {
+        trace.modules()?
+    } else {
+        Vec::new()
+    };
+    let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+
+    if stable_output() {
+        writeln!(stdout, "## allocscope memory report")?;
+    } else {
+        writeln!(
+            stdout,
+            "## allocscope {} memory report",
+            env!("CARGO_PKG_VERSION")
+        )?;
+    }
+    writeln!(stdout)?;
+    if let Some((session, count)) = trace.session_of()? {
+        writeln!(stdout, "Session {} of {}", session, count)?;
+        writeln!(stdout)?;
+    }
+    if time_range != summary::TimeRange::all() {
+        writeln!(stdout, "Events {}", format_time_range(time_range))?;
+        writeln!(stdout)?;
+    }
+    let max_sample_period = trace
+        .sample_periods()?
+        .iter()
+        .map(|change| change.period)
+        .max()
+        .unwrap_or(1);
+    if max_sample_period > 1 {
+        writeln!(
+            stdout,
+            "Allocations were sampled, as few as 1 in {}, to limit tracing overhead. \
+            Totals are scaled estimates.",
+            max_sample_period
+        )?;
+        writeln!(stdout)?;
+    }
+    if let Some(retained) = trace.retained_window() {
+        writeln!(
+            stdout,
+            "Only events from the last {:?} were kept, along with blocks still live. \
+            Peak, block and churn totals cover only that window.",
+            time::Duration::from_secs(retained)
+        )?;
+        writeln!(stdout)?;
+    }
+    if trace.ended_by_detach() {
+        writeln!(
+            stdout,
+            "The trace ended by detaching from a running process, so blocks not yet \
+            freed are reported as live rather than as leaks."
+        )?;
+        writeln!(stdout)?;
+    }
+
+    let mut headers = vec![
+        "Bytes",
+        "Held",
+        "Blocks",
+        if trace.ended_by_detach() {
+            "Live"
+        } else {
+            "Leaks"
+        },
+    ];
+    if columns.churn {
+        headers.push("Churn");
+    }
+    for column in columns::computed_columns() {
+        headers.push(&column.name);
+    }
+    if columns.rates {
+        headers.extend(["Alloc/s", "B/s"]);
+    }
+    if columns.times {
+        headers.extend(["First", "Last"]);
+    }
+    if columns.addresses {
+        headers.extend(["Address", "Module"]);
+    }
+    let table_header = format!(
+        "| {} | Function |\n|{}---|",
+        headers.join(" | "),
+        "---:|".repeat(headers.len())
+    );
+
+    let mut in_section = false;
+    rows::for_each_stackentry_row(
+        &mut transaction,
+        &mut rows::TreeCache::new(sort_order),
+        None,
+        row_limits,
+        0,
+        usize::MAX,
+        |entry| {
+            let function = format_function_name(&entry);
+            if entry.depth == 0 {
+                if in_section {
+                    writeln!(stdout)?;
+                    writeln!(stdout, "</details>")?;
+                    writeln!(stdout)?;
+                }
+                writeln!(stdout, "<details>")?;
+                writeln!(
+                    stdout,
+                    "<summary><code>{}</code> &mdash; {} peak, {} blocks</summary>",
+                    html_escape(&function),
+                    value_format.value(entry.maximum_size, 1024).trim(),
+                    value_format.value(entry.total_blocks, 1000).trim()
+                )?;
+                writeln!(stdout)?;
+                writeln!(stdout, "{}", table_header)?;
+                in_section = true;
+            }
+
+            let mut cells = vec![
+                value_format.value(entry.maximum_size, 1024),
+                format_held_value(&entry, free_tracking, value_format),
+                value_format.value(entry.total_blocks, 1000),
+                format_leaks_value(&entry, free_tracking, value_format),
+            ];
+            if columns.churn {
+                cells.push(churn_format.value(entry.total_bytes, 1024));
+            }
+            if !columns::computed_columns().is_empty() {
+                let summary = entry.summary();
+                for (index, column) in columns::computed_columns().iter().enumerate() {
+                    cells.push(column.value(columns::evaluate(index, &summary)));
+                }
+            }
+            if columns.rates {
+                cells.extend(
+                    format_rate_values(entry.total_blocks, entry.total_bytes, span)
+                        .split_whitespace()
+                        .map(str::to_string),
+                );
+            }
+            if columns.times {
+                cells.push(format_time_value(entry.first_time));
+                cells.push(format_time_value(entry.last_time));
+            }
+            if columns.addresses {
+                if entry.pruned > 0 {
+                    cells.extend([String::new(), String::new()]);
+                } else {
+                    let module = trace::module_at(&modules, entry.address)
+                        .and_then(|module| module.filename.rsplit('/').next())
+                        .unwrap_or("unknown");
+                    cells.push(format!("`0x{:x}`", entry.address));
+                    cells.push(markdown_code(module));
+                }
+            }
+            let cells: Vec<&str> = cells.iter().map(|cell| cell.trim()).collect();
+            writeln!(
+                stdout,
+                "| {} | {}{} |",
+                cells.join(" | "),
+                "&nbsp;&nbsp;".repeat(entry.depth),
+                markdown_code(&function)
+            )?;
+
+            Ok(())
+        },
+    )?;
+    if in_section {
+        writeln!(stdout)?;
+        writeln!(stdout, "</details>")?;
     }
+    stdout.flush()?;
 
     Ok(())
 }
@@ -16,11 +16,15 @@
     with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
+use crate::columns;
+use crate::report;
 use crate::trace;
 use cplus_demangle;
 use rustc_demangle;
+use std::cmp::Reverse;
 use std::collections;
 use std::error::Error;
+use std::sync;
 
 // The column by which we should sort rows generated.
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -31,11 +35,74 @@ pub enum SortMode {
     // Sort by max concurrent bytes allocated.
     Bytes,
 
+    // Sort by bytes still allocated at the end of the trace.
+    Held,
+
     // Sort by total blocks allocated.
     Blocks,
 
     // Sort by number of unfreed blocks allocated.
     Leaks,
+
+    // Sort by total bytes allocated, including those freed, to find the
+    // churn of blocks repeatedly allocated and freed.
+    Churn,
+
+    // Sort by the demangled name of the function.
+    Function,
+
+    // Sort by the computed column with an index.
+    Column(usize),
+}
+
+impl SortMode {
+    // Parse a sort mode from its name, as given on the commandline or in a
+    // saved state.  The name of a computed column sorts by that column.
+    pub fn parse(
+        name: &str,
+        columns: &[columns::ComputedColumn],
+    ) -> Result<SortMode, Box<dyn Error>> {
+        if let Some(index) = columns.iter().position(|column| column.name == name) {
+            return Ok(SortMode::Column(index));
+        }
+        match name {
+            "none" => Ok(SortMode::None),
+            "bytes" => Ok(SortMode::Bytes),
+            "held" => Ok(SortMode::Held),
+            "blocks" => Ok(SortMode::Blocks),
+            "leaks" => Ok(SortMode::Leaks),
+            "churn" => Ok(SortMode::Churn),
+            "function" => Ok(SortMode::Function),
+            _ => Err(format!("unknown sort order: {}", name).into()),
+        }
+    }
+
+    // The name of the sort mode.
+    pub fn name(&self) -> String {
+        match self {
+            SortMode::None => "none".to_string(),
+            SortMode::Bytes => "bytes".to_string(),
+            SortMode::Held => "held".to_string(),
+            SortMode::Blocks => "blocks".to_string(),
+            SortMode::Leaks => "leaks".to_string(),
+            SortMode::Churn => "churn".to_string(),
+            SortMode::Function => "function".to_string(),
+            SortMode::Column(index) => columns::computed_columns()
+                .get(*index)
+                .map_or("none".to_string(), |column| column.name.clone()),
+        }
+    }
+}
+
+// The order in which sibling rows are generated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SortOrder {
+    // The column by which rows are sorted.
+    pub mode: SortMode,
+
+    // If true, the order is reversed.  Values are otherwise sorted largest
+    // first, and function names alphabetically.
+    pub reversed: bool,
 }
 
 // A row generated for display, representing a stack frame location.
@@ -66,13 +133,38 @@ pub struct StackEntryRow {
     // and its descendents.
     pub maximum_size: u64,
 
+    // The bytes allocated by this stack frame and its descendents which
+    // were still allocated at the end of the trace.
+    pub held_size: u64,
+
     // The total number of blocks allocated by this stack frame and its
     // descendents.
     pub total_blocks: u64,
 
+    // The total bytes allocated by this stack frame and its descendents,
+    // including those freed.
+    pub total_bytes: u64,
+
     // The total number of blocks allocated by this stack frame and its
     // descendents which were never freed.
     pub unfreed_blocks: u64,
+
+    // The number of stack entries below the pruning threshold which this
+    // row stands for, with their values summed, or zero for the row of a
+    // single stack entry.
+    pub pruned: usize,
+
+    // True if this row has children left out by the depth limit, whose
+    // values are rolled up into its own.
+    pub rolled_up: bool,
+
+    // The time of the first allocation by this stack frame or its
+    // descendents, in nanoseconds since the start of the trace, if known.
+    pub first_time: Option<u64>,
+
+    // The time of the last allocation or free by this stack frame or its
+    // descendents, if known.
+    pub last_time: Option<u64>,
 }
 
 // Bookkeeping information used while generating rows to track information
@@ -87,6 +179,171 @@ struct WorkingEntry {
     // Entries in this vector are true if this row is the final descendent of
     // the corresponding ancestor.
     final_child_of_depth: Vec<bool>,
+
+    // The sibling stack entries below the pruning threshold, if this entry
+    // is queued to generate a single row standing for them.
+    pruned: Vec<trace::StackEntry>,
+}
+
+// Limits on the rows generated from the call tree, beyond those collapsed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RowLimits {
+    // The fraction of the total below which sibling entries are pruned,
+    // with a single row standing for them all.
+    pub prune: Option<f64>,
+
+    // The most sibling entries given rows of their own, in sorted order,
+    // with a single row standing for the rest.
+    pub top: Option<usize>,
+
+    // The number of levels of the call tree for which rows are generated,
+    // with deeper entries omitted.
+    pub max_depth: Option<usize>,
+}
+
+impl RowLimits {
+    // Limits which only prune entries below a fraction of the total, if
+    // given.
+    pub fn pruning(prune: Option<f64>) -> RowLimits {
+        RowLimits {
+            prune,
+            ..RowLimits::default()
+        }
+    }
+
+    // True if the children of an entry at 'depth' have rows generated.
+    fn includes_children_of(&self, depth: usize) -> bool {
+        match self.max_depth {
+            Some(max_depth) => depth + 1 < max_depth,
+            None => true,
+        }
+    }
+}
+
+// The values below which stack entries are pruned from the generated rows.
+#[derive(Clone, Copy)]
+struct PruneLimits {
+    // The maximum concurrent allocation size, in bytes.
+    bytes: f64,
+
+    // The total number of blocks allocated.
+    blocks: f64,
+}
+
+// The summaries and children of stack entries, kept between generations of
+// rows so that the rows shown as a large call tree is scrolled are generated
+// without querying and sorting the same entries again.  The cache must be
+// cleared when the summaries change.
+pub struct TreeCache {
+    // The order in which rows are generated, and children are sorted.
+    sort_order: SortOrder,
+
+    // The summaries of stack entries, or None for entries without one.
+    summaries: collections::HashMap<trace::StackEntryId, Option<trace::StackEntrySummary>>,
+
+    // The number of descendents of stack entries.
+    descendent_counts: collections::HashMap<trace::StackEntryId, usize>,
+
+    // The children of stack entries in the order of the trace, with the
+    // roots of the call tree under None.
+    children: collections::HashMap<Option<trace::StackEntryId>, Vec<trace::StackEntry>>,
+
+    // The children with summaries of stack entries in the sort order, with
+    // the roots of the call tree under None.
+    sorted_children: collections::HashMap<Option<trace::StackEntryId>, Vec<trace::StackEntry>>,
+}
+
+impl TreeCache {
+    // An empty cache, for generating rows in a sort order.
+    pub fn new(sort_order: SortOrder) -> TreeCache {
+        TreeCache {
+            sort_order,
+            summaries: collections::HashMap::new(),
+            descendent_counts: collections::HashMap::new(),
+            children: collections::HashMap::new(),
+            sorted_children: collections::HashMap::new(),
+        }
+    }
+
+    // Forget all cached entries, after the summaries have changed.
+    pub fn clear(&mut self) {
+        *self = TreeCache::new(self.sort_order);
+    }
+
+    // Generate rows in a sort order, forgetting children sorted in another.
+    pub fn set_sort_order(&mut self, sort_order: SortOrder) {
+        if self.sort_order != sort_order {
+            self.sort_order = sort_order;
+            self.sorted_children.clear();
+        }
+    }
+
+    // The summary of a stack entry.
+    fn summary(
+        &mut self,
+        transaction: &mut trace::Transaction,
+        id: trace::StackEntryId,
+    ) -> Option<trace::StackEntrySummary> {
+        self.summaries
+            .entry(id)
+            .or_insert_with(|| transaction.summary(id))
+            .clone()
+    }
+
+    // The number of descendents of a stack entry.
+    fn descendent_count(
+        &mut self,
+        transaction: &mut trace::Transaction,
+        id: trace::StackEntryId,
+    ) -> Result<usize, Box<dyn Error>> {
+        if let Some(count) = self.descendent_counts.get(&id) {
+            return Ok(*count);
+        }
+
+        let count = transaction.descendent_count(id)? as usize;
+        self.descendent_counts.insert(id, count);
+
+        Ok(count)
+    }
+
+    // The children of a stack entry, or the roots of the call tree, in the
+    // order of the trace.
+    fn children(
+        &mut self,
+        transaction: &mut trace::Transaction,
+        id: Option<trace::StackEntryId>,
+    ) -> Result<Vec<trace::StackEntry>, Box<dyn Error>> {
+        if let Some(children) = self.children.get(&id) {
+            return Ok(children.clone());
+        }
+
+        let children = match id {
+            Some(id) => transaction.get_stackentry_children(id)?,
+            None => transaction.root_stackentries()?,
+        };
+        self.children.insert(id, children.clone());
+
+        Ok(children)
+    }
+
+    // The children with summaries of a stack entry, or the roots of the
+    // call tree, in the sort order.
+    fn sorted_children(
+        &mut self,
+        transaction: &mut trace::Transaction,
+        id: Option<trace::StackEntryId>,
+    ) -> Result<Vec<trace::StackEntry>, Box<dyn Error>> {
+        if let Some(children) = self.sorted_children.get(&id) {
+            return Ok(children.clone());
+        }
+
+        let children = self.children(transaction, id)?;
+        let sort_order = self.sort_order;
+        let children = sort_cached_stackentries(transaction, self, children, sort_order);
+        self.sorted_children.insert(id, children.clone());
+
+        Ok(children)
+    }
 }
 
 impl StackEntryRow {
@@ -94,17 +351,26 @@ impl StackEntryRow {
     // and the open transaction to the database.
     fn new(
         transaction: &mut trace::Transaction,
+        cache: &mut TreeCache,
         entry: &WorkingEntry,
         has_children: bool,
     ) -> Option<StackEntryRow> {
         let location = transaction.location(entry.stackentry.location)?;
         let mut maximum_size = 0;
+        let mut held_size = 0;
         let mut total_blocks = 0;
+        let mut total_bytes = 0;
         let mut unfreed_blocks = 0;
-        if let Some(summary) = transaction.summary(entry.stackentry.id) {
+        let mut first_time = None;
+        let mut last_time = None;
+        if let Some(summary) = cache.summary(transaction, entry.stackentry.id) {
             maximum_size = summary.maximum_total;
+            held_size = summary.current_total;
             total_blocks = summary.alloc_count;
+            total_bytes = summary.total_bytes;
             unfreed_blocks = summary.alloc_count - summary.free_count;
+            first_time = summary.first_time;
+            last_time = summary.last_time;
         }
         if total_blocks == 0 {
             return None;
@@ -119,67 +385,434 @@ impl StackEntryRow {
             function: location.function?,
             offset: location.offset?,
             maximum_size,
+            held_size,
             total_blocks,
+            total_bytes,
             unfreed_blocks,
+            pruned: 0,
+            rolled_up: false,
+            first_time,
+            last_time,
         })
     }
+
+    // The values of the row as a summary, from which computed columns are
+    // computed.
+    pub fn summary(&self) -> trace::StackEntrySummary {
+        trace::StackEntrySummary {
+            stackentry: self.id,
+            current_total: self.held_size,
+            maximum_total: self.maximum_size,
+            alloc_count: self.total_blocks,
+            free_count: self.total_blocks - self.unfreed_blocks,
+            total_bytes: self.total_bytes,
+            first_time: self.first_time,
+            last_time: self.last_time,
+        }
+    }
+
+    // Generate a row standing for sibling stack entries below the pruning
+    // threshold, with their values summed.
+    fn new_pruned(
+        transaction: &mut trace::Transaction,
+        cache: &mut TreeCache,
+        entry: &WorkingEntry,
+    ) -> StackEntryRow {
+        let mut row = StackEntryRow {
+            id: entry.stackentry.id,
+            depth: entry.depth,
+            final_child_of_depth: entry.final_child_of_depth.clone(),
+            has_children: false,
+            address: 0,
+            function: format!("... {} other entries", entry.pruned.len()),
+            offset: 0,
+            maximum_size: 0,
+            held_size: 0,
+            total_blocks: 0,
+            total_bytes: 0,
+            unfreed_blocks: 0,
+            pruned: entry.pruned.len(),
+            rolled_up: false,
+            first_time: None,
+            last_time: None,
+        };
+        for stackentry in entry.pruned.iter() {
+            if let Some(summary) = cache.summary(transaction, stackentry.id) {
+                row.maximum_size += summary.maximum_total;
+                row.held_size += summary.current_total;
+                row.total_blocks += summary.alloc_count;
+                row.total_bytes += summary.total_bytes;
+                row.unfreed_blocks += summary.alloc_count - summary.free_count;
+                row.first_time = earliest_time(row.first_time, summary.first_time);
+                row.last_time = std::cmp::max(row.last_time, summary.last_time);
+            }
+        }
+
+        row
+    }
+}
+
+// The earlier of two times, either of which may be unknown.
+pub fn earliest_time(time: Option<u64>, other: Option<u64>) -> Option<u64> {
+    match (time, other) {
+        (Some(time), Some(other)) => Some(std::cmp::min(time, other)),
+        (time, other) => time.or(other),
+    }
+}
+
+// The totals of the peak bytes and the blocks of the roots of the call
+// tree.
+fn root_totals(transaction: &mut trace::Transaction) -> Result<(u64, u64), Box<dyn Error>> {
+    let mut bytes = 0;
+    let mut blocks = 0;
+    for root in transaction.root_stackentries()? {
+        if let Some(summary) = transaction.summary(root.id) {
+            bytes += summary.maximum_total;
+            blocks += summary.alloc_count;
+        }
+    }
+
+    Ok((bytes, blocks))
+}
+
+// The largest value of any row's columns, which no row can exceed, as each
+// stands for at most the totals of the roots.
+pub fn largest_value(transaction: &mut trace::Transaction) -> Result<u64, Box<dyn Error>> {
+    let (bytes, blocks) = root_totals(transaction)?;
+
+    Ok(std::cmp::max(bytes, blocks))
+}
+
+// The total bytes allocated by the roots of the call tree, which no row's
+// churn can exceed.
+pub fn largest_churn(transaction: &mut trace::Transaction) -> Result<u64, Box<dyn Error>> {
+    let mut churn = 0;
+    for root in transaction.root_stackentries()? {
+        if let Some(summary) = transaction.summary(root.id) {
+            churn += summary.total_bytes;
+        }
+    }
+
+    Ok(churn)
+}
+
+// The value of a summary measured by a sort mode, as drawn in the bar of
+// its row.  Modes which don't sort by a total of bytes or blocks, such as
+// computed columns, measure peak bytes.
+pub fn bar_value(summary: &trace::StackEntrySummary, mode: SortMode) -> u64 {
+    match mode {
+        SortMode::Held => summary.current_total,
+        SortMode::Blocks => summary.alloc_count,
+        SortMode::Leaks => summary.alloc_count - summary.free_count,
+        SortMode::Churn => summary.total_bytes,
+        SortMode::Bytes | SortMode::Column(_) | SortMode::Function | SortMode::None => {
+            summary.maximum_total
+        }
+    }
+}
+
+// The total of the roots of the call tree measured by a sort mode, of which
+// each row's bar shows a share.
+pub fn bar_total(
+    transaction: &mut trace::Transaction,
+    mode: SortMode,
+) -> Result<u64, Box<dyn Error>> {
+    let mut total = 0;
+    for root in transaction.root_stackentries()? {
+        if let Some(summary) = transaction.summary(root.id) {
+            total += bar_value(&summary, mode);
+        }
+    }
+
+    Ok(total)
+}
+
+// Compute the values below which stack entries are pruned, as a fraction of
+// the totals of the roots of the call tree.
+fn prune_limits(
+    transaction: &mut trace::Transaction,
+    fraction: f64,
+) -> Result<PruneLimits, Box<dyn Error>> {
+    let (bytes, blocks) = root_totals(transaction)?;
+
+    Ok(PruneLimits {
+        bytes: bytes as f64 * fraction,
+        blocks: blocks as f64 * fraction,
+    })
+}
+
+// Pair sibling stack entries with the entries below the pruning limits
+// which their rows stand for.  Entries above either limit stand for
+// nothing else, and the entries below both limits are gathered into a
+// final pair, along with any entries beyond the first 'top' kept.  A single
+// entry gathered is left as it is, because a row standing for it would
+// hide nothing.
+fn prune_stackentries(
+    transaction: &mut trace::Transaction,
+    cache: &mut TreeCache,
+    stackentries: Vec<trace::StackEntry>,
+    limits: Option<PruneLimits>,
+    top: Option<usize>,
+) -> Vec<(trace::StackEntry, Vec<trace::StackEntry>)> {
+    if limits.is_none() && top.is_none_or(|top| stackentries.len() <= top) {
+        return stackentries
+            .into_iter()
+            .map(|entry| (entry, Vec::new()))
+            .collect();
+    }
+
+    let mut kept = Vec::new();
+    let mut pruned = Vec::new();
+    for entry in stackentries {
+        let significant = match (limits, cache.summary(transaction, entry.id)) {
+            (Some(limits), Some(summary)) => {
+                summary.maximum_total as f64 >= limits.bytes
+                    || summary.alloc_count as f64 >= limits.blocks
+            }
+            _ => true,
+        };
+        if significant && top.is_none_or(|top| kept.len() < top) {
+            kept.push((entry, Vec::new()));
+        } else {
+            pruned.push(entry);
+        }
+    }
+    if pruned.len() == 1 {
+        kept.push((pruned.remove(0), Vec::new()));
+    } else if !pruned.is_empty() {
+        kept.push((pruned[0].clone(), pruned));
+    }
+
+    kept
+}
+
+// The name by which a stack entry is sorted when sorting by function.
+fn function_sort_name(transaction: &mut trace::Transaction, entry: &trace::StackEntry) -> String {
+    match transaction.location(entry.location) {
+        Some(location) => match location.function {
+            Some(function) if !function.is_empty() => demangle_function_name(&function),
+            _ => format!("0x{:x}", location.address),
+        },
+        None => String::new(),
+    }
 }
 
-// Sort stack entries by one of our sort modes.
+// The offset of a stack entry's location within its function, which
+// distinguishes call sites within the same function in stable reports.
+fn location_sort_offset(transaction: &mut trace::Transaction, entry: &trace::StackEntry) -> u64 {
+    match transaction.location(entry.location) {
+        Some(location) => location.offset.unwrap_or(0),
+        None => 0,
+    }
+}
+
+// Sort stack entries by one of our sort orders.
 pub fn sort_stackentries(
     transaction: &mut trace::Transaction,
     stackentries: &mut dyn Iterator<Item = trace::StackEntry>,
-    sort_mode: SortMode,
+    sort_order: SortOrder,
 ) -> Result<Vec<trace::StackEntry>, Box<dyn Error>> {
+    Ok(sort_cached_stackentries(
+        transaction,
+        &mut TreeCache::new(sort_order),
+        stackentries.collect(),
+        sort_order,
+    ))
+}
+
+// Sort stack entries by one of our sort orders, with their summaries read
+// through a cache.
+fn sort_cached_stackentries(
+    transaction: &mut trace::Transaction,
+    cache: &mut TreeCache,
+    stackentries: Vec<trace::StackEntry>,
+    sort_order: SortOrder,
+) -> Vec<trace::StackEntry> {
     // Filter out any entries without a summary.
-    let mut vec: Vec<trace::StackEntry> = stackentries
-        .filter(|entry| transaction.summary(entry.id).is_some())
+    let mut vec: Vec<(trace::StackEntry, trace::StackEntrySummary)> = stackentries
+        .into_iter()
+        .filter_map(|entry| {
+            let summary = cache.summary(transaction, entry.id)?;
+            Some((entry, summary))
+        })
         .collect();
 
-    match sort_mode {
-        SortMode::Bytes => vec.sort_by(|a, b| {
-            let summary_a = transaction.summary(a.id).unwrap();
-            let summary_b = transaction.summary(b.id).unwrap();
-            summary_b
-                .maximum_total
-                .partial_cmp(&summary_a.maximum_total)
-                .unwrap()
-        }),
+    // In stable reports, entries with equal values are ordered by their
+    // function, then by each of their values, and then by their offset in
+    // the function, rather than by the order in which they were recorded.  The sorts below are stable, so they
+    // keep this order for equal values.
+    if report::stable_output() {
+        vec.sort_by_cached_key(|(entry, summary)| {
+            (
+                function_sort_name(transaction, entry),
+                Reverse(summary.maximum_total),
+                Reverse(summary.current_total),
+                Reverse(summary.alloc_count),
+                Reverse(summary.free_count),
+                Reverse(summary.total_bytes),
+                location_sort_offset(transaction, entry),
+            )
+        });
+    }
+
+    match sort_order.mode {
+        SortMode::Bytes => vec.sort_by_key(|(_, summary)| Reverse(summary.maximum_total)),
+
+        SortMode::Held => vec.sort_by_key(|(_, summary)| Reverse(summary.current_total)),
+
+        SortMode::Blocks => vec.sort_by_key(|(_, summary)| Reverse(summary.alloc_count)),
+
+        SortMode::Leaks => {
+            vec.sort_by_key(|(_, summary)| Reverse(summary.alloc_count - summary.free_count))
+        }
+
+        SortMode::Churn => vec.sort_by_key(|(_, summary)| Reverse(summary.total_bytes)),
+
+        // Each value is computed once, rather than once per comparison.
+        SortMode::Column(index) => {
+            let mut computed: Vec<_> = vec
+                .into_iter()
+                .map(|(entry, summary)| {
+                    let value = columns::evaluate(index, &summary);
+                    (entry, summary, value)
+                })
+                .collect();
+            computed
+                .sort_by(|(_, _, value), (_, _, other)| columns::compare_values(*other, *value));
+            vec = computed
+                .into_iter()
+                .map(|(entry, summary, _)| (entry, summary))
+                .collect();
+        }
+
+        // Names are demangled once per entry, rather than once per
+        // comparison.
+        SortMode::Function => {
+            vec.sort_by_cached_key(|(entry, _)| function_sort_name(transaction, entry))
+        }
+
+        SortMode::None => {}
+    }
+    if sort_order.reversed {
+        vec.reverse();
+    }
+
+    vec.into_iter().map(|(entry, _)| entry).collect()
+}
+
+// The options with which function names are demangled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DemangleOptions {
+    // If false, names are shown as mangled in the trace, so that they
+    // match the symbols seen by other tools.
+    pub enabled: bool,
+
+    // If true, the hash suffixes of Rust names are kept.
+    pub rust_hashes: bool,
+
+    // If true, the template arguments of C++ names are kept.
+    pub cpp_templates: bool,
+}
+
+impl Default for DemangleOptions {
+    fn default() -> DemangleOptions {
+        DemangleOptions {
+            enabled: true,
+            rust_hashes: true,
+            cpp_templates: true,
+        }
+    }
+}
+
+impl DemangleOptions {
+    // Apply a comma separated list of options, such as 'no-hash' or
+    // 'no-templates', or 'none' to disable demangling.
+    pub fn apply(&mut self, spec: &str) -> Result<(), Box<dyn Error>> {
+        for option in spec.split(',').map(|option| option.trim()) {
+            match option {
+                "" => {}
+                "full" => *self = DemangleOptions::default(),
+                "hash" => self.rust_hashes = true,
+                "no-hash" => self.rust_hashes = false,
+                "no-templates" => self.cpp_templates = false,
+                "none" | "raw" => self.enabled = false,
+                "templates" => self.cpp_templates = true,
+                _ => Err(format!("invalid demangle option: {}", option))?,
+            }
+        }
+
+        Ok(())
+    }
+}
 
-        SortMode::Blocks => vec.sort_by(|a, b| {
-            let summary_a = transaction.summary(a.id).unwrap();
-            let summary_b = transaction.summary(b.id).unwrap();
-            summary_b
-                .alloc_count
-                .partial_cmp(&summary_a.alloc_count)
-                .unwrap()
-        }),
+// The options with which names are demangled, set once when the viewer
+// starts.
+static DEMANGLE_OPTIONS: sync::OnceLock<DemangleOptions> = sync::OnceLock::new();
 
-        SortMode::Leaks => vec.sort_by(|a, b| {
-            let summary_a = transaction.summary(a.id).unwrap();
-            let summary_b = transaction.summary(b.id).unwrap();
-            let leaks_a = summary_a.alloc_count - summary_a.free_count;
-            let leaks_b = summary_b.alloc_count - summary_b.free_count;
-            leaks_b.partial_cmp(&leaks_a).unwrap()
-        }),
+// Set the options with which names are demangled for the remainder of the
+// run.  Names demangled before this use the default options.
+pub fn set_demangle_options(options: DemangleOptions) {
+    let _ = DEMANGLE_OPTIONS.set(options);
+}
 
-        _ => {}
+// Remove the template arguments from a demangled C++ name, leaving the
+// angle brackets of operators such as 'operator<<' in place.
+fn strip_template_arguments(name: &str) -> String {
+    let mut stripped = String::new();
+    let mut depth = 0;
+    let mut in_operator = false;
+    for char in name.chars() {
+        if depth == 0
+            && (in_operator || stripped.ends_with("operator"))
+            && matches!(char, '<' | '>' | '=' | '-')
+        {
+            stripped.push(char);
+            in_operator = true;
+            continue;
+        }
+        in_operator = false;
+
+        match char {
+            '<' => {
+                // The space separating the arguments of a templated
+                // operator, as in 'operator<< <int>', goes with them.
+                if depth == 0 && stripped.ends_with(' ') {
+                    stripped.pop();
+                }
+                depth += 1;
+            }
+            '>' if depth > 0 => depth -= 1,
+            _ if depth == 0 => stripped.push(char),
+            _ => {}
+        }
     }
 
-    Ok(vec)
+    stripped
 }
 
 // Interpret any function name as potentially a C++ or Rust function and
-// demangle if possible.
-fn demangle_function_name(name: &str) -> String {
-    let mut function: String = name.to_string();
-    function = match cplus_demangle::demangle(&function) {
-        Ok(function) => function,
-        Err(_) => function,
-    };
-    function = rustc_demangle::demangle(&function).to_string();
+// demangle if possible, according to the demangling options.
+pub fn demangle_function_name(name: &str) -> String {
+    let options = DEMANGLE_OPTIONS.get().copied().unwrap_or_default();
+    if !options.enabled {
+        return name.to_string();
+    }
+
+    // Rust names are recognized first, as Rust's legacy mangling is also
+    // valid C++ mangling, which would keep the hash as a path component.
+    if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+        return if options.rust_hashes {
+            demangled.to_string()
+        } else {
+            format!("{:#}", demangled)
+        };
+    }
 
-    function
+    match cplus_demangle::demangle(name) {
+        Ok(function) if !options.cpp_templates => strip_template_arguments(&function),
+        Ok(function) => function,
+        Err(_) => name.to_string(),
+    }
 }
 
 // Given a set of stack entries, find the set of all ancestors of those stack
@@ -213,36 +846,87 @@ fn gather_ancestors(
 // Generate some number of rows for display from an open transaction to the database.
 pub fn iter_stackentry_rows(
     transaction: &mut trace::Transaction,
-    sort_mode: SortMode,
+    cache: &mut TreeCache,
     collapsed: Option<&collections::HashSet<trace::StackEntryId>>,
+    row_limits: RowLimits,
     skip_rows: usize,
     max_rows: usize,
 ) -> Result<Vec<StackEntryRow>, Box<dyn Error>> {
+    let mut rows = Vec::new();
+    for_each_stackentry_row(
+        transaction,
+        cache,
+        collapsed,
+        row_limits,
+        skip_rows,
+        max_rows,
+        |row| {
+            rows.push(row);
+            Ok(())
+        },
+    )?;
+
+    Ok(rows)
+}
+
+// Generate some number of rows from an open transaction to the database,
+// passing each to 'visit' as it is generated, so that the rows of a large
+// tree needn't all be held at once.
+pub fn for_each_stackentry_row(
+    transaction: &mut trace::Transaction,
+    cache: &mut TreeCache,
+    collapsed: Option<&collections::HashSet<trace::StackEntryId>>,
+    row_limits: RowLimits,
+    skip_rows: usize,
+    max_rows: usize,
+    mut visit: impl FnMut(StackEntryRow) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
     let collapsed_ancestors = gather_ancestors(transaction, collapsed)?;
+    let limits = match row_limits.prune {
+        Some(fraction) => Some(prune_limits(transaction, fraction)?),
+        None => None,
+    };
 
-    let mut rows = Vec::new();
+    let mut visited = 0;
     let mut entries: collections::VecDeque<WorkingEntry> = collections::VecDeque::new();
-    let roots = transaction.root_stackentries()?;
-    for stackentry in sort_stackentries(transaction, &mut roots.into_iter(), sort_mode)? {
+    let roots = cache.sorted_children(transaction, None)?;
+    for (stackentry, pruned) in
+        prune_stackentries(transaction, cache, roots, limits, row_limits.top)
+    {
         entries.push_back(WorkingEntry {
             stackentry,
             depth: 0,
             final_child_of_depth: Vec::new(),
+            pruned,
         })
     }
 
     let mut skipped = 0;
-    while rows.len() < max_rows {
+    while visited < max_rows {
         if let Some(entry) = entries.pop_front() {
-            let descendent_count = transaction.descendent_count(entry.stackentry.id)? as usize;
+            if !entry.pruned.is_empty() {
+                if skipped < skip_rows {
+                    skipped += 1;
+                } else {
+                    visit(StackEntryRow::new_pruned(transaction, cache, &entry))?;
+                    visited += 1;
+                }
+                continue;
+            }
+
+            let descendent_count = cache.descendent_count(transaction, entry.stackentry.id)?;
 
-            let mut row = StackEntryRow::new(transaction, &entry, descendent_count > 0)
-                .ok_or("failure retrieving entry row")?;
+            // Rows are only completed for the entries visited, so that
+            // skipping rows needn't read their locations.
             if skipped < skip_rows {
                 skipped += 1;
             } else {
+                let mut row = StackEntryRow::new(transaction, cache, &entry, descendent_count > 0)
+                    .ok_or("failure retrieving entry row")?;
+                row.rolled_up = row.has_children && !row_limits.includes_children_of(entry.depth);
                 row.function = demangle_function_name(&row.function);
-                rows.push(row);
+                visit(row)?;
+                visited += 1;
             }
 
             let entry_collapsed = match collapsed {
@@ -250,22 +934,25 @@ pub fn iter_stackentry_rows(
                 None => false,
             };
 
-            if !entry_collapsed {
+            if !entry_collapsed && row_limits.includes_children_of(entry.depth) {
                 // If we know no children are collapsed, we can use the
                 // precomputed descendent count to skip rows, which speeds
-                // up large traces to make the UI usable.
+                // up large traces to make the UI usable.  The count
+                // includes pruned descendents, so it can't be used when
+                // rows are limited.
                 if skipped + descendent_count < skip_rows
                     && !collapsed_ancestors.contains(&entry.stackentry.id)
+                    && row_limits == RowLimits::default()
                 {
                     skipped += descendent_count;
                 } else {
-                    let children = transaction.get_stackentry_children(entry.stackentry.id)?;
+                    let children = cache.sorted_children(transaction, Some(entry.stackentry.id))?;
 
                     let mut final_child = true;
                     // We are reversing here because we are pushing entries on
                     // the *front* of the working vector.
-                    for child in
-                        sort_stackentries(transaction, &mut children.into_iter(), sort_mode)?
+                    for (child, pruned) in
+                        prune_stackentries(transaction, cache, children, limits, row_limits.top)
                             .into_iter()
                             .rev()
                     {
@@ -277,6 +964,7 @@ pub fn iter_stackentry_rows(
                             stackentry: child,
                             depth: entry.depth + 1,
                             final_child_of_depth: final_child_of_depth,
+                            pruned,
                         });
                     }
                 }
@@ -286,7 +974,7 @@ pub fn iter_stackentry_rows(
         }
     }
 
-    Ok(rows)
+    Ok(())
 }
 
 // Count all the rows which can be potentially be displayed.  Used by
@@ -294,22 +982,36 @@ pub fn iter_stackentry_rows(
 // the trace.
 pub fn count_rows(
     transaction: &mut trace::Transaction,
+    cache: &mut TreeCache,
     collapsed: Option<&collections::HashSet<trace::StackEntryId>>,
+    row_limits: RowLimits,
 ) -> Result<usize, Box<dyn Error>> {
     let collapsed_ancestors = gather_ancestors(transaction, collapsed)?;
+    let limits = match row_limits.prune {
+        Some(fraction) => Some(prune_limits(transaction, fraction)?),
+        None => None,
+    };
     let mut count = 0;
 
     let mut entries: collections::VecDeque<WorkingEntry> = collections::VecDeque::new();
-    for stackentry in transaction.root_stackentries()? {
+    let roots = cache.children(transaction, None)?;
+    for (stackentry, pruned) in
+        prune_stackentries(transaction, cache, roots, limits, row_limits.top)
+    {
         entries.push_back(WorkingEntry {
             stackentry,
             depth: 0,
             final_child_of_depth: Vec::new(),
+            pruned,
         })
     }
 
     while let Some(entry) = entries.pop_front() {
-        if let Some(_) = StackEntryRow::new(transaction, &entry, false) {
+        if !entry.pruned.is_empty() {
+            count += 1;
+            continue;
+        }
+        if StackEntryRow::new(transaction, cache, &entry, false).is_some() {
             count += 1;
         } else {
             continue;
@@ -320,18 +1022,25 @@ pub fn count_rows(
             None => false,
         };
 
-        if !entry_collapsed {
-            let descendent_count = transaction.descendent_count(entry.stackentry.id)? as usize;
+        if !entry_collapsed && row_limits.includes_children_of(entry.depth) {
+            let descendent_count = cache.descendent_count(transaction, entry.stackentry.id)?;
 
-            if !collapsed_ancestors.contains(&entry.stackentry.id) {
+            if !collapsed_ancestors.contains(&entry.stackentry.id)
+                && row_limits == RowLimits::default()
+            {
                 count += descendent_count;
             } else {
-                let children = transaction.get_stackentry_children(entry.stackentry.id)?;
-                for ix in (0..children.len()).rev() {
+                let children = cache.children(transaction, Some(entry.stackentry.id))?;
+                for (child, pruned) in
+                    prune_stackentries(transaction, cache, children, limits, row_limits.top)
+                        .into_iter()
+                        .rev()
+                {
                     entries.push_front(WorkingEntry {
-                        stackentry: children[ix].clone(),
+                        stackentry: child,
                         depth: entry.depth + 1,
                         final_child_of_depth: Vec::new(),
+                        pruned,
                     });
                 }
             }
@@ -0,0 +1,217 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::focus;
+use crate::trace;
+use std::collections::HashMap;
+use std::error::Error;
+
+// The score of each character of the pattern matched.
+const SCORE_MATCH: i64 = 16;
+
+// The bonus for a character matched at the start of a word, such as after
+// '::' or '_', or at a change from lower to upper case.
+const BONUS_BOUNDARY: i64 = 8;
+
+// The bonus for a character matched immediately after the previous one.
+const BONUS_CONSECUTIVE: i64 = 6;
+
+// The penalty for the first character skipped between matched characters.
+const PENALTY_GAP_START: i64 = 3;
+
+// The penalty for each further character skipped.
+const PENALTY_GAP_EXTENSION: i64 = 1;
+
+// A function of the call tree, with the totals of the allocations made
+// through it.
+pub struct SearchableFunction {
+    // The name of the function.
+    pub function: String,
+
+    // The totals of allocations made through the function, counting
+    // recursive calls once.
+    pub totals: focus::FocusTotals,
+
+    // The entry of the function with the largest peak, selected when the
+    // function is chosen.
+    pub stackentry_id: trace::StackEntryId,
+
+    // The peak bytes of the selected entry.
+    stackentry_size: u64,
+}
+
+// Every function of the call tree, with those matching a pattern, for
+// finding functions not shown on screen.
+pub struct FunctionSearch {
+    // The functions of the call tree, in no particular order.
+    pub functions: Vec<SearchableFunction>,
+
+    // The pattern typed so far.
+    pub pattern: String,
+
+    // The indices of the functions matching the pattern, best match first.
+    pub matches: Vec<usize>,
+}
+
+// True if 'a' matches 'b', ignoring case unless 'case_sensitive'.
+fn chars_match(a: char, b: char, case_sensitive: bool) -> bool {
+    a == b || (!case_sensitive && a.to_lowercase().eq(b.to_lowercase()))
+}
+
+// True if 'current' starts a word, given the character before it.
+fn is_word_boundary(previous: char, current: char) -> bool {
+    !previous.is_alphanumeric() || (previous.is_lowercase() && current.is_uppercase())
+}
+
+// Score how well 'candidate' matches 'pattern', in the manner of fzf:
+// the characters of the pattern must appear in order, with matches at the
+// start of words and runs of adjacent matches scoring higher, and gaps
+// between them scoring lower.  Case is ignored unless the pattern has an
+// upper case character.  Returns None if the candidate doesn't match.
+pub fn fuzzy_score(pattern: &str, candidate: &str) -> Option<i64> {
+    let case_sensitive = pattern.chars().any(|c| c.is_uppercase());
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = candidate.chars().collect();
+    if pattern.is_empty() {
+        return Some(0);
+    }
+
+    // Find the earliest end of a match, then the latest start of a match
+    // ending there, for the shortest span containing the pattern.
+    let mut matched = 0;
+    let mut end = None;
+    for (index, c) in text.iter().enumerate() {
+        if chars_match(*c, pattern[matched], case_sensitive) {
+            matched += 1;
+            if matched == pattern.len() {
+                end = Some(index);
+                break;
+            }
+        }
+    }
+    let end = end?;
+    let mut start = end;
+    for index in (0..=end).rev() {
+        if chars_match(text[index], pattern[matched - 1], case_sensitive) {
+            matched -= 1;
+            if matched == 0 {
+                start = index;
+                break;
+            }
+        }
+    }
+
+    let mut score = 0;
+    let mut previous_match: Option<usize> = None;
+    for index in start..=end {
+        if matched < pattern.len() && chars_match(text[index], pattern[matched], case_sensitive) {
+            score += SCORE_MATCH;
+            if index == 0 || is_word_boundary(text[index - 1], text[index]) {
+                score += BONUS_BOUNDARY;
+            }
+            if previous_match == Some(index.wrapping_sub(1)) {
+                score += BONUS_CONSECUTIVE;
+            }
+            previous_match = Some(index);
+            matched += 1;
+        } else if previous_match == Some(index.wrapping_sub(1)) {
+            score -= PENALTY_GAP_START;
+        } else {
+            score -= PENALTY_GAP_EXTENSION;
+        }
+    }
+
+    Some(score)
+}
+
+impl FunctionSearch {
+    // Gather every named function of the summarized call tree, including
+    // those beneath collapsed, pruned or depth limited entries.
+    pub fn new(transaction: &mut trace::Transaction) -> Result<FunctionSearch, Box<dyn Error>> {
+        let mut functions: HashMap<String, SearchableFunction> = HashMap::new();
+
+        // Entries to visit, with the functions on the path above them.
+        let mut entries: Vec<(trace::StackEntry, Vec<String>)> = transaction
+            .root_stackentries()?
+            .into_iter()
+            .map(|root| (root, Vec::new()))
+            .collect();
+        while let Some((entry, mut path)) = entries.pop() {
+            let summary = match transaction.summary(entry.id) {
+                Some(summary) => summary,
+                None => continue,
+            };
+            let name = focus::entry_function(transaction, &entry);
+            if !name.is_empty() {
+                let function =
+                    functions
+                        .entry(name.clone())
+                        .or_insert_with(|| SearchableFunction {
+                            function: name.clone(),
+                            totals: focus::FocusTotals::default(),
+                            stackentry_id: entry.id,
+                            stackentry_size: summary.maximum_total,
+                        });
+                if !path.contains(&name) {
+                    function.totals.add(&summary);
+                }
+                if summary.maximum_total > function.stackentry_size {
+                    function.stackentry_id = entry.id;
+                    function.stackentry_size = summary.maximum_total;
+                }
+            }
+
+            path.push(name);
+            for child in transaction.get_stackentry_children(entry.id)? {
+                entries.push((child, path.clone()));
+            }
+        }
+
+        let mut search = FunctionSearch {
+            functions: functions.into_values().collect(),
+            pattern: String::new(),
+            matches: Vec::new(),
+        };
+        search.set_pattern("");
+        Ok(search)
+    }
+
+    // Match the functions against a new pattern.  Matches are ordered by
+    // score, and then by peak bytes, so that an empty pattern lists the
+    // largest functions first.
+    pub fn set_pattern(&mut self, pattern: &str) {
+        let mut scored: Vec<(i64, usize)> = self
+            .functions
+            .iter()
+            .enumerate()
+            .filter_map(|(index, function)| {
+                fuzzy_score(pattern, &function.function).map(|score| (score, index))
+            })
+            .collect();
+        scored.sort_by(|(score_a, a), (score_b, b)| {
+            let (a, b) = (&self.functions[*a], &self.functions[*b]);
+            score_b
+                .cmp(score_a)
+                .then(b.totals.maximum_size.cmp(&a.totals.maximum_size))
+                .then(a.function.cmp(&b.function))
+        });
+
+        self.pattern = pattern.to_string();
+        self.matches = scored.into_iter().map(|(_, index)| index).collect();
+    }
+}
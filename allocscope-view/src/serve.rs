@@ -0,0 +1,242 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::export;
+use crate::signals;
+use crate::trace;
+use std::error::Error;
+use std::io;
+use std::io::{BufRead, Read, Write};
+use std::net;
+use std::thread;
+use std::time;
+
+// The page of the web UI, which reads the call tree from the server as it
+// is browsed.
+const PAGE: &str = include_str!("serve.html");
+
+// The deepest subtree which can be requested at once.
+const MAX_REQUEST_DEPTH: usize = 32;
+
+// The interval at which the server checks for a request to stop while no
+// connection is waiting.
+const POLL_INTERVAL: time::Duration = time::Duration::from_millis(100);
+
+// The longest request line and headers read, so that a client can't make
+// the server buffer without bound.
+const MAX_REQUEST_SIZE: u64 = 8192;
+
+// The time a client is given to send its request.  Requests are handled
+// one at a time, so this is kept short, as an idle client holds up others.
+const REQUEST_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+// A request for part of the call tree.
+struct TreeRequest {
+    // The stack entry at the top of the subtree, or None for the whole tree.
+    id: Option<trace::StackEntryId>,
+
+    // The number of levels of descendents included.
+    depth: usize,
+
+    // The peak bytes below which descendents are left out.
+    min_bytes: u64,
+}
+
+// Parse the query of a request for part of the call tree.
+fn parse_tree_request(query: &str) -> Result<TreeRequest, Box<dyn Error>> {
+    let mut request = TreeRequest {
+        id: None,
+        depth: 1,
+        min_bytes: 0,
+    };
+    for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+        let (name, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+        match name {
+            "depth" => {
+                request.depth = std::cmp::min(value.parse()?, MAX_REQUEST_DEPTH);
+            }
+            "id" if !value.is_empty() => request.id = Some(value.parse()?),
+            "min" => request.min_bytes = value.parse()?,
+            _ => {}
+        }
+    }
+
+    Ok(request)
+}
+
+// Respond with part of the call tree, along with what the page needs to
+// know of the trace.
+fn tree_response(
+    trace: &trace::Trace,
+    trace_name: &str,
+    query: &str,
+) -> Result<String, Box<dyn Error>> {
+    let request = parse_tree_request(query)?;
+    let mut transaction = trace::Transaction::new(trace)?;
+    let entry = match request.id {
+        Some(id) => Some(transaction.stackentry(id).ok_or("no such stack entry")?),
+        None => None,
+    };
+
+    let mut json = format!(
+        "{{{},\"tree\":",
+        export::trace_json_fields(trace, trace_name)
+    );
+    export::write_tree_json(
+        &mut transaction,
+        entry.as_ref(),
+        request.depth,
+        request.min_bytes,
+        &mut json,
+    )?;
+    json.push('}');
+
+    Ok(json)
+}
+
+// Write an HTTP response.
+fn write_response(
+    stream: &mut net::TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> Result<(), Box<dyn Error>> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+        Cache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+
+    Ok(())
+}
+
+// Read a request from a connection and respond to it.  Only the page and
+// the call tree are served.  Requests too long to read whole are refused.
+fn handle_connection(
+    trace: &trace::Trace,
+    trace_name: &str,
+    mut stream: net::TcpStream,
+) -> Result<(), Box<dyn Error>> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let mut reader = io::BufReader::new(stream.try_clone()?.take(MAX_REQUEST_SIZE));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The headers are read but unused.  A line cut off without its newline
+    // has run past the size limit, or the client closed the connection.
+    let mut complete = request_line.ends_with('\n');
+    while complete {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        complete = header.ends_with('\n');
+        if header.trim().is_empty() {
+            break;
+        }
+    }
+    if !complete {
+        return write_response(
+            &mut stream,
+            "431 Request Header Fields Too Large",
+            "text/plain",
+            "request too long or incomplete\n",
+        );
+    }
+
+    let mut words = request_line.split_whitespace();
+    let (method, target) = match (words.next(), words.next()) {
+        (Some(method), Some(target)) => (method, target),
+        _ => {
+            return write_response(
+                &mut stream,
+                "400 Bad Request",
+                "text/plain",
+                "bad request\n",
+            )
+        }
+    };
+    if method != "GET" {
+        return write_response(
+            &mut stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            "only GET is supported\n",
+        );
+    }
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    match path {
+        "/" => write_response(&mut stream, "200 OK", "text/html; charset=utf-8", PAGE),
+        "/api/tree" => match tree_response(trace, trace_name, query) {
+            Ok(json) => write_response(&mut stream, "200 OK", "application/json", &json),
+            Err(err) => write_response(
+                &mut stream,
+                "400 Bad Request",
+                "text/plain",
+                &format!("{}\n", err),
+            ),
+        },
+        _ => write_response(&mut stream, "404 Not Found", "text/plain", "not found\n"),
+    }
+}
+
+// Serve the call tree over HTTP at an address, such as '127.0.0.1:8080',
+// until interrupted.  Requests are handled one at a time, as each reads
+// from the trace, with a short time given to clients to send them.
+pub fn serve(
+    trace: &trace::Trace,
+    atrace_filename: &str,
+    address: &str,
+) -> Result<(), Box<dyn Error>> {
+    let listener =
+        net::TcpListener::bind(address).map_err(|err| format!("{}: {}", address, err))?;
+    listener.set_nonblocking(true)?;
+    // Termination signals are taken between requests, so that the server
+    // stops cleanly.
+    signals::block_term_signals()?;
+
+    let trace_name = std::path::Path::new(atrace_filename)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| atrace_filename.to_string());
+    eprintln!(
+        "Serving {} at http://{}/  (Ctrl-C to stop)",
+        trace_name,
+        listener.local_addr()?
+    );
+
+    while !signals::is_term_signal_pending() {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                // A failed request doesn't stop the server.
+                if let Err(err) = handle_connection(trace, &trace_name, stream) {
+                    eprintln!("Request failed: {}", err);
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(err) => Err(err)?,
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,69 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::error::Error;
+use std::io;
+use std::ptr;
+
+// The set of signals requesting termination, SIGTERM and SIGINT.
+fn term_signals() -> libc::sigset_t {
+    unsafe {
+        let mut sigset = std::mem::MaybeUninit::<libc::sigset_t>::zeroed().assume_init();
+        libc::sigemptyset(&mut sigset);
+        libc::sigaddset(&mut sigset, libc::SIGTERM);
+        libc::sigaddset(&mut sigset, libc::SIGINT);
+
+        sigset
+    }
+}
+
+// Block the signals requesting termination, so that they can be taken at
+// a point where work can be stopped cleanly.
+pub fn block_term_signals() -> Result<(), Box<dyn Error>> {
+    let sigset = term_signals();
+    unsafe {
+        if libc::sigprocmask(libc::SIG_BLOCK, &sigset, ptr::null_mut()) == -1 {
+            Err(io::Error::last_os_error())?
+        }
+    }
+
+    Ok(())
+}
+
+// Unblock the signals requesting termination, so that they again end the
+// process as soon as they are received.
+pub fn unblock_term_signals() -> Result<(), Box<dyn Error>> {
+    let sigset = term_signals();
+    unsafe {
+        if libc::sigprocmask(libc::SIG_UNBLOCK, &sigset, ptr::null_mut()) == -1 {
+            Err(io::Error::last_os_error())?
+        }
+    }
+
+    Ok(())
+}
+
+// Returns true if a blocked termination signal is pending.
+pub fn is_term_signal_pending() -> bool {
+    unsafe {
+        let mut sigset = std::mem::MaybeUninit::<libc::sigset_t>::zeroed().assume_init();
+        libc::sigpending(&mut sigset) == 0
+            && (libc::sigismember(&sigset, libc::SIGTERM) != 0
+                || libc::sigismember(&sigset, libc::SIGINT) != 0)
+    }
+}
@@ -0,0 +1,289 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::trace;
+use object::{Object, ObjectSegment};
+use std::collections;
+use std::error::Error;
+use std::path;
+use std::process;
+
+// A line of source code.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceLocation {
+    // The path of the source file, as recorded in the debugging information.
+    pub filename: String,
+
+    // The line within the file, counting from one.
+    pub line: u64,
+}
+
+// Source locations previously found, and source files previously read, so
+// that addr2line isn't run again and files aren't read again each time the
+// UI is drawn.
+pub struct SourceCache {
+    // The source location of each address looked up, or the reason none
+    // was found.
+    locations: collections::HashMap<(u64, bool), Result<SourceLocation, String>>,
+
+    // The lines of each source file read, or None if it couldn't be read.
+    files: collections::HashMap<String, Option<Vec<String>>>,
+
+    // Rules replacing a prefix of the paths of source files, as given by
+    // --source-map, for sources built elsewhere.
+    source_map: Vec<(String, String)>,
+}
+
+// The addresses of locations within the object containing them, as used by
+// the object's debugging information, given the mapping of the object.
+fn object_addresses(module: &trace::Module, addresses: &[u64]) -> Result<Vec<u64>, String> {
+    let offset = module
+        .offset
+        .ok_or("the trace didn't record where objects were mapped")?;
+    let data =
+        std::fs::read(&module.filename).map_err(|err| format!("{}: {}", module.filename, err))?;
+    let object =
+        object::File::parse(&*data).map_err(|err| format!("{}: {}", module.filename, err))?;
+    let segments: Vec<(u64, u64, u64)> = object
+        .segments()
+        .map(|segment| {
+            let (start, size) = segment.file_range();
+            (start, size, segment.address())
+        })
+        .collect();
+
+    let mut object_addresses = Vec::new();
+    for address in addresses {
+        let file_offset = address - module.address + offset;
+        let segment = segments
+            .iter()
+            .find(|(start, size, _)| file_offset >= *start && file_offset < start + size)
+            .ok_or_else(|| {
+                format!(
+                    "{}: no segment contains offset 0x{:x}",
+                    module.filename, file_offset
+                )
+            })?;
+        object_addresses.push(segment.2 + file_offset - segment.0);
+    }
+
+    Ok(object_addresses)
+}
+
+// Parse a line written by addr2line, as 'FILE:LINE', possibly followed by
+// a discriminator, or as '??:0' if there is no debugging information.
+fn parse_addr2line_output(output: &str) -> Option<SourceLocation> {
+    let location = output.split(" (").next()?;
+    let (filename, line) = location.rsplit_once(':')?;
+    let line = line.parse::<u64>().ok()?;
+    if filename == "??" || line == 0 {
+        return None;
+    }
+
+    Some(SourceLocation {
+        filename: filename.to_string(),
+        line,
+    })
+}
+
+// Find the lines of source code of addresses within a single object, with
+// one run of addr2line.
+fn object_source_locations(
+    module: &trace::Module,
+    addresses: &[u64],
+) -> Result<Vec<Result<SourceLocation, String>>, String> {
+    let object_addresses = object_addresses(module, addresses)?;
+    let output = process::Command::new("addr2line")
+        .arg("-e")
+        .arg(&module.filename)
+        .args(
+            object_addresses
+                .iter()
+                .map(|address| format!("0x{:x}", address)),
+        )
+        .output()
+        .map_err(|err| format!("addr2line: {}", err))?;
+    let text = String::from_utf8_lossy(&output.stdout);
+
+    let mut lines = text.lines();
+    Ok(object_addresses
+        .iter()
+        .map(|address| {
+            lines
+                .next()
+                .and_then(parse_addr2line_output)
+                .ok_or_else(|| {
+                    format!(
+                        "{}: no debugging information for address 0x{:x}",
+                        module.filename, address
+                    )
+                })
+        })
+        .collect())
+}
+
+// Find the lines of source code of addresses in the traced process, using
+// addr2line from binutils to read the debugging information of the objects
+// containing them.  Return addresses are looked up a byte earlier, so that
+// the line of the call is found rather than the line following it.
+fn source_locations(
+    modules: &[trace::Module],
+    addresses: &[(u64, bool)],
+) -> Vec<Result<SourceLocation, String>> {
+    let mut locations: Vec<Result<SourceLocation, String>> = addresses
+        .iter()
+        .map(|(address, _)| Err(format!("no object contains address 0x{:x}", address)))
+        .collect();
+
+    // Addresses are grouped by the object containing them, keyed by the
+    // address at which it is mapped.
+    let mut by_module = collections::HashMap::new();
+    for (index, (address, return_address)) in addresses.iter().enumerate() {
+        if let Some(module) = trace::module_at(modules, *address) {
+            let lookup_address = if *return_address && *address > module.address {
+                address - 1
+            } else {
+                *address
+            };
+            by_module
+                .entry(module.address)
+                .or_insert((module, Vec::new()))
+                .1
+                .push((index, lookup_address));
+        }
+    }
+
+    for (module, entries) in by_module.into_values() {
+        let lookup_addresses: Vec<u64> = entries.iter().map(|(_, address)| *address).collect();
+        match object_source_locations(module, &lookup_addresses) {
+            Ok(found) => {
+                for ((index, _), location) in entries.iter().zip(found) {
+                    locations[*index] = location;
+                }
+            }
+            Err(err) => {
+                for (index, _) in entries.iter() {
+                    locations[*index] = Err(err.clone());
+                }
+            }
+        }
+    }
+
+    locations
+}
+
+// Parse a rule for remapping the paths of source files, as 'FROM=TO'.
+pub fn parse_source_map_rule(rule: &str) -> Result<(String, String), Box<dyn Error>> {
+    match rule.split_once('=') {
+        Some((from, to)) if !from.is_empty() => Ok((from.to_string(), to.to_string())),
+        _ => Err(format!("invalid source map: {}", rule))?,
+    }
+}
+
+impl SourceCache {
+    // Construct an empty cache, remapping source paths by rules given as
+    // pairs of prefixes.
+    pub fn new(source_map: Vec<(String, String)>) -> SourceCache {
+        SourceCache {
+            locations: collections::HashMap::new(),
+            files: collections::HashMap::new(),
+            source_map,
+        }
+    }
+
+    // The path at which a source file is read, replacing the prefix of the
+    // first matching rule of the source map.
+    pub fn remap(&self, filename: &str) -> String {
+        for (from, to) in self.source_map.iter() {
+            if let Some(rest) = filename.strip_prefix(from.as_str()) {
+                return format!("{}{}", to, rest);
+            }
+        }
+
+        filename.to_string()
+    }
+
+    // Find the lines of source code of addresses, looking up those not
+    // found before.
+    pub fn locations(
+        &mut self,
+        modules: &[trace::Module],
+        addresses: &[(u64, bool)],
+    ) -> Vec<Result<SourceLocation, String>> {
+        let missing: Vec<(u64, bool)> = addresses
+            .iter()
+            .filter(|address| !self.locations.contains_key(address))
+            .copied()
+            .collect();
+        if !missing.is_empty() {
+            for (address, location) in missing.iter().zip(source_locations(modules, &missing)) {
+                self.locations.insert(*address, location);
+            }
+        }
+
+        addresses
+            .iter()
+            .map(|address| self.locations[address].clone())
+            .collect()
+    }
+
+    // The lines of a source file, read through the source map, or None if
+    // it can't be read.
+    pub fn lines(&mut self, filename: &str) -> Option<&Vec<String>> {
+        let path = self.remap(filename);
+        self.files
+            .entry(path)
+            .or_insert_with_key(|path| {
+                std::fs::read(path).ok().map(|data| {
+                    String::from_utf8_lossy(&data)
+                        .lines()
+                        .map(|line| line.to_string())
+                        .collect()
+                })
+            })
+            .as_ref()
+    }
+}
+
+// Open a line of source code in the editor named by VISUAL or EDITOR, or
+// in vi if neither is set, waiting for the editor to exit.  The editor may
+// be given with arguments, such as 'code --wait'.
+pub fn open_in_editor(location: &SourceLocation) -> Result<(), Box<dyn Error>> {
+    if !path::Path::new(&location.filename).is_file() {
+        Err(format!("source file not found: {}", location.filename))?;
+    }
+
+    let editor = ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let status = process::Command::new(program)
+        .args(words)
+        .arg(format!("+{}", location.line))
+        .arg(&location.filename)
+        .status()
+        .map_err(|err| format!("{}: {}", program, err))?;
+    if !status.success() {
+        Err(format!("{} exited with {}", program, status))?;
+    }
+
+    Ok(())
+}
@@ -16,113 +16,488 @@
     with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
+use crate::signals;
 use crate::trace;
+use crate::transform;
+use std::collections::HashMap;
 use std::error::Error;
 use std::io;
 use std::io::Write;
 use std::time;
 
-// Add an allocation to the summary for its stack entry and all ancestor
-// stack entries.
-fn add_to_summary(
-    transaction: &mut trace::Transaction,
-    bottom_id: trace::StackEntryId,
-    allocation: bool,
-    size: i64,
-) -> Result<(), Box<dyn Error>> {
-    let mut id = Some(bottom_id);
-    while let Some(entry_id) = id {
-        if let Some(stackentry) = transaction.stackentry(entry_id) {
-            transaction.add_to_summary(entry_id, allocation, size)?;
-            id = stackentry.next;
-        } else {
-            break;
-        }
-    }
-
-    Ok(())
+// A span of time in the trace, in nanoseconds since the start of the trace,
+// to which summaries can be limited.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeRange {
+    // The earliest time included.
+    pub start: u64,
+
+    // The latest time included.
+    pub end: u64,
 }
 
-// Given an allocation, track its originating event as indexed by address,
-// and add its size to the stack entry summaries.
-fn process_alloc(
-    transaction: &mut trace::Transaction,
-    event: &trace::Event,
-) -> Result<(), Box<dyn Error>> {
-    transaction.insert_allocation_origin(event.address, event.id)?;
-    if let Some(callstack_id) = event.callstack {
-        if let Some(size) = event.size {
-            add_to_summary(transaction, callstack_id, true, size as i64)?;
+impl TimeRange {
+    // The range including the whole of a trace.
+    pub fn all() -> TimeRange {
+        TimeRange {
+            start: 0,
+            end: u64::MAX,
         }
     }
 
-    Ok(())
+    // The length of the range, in nanoseconds, within a trace of the given
+    // duration.
+    pub fn span(&self, duration: u64) -> u64 {
+        std::cmp::min(self.end, duration).saturating_sub(self.start)
+    }
+
+    // True if the range includes a time.
+    pub fn contains(&self, time: u64) -> bool {
+        time >= self.start && time <= self.end
+    }
+}
+
+// The span of a trace from the start of the first phase named 'start' to
+// the start of the first phase named 'end' which follows it.
+pub fn phase_range(
+    phases: &[trace::Phase],
+    start: &str,
+    end: &str,
+) -> Result<TimeRange, Box<dyn Error>> {
+    let start_index = phases
+        .iter()
+        .position(|phase| phase.name == start)
+        .ok_or_else(|| format!("no phase named {} in the trace", start))?;
+    let end_phase = phases[start_index..]
+        .iter()
+        .find(|phase| phase.name == end)
+        .ok_or_else(|| format!("no phase named {} following {} in the trace", end, start))?;
+
+    Ok(TimeRange {
+        start: phases[start_index].time,
+        end: end_phase.time,
+    })
+}
+
+// The point reached in summarizing a trace, from which the summary can be
+// extended as more of a trace still being recorded is written.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SummaryPosition {
+    // The last event summarized.
+    pub event_id: trace::EventId,
+
+    // The last stack entry counted as a descendent of its ancestors.
+    pub stackentry_id: trace::StackEntryId,
+}
+
+// The number of allocations made for each recorded at a time, given the
+// changes to the sampling of allocations.
+pub fn sample_period_at(sample_periods: &[trace::SamplePeriod], time: u64) -> u64 {
+    let changes = sample_periods.partition_point(|change| change.time <= time);
+    if changes == 0 {
+        1
+    } else {
+        sample_periods[changes - 1].period
+    }
+}
+
+// The number of events read from the trace at once while summarizing, or
+// while otherwise going through all events.
+pub const EVENT_CHUNK_SIZE: usize = 65536;
+
+// The number of events summarized between checks for a signal requesting
+// that summarization stop.
+const INTERRUPT_CHECK_INTERVAL: usize = 1024;
+
+// Where the stack entry summarized for a block not yet freed is found.
+enum LiveBlockOrigin {
+    // The block was allocated by an event summarized in this pass, which
+    // mapped to a stack entry and had a size.
+    Summarized {
+        callstack: trace::StackEntryId,
+        size: u64,
+        weight: u64,
+    },
+
+    // The block was allocated by an event summarized in this pass which
+    // didn't contribute to any summary.
+    Unsummarized,
+
+    // The block was allocated by an event summarized in an earlier pass,
+    // and is found by reading that event.
+    Saved,
+}
+
+// A block allocated and not yet freed.
+struct LiveBlock {
+    // The event which allocated the block.
+    event_id: trace::EventId,
+
+    // How to find the stack entry summarized for the block.
+    origin: LiveBlockOrigin,
+}
+
+// The state of a summary in progress.  Summaries, descendent counts and the
+// blocks not yet freed are kept in memory while the events are processed,
+// and written to the scratch database together when complete, rather than
+// being read and rewritten for every event.
+struct Summarizer {
+    // The summaries changed so far, by stack entry.
+    summaries: HashMap<trace::StackEntryId, trace::StackEntrySummary>,
+
+    // The parent of each stack entry visited, or None if it has no parent.
+    parents: HashMap<trace::StackEntryId, Option<trace::StackEntryId>>,
+
+    // The descendent counts changed so far, by stack entry.
+    descendent_counts: HashMap<trace::StackEntryId, u64>,
+
+    // The blocks not yet freed, by address.
+    live_blocks: HashMap<u64, LiveBlock>,
+
+    // The addresses of blocks allocated in an earlier pass and freed in this
+    // one.
+    freed_saved_blocks: Vec<u64>,
+
+    // If false, the trace was recorded with --no-free-tracking, so blocks
+    // are never freed and live blocks aren't tracked.
+    free_tracking: bool,
 }
 
-// Given a free event, remove the originating event from the address index
-// and update the stack entry summaries.
-fn process_free(
-    transaction: &mut trace::Transaction,
-    event: &trace::Event,
-) -> Result<(), Box<dyn Error>> {
-    if let Some(alloc_event_id) = transaction.allocation_origin(event.address) {
-        if let Some(alloc_event) = transaction.event(alloc_event_id) {
-            if let Some(callstack_id) = alloc_event.callstack {
-                if let Some(size) = alloc_event.size {
-                    add_to_summary(transaction, callstack_id, false, -(size as i64))?;
+impl Summarizer {
+    // Start a summary, continuing from the blocks left unfreed by any
+    // earlier pass.
+    fn new(transaction: &mut trace::Transaction) -> Result<Summarizer, Box<dyn Error>> {
+        let mut live_blocks = HashMap::new();
+        for (address, event_id) in transaction.allocation_origins()? {
+            live_blocks.insert(
+                address,
+                LiveBlock {
+                    event_id,
+                    origin: LiveBlockOrigin::Saved,
+                },
+            );
+        }
+
+        Ok(Summarizer {
+            summaries: HashMap::new(),
+            parents: HashMap::new(),
+            descendent_counts: HashMap::new(),
+            live_blocks,
+            freed_saved_blocks: Vec::new(),
+            free_tracking: transaction
+                .trace
+                .has_capability(trace::CAPABILITY_FREE_TRACKING),
+        })
+    }
+
+    // The parent of a stack entry, or Err if the stack entry doesn't exist.
+    fn parent(
+        &mut self,
+        transaction: &mut trace::Transaction,
+        id: trace::StackEntryId,
+    ) -> Result<Option<trace::StackEntryId>, Box<dyn Error>> {
+        if let Some(parent) = self.parents.get(&id) {
+            return Ok(*parent);
+        }
+
+        let parent = transaction.stackentry(id).ok_or("missing stackentry")?.next;
+        self.parents.insert(id, parent);
+
+        Ok(parent)
+    }
+
+    // The summary for a stack entry, to be changed.
+    fn summary_mut(
+        &mut self,
+        transaction: &mut trace::Transaction,
+        id: trace::StackEntryId,
+    ) -> &mut trace::StackEntrySummary {
+        self.summaries
+            .entry(id)
+            .or_insert_with(|| match transaction.summary(id) {
+                Some(summary) => summary,
+                None => trace::StackEntrySummary {
+                    stackentry: id,
+                    current_total: 0,
+                    maximum_total: 0,
+                    alloc_count: 0,
+                    free_count: 0,
+                    total_bytes: 0,
+                    first_time: None,
+                    last_time: None,
+                },
+            })
+    }
+
+    // The number of allocations summarized for a stack entry.
+    fn alloc_count(&self, transaction: &mut trace::Transaction, id: trace::StackEntryId) -> u64 {
+        match self.summaries.get(&id) {
+            Some(summary) => summary.alloc_count,
+            None => match transaction.summary(id) {
+                Some(summary) => summary.alloc_count,
+                None => 0,
+            },
+        }
+    }
+
+    // Add an allocation or free at 'time' to the summary for its stack
+    // entry and all ancestor stack entries.  A sampled allocation stands in
+    // for 'weight' allocations.
+    fn add_to_summary(
+        &mut self,
+        transaction: &mut trace::Transaction,
+        bottom_id: trace::StackEntryId,
+        allocation: bool,
+        size: i64,
+        weight: u64,
+        time: u64,
+    ) {
+        let size = size * weight as i64;
+        let mut id = Some(bottom_id);
+        while let Some(entry_id) = id {
+            id = match self.parent(transaction, entry_id) {
+                Ok(parent) => parent,
+                Err(_) => break,
+            };
+
+            let summary = self.summary_mut(transaction, entry_id);
+            let new_total = summary.current_total as i64 + size;
+            summary.current_total = new_total as u64;
+            summary.maximum_total = std::cmp::max(new_total, summary.maximum_total as i64) as u64;
+            if allocation {
+                summary.alloc_count += weight;
+                summary.total_bytes = (summary.total_bytes as i64 + size) as u64;
+                if summary.first_time.is_none() {
+                    summary.first_time = Some(time);
                 }
+            } else {
+                summary.free_count += weight;
             }
+            summary.last_time = std::cmp::max(summary.last_time, Some(time));
         }
     }
-    transaction.remove_allocation_origin(event.address)?;
 
-    Ok(())
-}
+    // Given an allocation, track its originating event as indexed by
+    // address, and add its size to the stack entry summaries.  Without free
+    // tracking, addresses are reused with no free between allocations, so
+    // blocks aren't tracked.
+    fn process_alloc(
+        &mut self,
+        transaction: &mut trace::Transaction,
+        tree_builder: &mut transform::TreeBuilder,
+        sample_periods: &[trace::SamplePeriod],
+        event: &trace::Event,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.free_tracking && self.live_blocks.contains_key(&event.address) {
+            Err(format!(
+                "address {:#x} allocated again before being freed",
+                event.address
+            ))?;
+        }
+
+        let mut origin = LiveBlockOrigin::Unsummarized;
+        if let Some(callstack) = tree_builder.map_callstack(transaction, event)? {
+            if let Some(size) = event.size {
+                let weight = sample_period_at(sample_periods, event.time);
+                self.add_to_summary(
+                    transaction,
+                    callstack,
+                    true,
+                    size as i64,
+                    weight,
+                    event.time,
+                );
+                origin = LiveBlockOrigin::Summarized {
+                    callstack,
+                    size,
+                    weight,
+                };
+            }
+        }
+        if !self.free_tracking {
+            return Ok(());
+        }
+        self.live_blocks.insert(
+            event.address,
+            LiveBlock {
+                event_id: event.id,
+                origin,
+            },
+        );
 
-// Given a stack entry, increment the descendent count for all ancestors
-// of that entry.
-fn increment_descendent_counts(
-    transaction: &mut trace::Transaction,
-    stackentry: trace::StackEntryId,
-) -> Result<(), Box<dyn Error>> {
-    let mut total_blocks = 0;
-    if let Some(summary) = transaction.summary(stackentry) {
-        total_blocks = summary.alloc_count;
-    }
-    if total_blocks == 0 {
-        return Ok(());
-    }
-
-    let mut ancestor = transaction
-        .stackentry(stackentry)
-        .ok_or("missing stackentry")?
-        .next;
-    while ancestor.is_some() {
-        let ancestor_id = ancestor.unwrap();
-        transaction.increment_descendents(ancestor_id)?;
-        ancestor = transaction
-            .stackentry(ancestor_id)
-            .ok_or("missing stackentry")?
-            .next;
-    }
-
-    Ok(())
+        Ok(())
+    }
+
+    // Given a free event, stop tracking the block freed and update the stack
+    // entry summaries.
+    fn process_free(
+        &mut self,
+        transaction: &mut trace::Transaction,
+        tree_builder: &mut transform::TreeBuilder,
+        sample_periods: &[trace::SamplePeriod],
+        event: &trace::Event,
+    ) -> Result<(), Box<dyn Error>> {
+        let block = match self.live_blocks.remove(&event.address) {
+            Some(block) => block,
+            None => return Ok(()),
+        };
+
+        match block.origin {
+            LiveBlockOrigin::Summarized {
+                callstack,
+                size,
+                weight,
+            } => {
+                self.add_to_summary(
+                    transaction,
+                    callstack,
+                    false,
+                    -(size as i64),
+                    weight,
+                    event.time,
+                );
+            }
+            LiveBlockOrigin::Unsummarized => (),
+            LiveBlockOrigin::Saved => {
+                self.freed_saved_blocks.push(event.address);
+                if let Some(alloc_event) = transaction.event(block.event_id) {
+                    if let Some(callstack) =
+                        tree_builder.map_callstack(transaction, &alloc_event)?
+                    {
+                        if let Some(size) = alloc_event.size {
+                            let weight = sample_period_at(sample_periods, alloc_event.time);
+                            self.add_to_summary(
+                                transaction,
+                                callstack,
+                                false,
+                                -(size as i64),
+                                weight,
+                                event.time,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Given the totals for a callstack from a trace recorded with
+    // --aggregate, add them to the summaries of its stack entries.  The
+    // peaks of the aggregates are summed, since their times aren't known,
+    // so the maximum of an entry with several aggregates is an upper bound.
+    fn process_aggregate(
+        &mut self,
+        transaction: &mut trace::Transaction,
+        tree_builder: &mut transform::TreeBuilder,
+        aggregate: &trace::Aggregate,
+    ) -> Result<(), Box<dyn Error>> {
+        // Stand in an event for the aggregate, so the callstack is mapped to
+        // the displayed tree in the same way as a recorded event.
+        let event = trace::Event {
+            id: 0,
+            allocation: true,
+            address: 0,
+            size: None,
+            callstack: Some(aggregate.callstack),
+            task: aggregate.task,
+            thread: None,
+            cpu: None,
+            time: 0,
+        };
+
+        let mut id = tree_builder.map_callstack(transaction, &event)?;
+        while let Some(entry_id) = id {
+            id = match self.parent(transaction, entry_id) {
+                Ok(parent) => parent,
+                Err(_) => break,
+            };
+
+            let summary = self.summary_mut(transaction, entry_id);
+            summary.current_total += aggregate.current_bytes;
+            summary.maximum_total += aggregate.peak_bytes;
+            summary.alloc_count += aggregate.alloc_count;
+            summary.free_count += aggregate.free_count;
+        }
+
+        Ok(())
+    }
+
+    // Given a stack entry, increment the descendent count for all ancestors
+    // of that entry.
+    fn increment_descendent_counts(
+        &mut self,
+        transaction: &mut trace::Transaction,
+        stackentry: trace::StackEntryId,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.alloc_count(transaction, stackentry) == 0 {
+            return Ok(());
+        }
+
+        let mut ancestor = self.parent(transaction, stackentry)?;
+        while let Some(ancestor_id) = ancestor {
+            let count = match self.descendent_counts.get(&ancestor_id) {
+                Some(count) => *count,
+                None => transaction.descendent_count(ancestor_id)?,
+            };
+            self.descendent_counts.insert(ancestor_id, count + 1);
+            ancestor = self.parent(transaction, ancestor_id)?;
+        }
+
+        Ok(())
+    }
+
+    // Write the summaries, descendent counts and blocks not yet freed to the
+    // scratch database.
+    fn save(self, transaction: &mut trace::Transaction) -> Result<(), Box<dyn Error>> {
+        for address in &self.freed_saved_blocks {
+            transaction.remove_allocation_origin(*address)?;
+        }
+        for (address, block) in &self.live_blocks {
+            if !matches!(block.origin, LiveBlockOrigin::Saved) {
+                transaction.insert_allocation_origin(*address, block.event_id)?;
+            }
+        }
+        for summary in self.summaries.values() {
+            transaction.set_summary(summary)?;
+        }
+        for (stackentry, count) in &self.descendent_counts {
+            transaction.set_descendent_count(*stackentry, *count)?;
+        }
+
+        Ok(())
+    }
 }
 
-// Print to stdout an indication of how complete the summarization process is.
+// Print to stdout an indication of how complete the summarization process
+// is, with an estimate of the time remaining from the rate of progress since
+// processing began after 'first_id'.
 fn summary_progress(
     start: time::Instant,
     now: time::Instant,
     noun: &str,
+    first_id: u64,
     current_id: u64,
     max_id: u64,
 ) {
+    let done = current_id.saturating_sub(first_id);
+    let remaining = max_id.saturating_sub(current_id);
+    let percent = (current_id * 100).checked_div(max_id).unwrap_or(100);
+    let estimate = if done > 0 && remaining > 0 {
+        let left = (now - start).mul_f64(remaining as f64 / done as f64);
+        format!(", about {:.1?} left", left)
+    } else {
+        String::new()
+    };
     print!(
-        "{}/{} {} processed ({:.2?})        \r",
+        "{}/{} {} processed, {}% ({:.2?}{})        \r",
         current_id,
         max_id,
         noun,
-        now - start
+        percent,
+        now - start,
+        estimate
     );
     io::stdout().flush().unwrap();
 }
@@ -130,76 +505,199 @@ fn summary_progress(
 // Process a complete trace.  For each stack entry, generate a summary of the
 // allocations made by each of its descendents.  Also, count the total number
 // of descendents for each stack entry.
+//
+// The stack entries summarized are those of the tree built by
+// 'tree_builder', which may differ from the callstacks in the trace.  Only
+// events within 'time_range' are summarized, so blocks allocated within the
+// range but freed after it are counted as unfreed.
 pub fn summarize_allocations(
     trace: &mut trace::Trace,
+    tree_builder: &mut transform::TreeBuilder,
+    time_range: TimeRange,
+    show_progress: bool,
+) -> Result<SummaryPosition, Box<dyn Error>> {
+    extend_summary(
+        trace,
+        tree_builder,
+        time_range,
+        SummaryPosition::default(),
+        show_progress,
+    )
+}
+
+// Add the events and stack entries written after 'position' to the summary
+// of a trace, returning the position reached.  Summaries of traces made with
+// --aggregate can't be extended, as their totals are rewritten in place, so
+// they must be cleared and summarized again.
+//
+// If termination signals are blocked and one is received, summarization
+// stops at the event reached, before counting descendents, and the summary
+// can be extended from the position returned.
+pub fn extend_summary(
+    trace: &mut trace::Trace,
+    tree_builder: &mut transform::TreeBuilder,
+    time_range: TimeRange,
+    position: SummaryPosition,
     show_progress: bool,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<SummaryPosition, Box<dyn Error>> {
     let mut start_time = time::Instant::now();
     let mut last_time = start_time - time::Duration::new(1, 0);
 
-    let max_event_id = trace.max_event_id()?;
-    let max_stackentry_id = trace.max_stackentry_id()?;
+    trace.use_scratch_tree(!tree_builder.is_identity());
+
+    // Traces recorded with --aggregate hold totals by callstack in place
+    // of events.
+    let (aggregates, max_event_id) = if trace.has_capability(trace::CAPABILITY_AGGREGATE) {
+        if time_range != TimeRange::all() {
+            Err("traces made with --aggregate can't be limited to a time range")?;
+        }
+        if position != SummaryPosition::default() {
+            Err("summaries of traces made with --aggregate can't be extended")?;
+        }
+        (trace.aggregates()?, 0)
+    } else {
+        (Vec::new(), trace.max_event_id()?)
+    };
+    let sample_periods = trace.sample_periods()?;
+    let max_stackentry_id;
+    let mut last_event_id = position.event_id;
+    let mut interrupted = false;
     {
         let mut transaction = trace::Transaction::new(&trace)?;
+        let mut summarizer = Summarizer::new(&mut transaction)?;
 
-        // Go through all events, adding allocations and frees to the summary.
-        for event_id in 1..=max_event_id {
-            if show_progress {
-                let now = time::Instant::now();
-                if now - last_time > time::Duration::from_millis(100) {
-                    summary_progress(start_time, now, "events", event_id, max_event_id);
-                    last_time = now;
-                }
+        for aggregate in &aggregates {
+            if let Err(error) =
+                summarizer.process_aggregate(&mut transaction, tree_builder, aggregate)
+            {
+                eprintln!("Error processing aggregate: {:?}", error);
             }
+        }
+
+        // Go through all events, adding allocations and frees to the
+        // summary.  A pending termination signal stops summarization after
+        // the last event processed, from which it can be extended later.
+        let mut after_id = position.event_id;
+        'events: loop {
+            let events = transaction.events(after_id, max_event_id, EVENT_CHUNK_SIZE)?;
+            if events.is_empty() {
+                break;
+            }
+
+            for (index, event) in events.iter().enumerate() {
+                if show_progress {
+                    let now = time::Instant::now();
+                    if now - last_time > time::Duration::from_millis(100) {
+                        summary_progress(
+                            start_time,
+                            now,
+                            "events",
+                            position.event_id,
+                            event.id,
+                            max_event_id,
+                        );
+                        last_time = now;
+                    }
+                }
+                if index % INTERRUPT_CHECK_INTERVAL == 0 && signals::is_term_signal_pending() {
+                    interrupted = true;
+                    break 'events;
+                }
+
+                // Events are recorded in the order of their times.
+                if event.time > time_range.end {
+                    break 'events;
+                }
+                last_event_id = event.id;
+                if !time_range.contains(event.time) {
+                    continue;
+                }
 
-            if let Some(event) = transaction.event(event_id) {
                 let result = if event.allocation {
-                    process_alloc(&mut transaction, &event)
+                    summarizer.process_alloc(&mut transaction, tree_builder, &sample_periods, event)
                 } else {
-                    process_free(&mut transaction, &event)
+                    summarizer.process_free(&mut transaction, tree_builder, &sample_periods, event)
                 };
                 match result {
                     Err(error) => eprintln!("Error processing event: {:?}", error),
                     Ok(_) => (),
                 }
             }
+            after_id = events[events.len() - 1].id;
         }
 
         if show_progress {
             let now = time::Instant::now();
-            summary_progress(start_time, now, "events", max_event_id, max_event_id);
+            let reached_id = if interrupted {
+                last_event_id
+            } else {
+                max_event_id
+            };
+            summary_progress(
+                start_time,
+                now,
+                "events",
+                position.event_id,
+                reached_id,
+                max_event_id,
+            );
             println!("");
             start_time = now;
         }
 
+        // The derived tree is complete now that all events are processed.
+        max_stackentry_id = trace.max_stackentry_id()?;
+
         // Go through all stackentries, incrementing the descendent count of
-        // their ancestors for each.
-        for stackentry_id in 1..=max_stackentry_id {
-            if show_progress {
-                let now = time::Instant::now();
-                if now - last_time > time::Duration::from_millis(100) {
-                    summary_progress(start_time, now, "frames", stackentry_id, max_stackentry_id);
-                    last_time = now;
+        // their ancestors for each.  Descendents are counted only once all
+        // events are summarized, so an interrupted summary has no counts.
+        if !interrupted {
+            for stackentry_id in position.stackentry_id + 1..=max_stackentry_id {
+                if show_progress {
+                    let now = time::Instant::now();
+                    if now - last_time > time::Duration::from_millis(100) {
+                        summary_progress(
+                            start_time,
+                            now,
+                            "frames",
+                            position.stackentry_id,
+                            stackentry_id,
+                            max_stackentry_id,
+                        );
+                        last_time = now;
+                    }
                 }
-            }
 
-            increment_descendent_counts(&mut transaction, stackentry_id)?;
+                summarizer.increment_descendent_counts(&mut transaction, stackentry_id)?;
+            }
         }
 
+        summarizer.save(&mut transaction)?;
         transaction.commit()?;
     }
 
+    if interrupted {
+        return Ok(SummaryPosition {
+            event_id: last_event_id,
+            stackentry_id: position.stackentry_id,
+        });
+    }
+
     if show_progress {
         let end_time = time::Instant::now();
         summary_progress(
             start_time,
             end_time,
             "frames",
+            position.stackentry_id,
             max_stackentry_id,
             max_stackentry_id,
         );
         println!("");
     }
 
-    Ok(())
+    Ok(SummaryPosition {
+        event_id: std::cmp::max(max_event_id, position.event_id),
+        stackentry_id: max_stackentry_id,
+    })
 }
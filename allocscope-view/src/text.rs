@@ -0,0 +1,93 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+// The character ending text truncated to fit its space.
+const ELLIPSIS: char = '…';
+
+// The number of terminal columns occupied by text.  Wide characters, such
+// as those of CJK scripts, occupy two columns, and combining characters
+// none.
+pub fn width(text: &str) -> usize {
+    UnicodeWidthStr::width(text)
+}
+
+// The text remaining after skipping its first 'columns' terminal columns.
+// A wide character straddling the boundary is skipped along with any
+// combining characters following it, so that the text is never split
+// within a character.
+pub fn skip_columns(text: &str, columns: usize) -> &str {
+    let mut skipped = 0;
+    for (index, char) in text.char_indices() {
+        let char_width = char.width().unwrap_or(0);
+        if skipped >= columns && char_width > 0 {
+            return &text[index..];
+        }
+        skipped += char_width;
+    }
+
+    ""
+}
+
+// Truncate text to fit within 'width' terminal columns, ending it with an
+// ellipsis if anything was cut off.
+pub fn truncate(text: &str, width: usize) -> String {
+    if self::width(text) <= width {
+        return text.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+
+    let mut truncated = String::new();
+    let mut used = 0;
+    for char in text.chars() {
+        let char_width = char.width().unwrap_or(0);
+        if used + char_width > width - 1 {
+            break;
+        }
+        truncated.push(char);
+        used += char_width;
+    }
+    truncated.push(ELLIPSIS);
+
+    truncated
+}
+
+// Truncate text, or pad it with spaces, to fill exactly 'width' terminal
+// columns.
+pub fn fit(text: &str, width: usize) -> String {
+    let mut fitted = truncate(text, width);
+    let padding = width.saturating_sub(self::width(&fitted));
+    fitted.extend(std::iter::repeat_n(' ', padding));
+
+    fitted
+}
+
+// The width of the terminal to which stdout is written, or None if stdout
+// isn't a terminal.
+pub fn terminal_width() -> Option<usize> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if result == 0 && size.ws_col > 0 {
+        Some(size.ws_col as usize)
+    } else {
+        None
+    }
+}
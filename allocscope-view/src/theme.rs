@@ -0,0 +1,121 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::error::Error;
+
+// The index of the color pair used for the selected row and column.
+const SELECTED_PAIR: i16 = 2;
+
+// The index of the color pair used for headers and key help.
+const HEADER_PAIR: i16 = 3;
+
+// A color scheme for the curses UI.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Theme {
+    // Black headers on green, with the selection in white on blue.
+    Default,
+
+    // Colors suited to terminals with a light background.
+    Light,
+
+    // Muted colors for terminals with a dark background, using the 256
+    // color palette where available.
+    Dark,
+
+    // No colors, for monochrome terminals, using reverse video and
+    // underlining instead.
+    Mono,
+}
+
+// The attributes with which parts of the UI are drawn.
+#[derive(Clone, Copy, Debug)]
+pub struct Palette {
+    // Column headers and key help.
+    pub header: pancurses::chtype,
+
+    // The selected row, and the column by which rows are sorted.
+    pub selected: pancurses::chtype,
+}
+
+// Foreground and background colors for the headers and the selection.
+struct ThemeColors {
+    // The foreground and background of headers.
+    header: (i16, i16),
+
+    // The foreground and background of the selection.
+    selected: (i16, i16),
+}
+
+impl Theme {
+    // Parse a theme from its commandline name.
+    pub fn parse(name: &str) -> Result<Theme, Box<dyn Error>> {
+        match name {
+            "default" => Ok(Theme::Default),
+            "light" => Ok(Theme::Light),
+            "dark" => Ok(Theme::Dark),
+            "mono" => Ok(Theme::Mono),
+            _ => Err(format!("unknown theme: {}", name).into()),
+        }
+    }
+
+    // The colors of the theme, given the number of colors the terminal
+    // supports.
+    fn colors(&self, color_count: i32) -> ThemeColors {
+        let palette_256 = color_count >= 256;
+        match self {
+            Theme::Light if palette_256 => ThemeColors {
+                header: (231, 25),
+                selected: (16, 153),
+            },
+            Theme::Light => ThemeColors {
+                header: (pancurses::COLOR_WHITE, pancurses::COLOR_BLUE),
+                selected: (pancurses::COLOR_BLACK, pancurses::COLOR_CYAN),
+            },
+            Theme::Dark if palette_256 => ThemeColors {
+                header: (16, 108),
+                selected: (231, 24),
+            },
+            _ => ThemeColors {
+                header: (pancurses::COLOR_BLACK, pancurses::COLOR_GREEN),
+                selected: (pancurses::COLOR_WHITE, pancurses::COLOR_BLUE),
+            },
+        }
+    }
+
+    // Initialize the terminal's colors for the theme, returning the
+    // attributes to draw with.  Terminals without color are drawn as
+    // monochrome whatever the theme.
+    pub fn init(&self) -> Palette {
+        if *self == Theme::Mono || !pancurses::has_colors() {
+            return Palette {
+                header: pancurses::A_REVERSE,
+                selected: pancurses::A_REVERSE | pancurses::A_UNDERLINE,
+            };
+        }
+
+        pancurses::start_color();
+        let colors = self.colors(pancurses::COLORS());
+        pancurses::init_pair(SELECTED_PAIR, colors.selected.0, colors.selected.1);
+        pancurses::init_pair(HEADER_PAIR, colors.header.0, colors.header.1);
+
+        Palette {
+            header: pancurses::COLOR_PAIR(HEADER_PAIR as pancurses::chtype),
+            selected: pancurses::COLOR_PAIR(SELECTED_PAIR as pancurses::chtype),
+        }
+    }
+}
@@ -0,0 +1,149 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::summary;
+use crate::trace;
+use std::collections::HashMap;
+use std::error::Error;
+
+// The number of intervals into which the duration of a trace is divided.
+// Plots of the timeline combine neighboring intervals.
+const TIMELINE_INTERVALS: usize = 1024;
+
+// The bytes live over the duration of a trace.
+pub struct Timeline {
+    // The time of the last event, in nanoseconds since the start of the
+    // trace.
+    pub duration: u64,
+
+    // The maximum bytes live during each interval of the trace.
+    intervals: Vec<u64>,
+}
+
+impl Timeline {
+    // Compute the timeline from the events of a trace.  Traces recorded
+    // with --aggregate have no events, and so have no timeline.
+    pub fn new(trace: &trace::Trace) -> Result<Option<Timeline>, Box<dyn Error>> {
+        if trace.has_capability(trace::CAPABILITY_AGGREGATE) {
+            return Ok(None);
+        }
+        let sample_periods = trace.sample_periods()?;
+        let max_event_id = trace.max_event_id()?;
+        let mut transaction = trace::Transaction::new(trace)?;
+        let duration = match transaction.event(max_event_id) {
+            Some(event) => event.time,
+            None => return Ok(None),
+        };
+
+        // The maximum and final bytes live in each interval with events.
+        let mut intervals: Vec<Option<(u64, u64)>> = vec![None; TIMELINE_INTERVALS];
+        let mut live_blocks: HashMap<u64, u64> = HashMap::new();
+        let mut live_bytes: u64 = 0;
+        for event_id in 1..=max_event_id {
+            let event = match transaction.event(event_id) {
+                Some(event) => event,
+                None => continue,
+            };
+
+            if event.allocation {
+                if let Some(size) = event.size {
+                    let weighted_size =
+                        size * summary::sample_period_at(&sample_periods, event.time);
+                    live_blocks.insert(event.address, weighted_size);
+                    live_bytes += weighted_size;
+                }
+            } else if let Some(size) = live_blocks.remove(&event.address) {
+                live_bytes -= size;
+            }
+
+            let interval =
+                (event.time as u128 * TIMELINE_INTERVALS as u128 / (duration as u128 + 1)) as usize;
+            let (maximum, last) = intervals[interval].get_or_insert((0, 0));
+            *maximum = std::cmp::max(*maximum, live_bytes);
+            *last = live_bytes;
+        }
+
+        // Intervals without events hold the bytes live at the end of the
+        // previous interval.
+        let mut previous = 0;
+        let intervals = intervals
+            .into_iter()
+            .map(|interval| match interval {
+                Some((maximum, last)) => {
+                    previous = last;
+                    maximum
+                }
+                None => previous,
+            })
+            .collect();
+
+        Ok(Some(Timeline {
+            duration,
+            intervals,
+        }))
+    }
+
+    // The maximum bytes live at any time in the trace.
+    pub fn peak(&self) -> u64 {
+        self.intervals.iter().copied().max().unwrap_or(0)
+    }
+
+    // The maximum bytes live in each of 'count' equal spans of the trace,
+    // for plotting the timeline in 'count' columns.
+    pub fn columns(&self, count: usize) -> Vec<u64> {
+        (0..count)
+            .map(|column| {
+                let start = column * TIMELINE_INTERVALS / count;
+                let end = std::cmp::max((column + 1) * TIMELINE_INTERVALS / count, start + 1);
+                self.intervals[start..end.min(TIMELINE_INTERVALS)]
+                    .iter()
+                    .copied()
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect()
+    }
+
+    // The first time in an interval of the trace.
+    fn interval_start(&self, interval: usize) -> u64 {
+        let span = self.duration as u128 + 1;
+        ((interval as u128 * span).div_ceil(TIMELINE_INTERVALS as u128)) as u64
+    }
+
+    // The column, of 'count' columns, in which a time is plotted.
+    pub fn column_at(&self, time: u64, count: usize) -> usize {
+        let interval = std::cmp::min(
+            (time as u128 * TIMELINE_INTERVALS as u128 / (self.duration as u128 + 1)) as usize,
+            TIMELINE_INTERVALS - 1,
+        );
+        (0..count)
+            .rev()
+            .find(|column| column * TIMELINE_INTERVALS / count <= interval)
+            .unwrap_or(0)
+    }
+
+    // The first and last times plotted in a column, of 'count' columns.
+    pub fn column_span(&self, column: usize, count: usize) -> (u64, u64) {
+        let start = column * TIMELINE_INTERVALS / count;
+        let end = std::cmp::max((column + 1) * TIMELINE_INTERVALS / count, start + 1);
+        (
+            self.interval_start(start),
+            self.interval_start(end.min(TIMELINE_INTERVALS)) - 1,
+        )
+    }
+}
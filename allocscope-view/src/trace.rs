@@ -17,8 +17,17 @@
 */
 
 use rusqlite;
+use std::collections::HashMap;
 use std::error::Error;
 
+// The capability of a trace in which frees were recorded, so that leaks
+// can be reported.
+pub const CAPABILITY_FREE_TRACKING: &str = "free-tracking";
+
+// The capability of a trace holding per-callstack aggregates, rather than
+// individual events.
+pub const CAPABILITY_AGGREGATE: &str = "aggregate";
+
 // Primary key identifiers for tables in the trace.
 pub type EventId = u64;
 pub type StackEntryId = u64;
@@ -41,6 +50,18 @@ pub struct Event {
 
     // The leaf stack entry of the callstack when the event was generated.
     pub callstack: Option<StackEntryId>,
+
+    // The async task being polled when the event was generated.
+    pub task: Option<u64>,
+
+    // The thread id of the thread which generated the event.
+    pub thread: Option<u64>,
+
+    // The CPU on which the event was generated.
+    pub cpu: Option<u64>,
+
+    // The time of the event, in nanoseconds since the start of the trace.
+    pub time: u64,
 }
 
 // A row from the stack entry table.  A callstack consists of multiple chained
@@ -74,6 +95,232 @@ pub struct Location {
     pub offset: Option<u64>,
 }
 
+// Failed allocations from a single callstack.
+#[derive(Clone, Debug)]
+pub struct FailureSummary {
+    // The leaf stack entry of the callstack, as recorded in the trace.
+    pub callstack: Option<StackEntryId>,
+
+    // The number of failed allocations.
+    pub count: u64,
+
+    // The total number of bytes requested by the failed allocations.
+    pub bytes: u64,
+}
+
+// A row from the mapping table, recording a change to the mappings of the
+// traced process.
+#[derive(Clone, Debug)]
+pub struct MappingChange {
+    // If true, the range was mapped.  If false, it was unmapped.
+    pub mapped: bool,
+
+    // The first address of the range.
+    pub address: u64,
+
+    // The length of the range in bytes.
+    pub size: u64,
+
+    // The kind of memory mapped: "anonymous", "file" or "shared".  Not
+    // present for unmapped ranges, or ranges moved by mremap.
+    pub kind: Option<String>,
+
+    // The path of the file mapped, if any.
+    pub path: Option<String>,
+}
+
+// A change to the sampling of allocations made by the tracer to limit its
+// overhead.  From 'time', one allocation in 'period' was recorded.
+#[derive(Clone, Debug)]
+pub struct SamplePeriod {
+    // The time of the change, in nanoseconds since the start of the trace.
+    pub time: u64,
+
+    // The number of allocations made for each one recorded.
+    pub period: u64,
+}
+
+// A thread of the traced process which generated events.
+#[derive(Clone, Debug)]
+pub struct TraceThread {
+    // The thread id.
+    pub tid: u64,
+
+    // The last name recorded for the thread, if any.
+    pub name: Option<String>,
+
+    // The number of events generated by the thread.
+    pub event_count: u64,
+}
+
+// The start of a named phase of the traced program, marked by its call to
+// allocscope_phase.
+#[derive(Clone, Debug)]
+pub struct Phase {
+    // The time the phase started, in nanoseconds since the start of the
+    // trace.
+    pub time: u64,
+
+    // The name of the phase.
+    pub name: String,
+}
+
+// A burst of allocation marked by the tracer.
+#[derive(Clone, Debug)]
+pub struct Burst {
+    // The time of the first allocation of the burst, in nanoseconds since
+    // the start of the trace.
+    pub time: u64,
+
+    // The time from the first allocation of the burst to the last, in
+    // nanoseconds.
+    pub duration: u64,
+
+    // The total bytes allocated during the burst.
+    pub bytes: u64,
+
+    // The leaf stack entry of the callstack which allocated the most
+    // during the burst.
+    pub callstack: Option<StackEntryId>,
+}
+
+// The thread which crashed the traced process.
+#[derive(Clone, Debug)]
+pub struct Crash {
+    // The time of the crash, in nanoseconds since the start of the trace.
+    pub time: u64,
+
+    // The thread id of the crashing thread.
+    pub thread: u64,
+
+    // The signal which terminated the process.
+    pub signal: i32,
+
+    // For a fault, the address which caused it.
+    pub fault_address: Option<u64>,
+
+    // The general purpose registers of the thread, by name.
+    pub registers: Vec<(String, u64)>,
+
+    // The leaf stack entry of the callstack of the crashing thread.
+    pub callstack: Option<StackEntryId>,
+}
+
+// The sample with the most memory charged to the traced process's cgroup.
+#[derive(Clone, Debug)]
+pub struct CgroupMemoryPeak {
+    // The time of the sample, in nanoseconds since the start of the trace.
+    pub time: u64,
+
+    // The time of the sample before the peak, or the start of the trace.
+    pub previous_time: u64,
+
+    // The memory charged to the cgroup, in bytes.
+    pub current: u64,
+
+    // The limit of the cgroup's memory, if it has one.
+    pub max: Option<u64>,
+}
+
+// Bytes allocated from a single callstack over a span of time.
+#[derive(Clone, Debug)]
+pub struct CallstackGrowth {
+    // The leaf stack entry of the callstack, as recorded in the trace.
+    pub callstack: Option<StackEntryId>,
+
+    // The number of allocations made.
+    pub count: u64,
+
+    // The total number of bytes allocated.
+    pub bytes: u64,
+}
+
+// An executable mapping of an object in the traced process.
+#[derive(Clone, Debug)]
+pub struct Module {
+    // The address at which the object is mapped.
+    pub address: u64,
+
+    // The size of the mapping in bytes.
+    pub size: u64,
+
+    // The path of the mapped object.
+    pub filename: String,
+
+    // The offset within the object at which the mapping starts, if the
+    // trace recorded it.
+    pub offset: Option<u64>,
+}
+
+// Find the module containing an address, given modules ordered by address.
+pub fn module_at(modules: &[Module], address: u64) -> Option<&Module> {
+    let index = modules.partition_point(|module| module.address <= address);
+    let module = modules.get(index.checked_sub(1)?)?;
+    if address >= module.address + module.size {
+        return None;
+    }
+
+    Some(module)
+}
+
+// The description of a session of a trace, recorded so that a trace file
+// describes itself.  Traces recorded by older versions of the tracer lack
+// some of it.
+#[derive(Clone, Debug, Default)]
+pub struct TraceInfo {
+    // The version of the tracer which recorded the session.
+    pub version: Option<String>,
+
+    // The time at which tracing started and ended, in UTC.
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+
+    // How tracing ended, as 'exit' or 'detach'.
+    pub ending: Option<String>,
+
+    // The commandline, process-id and host of the traced process.
+    pub command: Option<String>,
+    pub pid: Option<String>,
+    pub host: Option<String>,
+
+    // The tracer options which limited the events recorded.
+    pub filters: Option<String>,
+
+    // The number of events not stored in the trace.
+    pub dropped_events: Option<u64>,
+
+    // The window of events kept with --retain, in seconds, and the number
+    // of events pruned from the trace as they fell out of it.
+    pub retained: Option<u64>,
+    pub pruned_events: Option<u64>,
+
+    // The number of events stored in the trace.
+    pub event_count: u64,
+}
+
+// A row from the aggregate table of a trace recorded with --aggregate,
+// holding running totals for the allocations from a callstack.
+#[derive(Clone, Debug)]
+pub struct Aggregate {
+    // The leaf stack entry of the callstack.
+    pub callstack: StackEntryId,
+
+    // The async task being polled when the allocations were made.
+    pub task: Option<u64>,
+
+    // The number of bytes still allocated at the end of the trace.
+    pub current_bytes: u64,
+
+    // The largest number of bytes allocated at once.
+    pub peak_bytes: u64,
+
+    // The number of allocations made.
+    pub alloc_count: u64,
+
+    // The number of allocations freed.
+    pub free_count: u64,
+}
+
 // A row from the stack entry summary table.
 #[derive(Clone, Debug)]
 pub struct StackEntrySummary {
@@ -93,6 +340,17 @@ pub struct StackEntrySummary {
 
     // The number of allocations freed so far.
     pub free_count: u64,
+
+    // The total number of bytes allocated so far, including those freed.
+    // Traces recorded with --aggregate don't record this total.
+    pub total_bytes: u64,
+
+    // The time of the first allocation, in nanoseconds since the start of
+    // the trace.  Traces recorded with --aggregate don't record times.
+    pub first_time: Option<u64>,
+
+    // The time of the last allocation or free.
+    pub last_time: Option<u64>,
 }
 
 // SQLite database connections for a trace.
@@ -102,6 +360,19 @@ pub struct Trace {
 
     // The scratch database ued by allocscope-view for summaries.
     scratch_connection: rusqlite::Connection,
+
+    // If true, the displayed call tree has been derived from the trace's
+    // callstacks and stored in the scratch database, rather than being read
+    // directly from the trace.
+    scratch_tree: bool,
+
+    // The session of the trace being viewed, for traces with sessions.
+    session: Option<u64>,
+
+    // The columns of the event table added by later versions of the
+    // tracer, as selected for each event, with NULL in place of those
+    // missing from the trace.
+    event_columns: String,
 }
 
 // A SQLite transaction used to retrieve data from the trace and summarize.
@@ -115,6 +386,9 @@ pub struct Transaction<'trace_lifetime> {
     // Prepared SQL for retrieving an event by id.
     event_statement: rusqlite::Statement<'trace_lifetime>,
 
+    // Prepared SQL for retrieving the events following an event, in order.
+    events_statement: rusqlite::Statement<'trace_lifetime>,
+
     // Prepared SQL for retrieving a stack entry by id.
     stackentry_statement: rusqlite::Statement<'trace_lifetime>,
 
@@ -128,9 +402,22 @@ pub struct Transaction<'trace_lifetime> {
     // Prepared SQL for retrieving all children of a stack entry.
     get_stackentry_children_statement: rusqlite::Statement<'trace_lifetime>,
 
-    // Prepared SQL for retrieving the originating event by allocation
-    // address.
-    allocation_origin_statement: rusqlite::Statement<'trace_lifetime>,
+    // Prepared SQL for retrieving a stack entry by id from the trace, even
+    // when the displayed tree is derived in the scratch database.
+    original_stackentry_statement: rusqlite::Statement<'trace_lifetime>,
+
+    // Prepared SQL for retrieving a location by id from the trace.
+    original_location_statement: rusqlite::Statement<'trace_lifetime>,
+
+    // Prepared SQL for inserting a location into the derived tree.
+    insert_tree_location_statement: rusqlite::Statement<'trace_lifetime>,
+
+    // Prepared SQL for inserting a stack entry into the derived tree.
+    insert_tree_stackentry_statement: rusqlite::Statement<'trace_lifetime>,
+
+    // Prepared SQL for retrieving the originating events of all allocations
+    // not yet freed.
+    allocation_origins_statement: rusqlite::Statement<'trace_lifetime>,
 
     // Prepared SQL for inserting the originating event by allocation address.
     insert_allocation_origin_statement: rusqlite::Statement<'trace_lifetime>,
@@ -143,13 +430,13 @@ pub struct Transaction<'trace_lifetime> {
     summary_statement: rusqlite::Statement<'trace_lifetime>,
 
     // Prepared SQL for replacing the summary values for a stackentry id.
-    add_to_summary_statement: rusqlite::Statement<'trace_lifetime>,
+    set_summary_statement: rusqlite::Statement<'trace_lifetime>,
 
     // Prepared SQL for retrieving the descendent count for a stackentry.
     descendent_count: rusqlite::Statement<'trace_lifetime>,
 
     // Prepared SQL for replacing the descendent count for a stackentry.
-    add_to_descendent_count: rusqlite::Statement<'trace_lifetime>,
+    set_descendent_count: rusqlite::Statement<'trace_lifetime>,
 }
 
 impl<'trace_lifetime> Transaction<'trace_lifetime> {
@@ -158,30 +445,45 @@ impl<'trace_lifetime> Transaction<'trace_lifetime> {
         trace: &'trace_lifetime Trace,
     ) -> Result<Transaction<'trace_lifetime>, Box<dyn Error>> {
         trace.scratch_connection.execute("BEGIN TRANSACTION", [])?;
+        let tree_connection = trace.tree_connection();
 
         Ok(Transaction {
             trace: trace,
             complete: false,
 
-            event_statement: trace
-                .atrace_connection
-                .prepare("SELECT allocation, address, size, callstack FROM event WHERE id = ?")?,
-            stackentry_statement: trace
-                .atrace_connection
+            event_statement: trace.atrace_connection.prepare(&format!(
+                "SELECT allocation, address, size, callstack, {}, time FROM event WHERE id = ?",
+                trace.event_columns
+            ))?,
+            events_statement: trace.atrace_connection.prepare(&format!(
+                "SELECT id, allocation, address, size, callstack, {}, time
+                    FROM event WHERE id > ? AND id <= ? ORDER BY id LIMIT ?",
+                trace.event_columns
+            ))?,
+            stackentry_statement: tree_connection
                 .prepare("SELECT location, next FROM stackentry WHERE id = ?")?,
-            location_statement: trace
-                .atrace_connection
+            location_statement: tree_connection
                 .prepare("SELECT address, function, offset FROM location WHERE id = ?")?,
-            root_stackentries_statement: trace
-                .atrace_connection
+            root_stackentries_statement: tree_connection
                 .prepare("SELECT id, location FROM stackentry WHERE next IS NULL")?,
-            get_stackentry_children_statement: trace
-                .atrace_connection
+            get_stackentry_children_statement: tree_connection
                 .prepare("SELECT id, location FROM stackentry WHERE next = ?")?,
+            original_stackentry_statement: trace
+                .atrace_connection
+                .prepare("SELECT location, next FROM stackentry WHERE id = ?")?,
+            original_location_statement: trace
+                .atrace_connection
+                .prepare("SELECT address, function, offset FROM location WHERE id = ?")?,
+            insert_tree_location_statement: trace
+                .scratch_connection
+                .prepare("INSERT INTO location (address, function, offset) VALUES (?, ?, ?)")?,
+            insert_tree_stackentry_statement: trace
+                .scratch_connection
+                .prepare("INSERT INTO stackentry (location, next) VALUES (?, ?)")?,
 
-            allocation_origin_statement: trace
+            allocation_origins_statement: trace
                 .scratch_connection
-                .prepare("SELECT event FROM allocation_origin WHERE address = ?")?,
+                .prepare("SELECT address, event FROM allocation_origin")?,
             insert_allocation_origin_statement: trace.scratch_connection.prepare(
                 "INSERT INTO allocation_origin (address, event)
                     VALUES (?, ?)",
@@ -190,18 +492,20 @@ impl<'trace_lifetime> Transaction<'trace_lifetime> {
                 .scratch_connection
                 .prepare("DELETE FROM allocation_origin WHERE address = ?")?,
             summary_statement: trace.scratch_connection.prepare(
-                "SELECT current_total, maximum_total, alloc_count, free_count
+                "SELECT current_total, maximum_total, alloc_count, free_count, total_bytes,
+                        first_time, last_time
                     FROM stackentry_summary WHERE stackentry = ?",
             )?,
-            add_to_summary_statement: trace.scratch_connection.prepare(
+            set_summary_statement: trace.scratch_connection.prepare(
                 "INSERT OR REPLACE INTO stackentry_summary
-                    (stackentry, current_total, maximum_total, alloc_count, free_count)
-                    VALUES (?, ?, ?, ?, ?)",
+                    (stackentry, current_total, maximum_total, alloc_count, free_count,
+                        total_bytes, first_time, last_time)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
             )?,
             descendent_count: trace.scratch_connection.prepare(
                 "SELECT descendent_count FROM stackentry_descendents WHERE stackentry = ?",
             )?,
-            add_to_descendent_count: trace.scratch_connection.prepare(
+            set_descendent_count: trace.scratch_connection.prepare(
                 "INSERT OR REPLACE INTO stackentry_descendents
                     (stackentry, descendent_count) VALUES (?, ?)",
             )?,
@@ -231,9 +535,42 @@ impl<'trace_lifetime> Transaction<'trace_lifetime> {
             address: row.get(1).ok()?,
             size: row.get(2).ok(),
             callstack: row.get(3).ok(),
+            task: row.get(4).ok(),
+            thread: row.get(5).ok(),
+            cpu: row.get(6).ok(),
+            time: row.get(7).unwrap_or(0),
         })
     }
 
+    // Retrieve up to 'limit' events following the event 'after', up to and
+    // including the event 'last', in order.
+    pub fn events(
+        &mut self,
+        after: EventId,
+        last: EventId,
+        limit: usize,
+    ) -> Result<Vec<Event>, Box<dyn Error>> {
+        let mut rows = self
+            .events_statement
+            .query(rusqlite::params![after, last, limit])?;
+        let mut events = Vec::new();
+        while let Some(row) = rows.next()? {
+            events.push(Event {
+                id: row.get(0)?,
+                allocation: row.get(1)?,
+                address: row.get(2)?,
+                size: row.get(3).ok(),
+                callstack: row.get(4).ok(),
+                task: row.get(5).ok(),
+                thread: row.get(6).ok(),
+                cpu: row.get(7).ok(),
+                time: row.get(8).unwrap_or(0),
+            });
+        }
+
+        Ok(events)
+    }
+
     // Retrieve a stack entry by id.
     pub fn stackentry(&mut self, id: StackEntryId) -> Option<StackEntry> {
         let mut rows = self
@@ -262,6 +599,63 @@ impl<'trace_lifetime> Transaction<'trace_lifetime> {
         })
     }
 
+    // Retrieve a stack entry by id from the trace itself, rather than from
+    // the displayed tree.
+    pub fn original_stackentry(&mut self, id: StackEntryId) -> Option<StackEntry> {
+        let mut rows = self
+            .original_stackentry_statement
+            .query(rusqlite::params![id])
+            .ok()?;
+        let row = rows.next().ok()??;
+
+        Some(StackEntry {
+            id,
+            location: row.get(0).ok()?,
+            next: row.get(1).ok(),
+        })
+    }
+
+    // Retrieve a location by id from the trace itself.
+    pub fn original_location(&mut self, id: LocationId) -> Option<Location> {
+        let mut rows = self
+            .original_location_statement
+            .query(rusqlite::params![id])
+            .ok()?;
+        let row = rows.next().ok()??;
+
+        Some(Location {
+            id,
+            address: row.get(0).ok()?,
+            function: row.get(1).ok(),
+            offset: row.get(2).ok(),
+        })
+    }
+
+    // Insert a location into the derived tree, returning its id.
+    pub fn insert_tree_location(
+        &mut self,
+        address: u64,
+        function: &str,
+        offset: u64,
+    ) -> Result<LocationId, Box<dyn Error>> {
+        self.insert_tree_location_statement
+            .execute(rusqlite::params![address, function, offset])?;
+
+        Ok(self.trace.scratch_connection.last_insert_rowid() as LocationId)
+    }
+
+    // Insert a stack entry into the derived tree, returning its id.
+    pub fn insert_tree_stackentry(
+        &mut self,
+        location: LocationId,
+        next: Option<StackEntryId>,
+    ) -> Result<StackEntryId, Box<dyn Error>> {
+        self.insert_tree_stackentry_statement
+            .execute(rusqlite::params![location, next])?;
+
+        Ok(self.trace.scratch_connection.last_insert_rowid() as StackEntryId)
+    }
+
     // Get all stack entries which have no children.
     pub fn root_stackentries(&mut self) -> Result<Vec<StackEntry>, Box<dyn Error>> {
         let mut entries = Vec::new();
@@ -300,15 +694,16 @@ impl<'trace_lifetime> Transaction<'trace_lifetime> {
         Ok(entries)
     }
 
-    // Retrieve the originating event for an allocation given its address.
-    pub fn allocation_origin(&mut self, address: u64) -> Option<EventId> {
-        let mut rows = self
-            .allocation_origin_statement
-            .query(rusqlite::params![address])
-            .ok()?;
-        let row = rows.next().ok()??;
+    // Retrieve the addresses of all allocations not yet freed, with their
+    // originating events.
+    pub fn allocation_origins(&mut self) -> Result<Vec<(u64, EventId)>, Box<dyn Error>> {
+        let mut rows = self.allocation_origins_statement.query([])?;
+        let mut origins = Vec::new();
+        while let Some(row) = rows.next()? {
+            origins.push((row.get(0)?, row.get(1)?));
+        }
 
-        row.get(0).ok()
+        Ok(origins)
     }
 
     // Assocate an event as originating an allocation for an address.
@@ -345,38 +740,25 @@ impl<'trace_lifetime> Transaction<'trace_lifetime> {
             maximum_total: row.get(1).ok()?,
             alloc_count: row.get(2).ok()?,
             free_count: row.get(3).ok()?,
+            total_bytes: row.get(4).ok()?,
+            first_time: row.get(5).ok()?,
+            last_time: row.get(6).ok()?,
         })
     }
 
-    // Add a new allocation or free event to the summary for a stackentry.
-    pub fn add_to_summary(
-        &mut self,
-        stackentry: StackEntryId,
-        allocation: bool,
-        size: i64,
-    ) -> Result<(), Box<dyn Error>> {
-        let previous = match self.summary(stackentry) {
-            Some(summary) => summary,
-            None => StackEntrySummary {
-                stackentry,
-                current_total: 0,
-                maximum_total: 0,
-                alloc_count: 0,
-                free_count: 0,
-            },
-        };
-
-        let new_total = previous.current_total as i64 + size;
-        let new_max = std::cmp::max(new_total, previous.maximum_total as i64);
-        let new_alloc_count = previous.alloc_count + if allocation { 1 } else { 0 };
-        let new_free_count = previous.free_count + if allocation { 0 } else { 1 };
-        self.add_to_summary_statement.execute(rusqlite::params![
-            stackentry,
-            new_total,
-            new_max,
-            new_alloc_count,
-            new_free_count
+    // Replace the allocation summary for a stack entry.
+    pub fn set_summary(&mut self, summary: &StackEntrySummary) -> Result<(), Box<dyn Error>> {
+        self.set_summary_statement.execute(rusqlite::params![
+            summary.stackentry,
+            summary.current_total,
+            summary.maximum_total,
+            summary.alloc_count,
+            summary.free_count,
+            summary.total_bytes,
+            summary.first_time,
+            summary.last_time
         ])?;
+
         Ok(())
     }
 
@@ -391,15 +773,14 @@ impl<'trace_lifetime> Transaction<'trace_lifetime> {
         }
     }
 
-    // Increase the descendent count for a stack entry by one.
-    pub fn increment_descendents(
+    // Replace the descendent count for a stack entry.
+    pub fn set_descendent_count(
         &mut self,
         stackentry: StackEntryId,
+        count: u64,
     ) -> Result<(), Box<dyn Error>> {
-        let count = self.descendent_count(stackentry)?;
-
-        self.add_to_descendent_count
-            .execute(rusqlite::params![stackentry, count + 1,])?;
+        self.set_descendent_count
+            .execute(rusqlite::params![stackentry, count])?;
 
         Ok(())
     }
@@ -419,6 +800,61 @@ impl<'trace_lifetime> Drop for Transaction<'trace_lifetime> {
     }
 }
 
+// The version of the summaries saved to a trace, to be increased whenever
+// the way in which summaries are computed changes, so that summaries saved
+// by other versions of the viewer are computed again.
+const SUMMARY_VERSION: u64 = 2;
+
+// The tables of the scratch database saved to a trace, with their columns,
+// all of which hold integers or NULL.  Each is saved to a table of the trace named
+// with a prefix of 'saved_'.
+const SAVED_SUMMARY_TABLES: [(&str, &[&str]); 3] = [
+    ("allocation_origin", &["address", "event"]),
+    (
+        "stackentry_summary",
+        &[
+            "stackentry",
+            "current_total",
+            "maximum_total",
+            "alloc_count",
+            "free_count",
+            "total_bytes",
+            "first_time",
+            "last_time",
+        ],
+    ),
+    (
+        "stackentry_descendents",
+        &["stackentry", "descendent_count"],
+    ),
+];
+
+// The tables of a trace holding rows from every session recorded to it.
+const SESSION_TABLES: [&str; 18] = [
+    "aggregate",
+    "allocator_stats",
+    "capability",
+    "cgroup_memory",
+    "clock_anchor",
+    "crash",
+    "event",
+    "failure",
+    "mapping",
+    "marker",
+    "metadata",
+    "module",
+    "phase",
+    "resident_memory",
+    "sampling",
+    "signal",
+    "thread",
+    "thread_name",
+];
+
+// The columns of the event table added by later versions of the tracer,
+// in the order they are selected.
+const LATER_EVENT_COLUMNS: [&str; 3] = ["task", "thread", "cpu"];
+
 #[allow(dead_code)]
 impl Trace {
     // Open a trace file and create a corresponding scratch database for
@@ -455,7 +891,10 @@ impl Trace {
                 current_total INTEGER NOT NULL,
                 maximum_total INTEGER NOT NULL,
                 alloc_count INTEGER NOT NULL,
-                free_count INTEGER NOT NULL
+                free_count INTEGER NOT NULL,
+                total_bytes INTEGER NOT NULL,
+                first_time INTEGER,
+                last_time INTEGER
             )",
             [],
         )?;
@@ -468,25 +907,764 @@ impl Trace {
             [],
         )?;
 
+        // Tables for a call tree derived from the trace's callstacks, used
+        // when the displayed tree differs from the recorded callstacks.
+        scratch_connection.execute(
+            "CREATE TABLE IF NOT EXISTS stackentry (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                location INTEGER NOT NULL,
+                next INTEGER
+            )",
+            [],
+        )?;
+
+        scratch_connection.execute(
+            "CREATE TABLE IF NOT EXISTS location (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                address INTEGER NOT NULL,
+                function TEXT,
+                offset INTEGER
+            )",
+            [],
+        )?;
+
+        scratch_connection.execute(
+            "CREATE INDEX IF NOT EXISTS stackentry_next_ix ON stackentry (next)",
+            [],
+        )?;
+
+        let event_columns = Trace::event_columns(&atrace_connection)?;
+
         Ok(Trace {
             atrace_connection,
             scratch_connection,
+            scratch_tree: false,
+            session: None,
+            event_columns,
         })
     }
 
-    // Return the largest id from the event table.
-    pub fn max_event_id(&self) -> Result<EventId, Box<dyn Error>> {
+    // The columns of the event table added by later versions of the tracer,
+    // to be selected for each event.  Columns missing from traces recorded
+    // before they were added are selected as NULL.  The times of events in
+    // traces recorded by the earliest versions aren't integers, and are
+    // read as zero.
+    fn event_columns(atrace_connection: &rusqlite::Connection) -> Result<String, Box<dyn Error>> {
+        let mut statement = atrace_connection.prepare("PRAGMA table_info(event)")?;
+        let present = statement
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<Result<Vec<String>, _>>()?;
+
+        Ok(LATER_EVENT_COLUMNS
+            .iter()
+            .map(|column| {
+                if present.iter().any(|name| name == column) {
+                    column.to_string()
+                } else {
+                    format!("NULL AS {}", column)
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(", "))
+    }
+
+    // View a single session of the trace, or the most recent session if
+    // none is given.  Each table holding rows from every session is
+    // shadowed by a temporary view of the session's rows.  Traces
+    // recorded before sessions are viewed as they are.
+    pub fn select_session(&mut self, session: Option<u64>) -> Result<(), Box<dyn Error>> {
+        let last_session =
+            match self
+                .atrace_connection
+                .query_row("SELECT MAX(id) FROM trace", [], |row| {
+                    row.get::<_, Option<u64>>(0)
+                }) {
+                Ok(Some(last_session)) => last_session,
+                _ => match session {
+                    Some(_) => Err("trace was recorded without sessions")?,
+                    None => return Ok(()),
+                },
+            };
+        let session = session.unwrap_or(last_session);
+        let exists: u64 = self.atrace_connection.query_row(
+            "SELECT COUNT(*) FROM trace WHERE id = ?",
+            rusqlite::params![session],
+            |row| row.get(0),
+        )?;
+        if exists == 0 {
+            Err(format!("no session {} in trace", session))?;
+        }
+
+        for table in SESSION_TABLES {
+            // Tables added by later versions of the tracer may be missing.
+            let query = format!("SELECT session FROM main.{}", table);
+            if self.atrace_connection.prepare(&query).is_err() {
+                continue;
+            }
+
+            self.atrace_connection.execute(
+                &format!(
+                    "CREATE TEMP VIEW {} AS SELECT * FROM main.{} WHERE session = {}",
+                    table, table, session
+                ),
+                [],
+            )?;
+        }
+        self.session = Some(session);
+
+        Ok(())
+    }
+
+    // The session being viewed and the number of sessions in the trace,
+    // for traces with more than one session.
+    pub fn session_of(&self) -> Result<Option<(u64, u64)>, Box<dyn Error>> {
+        let session = match self.session {
+            Some(session) => session,
+            None => return Ok(None),
+        };
+        let count: u64 =
+            self.atrace_connection
+                .query_row("SELECT COUNT(*) FROM trace", [], |row| row.get(0))?;
+
+        Ok(if count > 1 {
+            Some((session, count))
+        } else {
+            None
+        })
+    }
+
+    // True if tracing of the session being viewed ended by detaching from a
+    // process which continued to run, so that blocks not freed were still
+    // live rather than leaked.  Older traces didn't record the ending.
+    pub fn ended_by_detach(&self) -> bool {
+        let session = match self.session {
+            Some(session) => session,
+            None => return false,
+        };
+        match self.atrace_connection.query_row(
+            "SELECT ending FROM trace WHERE id = ?",
+            rusqlite::params![session],
+            |row| row.get::<_, Option<String>>(0),
+        ) {
+            Ok(ending) => ending.as_deref() == Some("detach"),
+            Err(_) => false,
+        }
+    }
+
+    // The window of time, in seconds, to which the events of the session
+    // being viewed were limited by --retain, if they were.  The totals of
+    // such a session cover only the window.
+    pub fn retained_window(&self) -> Option<u64> {
         self.atrace_connection
-            .query_row("SELECT MAX(id) FROM event", [], |row| row.get(0))
+            .query_row(
+                "SELECT value FROM metadata WHERE name = 'retained'",
+                [],
+                |row| row.get::<_, String>(0),
+            )
             .ok()
-            .ok_or("failure selecting max event id".into())
+            .and_then(|value| value.parse().ok())
     }
 
-    // Return the lagest id from the stack entry table.
-    pub fn max_stackentry_id(&self) -> Result<StackEntryId, Box<dyn Error>> {
-        self.atrace_connection
-            .query_row("SELECT MAX(id) FROM stackentry", [], |row| row.get(0))
+    // Read the description of the session being viewed.
+    pub fn info(&self) -> Result<TraceInfo, Box<dyn Error>> {
+        let mut info = TraceInfo {
+            event_count: self
+                .atrace_connection
+                .query_row("SELECT COUNT(*) FROM event", [], |row| row.get(0))
+                .unwrap_or(0),
+            ..TraceInfo::default()
+        };
+
+        // Traces recorded before sessions have a single row without an
+        // ending, and older traces have no row at all.
+        let session_row = match self.session {
+            Some(session) => self.atrace_connection.query_row(
+                "SELECT version, time, ending FROM trace WHERE id = ?",
+                rusqlite::params![session],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            ),
+            None => self.atrace_connection.query_row(
+                "SELECT version, time, NULL FROM trace ORDER BY rowid DESC LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            ),
+        };
+        if let Ok((version, start_time, ending)) = session_row {
+            info.version = Some(version);
+            info.start_time = Some(start_time);
+            info.ending = ending;
+        }
+
+        let mut statement = match self
+            .atrace_connection
+            .prepare("SELECT name, value FROM metadata")
+        {
+            Ok(statement) => statement,
+            Err(_) => return Ok(info),
+        };
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let value: Option<String> = row.get(1)?;
+            match row.get::<_, String>(0)?.as_str() {
+                "command" => info.command = value,
+                "pid" => info.pid = value,
+                "host" => info.host = value,
+                "filters" => info.filters = value,
+                "end_time" => info.end_time = value,
+                "dropped_events" => {
+                    info.dropped_events = value.and_then(|value| value.parse().ok())
+                }
+                "retained" => info.retained = value.and_then(|value| value.parse().ok()),
+                "pruned_events" => info.pruned_events = value.and_then(|value| value.parse().ok()),
+                _ => {}
+            }
+        }
+
+        Ok(info)
+    }
+
+    // Remove the summaries of stack entries, so that they can be computed
+    // again for a different span of the trace.
+    pub fn clear_summaries(&self) -> Result<(), Box<dyn Error>> {
+        self.scratch_connection.execute_batch(
+            "DELETE FROM stackentry_summary;
+            DELETE FROM stackentry_descendents;
+            DELETE FROM allocation_origin;",
+        )?;
+
+        Ok(())
+    }
+
+    // Remove the call tree derived in the scratch database, along with the
+    // summaries of its stack entries, so that a different tree can be
+    // derived.
+    pub fn clear_scratch_tree(&self) -> Result<(), Box<dyn Error>> {
+        self.clear_summaries()?;
+        self.scratch_connection.execute_batch(
+            "DELETE FROM stackentry;
+            DELETE FROM location;
+            DELETE FROM sqlite_sequence WHERE name IN ('stackentry', 'location');",
+        )?;
+
+        Ok(())
+    }
+
+    // Load the summaries saved to the trace when it was last viewed, if
+    // they were saved by this version of the viewer for the session being
+    // viewed.  Returns the last event summarized, or None if there are no
+    // such summaries, in which case they must be computed.
+    //
+    // Summaries saved before all events were summarized, because
+    // summarization was interrupted or events were written since, must be
+    // extended from the event returned.  Their descendent counts are
+    // cleared, to be counted again for all stack entries.
+    pub fn load_saved_summary(&self) -> Result<Option<EventId>, Box<dyn Error>> {
+        let saved = self.atrace_connection.query_row(
+            "SELECT version, session, max_event_id FROM main.saved_summary",
+            [],
+            |row| {
+                Ok((
+                    row.get::<_, u64>(0)?,
+                    row.get::<_, Option<u64>>(1)?,
+                    row.get::<_, EventId>(2)?,
+                ))
+            },
+        );
+        let saved_event_id = match saved {
+            Ok((version, session, max_event_id))
+                if version == SUMMARY_VERSION
+                    && session == self.session
+                    && max_event_id <= self.max_event_id()? =>
+            {
+                max_event_id
+            }
+            _ => return Ok(None),
+        };
+
+        self.clear_summaries()?;
+        self.scratch_connection.execute("BEGIN TRANSACTION", [])?;
+        for (table, columns) in SAVED_SUMMARY_TABLES {
+            let mut select = self.atrace_connection.prepare(&format!(
+                "SELECT {} FROM main.saved_{}",
+                columns.join(", "),
+                table
+            ))?;
+            let mut insert = self.scratch_connection.prepare(&format!(
+                "INSERT INTO {} ({}) VALUES ({})",
+                table,
+                columns.join(", "),
+                vec!["?"; columns.len()].join(", ")
+            ))?;
+            let mut rows = select.query([])?;
+            while let Some(row) = rows.next()? {
+                let values = (0..columns.len())
+                    .map(|index| row.get::<_, Option<i64>>(index))
+                    .collect::<Result<Vec<Option<i64>>, _>>()?;
+                insert.execute(rusqlite::params_from_iter(values))?;
+            }
+        }
+        if saved_event_id < self.max_event_id()? {
+            self.scratch_connection
+                .execute("DELETE FROM stackentry_descendents", [])?;
+        }
+        self.scratch_connection.execute("COMMIT", [])?;
+
+        Ok(Some(saved_event_id))
+    }
+
+    // Save the summaries of the scratch database, made of the events up to
+    // and including 'last_event_id', to the trace, replacing any saved
+    // previously, so that they can be loaded rather than computed when the
+    // trace is next viewed.  Only summaries of the whole of the trace's own
+    // callstacks should be saved.
+    pub fn save_summary(
+        &self,
+        atrace_filename: &str,
+        last_event_id: EventId,
+    ) -> Result<(), Box<dyn Error>> {
+        let connection = rusqlite::Connection::open_with_flags(
+            atrace_filename,
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        connection.execute("BEGIN TRANSACTION", [])?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS saved_summary (
+                version INTEGER NOT NULL,
+                session INTEGER,
+                max_event_id INTEGER NOT NULL
+            );
+            DELETE FROM saved_summary;",
+        )?;
+        connection.execute(
+            "INSERT INTO saved_summary (version, session, max_event_id) VALUES (?, ?, ?)",
+            rusqlite::params![SUMMARY_VERSION, self.session, last_event_id],
+        )?;
+
+        for (table, columns) in SAVED_SUMMARY_TABLES {
+            let definitions: Vec<String> = columns
+                .iter()
+                .map(|column| format!("{} INTEGER", column))
+                .collect();
+            // The table is created again, as its columns may differ from
+            // those saved by another version.
+            connection.execute_batch(&format!(
+                "DROP TABLE IF EXISTS saved_{};
+                CREATE TABLE saved_{} ({});",
+                table,
+                table,
+                definitions.join(", ")
+            ))?;
+
+            let mut select = self.scratch_connection.prepare(&format!(
+                "SELECT {} FROM {}",
+                columns.join(", "),
+                table
+            ))?;
+            let mut insert = connection.prepare(&format!(
+                "INSERT INTO saved_{} ({}) VALUES ({})",
+                table,
+                columns.join(", "),
+                vec!["?"; columns.len()].join(", ")
+            ))?;
+            let mut rows = select.query([])?;
+            while let Some(row) = rows.next()? {
+                let values = (0..columns.len())
+                    .map(|index| row.get::<_, Option<i64>>(index))
+                    .collect::<Result<Vec<Option<i64>>, _>>()?;
+                insert.execute(rusqlite::params_from_iter(values))?;
+            }
+        }
+        connection.execute("COMMIT", [])?;
+
+        Ok(())
+    }
+
+    // Display a call tree derived in the scratch database rather than the
+    // callstacks as recorded in the trace, or display the recorded
+    // callstacks again.
+    pub fn use_scratch_tree(&mut self, scratch_tree: bool) {
+        self.scratch_tree = scratch_tree;
+    }
+
+    // The connection from which the displayed call tree is read.
+    fn tree_connection(&self) -> &rusqlite::Connection {
+        if self.scratch_tree {
+            &self.scratch_connection
+        } else {
+            &self.atrace_connection
+        }
+    }
+
+    // Returns true if the trace recorded the data for a capability.  Traces
+    // recorded before capabilities were stored tracked frees, and predate
+    // aggregation.
+    pub fn has_capability(&self, name: &str) -> bool {
+        match self.atrace_connection.query_row(
+            "SELECT COUNT(*) FROM capability WHERE name = ?",
+            rusqlite::params![name],
+            |row| row.get::<_, u64>(0),
+        ) {
+            Ok(count) => count > 0,
+            Err(_) => name != CAPABILITY_AGGREGATE,
+        }
+    }
+
+    // Summarize failed allocations by callstack, most frequent first.
+    // Traces recorded before failures were tracked have no failures.
+    pub fn failure_summaries(&self) -> Result<Vec<FailureSummary>, Box<dyn Error>> {
+        let mut statement = match self.atrace_connection.prepare(
+            "SELECT callstack, COUNT(*), SUM(size) FROM failure
+                GROUP BY callstack ORDER BY COUNT(*) DESC, SUM(size) DESC",
+        ) {
+            Ok(statement) => statement,
+            Err(_) => return Ok(Vec::new()),
+        };
+
+        let mut summaries = Vec::new();
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            summaries.push(FailureSummary {
+                callstack: row.get(0)?,
+                count: row.get(1)?,
+                bytes: row.get(2)?,
+            });
+        }
+
+        Ok(summaries)
+    }
+
+    // Read the changes to the traced process's mappings, in the order they
+    // were made.  Traces recorded without --mmap have none.
+    pub fn mapping_changes(&self) -> Result<Vec<MappingChange>, Box<dyn Error>> {
+        let mut statement = match self
+            .atrace_connection
+            .prepare("SELECT mapped, address, size, kind, path FROM mapping ORDER BY id")
+        {
+            Ok(statement) => statement,
+            Err(_) => return Ok(Vec::new()),
+        };
+
+        let mut changes = Vec::new();
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            changes.push(MappingChange {
+                mapped: row.get(0)?,
+                address: row.get(1)?,
+                size: row.get(2)?,
+                kind: row.get(3)?,
+                path: row.get(4)?,
+            });
+        }
+
+        Ok(changes)
+    }
+
+    // Read the changes to the sampling of allocations, in the order they
+    // were made.  Traces recorded without --max-slowdown have none.
+    pub fn sample_periods(&self) -> Result<Vec<SamplePeriod>, Box<dyn Error>> {
+        let mut statement = match self
+            .atrace_connection
+            .prepare("SELECT time, period FROM sampling ORDER BY time, id")
+        {
+            Ok(statement) => statement,
+            Err(_) => return Ok(Vec::new()),
+        };
+
+        let mut periods = Vec::new();
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            periods.push(SamplePeriod {
+                time: row.get(0)?,
+                period: row.get(1)?,
+            });
+        }
+
+        Ok(periods)
+    }
+
+    // Read the phases marked by the traced program, in the order they
+    // started.  Traces of programs which don't mark phases have none.
+    pub fn phases(&self) -> Result<Vec<Phase>, Box<dyn Error>> {
+        let mut statement = match self
+            .atrace_connection
+            .prepare("SELECT time, name FROM phase ORDER BY time, id")
+        {
+            Ok(statement) => statement,
+            Err(_) => return Ok(Vec::new()),
+        };
+
+        let mut phases = Vec::new();
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            phases.push(Phase {
+                time: row.get(0)?,
+                name: row.get(1)?,
+            });
+        }
+
+        Ok(phases)
+    }
+
+    // Read the threads which generated events, ordered by thread id, with
+    // the last name recorded for each.  Traces recorded before thread ids
+    // were tracked have none, and those recorded before thread names were
+    // tracked lack names.
+    pub fn threads(&self) -> Result<Vec<TraceThread>, Box<dyn Error>> {
+        let mut names = HashMap::new();
+        if let Ok(mut statement) = self
+            .atrace_connection
+            .prepare("SELECT tid, name FROM thread_name ORDER BY time, id")
+        {
+            let mut rows = statement.query([])?;
+            while let Some(row) = rows.next()? {
+                names.insert(row.get::<_, u64>(0)?, row.get::<_, String>(1)?);
+            }
+        }
+
+        let mut statement = match self.atrace_connection.prepare(
+            "SELECT thread, COUNT(*) FROM event WHERE thread IS NOT NULL
+                GROUP BY thread ORDER BY thread",
+        ) {
+            Ok(statement) => statement,
+            Err(_) => return Ok(Vec::new()),
+        };
+
+        let mut threads = Vec::new();
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let tid = row.get(0)?;
+            threads.push(TraceThread {
+                tid,
+                name: names.remove(&tid),
+                event_count: row.get(1)?,
+            });
+        }
+
+        Ok(threads)
+    }
+
+    // Read the largest bursts of allocation marked in the trace, in the
+    // order they occurred.  Traces recorded without --bursts have none.
+    pub fn largest_bursts(&self, limit: u64) -> Result<Vec<Burst>, Box<dyn Error>> {
+        let mut statement = match self.atrace_connection.prepare(
+            "SELECT time, duration, bytes, callstack FROM
+                (SELECT * FROM marker WHERE kind = 'burst' ORDER BY bytes DESC LIMIT ?)
+                ORDER BY time",
+        ) {
+            Ok(statement) => statement,
+            Err(_) => return Ok(Vec::new()),
+        };
+
+        let mut bursts = Vec::new();
+        let mut rows = statement.query(rusqlite::params![limit])?;
+        while let Some(row) = rows.next()? {
+            bursts.push(Burst {
+                time: row.get(0)?,
+                duration: row.get(1)?,
+                bytes: row.get(2)?,
+                callstack: row.get(3)?,
+            });
+        }
+
+        Ok(bursts)
+    }
+
+    // Read the crash of the traced process.  Traces of processes which
+    // didn't crash have none.
+    pub fn crash(&self) -> Result<Option<Crash>, Box<dyn Error>> {
+        let crash = self.atrace_connection.query_row(
+            "SELECT id, time, thread, signal, fault_address, callstack FROM crash LIMIT 1",
+            [],
+            |row| {
+                Ok((
+                    row.get::<_, u64>(0)?,
+                    Crash {
+                        time: row.get(1)?,
+                        thread: row.get(2)?,
+                        signal: row.get(3)?,
+                        fault_address: row.get(4)?,
+                        registers: Vec::new(),
+                        callstack: row.get(5)?,
+                    },
+                ))
+            },
+        );
+        let (id, mut crash) = match crash {
+            Ok(crash) => crash,
+            Err(_) => return Ok(None),
+        };
+
+        let mut statement = self
+            .atrace_connection
+            .prepare("SELECT name, value FROM crash_register WHERE crash = ? ORDER BY id")?;
+        let mut rows = statement.query(rusqlite::params![id])?;
+        while let Some(row) = rows.next()? {
+            crash.registers.push((row.get(0)?, row.get(1)?));
+        }
+
+        Ok(Some(crash))
+    }
+
+    // Find the cgroup memory sample with the most memory charged.  Traces
+    // recorded without cgroup sampling have no peak.
+    pub fn cgroup_memory_peak(&self) -> Result<Option<CgroupMemoryPeak>, Box<dyn Error>> {
+        let peak = self.atrace_connection.query_row(
+            "SELECT time, current, max FROM cgroup_memory ORDER BY current DESC, time LIMIT 1",
+            [],
+            |row| Ok((row.get::<_, u64>(0)?, row.get(1)?, row.get(2)?)),
+        );
+        let (time, current, max) = match peak {
+            Ok(peak) => peak,
+            Err(_) => return Ok(None),
+        };
+
+        let previous_time = self.atrace_connection.query_row(
+            "SELECT COALESCE(MAX(time), 0) FROM cgroup_memory WHERE time < ?",
+            rusqlite::params![time],
+            |row| row.get(0),
+        )?;
+
+        Ok(Some(CgroupMemoryPeak {
+            time,
+            previous_time,
+            current,
+            max,
+        }))
+    }
+
+    // Summarize the allocations made after one time, up to and including
+    // another, by callstack, largest first.
+    pub fn growth_between(
+        &self,
+        begin: u64,
+        end: u64,
+        limit: u64,
+    ) -> Result<Vec<CallstackGrowth>, Box<dyn Error>> {
+        let mut statement = self.atrace_connection.prepare(
+            "SELECT callstack, COUNT(*), SUM(size) FROM event
+                WHERE allocation AND time > ? AND time <= ?
+                GROUP BY callstack ORDER BY SUM(size) DESC LIMIT ?",
+        )?;
+
+        let mut growth = Vec::new();
+        let mut rows = statement.query(rusqlite::params![begin, end, limit])?;
+        while let Some(row) = rows.next()? {
+            growth.push(CallstackGrowth {
+                callstack: row.get(0)?,
+                count: row.get(1)?,
+                bytes: row.get(2)?,
+            });
+        }
+
+        Ok(growth)
+    }
+
+    // Read the callstacks which created the threads of the traced process,
+    // by thread id.  Traces recorded before thread creation was tracked
+    // have none.
+    pub fn thread_creators(&self) -> Result<HashMap<u64, StackEntryId>, Box<dyn Error>> {
+        let mut statement = match self
+            .atrace_connection
+            .prepare("SELECT tid, callstack FROM thread WHERE callstack IS NOT NULL")
+        {
+            Ok(statement) => statement,
+            Err(_) => return Ok(HashMap::new()),
+        };
+
+        let mut creators = HashMap::new();
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            creators.insert(row.get(0)?, row.get(1)?);
+        }
+
+        Ok(creators)
+    }
+
+    // Read the executable mappings of the traced process, ordered by
+    // address.  Traces recorded before modules were tracked have none, and
+    // those recorded before their offsets were tracked lack offsets.
+    pub fn modules(&self) -> Result<Vec<Module>, Box<dyn Error>> {
+        let mut statement = match self
+            .atrace_connection
+            .prepare("SELECT address, size, filename, offset FROM module ORDER BY address")
+            .or_else(|_| {
+                self.atrace_connection
+                    .prepare("SELECT address, size, filename, NULL FROM module ORDER BY address")
+            }) {
+            Ok(statement) => statement,
+            Err(_) => return Ok(Vec::new()),
+        };
+
+        let mut modules = Vec::new();
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            modules.push(Module {
+                address: row.get(0)?,
+                size: row.get(1)?,
+                filename: row.get(2)?,
+                offset: row.get(3)?,
+            });
+        }
+
+        Ok(modules)
+    }
+
+    // Read the per-callstack totals of a trace recorded with --aggregate.
+    pub fn aggregates(&self) -> Result<Vec<Aggregate>, Box<dyn Error>> {
+        let mut statement = self.atrace_connection.prepare(
+            "SELECT callstack, task, current_bytes, peak_bytes, alloc_count, free_count
+                FROM aggregate ORDER BY id",
+        )?;
+
+        let mut aggregates = Vec::new();
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            aggregates.push(Aggregate {
+                callstack: row.get(0)?,
+                task: row.get(1)?,
+                current_bytes: row.get(2)?,
+                peak_bytes: row.get(3)?,
+                alloc_count: row.get(4)?,
+                free_count: row.get(5)?,
+            });
+        }
+
+        Ok(aggregates)
+    }
+
+    // Return the time of the last event, in nanoseconds since the start of
+    // the trace, or zero if events weren't timed.
+    pub fn duration(&self) -> Result<u64, Box<dyn Error>> {
+        Ok(self
+            .atrace_connection
+            .query_row("SELECT MAX(time) FROM event", [], |row| {
+                row.get::<_, Option<u64>>(0)
+            })
+            .unwrap_or(None)
+            .unwrap_or(0))
+    }
+
+    // Return the largest id from the event table, or zero if there are no
+    // events, as in traces made with --aggregate.
+    pub fn max_event_id(&self) -> Result<EventId, Box<dyn Error>> {
+        let max_event_id = self
+            .atrace_connection
+            .query_row("SELECT MAX(id) FROM event", [], |row| {
+                row.get::<_, Option<EventId>>(0)
+            })
             .ok()
-            .ok_or("failure selecting stack entry id".into())
+            .ok_or("failure selecting max event id")?;
+        Ok(max_event_id.unwrap_or(0))
+    }
+
+    // Return the lagest id from the stack entry table of the displayed tree.
+    pub fn max_stackentry_id(&self) -> Result<StackEntryId, Box<dyn Error>> {
+        // A tree with every callstack filtered out has no stack entries.
+        self.tree_connection()
+            .query_row("SELECT MAX(id) FROM stackentry", [], |row| {
+                row.get::<_, Option<StackEntryId>>(0)
+            })
+            .map(|id| id.unwrap_or(0))
+            .map_err(|_| "failure selecting stack entry id".into())
     }
 }
@@ -0,0 +1,506 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::rows;
+use crate::trace;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+// The grouping applied to the roots of the displayed call tree.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GroupMode {
+    // Display callstacks as recorded.
+    None,
+
+    // Group callstacks under a root entry for the async task which was
+    // being polled when the allocation was made.
+    Task,
+
+    // Place callstacks beneath the callstack which created the thread
+    // making the allocation.
+    Spawner,
+
+    // Group callstacks under a root entry for the CPU on which the
+    // allocation was made.
+    Cpu,
+}
+
+// A frame of a callstack in the derived tree, identified by the values
+// of its location rather than by location id, so that frames from
+// different sources can be merged.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct Frame {
+    // The address of the instruction.
+    address: u64,
+
+    // The name of the function, or a label for synthesized frames.
+    function: String,
+
+    // The offset from the start of the function.
+    offset: u64,
+}
+
+// Builds a call tree in the scratch database derived from the callstacks
+// recorded in the trace, by mapping each event's callstack to a path in
+// the derived tree.
+pub struct TreeBuilder {
+    // The grouping to apply.
+    group_mode: GroupMode,
+
+    // Locations already inserted into the derived tree.
+    locations: HashMap<Frame, trace::LocationId>,
+
+    // Stack entries already inserted, by location and parent.
+    stackentries: HashMap<(trace::LocationId, Option<trace::StackEntryId>), trace::StackEntryId>,
+
+    // Previously mapped callstacks, by original callstack and group key.
+    // Callstacks excluded by the filter are mapped to nothing.
+    callstacks: HashMap<(trace::StackEntryId, Option<u64>), Option<trace::StackEntryId>>,
+
+    // The callstacks which created each thread, by thread id.
+    thread_creators: HashMap<u64, trace::StackEntryId>,
+
+    // If true, callstacks are inverted, so that the functions making
+    // allocations are roots with their callers beneath them.
+    inverted: bool,
+
+    // If set, only callstacks with a function matching the filter are
+    // included in the tree.
+    filter: Option<Regex>,
+
+    // Frames with a function matching any of these rules are folded into
+    // their callers.
+    fold_rules: Vec<Regex>,
+
+    // If set, consecutive frames in the same shared object are rolled
+    // into a single frame labelled with the object's name.  The modules
+    // are ordered by address.
+    modules: Option<Vec<trace::Module>>,
+
+    // The names of shared objects whose frames are shown individually
+    // even while frames are rolled by module.
+    expanded_modules: HashSet<String>,
+
+    // If true, the frames of recursive calls are removed, so that each
+    // allocation is counted once for each function in its callstack.
+    collapse_recursion: bool,
+
+    // If set, only events generated by these threads are included in the
+    // tree.
+    threads: Option<HashSet<u64>>,
+}
+
+impl GroupMode {
+    // Parse a grouping mode from its commandline name.
+    pub fn parse(name: &str) -> Result<GroupMode, Box<dyn Error>> {
+        match name {
+            "none" => Ok(GroupMode::None),
+            "task" => Ok(GroupMode::Task),
+            "spawner" => Ok(GroupMode::Spawner),
+            "cpu" => Ok(GroupMode::Cpu),
+            _ => Err(format!("unknown grouping: {}", name).into()),
+        }
+    }
+}
+
+impl TreeBuilder {
+    // Create a new builder for a derived tree.
+    pub fn new(group_mode: GroupMode) -> TreeBuilder {
+        TreeBuilder {
+            group_mode,
+            locations: HashMap::new(),
+            stackentries: HashMap::new(),
+            callstacks: HashMap::new(),
+            thread_creators: HashMap::new(),
+            inverted: false,
+            filter: None,
+            fold_rules: Vec::new(),
+            modules: None,
+            expanded_modules: HashSet::new(),
+            collapse_recursion: false,
+            threads: None,
+        }
+    }
+
+    // Forget the tree already built, so that a different tree can be built
+    // in its place.
+    fn forget_tree(&mut self) {
+        self.locations.clear();
+        self.stackentries.clear();
+        self.callstacks.clear();
+    }
+
+    // Invert callstacks, or restore their order.  The tree already built is
+    // forgotten, so the summaries must be computed again.
+    pub fn set_inverted(&mut self, inverted: bool) {
+        self.inverted = inverted;
+        self.forget_tree();
+    }
+
+    // Include only callstacks with a function matching a filter, or all
+    // callstacks if there is no filter.  The tree already built is
+    // forgotten, so the summaries must be computed again.
+    pub fn set_filter(&mut self, filter: Option<Regex>) {
+        self.filter = filter;
+        self.forget_tree();
+    }
+
+    // Include only events generated by the given threads, or events from
+    // all threads if none are given.  The tree already built is forgotten,
+    // so the summaries must be computed again.
+    pub fn set_threads(&mut self, threads: Option<HashSet<u64>>) {
+        self.threads = threads;
+        self.forget_tree();
+    }
+
+    // The threads to which events are limited, if any.
+    pub fn threads(&self) -> Option<&HashSet<u64>> {
+        self.threads.as_ref()
+    }
+
+    // Fold frames with a function matching any of the rules into their
+    // callers.  The tree already built is forgotten, so the summaries must
+    // be computed again.
+    pub fn set_fold_rules(&mut self, fold_rules: Vec<Regex>) {
+        self.fold_rules = fold_rules;
+        self.forget_tree();
+    }
+
+    // Remove the frames matching a fold rule from a callstack, starting
+    // with the root, so that allocations made beneath them are attributed
+    // to their callers.  The root frame has no caller, so it is kept.
+    fn fold_frames(&self, frames: Vec<Frame>) -> Vec<Frame> {
+        frames
+            .into_iter()
+            .enumerate()
+            .filter(|(index, frame)| {
+                *index == 0
+                    || frame.function.is_empty()
+                    || !self
+                        .fold_rules
+                        .iter()
+                        .any(|rule| rule.is_match(&rows::demangle_function_name(&frame.function)))
+            })
+            .map(|(_, frame)| frame)
+            .collect()
+    }
+
+    // Remove the frames of recursive calls, leaving the outermost call of
+    // each function, or show them as recorded.  The tree already built is
+    // forgotten, so the summaries must be computed again.
+    pub fn set_collapse_recursion(&mut self, collapse_recursion: bool) {
+        self.collapse_recursion = collapse_recursion;
+        self.forget_tree();
+    }
+
+    // True if the frames of recursive calls are removed.
+    pub fn is_collapsing_recursion(&self) -> bool {
+        self.collapse_recursion
+    }
+
+    // Remove the frames of recursive calls from a callstack, starting with
+    // the root, so that 'f > g > f > h' becomes 'f > g > h'.  Each function
+    // then appears once in the callstack, and allocations are counted once
+    // in its totals, rather than once per level of recursion.
+    fn collapse_recursive_frames(frames: Vec<Frame>) -> Vec<Frame> {
+        let mut functions: HashSet<String> = HashSet::new();
+        frames
+            .into_iter()
+            .filter(|frame| frame.function.is_empty() || functions.insert(frame.function.clone()))
+            .collect()
+    }
+
+    // The filter applied to callstacks, if any.
+    pub fn filter(&self) -> Option<&Regex> {
+        self.filter.as_ref()
+    }
+
+    // Roll frames by the shared object containing them, using the given
+    // mappings of the traced process, or show every frame if there are
+    // none.  The tree already built is forgotten, so the summaries must be
+    // computed again.
+    pub fn set_modules(&mut self, modules: Option<Vec<trace::Module>>) {
+        self.modules = modules;
+        self.expanded_modules.clear();
+        self.forget_tree();
+    }
+
+    // Show the frames of a rolled shared object individually.  The tree
+    // already built is forgotten, so the summaries must be computed again.
+    pub fn expand_module(&mut self, name: &str) {
+        self.expanded_modules.insert(name.to_string());
+        self.forget_tree();
+    }
+
+    // True if frames are rolled by shared object.
+    pub fn is_grouping_modules(&self) -> bool {
+        self.modules.is_some()
+    }
+
+    // The name of the shared object a rolled frame stands for, if the
+    // function name is the label of one.
+    pub fn rolled_module(&self, function: &str) -> Option<String> {
+        let name = function.strip_prefix('[')?.strip_suffix(']')?;
+        self.modules
+            .as_ref()?
+            .iter()
+            .any(|module| TreeBuilder::module_name(module) == Some(name))
+            .then(|| name.to_string())
+    }
+
+    // The name of a shared object, if the module is one.  The main
+    // executable is not, so its frames are never rolled.
+    fn module_name(module: &trace::Module) -> Option<&str> {
+        let name = module.filename.rsplit('/').next()?;
+        if name.contains(".so") {
+            Some(name)
+        } else {
+            None
+        }
+    }
+
+    // The name of the shared object containing an address, if its frames
+    // are to be rolled.
+    fn rolled_module_at(&self, address: u64) -> Option<&str> {
+        let module = trace::module_at(self.modules.as_ref()?, address)?;
+        let name = TreeBuilder::module_name(module)?;
+        if self.expanded_modules.contains(name) {
+            return None;
+        }
+
+        Some(name)
+    }
+
+    // Replace each run of consecutive frames in the same shared object
+    // with a single frame labelled with the object's name.
+    fn roll_modules(&self, frames: Vec<Frame>) -> Vec<Frame> {
+        let mut rolled: Vec<Frame> = Vec::new();
+        for frame in frames {
+            match self.rolled_module_at(frame.address) {
+                Some(name) => {
+                    let label = format!("[{}]", name);
+                    if rolled.last().map(|last| &last.function) != Some(&label) {
+                        rolled.push(Frame {
+                            address: 0,
+                            function: label,
+                            offset: 0,
+                        });
+                    }
+                }
+                None => rolled.push(frame),
+            }
+        }
+
+        rolled
+    }
+
+    // True if callstacks are inverted.
+    pub fn is_inverted(&self) -> bool {
+        self.inverted
+    }
+
+    // Set the callstacks which created each thread, used when grouping by
+    // the spawner of threads.
+    pub fn set_thread_creators(&mut self, thread_creators: HashMap<u64, trace::StackEntryId>) {
+        self.thread_creators = thread_creators;
+    }
+
+    // Returns true if the derived tree would be identical to the recorded
+    // callstacks, in which case the trace's own tables can be displayed.
+    pub fn is_identity(&self) -> bool {
+        self.group_mode == GroupMode::None
+            && !self.inverted
+            && self.filter.is_none()
+            && self.fold_rules.is_empty()
+            && self.modules.is_none()
+            && !self.collapse_recursion
+            && self.threads.is_none()
+    }
+
+    // The key used to group an event, and the label of the root entry for
+    // that group.
+    fn group(&self, event: &trace::Event) -> (Option<u64>, Option<String>) {
+        match self.group_mode {
+            GroupMode::None => (None, None),
+            GroupMode::Task => match event.task {
+                Some(task) => (Some(task), Some(format!("[task 0x{:x}]", task))),
+                None => (None, Some("[no task]".to_string())),
+            },
+
+            // Threads created from the same callstack are grouped together,
+            // as they are often the workers of a pool.
+            GroupMode::Spawner => match self.thread_creator(event) {
+                Some(creator) => (Some(creator), Some("[spawned thread]".to_string())),
+                None => (None, Some("[unknown spawner]".to_string())),
+            },
+            GroupMode::Cpu => match event.cpu {
+                Some(cpu) => (Some(cpu), Some(format!("[cpu {}]", cpu))),
+                None => (None, Some("[unknown cpu]".to_string())),
+            },
+        }
+    }
+
+    // The callstack which created the thread of an event, if known.
+    fn thread_creator(&self, event: &trace::Event) -> Option<trace::StackEntryId> {
+        self.thread_creators.get(&event.thread?).copied()
+    }
+
+    // Read the frames of a recorded callstack, starting with the root.
+    fn read_frames(
+        transaction: &mut trace::Transaction,
+        callstack: trace::StackEntryId,
+    ) -> Result<Vec<Frame>, Box<dyn Error>> {
+        let mut frames = Vec::new();
+        let mut id = Some(callstack);
+        while let Some(entry_id) = id {
+            let stackentry = transaction
+                .original_stackentry(entry_id)
+                .ok_or("missing stackentry")?;
+            let location = transaction
+                .original_location(stackentry.location)
+                .ok_or("missing location")?;
+            frames.push(Frame {
+                address: location.address,
+                function: location.function.unwrap_or_default(),
+                offset: location.offset.unwrap_or(0),
+            });
+            id = stackentry.next;
+        }
+        frames.reverse();
+
+        Ok(frames)
+    }
+
+    // Insert a path of frames, starting with the root, into the derived
+    // tree, returning the id of the leaf stack entry.
+    fn insert_path(
+        &mut self,
+        transaction: &mut trace::Transaction,
+        frames: Vec<Frame>,
+    ) -> Result<Option<trace::StackEntryId>, Box<dyn Error>> {
+        let mut parent: Option<trace::StackEntryId> = None;
+        for frame in frames {
+            let location = match self.locations.get(&frame) {
+                Some(location) => *location,
+                None => {
+                    let location = transaction.insert_tree_location(
+                        frame.address,
+                        &frame.function,
+                        frame.offset,
+                    )?;
+                    self.locations.insert(frame, location);
+                    location
+                }
+            };
+
+            let stackentry = match self.stackentries.get(&(location, parent)) {
+                Some(stackentry) => *stackentry,
+                None => {
+                    let stackentry = transaction.insert_tree_stackentry(location, parent)?;
+                    self.stackentries.insert((location, parent), stackentry);
+                    stackentry
+                }
+            };
+            parent = Some(stackentry);
+        }
+
+        Ok(parent)
+    }
+
+    // Map the callstack of an event to the leaf stack entry in the derived
+    // tree.
+    pub fn map_callstack(
+        &mut self,
+        transaction: &mut trace::Transaction,
+        event: &trace::Event,
+    ) -> Result<Option<trace::StackEntryId>, Box<dyn Error>> {
+        if self.is_identity() {
+            return Ok(event.callstack);
+        }
+        // Mapped callstacks are shared between threads, so events from
+        // other threads are excluded before looking up the callstack.
+        if let Some(threads) = &self.threads {
+            if !event.thread.is_some_and(|thread| threads.contains(&thread)) {
+                return Ok(None);
+            }
+        }
+        let callstack = match event.callstack {
+            Some(callstack) => callstack,
+            None => return Ok(None),
+        };
+
+        let (key, label) = self.group(event);
+        if let Some(mapped) = self.callstacks.get(&(callstack, key)) {
+            return Ok(*mapped);
+        }
+
+        let mut frames = Vec::new();
+        if self.group_mode == GroupMode::Spawner {
+            if let Some(creator) = self.thread_creator(event) {
+                frames.append(&mut TreeBuilder::read_frames(transaction, creator)?);
+            }
+        }
+        if let Some(label) = label {
+            frames.push(Frame {
+                address: 0,
+                function: label,
+                offset: 0,
+            });
+        }
+        let mut callstack_frames = TreeBuilder::read_frames(transaction, callstack)?;
+        if let Some(filter) = &self.filter {
+            let matched = frames.iter().chain(callstack_frames.iter()).any(|frame| {
+                !frame.function.is_empty()
+                    && filter.is_match(&rows::demangle_function_name(&frame.function))
+            });
+            if !matched {
+                self.callstacks.insert((callstack, key), None);
+                return Ok(None);
+            }
+        }
+        if !self.fold_rules.is_empty() {
+            callstack_frames = self.fold_frames(callstack_frames);
+        }
+        if self.modules.is_some() {
+            callstack_frames = self.roll_modules(callstack_frames);
+        }
+        if self.collapse_recursion {
+            callstack_frames = TreeBuilder::collapse_recursive_frames(callstack_frames);
+        }
+        if self.inverted {
+            // Frames are identified by function alone, so that each
+            // function is a single root, and its callers merge beneath it.
+            callstack_frames.reverse();
+            for frame in callstack_frames.iter_mut() {
+                if !frame.function.is_empty() {
+                    frame.address = 0;
+                }
+                frame.offset = 0;
+            }
+        }
+        frames.append(&mut callstack_frames);
+
+        let mapped = self.insert_path(transaction, frames)?;
+        if mapped.is_some() {
+            self.callstacks.insert((callstack, key), mapped);
+        }
+
+        Ok(mapped)
+    }
+}
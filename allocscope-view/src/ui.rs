@@ -16,14 +16,60 @@
     with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
+use crate::allocations;
+use crate::columns;
+use crate::commandline;
+use crate::fetch;
+use crate::flamegraph;
+use crate::focus;
 use crate::report;
 use crate::rows;
+use crate::search;
+use crate::source;
+use crate::summary;
+use crate::text;
+use crate::theme;
+use crate::timeline;
 use crate::trace;
+use crate::transform;
+use crate::viewstate;
 use pancurses;
+use regex::Regex;
 use std::collections;
 use std::error::Error;
 use std::time;
 
+// The number of screen rows used by the timeline pane, including the row
+// labeling it.
+const TIMELINE_HEIGHT: i32 = 8;
+
+// The number of screen rows used by the detail pane, including the row
+// labeling it.
+const DETAIL_HEIGHT: i32 = 8;
+
+// The number of screen rows used by the source pane, including the row
+// labeling it.
+const SOURCE_HEIGHT: i32 = 8;
+
+// The most screen rows used by the name of the function in the detail
+// pane.
+const DETAIL_NAME_ROWS: usize = 2;
+
+// The number of rows generated at a time while searching the call tree
+// for a row.
+const SEARCH_CHUNK_ROWS: usize = 256;
+
+// The number of rows scrolled by a turn of the mouse wheel.
+const WHEEL_SCROLL_ROWS: i32 = 3;
+
+// The fraction of the total below which entries are pruned when pruning is
+// toggled on without a threshold given on the commandline.
+const DEFAULT_PRUNE_THRESHOLD: f64 = 0.005;
+
+// The interval at which a trace still being recorded is read again, when
+// following it.
+const FOLLOW_INTERVAL: time::Duration = time::Duration::from_secs(2);
+
 // State data relevant to the curses UI.
 struct UIState {
     // The connectin to the SQLite database for the trace.
@@ -32,6 +78,9 @@ struct UIState {
     // The ncurses screen window.
     screen: pancurses::Window,
 
+    // The attributes with which parts of the UI are drawn.
+    palette: theme::Palette,
+
     // If true, the UI is exiting.
     exited: bool,
 
@@ -50,37 +99,224 @@ struct UIState {
     // The ids of stack entry rows which have been collapsed.
     collapsed: collections::HashSet<trace::StackEntryId>,
 
-    // The current sort mode for the UI.
-    sort_mode: rows::SortMode,
+    // The current sort order for the UI.
+    sort_order: rows::SortOrder,
+
+    // If false, the trace didn't record frees, so leaks are unknown.
+    free_tracking: bool,
+
+    // If true, the rates of allocation are shown.
+    show_rates: bool,
+
+    // If true, the times of the first allocation and the last activity are
+    // shown.
+    show_times: bool,
+
+    // If true, show the total bytes allocated by each entry, including
+    // those freed.
+    show_churn: bool,
+
+    // If true, show the instruction address of each entry and the module
+    // containing it.
+    show_addresses: bool,
+
+    // If true, a bar in each row shows its share of the total of the
+    // sorted column.
+    show_bars: bool,
+
+    // The total of the roots of the call tree in the sorted column, of
+    // which each row's bar shows a share.
+    bar_total: u64,
+
+    // The style in which the values of the call tree are shown.
+    value_style: report::ValueStyle,
+
+    // The style of exact values, to which 'e' toggles from abbreviated
+    // values.
+    exact_style: report::ValueStyle,
+
+    // The format of the values of the call tree, wide enough for the
+    // largest of them.
+    value_format: report::ValueFormat,
+
+    // The format of the churn column, wide enough for the total bytes
+    // allocated.
+    churn_format: report::ValueFormat,
+
+    // If true, entries below the pruning threshold are hidden.
+    prune: bool,
+
+    // The fraction of the total below which entries are pruned.
+    prune_threshold: f64,
+
+    // The number of levels of the call tree shown, if limited, with deeper
+    // entries rolled up into the deepest level shown.
+    max_depth: Option<usize>,
+
+    // The length of the span of the trace summarized, in nanoseconds, used
+    // to compute rates.
+    summarized_span: u64,
+
+    // If true, the trace ended by detaching, so blocks not freed are shown
+    // as live rather than leaked.
+    ended_by_detach: bool,
+
+    // If the trace kept only a window of events with --retain, the length
+    // of the window in seconds, which the totals cover.
+    retained_window: Option<u64>,
+
+    // The bytes live over time, computed when first shown.
+    timeline: Option<timeline::Timeline>,
+
+    // If true, the timeline pane is shown above the call tree.
+    show_timeline: bool,
+
+    // If true, the detail pane is shown below the call tree.
+    show_detail: bool,
+
+    // The executable mappings of the traced process, read when the detail
+    // pane is first shown.
+    modules: Option<Vec<trace::Module>>,
+
+    // If true, the source pane is shown below the call tree.
+    show_source: bool,
+
+    // The source locations found and the source files read for the source
+    // pane.
+    source_cache: source::SourceCache,
+
+    // The time at the cursor of the timeline pane.
+    timeline_cursor: u64,
+
+    // The builder of the displayed call tree, used again when the summaries
+    // are recomputed.
+    tree_builder: transform::TreeBuilder,
+
+    // The span of the trace summarized.
+    time_range: summary::TimeRange,
+
+    // The point reached in summarizing the trace, from which the summary
+    // is extended when the trace is read again.
+    summary_position: summary::SummaryPosition,
+
+    // If true, the trace is read again periodically while it is recorded.
+    follow: bool,
+
+    // The time at which the trace was last read.
+    last_refresh: time::Instant,
+
+    // The error from the last attempt to read the trace again while
+    // following it, shown until the trace is next read successfully.
+    refresh_error: Option<String>,
+
+    // The callers and callees of a function, shown in place of the call
+    // tree.
+    focus: Option<focus::FocusView>,
+
+    // The index of the selected caller or callee in the focus view, with
+    // callers before callees.
+    focus_selected: usize,
+
+    // The individual allocations made beneath a stack entry, shown in
+    // place of the call tree.
+    allocations: Option<allocations::AllocationList>,
+
+    // The number of allocations scrolled past in the list of allocations.
+    allocations_offset: usize,
+
+    // The distribution of block sizes allocated beneath a stack entry,
+    // shown in place of the call tree.
+    histogram: Option<allocations::SizeHistogram>,
+
+    // The allocations made beneath a stack entry counted by lifetime,
+    // shown in place of the call tree.
+    lifetimes: Option<allocations::LifetimeHistogram>,
+
+    // Lines of text shown in place of the call tree, describing the blocks
+    // allocated over an inspected address, the callstacks freeing or the
+    // reallocations of the blocks of an entry, or the trace itself.
+    text_lines: Option<Vec<String>>,
+
+    // The number of lines of text scrolled past.
+    text_offset: usize,
+
+    // The phases marked in the trace, while they are listed to choose the
+    // span of the trace summarized.
+    phases: Option<Vec<trace::Phase>>,
+
+    // The index of the selected phase.
+    phase_selected: usize,
+
+    // The threads which generated events, while they are listed to choose
+    // those whose events are shown.
+    threads: Option<Vec<trace::TraceThread>>,
+
+    // The index of the selected thread.
+    thread_selected: usize,
+
+    // The ids of the threads chosen while the threads are listed.
+    threads_chosen: collections::HashSet<u64>,
+
+    // The call tree laid out as a flamegraph, shown in place of the call
+    // tree.
+    flamegraph: Option<flamegraph::Flamegraph>,
+
+    // The level of the flamegraph, and index within it, of the selected
+    // box.
+    flame_selected: (usize, usize),
+
+    // The number of levels of the flamegraph scrolled past.
+    flame_offset: usize,
+
+    // The functions of the call tree matching a pattern as it is typed,
+    // shown in place of the call tree.
+    search: Option<search::FunctionSearch>,
+
+    // The index of the selected match of the search.
+    search_selected: usize,
+
+    // The summaries and sorted children of the stack entries displayed,
+    // cleared whenever the trace is summarized again.
+    tree_cache: rows::TreeCache,
 }
 
 // Print a column header.
-fn print_header(screen: &pancurses::Window, text: &str, selected: bool) {
+fn print_header(screen: &pancurses::Window, palette: &theme::Palette, text: &str, selected: bool) {
     if selected {
-        screen.attroff(pancurses::COLOR_PAIR(3));
-        screen.attron(pancurses::COLOR_PAIR(2));
+        screen.attroff(palette.header);
+        screen.attron(palette.selected);
     }
 
     screen.printw(text);
 
     if selected {
-        screen.attroff(pancurses::COLOR_PAIR(2));
-        screen.attron(pancurses::COLOR_PAIR(3));
+        screen.attroff(palette.selected);
+        screen.attron(palette.header);
     }
 }
 
 // Print a keyboard shortcut.
-fn print_key(screen: &pancurses::Window, column_limit: usize, key: &str, description: &str) {
+fn print_key(
+    screen: &pancurses::Window,
+    palette: &theme::Palette,
+    column_limit: usize,
+    key: &str,
+    description: &str,
+) {
+    // Leave the last column free, as the cursor can't advance past the
+    // bottom right corner of the screen.
     let cur_x = screen.get_cur_x();
-    if cur_x as usize + key.len() + description.len() > column_limit {
+    let key_width = std::cmp::max(key.len() + description.len() + 2, 8);
+    if cur_x as usize + key_width >= column_limit {
         return;
     }
 
-    screen.attroff(pancurses::COLOR_PAIR(3));
+    screen.attroff(palette.header);
     screen.printw(key);
-    screen.attron(pancurses::COLOR_PAIR(3));
+    screen.attron(palette.header);
     screen.printw(" ");
     screen.printw(description);
+    screen.printw(" ");
 
     while screen.get_cur_x() < cur_x + 8 {
         screen.printw(" ");
@@ -89,38 +325,222 @@ fn print_key(screen: &pancurses::Window, column_limit: usize, key: &str, descrip
 
 impl UIState {
     // Construct a new curses UI state.
-    fn new(trace: trace::Trace, screen: pancurses::Window) -> UIState {
+    fn new(
+        trace: trace::Trace,
+        screen: pancurses::Window,
+        tree_builder: transform::TreeBuilder,
+        time_range: summary::TimeRange,
+        show_rates: bool,
+        prune: Option<f64>,
+        theme: theme::Theme,
+    ) -> UIState {
         pancurses::noecho();
         pancurses::curs_set(0);
-        pancurses::start_color();
-        pancurses::init_pair(1, pancurses::COLOR_WHITE, pancurses::COLOR_BLACK);
-        pancurses::init_pair(2, pancurses::COLOR_WHITE, pancurses::COLOR_BLUE);
-        pancurses::init_pair(3, pancurses::COLOR_BLACK, pancurses::COLOR_GREEN);
+        let palette = theme.init();
         screen.keypad(true);
+        pancurses::mousemask(pancurses::ALL_MOUSE_EVENTS, None);
+
+        let free_tracking = trace.has_capability(trace::CAPABILITY_FREE_TRACKING);
+        let ended_by_detach = trace.ended_by_detach();
+        let retained_window = trace.retained_window();
+        let summarized_span = time_range.span(trace.duration().unwrap_or(0));
 
         UIState {
             trace,
             screen,
+            palette,
             exited: false,
             display_rows: Vec::new(),
             scroll_offset: 0,
             column_offset: 0,
             selected_row: 0,
             collapsed: collections::HashSet::new(),
-            sort_mode: rows::SortMode::Bytes,
+            sort_order: rows::SortOrder {
+                mode: rows::SortMode::Bytes,
+                reversed: false,
+            },
+            free_tracking,
+            show_rates,
+            show_times: false,
+            show_churn: false,
+            show_addresses: false,
+            show_bars: false,
+            bar_total: 0,
+            value_style: report::ValueStyle::Abbreviated,
+            exact_style: report::ValueStyle::Separated,
+            value_format: report::ValueFormat::new(report::ValueStyle::Abbreviated, 0),
+            churn_format: report::ValueFormat::new(report::ValueStyle::Abbreviated, 0),
+            prune: prune.is_some(),
+            prune_threshold: prune.unwrap_or(DEFAULT_PRUNE_THRESHOLD),
+            max_depth: None,
+            summarized_span,
+            ended_by_detach,
+            retained_window,
+            timeline: None,
+            show_timeline: false,
+            show_detail: false,
+            modules: None,
+            show_source: false,
+            source_cache: source::SourceCache::new(Vec::new()),
+            timeline_cursor: time_range.start,
+            tree_builder,
+            time_range,
+            summary_position: summary::SummaryPosition::default(),
+            follow: false,
+            last_refresh: time::Instant::now(),
+            refresh_error: None,
+            focus: None,
+            focus_selected: 0,
+            allocations: None,
+            allocations_offset: 0,
+            histogram: None,
+            lifetimes: None,
+            text_lines: None,
+            text_offset: 0,
+            phases: None,
+            phase_selected: 0,
+            threads: None,
+            thread_selected: 0,
+            threads_chosen: collections::HashSet::new(),
+            flamegraph: None,
+            flame_selected: (0, 0),
+            flame_offset: 0,
+            search: None,
+            search_selected: 0,
+            tree_cache: rows::TreeCache::new(rows::SortOrder {
+                mode: rows::SortMode::Bytes,
+                reversed: false,
+            }),
+        }
+    }
+
+    // The limits on the rows of the call tree, which are pruned if pruning
+    // is toggled on, and cut off at the depth limit.
+    fn row_limits(&self) -> rows::RowLimits {
+        rows::RowLimits {
+            max_depth: self.max_depth,
+            ..rows::RowLimits::pruning(self.prune.then_some(self.prune_threshold))
+        }
+    }
+
+    // Restore the state of the UI from when the trace was last viewed.
+    // Collapsed entries no longer in the call tree are forgotten.
+    fn restore_view_state(&mut self, view_state: viewstate::ViewState) {
+        if let Some(sort_order) = view_state.sort_order {
+            self.sort_order = sort_order;
+            self.show_churn |= sort_order.mode == rows::SortMode::Churn;
+        }
+
+        let mut row_count = 0;
+        if let Ok(mut transaction) = trace::Transaction::new(&self.trace) {
+            for path in view_state.collapsed.iter() {
+                if let Ok(Some(id)) = viewstate::find_stackentry(&mut transaction, path) {
+                    self.collapsed.insert(id);
+                }
+            }
+            let row_limits = self.row_limits();
+            row_count = rows::count_rows(
+                &mut transaction,
+                &mut self.tree_cache,
+                Some(&self.collapsed),
+                row_limits,
+            )
+            .unwrap_or(0) as i64;
+        }
+
+        self.selected_row = view_state
+            .selected_row
+            .clamp(0, std::cmp::max(row_count - 1, 0));
+        self.scroll_offset = view_state.scroll_offset.clamp(0, self.selected_row);
+        self.scroll_to_selection();
+    }
+
+    // The state of the UI to be restored when the trace is next viewed.
+    fn view_state(&self) -> Result<viewstate::ViewState, Box<dyn Error>> {
+        let mut transaction = trace::Transaction::new(&self.trace)?;
+        let mut collapsed = Vec::new();
+        for id in self.collapsed.iter() {
+            if let Some(path) = viewstate::stackentry_path(&mut transaction, *id) {
+                collapsed.push(path);
+            }
+        }
+
+        Ok(viewstate::ViewState {
+            sort_order: Some(self.sort_order),
+            selected_row: self.selected_row,
+            scroll_offset: self.scroll_offset,
+            filter: self
+                .tree_builder
+                .filter()
+                .map(|filter| filter.as_str().to_string()),
+            collapsed,
+        })
+    }
+
+    // The number of screen rows used by panes above the call tree.
+    fn pane_height(&self) -> i32 {
+        if self.show_timeline {
+            TIMELINE_HEIGHT
+        } else {
+            0
+        }
+    }
+
+    // The number of screen rows used by the detail pane below the call
+    // tree.
+    fn detail_height(&self) -> i32 {
+        if self.show_detail {
+            DETAIL_HEIGHT
+        } else {
+            0
+        }
+    }
+
+    // The number of screen rows used by the source pane below the call
+    // tree.
+    fn source_height(&self) -> i32 {
+        if self.show_source {
+            SOURCE_HEIGHT
+        } else {
+            0
         }
     }
 
+    // The number of screen rows available for call tree rows.
+    fn tree_height(&self) -> i64 {
+        self.screen.get_max_y() as i64
+            - 2
+            - self.pane_height() as i64
+            - self.detail_height() as i64
+            - self.source_height() as i64
+    }
+
+    // Update the format of values to the current style, with columns wide
+    // enough for the largest value of the call tree.
+    fn update_value_format(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut transaction = trace::Transaction::new(&self.trace)?;
+        self.value_format =
+            report::ValueFormat::new(self.value_style, rows::largest_value(&mut transaction)?);
+        self.churn_format =
+            report::ValueFormat::new(self.value_style, rows::largest_churn(&mut transaction)?);
+        self.bar_total = rows::bar_total(&mut transaction, self.sort_order.mode)?;
+
+        Ok(())
+    }
+
     // Generate and cache currently displayed rows, using the current screen
     // size, scroll offset and sort mode.
     fn generate_display_rows(&mut self) -> Result<(), Box<dyn Error>> {
-        let max_rows = self.screen.get_max_y() as usize - 1;
+        let max_rows = std::cmp::max(self.tree_height() + 1, 0) as usize;
+        let row_limits = self.row_limits();
         let mut transaction = trace::Transaction::new(&self.trace)?;
 
+        self.tree_cache.set_sort_order(self.sort_order);
         self.display_rows = rows::iter_stackentry_rows(
             &mut transaction,
-            self.sort_mode,
+            &mut self.tree_cache,
             Some(&self.collapsed),
+            row_limits,
             self.scroll_offset as usize,
             max_rows,
         )?;
@@ -130,28 +550,122 @@ impl UIState {
 
     // Draw the header for the stackentry related columns.
     fn draw_stack_header(&self) {
-        self.screen.mv(0, 0);
-        self.screen.attron(pancurses::COLOR_PAIR(3));
+        self.screen.mv(self.pane_height(), 0);
+        self.screen.attron(self.palette.header);
 
         print_header(
             &self.screen,
-            "BYTES",
-            self.sort_mode == rows::SortMode::Bytes,
+            &self.palette,
+            &self.value_format.header("BYTES"),
+            self.sort_order.mode == rows::SortMode::Bytes,
         );
         self.screen.printw(" ");
+        if !self.free_tracking {
+            self.screen.attron(pancurses::A_DIM);
+        }
+        print_header(
+            &self.screen,
+            &self.palette,
+            &self.value_format.header("HELD"),
+            self.sort_order.mode == rows::SortMode::Held,
+        );
+        self.screen.attroff(pancurses::A_DIM);
+        self.screen.printw(" ");
         print_header(
             &self.screen,
-            "BLOCK",
-            self.sort_mode == rows::SortMode::Blocks,
+            &self.palette,
+            &self.value_format.header("BLOCK"),
+            self.sort_order.mode == rows::SortMode::Blocks,
         );
         self.screen.printw(" ");
+        if !self.free_tracking {
+            self.screen.attron(pancurses::A_DIM);
+        }
         print_header(
             &self.screen,
-            "LEAKS",
-            self.sort_mode == rows::SortMode::Leaks,
+            &self.palette,
+            &self.value_format.header(if self.ended_by_detach {
+                "LIVE"
+            } else {
+                "LEAKS"
+            }),
+            self.sort_order.mode == rows::SortMode::Leaks,
         );
+        self.screen.attroff(pancurses::A_DIM);
+        if self.show_churn {
+            self.screen.printw(" ");
+            print_header(
+                &self.screen,
+                &self.palette,
+                &self.churn_format.header("CHURN"),
+                self.sort_order.mode == rows::SortMode::Churn,
+            );
+        }
+        for (index, column) in columns::computed_columns().iter().enumerate() {
+            self.screen.printw(" ");
+            print_header(
+                &self.screen,
+                &self.palette,
+                &column.header(),
+                self.sort_order.mode == rows::SortMode::Column(index),
+            );
+        }
+        if self.show_rates {
+            self.screen.printw(" ");
+            print_header(
+                &self.screen,
+                &self.palette,
+                &report::table_header("ALC/s"),
+                false,
+            );
+            self.screen.printw(" ");
+            print_header(
+                &self.screen,
+                &self.palette,
+                &report::table_header("B/s"),
+                false,
+            );
+        }
+        if self.show_times {
+            self.screen.printw(" ");
+            print_header(&self.screen, &self.palette, " FIRST", false);
+            self.screen.printw(" ");
+            print_header(&self.screen, &self.palette, "  LAST", false);
+        }
+        if self.showing_addresses() {
+            self.screen.printw(" ");
+            print_header(
+                &self.screen,
+                &self.palette,
+                &report::address_columns_header(),
+                false,
+            );
+        }
+        if self.showing_bars() {
+            self.screen.printw(" ");
+            print_header(
+                &self.screen,
+                &self.palette,
+                &text::fit("SHARE", report::BAR_WIDTH),
+                false,
+            );
+        }
         self.screen.printw("   ");
-        print_header(&self.screen, "Function", false);
+        print_header(
+            &self.screen,
+            &self.palette,
+            "Function",
+            self.sort_order.mode == rows::SortMode::Function,
+        );
+        if self.sort_order.reversed {
+            self.screen.printw(" (reversed)");
+        }
+        if let Some(retained) = self.retained_window {
+            self.screen.printw(format!(
+                " (totals of the last {:?})",
+                time::Duration::from_secs(retained)
+            ));
+        }
 
         let width = self.screen.get_max_x();
         let mut support_link = format!(
@@ -163,7 +677,7 @@ impl UIState {
         }
         self.screen.printw(support_link);
 
-        self.screen.attroff(pancurses::COLOR_PAIR(3));
+        self.screen.attroff(self.palette.header);
     }
 
     // Draw the keyboard help.
@@ -172,9 +686,68 @@ impl UIState {
         let height = self.screen.get_max_y();
 
         self.screen.mv(height - 1, 0);
-        self.screen.attron(pancurses::COLOR_PAIR(3));
-
-        print_key(&self.screen, width as usize, "F5", "Sort");
+        self.screen.attron(self.palette.header);
+
+        if self.allocations.is_some() {
+            print_key(&self.screen, &self.palette, width as usize, "PgDn", "Page");
+            print_key(&self.screen, &self.palette, width as usize, "Esc", "Back");
+        } else if self.histogram.is_some() || self.lifetimes.is_some() {
+            print_key(&self.screen, &self.palette, width as usize, "Esc", "Back");
+        } else if self.text_lines.is_some() {
+            print_key(&self.screen, &self.palette, width as usize, "PgDn", "Page");
+            print_key(&self.screen, &self.palette, width as usize, "Esc", "Back");
+        } else if self.phases.is_some() {
+            print_key(
+                &self.screen,
+                &self.palette,
+                width as usize,
+                "Enter",
+                "Summarize",
+            );
+            print_key(&self.screen, &self.palette, width as usize, "Esc", "Back");
+        } else if self.threads.is_some() {
+            print_key(
+                &self.screen,
+                &self.palette,
+                width as usize,
+                "Space",
+                "Choose",
+            );
+            print_key(&self.screen, &self.palette, width as usize, "a", "All");
+            print_key(
+                &self.screen,
+                &self.palette,
+                width as usize,
+                "Enter",
+                "Apply",
+            );
+            print_key(&self.screen, &self.palette, width as usize, "Esc", "Back");
+        } else if self.flamegraph.is_some() {
+            print_key(&self.screen, &self.palette, width as usize, "Enter", "Zoom");
+            print_key(&self.screen, &self.palette, width as usize, "g", "Tree");
+            print_key(&self.screen, &self.palette, width as usize, "Esc", "Back");
+        } else if let Some(search) = &self.search {
+            self.screen.attroff(self.palette.header);
+            self.screen.printw(text::truncate(
+                &self.search_prompt(search),
+                width as usize - 1,
+            ));
+            self.screen.attron(self.palette.header);
+            self.screen.printw(" ");
+            print_key(&self.screen, &self.palette, width as usize, "Enter", "Jump");
+            print_key(&self.screen, &self.palette, width as usize, "Esc", "Back");
+        } else if self.focus.is_some() {
+            print_key(
+                &self.screen,
+                &self.palette,
+                width as usize,
+                "Enter",
+                "Focus",
+            );
+            print_key(&self.screen, &self.palette, width as usize, "Esc", "Back");
+        } else {
+            self.draw_tree_key_help(width);
+        }
 
         let cur_x = self.screen.get_cur_x();
         let mut fill = "".to_string();
@@ -183,7 +756,98 @@ impl UIState {
         }
         self.screen.printw(fill);
 
-        self.screen.attroff(pancurses::COLOR_PAIR(3));
+        self.screen.attroff(self.palette.header);
+    }
+
+    // Draw the keyboard help for the call tree.
+    fn draw_tree_key_help(&self, width: i32) {
+        print_key(
+            &self.screen,
+            &self.palette,
+            width as usize,
+            "Enter",
+            "Focus",
+        );
+        print_key(&self.screen, &self.palette, width as usize, "F5", "Sort");
+        print_key(&self.screen, &self.palette, width as usize, "F6", "Reverse");
+        let last_column_key = 6 + std::cmp::min(columns::computed_columns().len(), 3);
+        print_key(
+            &self.screen,
+            &self.palette,
+            width as usize,
+            &format!("1-{}", last_column_key),
+            "Column",
+        );
+        let depth_label = match self.max_depth {
+            Some(depth) => format!("Depth {}", depth),
+            None => "Depth".to_string(),
+        };
+        print_key(
+            &self.screen,
+            &self.palette,
+            width as usize,
+            "+-",
+            &depth_label,
+        );
+        print_key(&self.screen, &self.palette, width as usize, "/", "Search");
+        print_key(
+            &self.screen,
+            &self.palette,
+            width as usize,
+            "a",
+            "Allocations",
+        );
+        print_key(&self.screen, &self.palette, width as usize, "A", "Address");
+        print_key(&self.screen, &self.palette, width as usize, "b", "Bars");
+        print_key(&self.screen, &self.palette, width as usize, "c", "Code");
+        print_key(&self.screen, &self.palette, width as usize, "C", "Churn");
+        print_key(&self.screen, &self.palette, width as usize, "d", "Detail");
+        print_key(&self.screen, &self.palette, width as usize, "e", "Exact");
+        print_key(&self.screen, &self.palette, width as usize, "f", "Filter");
+        print_key(&self.screen, &self.palette, width as usize, "F", "Freed by");
+        print_key(&self.screen, &self.palette, width as usize, "g", "Flame");
+        print_key(&self.screen, &self.palette, width as usize, "G", "Growth");
+        print_key(&self.screen, &self.palette, width as usize, "h", "Parent");
+        print_key(&self.screen, &self.palette, width as usize, "H", "Heaviest");
+        print_key(&self.screen, &self.palette, width as usize, "i", "Invert");
+        print_key(&self.screen, &self.palette, width as usize, "I", "Info");
+        print_key(&self.screen, &self.palette, width as usize, "JK", "Sibling");
+        print_key(
+            &self.screen,
+            &self.palette,
+            width as usize,
+            "l",
+            "Lifetimes",
+        );
+        print_key(&self.screen, &self.palette, width as usize, "m", "Modules");
+        print_key(&self.screen, &self.palette, width as usize, "o", "Open");
+        print_key(&self.screen, &self.palette, width as usize, "p", "Prune");
+        print_key(&self.screen, &self.palette, width as usize, "P", "Phases");
+        print_key(&self.screen, &self.palette, width as usize, "r", "Rates");
+        print_key(&self.screen, &self.palette, width as usize, "R", "Refresh");
+        print_key(&self.screen, &self.palette, width as usize, "s", "Sizes");
+        print_key(&self.screen, &self.palette, width as usize, "t", "Timeline");
+        print_key(&self.screen, &self.palette, width as usize, "T", "Times");
+        print_key(&self.screen, &self.palette, width as usize, "w", "Threads");
+        print_key(
+            &self.screen,
+            &self.palette,
+            width as usize,
+            "u",
+            "Recursion",
+        );
+        print_key(
+            &self.screen,
+            &self.palette,
+            width as usize,
+            "X",
+            "Addresses",
+        );
+        if self.show_timeline {
+            print_key(&self.screen, &self.palette, width as usize, "<>", "Cursor");
+            print_key(&self.screen, &self.palette, width as usize, "[]", "Range");
+            print_key(&self.screen, &self.palette, width as usize, "x", "All");
+        }
     }
 
     // Draw stack entry rows.
@@ -191,199 +855,2553 @@ impl UIState {
         let mut row: i64 = 0;
 
         let width = self.screen.get_max_x() as usize;
-        let height = self.screen.get_max_y() as usize;
+        let top = self.pane_height() + 1;
         while let Some(entry) = rows.next() {
-            if row + 2 >= height as i64 {
+            if row >= self.tree_height() {
                 break;
             }
 
             let function_str = report::format_function_tree_row(Some(&self.collapsed), &entry);
-            let mut function_substr = "";
-            if self.column_offset < function_str.len() as i64 {
-                function_substr = &function_str.as_str()[self.column_offset as usize..];
-            }
-
-            let mut str = format!(
-                "{} {} {} {}",
-                report::format_table_value(entry.maximum_size, 1024),
-                report::format_table_value(entry.total_blocks, 1000),
-                report::format_table_value(entry.unfreed_blocks, 1000),
+            let function_substr = text::skip_columns(&function_str, self.column_offset as usize);
+
+            let line = format!(
+                "{} {} {} {}{}{}{}{}{}{} {}",
+                self.value_format.value(entry.maximum_size, 1024),
+                report::format_held_value(entry, self.free_tracking, self.value_format),
+                self.value_format.value(entry.total_blocks, 1000),
+                report::format_leaks_value(entry, self.free_tracking, self.value_format),
+                self.format_churn_column(entry.total_bytes),
+                report::format_computed_values(&entry.summary()),
+                self.format_rate_columns(entry.total_blocks, entry.total_bytes),
+                self.format_time_columns(entry.first_time, entry.last_time),
+                self.format_address_columns(entry),
+                self.format_bar_column(entry),
                 function_substr,
             );
-            while str.len() < width {
-                str = str + " ";
-            }
+            let str = text::fit(&line, width);
 
             let mut selected = false;
             if self.selected_row == row + self.scroll_offset {
                 selected = true;
 
-                self.screen.attron(pancurses::COLOR_PAIR(2));
+                self.screen.attron(self.palette.selected);
                 self.screen.attron(pancurses::A_BOLD);
             }
 
-            self.screen.mv(row as i32 + 1, 0);
+            self.screen.mv(row as i32 + top, 0);
             self.screen.printw(str);
 
             if selected {
                 self.screen.attroff(pancurses::A_BOLD);
-                self.screen.attroff(pancurses::COLOR_PAIR(2));
+                self.screen.attroff(self.palette.selected);
             }
 
             row += 1;
         }
     }
 
-    // An unexpected error has occurred while generating the display.
-    // Draw it.
-    fn draw_error(&mut self, err: Box<dyn Error>) {
-        let err_str = format!("{:?}", err);
-        self.screen.mv(self.screen.get_max_y() - 1, 0);
-        self.screen.printw(err_str);
-    }
-
-    // Redraw all components of the user interface.
-    fn draw(&mut self, report_perf: bool) {
-        let start_draw_time = time::Instant::now();
-        self.screen.erase();
-
-        self.draw_stack_header();
-        match self.generate_display_rows() {
-            Ok(()) => {
-                self.draw_stackentry_rows(&mut self.display_rows.iter());
+    // Draw the timeline pane, plotting the bytes live over the duration of
+    // the trace, with one column of the screen for each span of time.
+    fn draw_timeline(&self) {
+        let timeline = match &self.timeline {
+            Some(timeline) if self.show_timeline => timeline,
+            _ => return,
+        };
+        let width = self.screen.get_max_x() as usize;
+        let plot_rows = TIMELINE_HEIGHT as u64 - 1;
+        let peak = std::cmp::max(timeline.peak(), 1);
+
+        // Each row of the plot is split in two, with '.' filling the lower
+        // half and '#' filling the whole row.
+        let levels: Vec<u64> = timeline
+            .columns(width)
+            .iter()
+            .map(|bytes| (bytes * plot_rows * 2).div_ceil(peak))
+            .collect();
+
+        // The selected span of time is highlighted, and the cursor is
+        // shown in reverse.
+        let cursor_column = timeline.column_at(self.timeline_cursor, width);
+        let first_selected = timeline.column_at(self.time_range.start, width);
+        let last_selected = timeline.column_at(self.time_range.end, width);
+        for plot_row in 0..plot_rows {
+            let floor = (plot_rows - 1 - plot_row) * 2;
+            self.screen.mv(plot_row as i32, 0);
+            for (column, level) in levels.iter().enumerate() {
+                let char = if *level >= floor + 2 {
+                    "#"
+                } else if *level > floor {
+                    "."
+                } else {
+                    " "
+                };
+                let selected = self.time_range != summary::TimeRange::all()
+                    && column >= first_selected
+                    && column <= last_selected;
+
+                if selected {
+                    self.screen.attron(self.palette.selected);
+                }
+                if column == cursor_column {
+                    self.screen.attron(pancurses::A_REVERSE);
+                }
+                self.screen.printw(char);
+                self.screen.attroff(pancurses::A_REVERSE);
+                self.screen.attroff(self.palette.selected);
             }
-            Err(err) => self.draw_error(err),
         }
-        self.draw_key_help();
-        let end_draw_time = time::Instant::now();
 
-        if report_perf {
-            let time_str = format!("draw: {:.2?} ", end_draw_time - start_draw_time);
-            let rows = self.screen.get_max_y();
-            self.screen.mv(rows - 1, 0);
-            self.screen.printw(time_str);
+        let mut label = format!(
+            "Live bytes over {:.2?}, peak {}, cursor at {:.2?}",
+            time::Duration::from_nanos(timeline.duration),
+            report::format_table_value(timeline.peak(), 1024).trim_start(),
+            time::Duration::from_nanos(self.timeline_cursor),
+        );
+        if self.time_range != summary::TimeRange::all() {
+            label += &format!(", events {}", report::format_time_range(self.time_range));
         }
-
-        self.screen.refresh();
+        self.screen.mv(TIMELINE_HEIGHT - 1, 0);
+        self.screen.attron(pancurses::A_DIM);
+        self.screen.printw(text::fit(&label, width));
+        self.screen.attroff(pancurses::A_DIM);
     }
 
-    // Adjust the scroll offset such that the currently selected row is shown.
-    fn scroll_to_selection(&mut self) {
-        let rows = self.screen.get_max_y() as i64 - 2;
+    // The lines describing the selected row in the detail pane, each no
+    // wider than 'width'.  The function name is wrapped rather than
+    // truncated.
+    fn detail_lines(&self, row: &rows::StackEntryRow, width: usize) -> Vec<String> {
+        let label_width = 10;
+        let value_width = std::cmp::max(width, label_width + 1) - label_width;
+        let name_chars: Vec<char> = if row.pruned > 0 || row.function.is_empty() {
+            row.function.chars().collect()
+        } else {
+            format!("{} + 0x{:x}", row.function, row.offset)
+                .chars()
+                .collect()
+        };
+
+        let mut lines = Vec::new();
+        for (index, chunk) in name_chars
+            .chunks(value_width)
+            .take(DETAIL_NAME_ROWS)
+            .enumerate()
+        {
+            let label = if index == 0 { "Function" } else { "" };
+            lines.push(format!(
+                "{:<label_width$}{}",
+                label,
+                chunk.iter().collect::<String>()
+            ));
+        }
 
-        if self.scroll_offset > self.selected_row {
-            self.scroll_offset = self.selected_row;
+        if row.pruned == 0 {
+            let module = self
+                .modules
+                .as_ref()
+                .and_then(|modules| trace::module_at(modules, row.address))
+                .map(|module| module.filename.as_str())
+                .unwrap_or("unknown");
+            lines.push(format!("{:<label_width$}{}", "Module", module));
+            lines.push(format!(
+                "{:<label_width$}0x{:x}, offset 0x{:x}",
+                "Address", row.address, row.offset
+            ));
         }
-        if self.scroll_offset + rows <= self.selected_row {
-            self.scroll_offset = self.selected_row - rows + 1;
+        let mut peak = format!("{} bytes", row.maximum_size);
+        if self.free_tracking {
+            peak += &format!(", {} held at the end", row.held_size);
+        }
+        lines.push(format!("{:<label_width$}{}", "Peak", peak));
+        lines.push(format!(
+            "{:<label_width$}{} bytes in {} blocks",
+            "Total", row.total_bytes, row.total_blocks
+        ));
+        if self.free_tracking {
+            let (label, unfreed) = if self.ended_by_detach {
+                ("Live", "still allocated")
+            } else {
+                ("Leaks", "never freed")
+            };
+            lines.push(format!(
+                "{:<label_width$}{} blocks {}",
+                label, row.unfreed_blocks, unfreed
+            ));
         }
+
+        lines
+            .into_iter()
+            .map(|line| text::truncate(&line, width))
+            .collect()
     }
 
-    // Respond to a down keypress.
-    fn on_move_down(&mut self) {
-        if self.selected_row < self.scroll_offset + self.display_rows.len() as i64 - 1 {
-            self.selected_row += 1;
+    // Draw the detail pane, describing the selected row in full.
+    fn draw_detail(&self) {
+        if !self.show_detail || self.focus.is_some() {
+            return;
         }
-        self.scroll_to_selection();
-    }
+        let width = self.screen.get_max_x() as usize;
+        let top = self.screen.get_max_y() - 1 - DETAIL_HEIGHT;
 
-    // Respond to an up keypress.
-    fn on_move_up(&mut self) {
-        if self.selected_row > 0 {
-            self.selected_row -= 1;
+        let mut label = "Selected entry".to_string();
+        while label.len() < width {
+            label += " ";
         }
-        self.scroll_to_selection();
-    }
+        self.screen.mv(top, 0);
+        self.screen.attron(pancurses::A_DIM);
+        self.screen.printw(label);
+        self.screen.attroff(pancurses::A_DIM);
 
-    // Respond to a left keypress.
-    fn on_move_left(&mut self) {
-        self.column_offset = std::cmp::max(self.column_offset - 8, 0);
+        let row = match self
+            .display_rows
+            .get((self.selected_row - self.scroll_offset) as usize)
+        {
+            Some(row) => row,
+            None => return,
+        };
+        for (index, line) in self
+            .detail_lines(row, width)
+            .iter()
+            .take(DETAIL_HEIGHT as usize - 1)
+            .enumerate()
+        {
+            self.screen.mv(top + 1 + index as i32, 0);
+            self.screen.printw(line);
+        }
     }
 
-    // Respond to a right keypress.
-    fn on_move_right(&mut self) {
-        self.column_offset += 8;
+    // Show or hide the detail pane, reading the modules of the traced
+    // process when first shown.
+    fn on_toggle_detail(&mut self) {
+        if self.modules.is_none() {
+            self.modules = Some(self.trace.modules().unwrap_or_default());
+        }
+        self.show_detail = !self.show_detail;
+        self.scroll_to_selection();
     }
 
-    // Respond to a page down keypress.
-    fn on_page_down(&mut self) {
-        let rows = self.screen.get_max_y() as i64 - 2;
-
-        if self.selected_row == self.scroll_offset + rows - 1
-            && self.display_rows.len() as i64 > rows
-        {
-            self.scroll_offset += rows;
+    // The label and lines of the source pane, showing the source around the
+    // line of the selected frame.  Each line is annotated with the totals of
+    // the calls made from it, by the selected frame's function beneath the
+    // same caller.
+    fn source_lines(
+        &mut self,
+        id: trace::StackEntryId,
+        width: usize,
+    ) -> Result<(String, Vec<String>), Box<dyn Error>> {
+        if self.modules.is_none() {
+            self.modules = Some(self.trace.modules().unwrap_or_default());
         }
-        self.selected_row = self.scroll_offset + rows - 1;
+        let modules = self.modules.as_deref().unwrap_or_default();
 
-        match self.generate_display_rows() {
-            Ok(()) => {
-                let display_count = self.display_rows.len() as i64;
-                if self.selected_row > self.scroll_offset + display_count - 1 {
-                    self.selected_row = self.scroll_offset + display_count - 1;
+        let mut transaction = trace::Transaction::new(&self.trace)?;
+        let entry = transaction
+            .stackentry(id)
+            .ok_or("failure retrieving stack entry")?;
+        let function = transaction
+            .location(entry.location)
+            .and_then(|location| location.function);
+        let siblings = match entry.next {
+            Some(parent) => transaction.get_stackentry_children(parent)?,
+            None => transaction.root_stackentries()?,
+        };
+        let mut selected_index = 0;
+        let mut addresses = Vec::new();
+        let mut totals = Vec::new();
+        for sibling in siblings {
+            let location = match transaction.location(sibling.location) {
+                Some(location) if location.function == function => location,
+                _ => continue,
+            };
+            if let Some(summary) = transaction.summary(sibling.id) {
+                if sibling.id == id {
+                    selected_index = addresses.len();
+                }
+                addresses.push((location.address, location.offset.unwrap_or(0) > 0));
+                totals.push((summary.maximum_total, summary.alloc_count));
+            }
+        }
+        drop(transaction);
+
+        let locations = self.source_cache.locations(modules, &addresses);
+        let selected = locations
+            .get(selected_index)
+            .ok_or("the entry has no totals")?
+            .clone()?;
+        let mut annotations: collections::HashMap<u64, (u64, u64)> = collections::HashMap::new();
+        for (location, (bytes, blocks)) in locations.iter().zip(totals) {
+            if let Ok(location) = location {
+                if location.filename == selected.filename {
+                    let annotation = annotations.entry(location.line).or_default();
+                    annotation.0 += bytes;
+                    annotation.1 += blocks;
                 }
             }
-            _ => {}
         }
-    }
-
-    // Respond to a page up keypress.
-    fn on_page_up(&mut self) {
-        let rows = self.screen.get_max_y() as i64 - 2;
 
-        if self.selected_row == self.scroll_offset {
-            self.scroll_offset = std::cmp::max(self.scroll_offset - rows, 0);
+        let path = self.source_cache.remap(&selected.filename);
+        let value_format = self.value_format;
+        let label = format!(
+            "{} {}   {}:{}",
+            value_format.header("BYTES"),
+            value_format.header("BLOCK"),
+            path,
+            selected.line
+        );
+        let source = self
+            .source_cache
+            .lines(&selected.filename)
+            .ok_or_else(|| format!("source file not found: {}", path))?;
+
+        // The selected line is centered, unless near the start or end of
+        // the file.
+        let shown = SOURCE_HEIGHT as u64 - 1;
+        let last_start = std::cmp::max(source.len() as u64, shown) - shown + 1;
+        let start = (selected.line.saturating_sub(shown / 2)).clamp(1, last_start);
+        let mut lines = Vec::new();
+        for number in start..std::cmp::min(start + shown, source.len() as u64 + 1) {
+            let values = match annotations.get(&number) {
+                Some((bytes, blocks)) => format!(
+                    "{} {}",
+                    value_format.value(*bytes, 1024),
+                    value_format.value(*blocks, 1000)
+                ),
+                None => format!("{} {}", value_format.header(""), value_format.header("")),
+            };
+            let marker = if number == selected.line { ">" } else { " " };
+            let line = format!(
+                "{} {}{:>5}  {}",
+                values,
+                marker,
+                number,
+                source[number as usize - 1].replace('\t', "    ")
+            );
+            lines.push(text::fit(&line, width));
         }
-        self.selected_row = self.scroll_offset;
-    }
 
-    // On a home keypress, scroll to the top.
-    fn on_home(&mut self) {
-        self.selected_row = 0;
-        self.scroll_offset = 0;
+        Ok((text::fit(&label, width), lines))
     }
 
-    // On an end keypress, scroll to the bottom.
-    fn on_end(&mut self) {
-        let display_rows = self.screen.get_max_y() as i64 - 2;
-        if let Ok(mut transaction) = trace::Transaction::new(&self.trace) {
-            if let Ok(total_rows) = rows::count_rows(&mut transaction, Some(&self.collapsed)) {
-                self.selected_row = total_rows as i64 - 1;
-                self.scroll_offset = std::cmp::max(self.selected_row - display_rows + 1, 0);
-            }
+    // Draw the source pane, showing the source code of the selected frame.
+    fn draw_source(&mut self) {
+        if !self.show_source || self.focus.is_some() {
+            return;
         }
-    }
+        let width = self.screen.get_max_x() as usize;
+        let top = self.screen.get_max_y() - 1 - self.detail_height() - SOURCE_HEIGHT;
 
-    // Collapse or expand the currently selected row.
-    fn on_toggle_collapse(&mut self) {
-        if let Some(row) = self
+        let id = match self
             .display_rows
             .get((self.selected_row - self.scroll_offset) as usize)
         {
-            if self.collapsed.contains(&row.id) {
-                self.collapsed.remove(&row.id);
-            } else {
-                self.collapsed.insert(row.id);
+            Some(row) if row.pruned == 0 => Some(row.id),
+            _ => None,
+        };
+        let (label, lines) = match id.map(|id| self.source_lines(id, width)) {
+            Some(Ok((label, lines))) => (label, lines),
+            Some(Err(err)) => (text::fit("Source", width), vec![err.to_string()]),
+            None => (text::fit("Source", width), Vec::new()),
+        };
+
+        self.screen.mv(top, 0);
+        self.screen.attron(pancurses::A_DIM);
+        self.screen.addstr(label);
+        self.screen.attroff(pancurses::A_DIM);
+        for (index, line) in lines.iter().take(SOURCE_HEIGHT as usize - 1).enumerate() {
+            self.screen.mv(top + 1 + index as i32, 0);
+            self.screen.addstr(text::truncate(line, width));
+        }
+    }
+
+    // Show or hide the source pane.
+    fn on_toggle_source(&mut self) {
+        self.show_source = !self.show_source;
+        self.scroll_to_selection();
+    }
+
+    // Open the source of the selected frame in the user's editor,
+    // suspending the UI until the editor exits.
+    fn on_open_source(&mut self) {
+        if self.modules.is_none() {
+            self.modules = Some(self.trace.modules().unwrap_or_default());
+        }
+        let row = match self
+            .display_rows
+            .get((self.selected_row - self.scroll_offset) as usize)
+        {
+            Some(row) => row,
+            None => return,
+        };
+
+        let modules = self.modules.as_deref().unwrap_or_default();
+        let result = self
+            .source_cache
+            .locations(modules, &[(row.address, row.offset > 0)])
+            .remove(0)
+            .map_err(|err| err.into())
+            .and_then(|location| {
+                let location = source::SourceLocation {
+                    filename: self.source_cache.remap(&location.filename),
+                    ..location
+                };
+                pancurses::def_prog_mode();
+                pancurses::endwin();
+                let result = source::open_in_editor(&location);
+                pancurses::reset_prog_mode();
+                self.screen.clearok(true);
+                result
+            });
+        if let Err(err) = result {
+            self.draw_error(err);
+            self.screen.getch();
+        }
+    }
+
+    // Format the churn column, if shown, with a leading space.
+    fn format_churn_column(&self, total_bytes: u64) -> String {
+        if self.show_churn {
+            format!(" {}", self.churn_format.value(total_bytes, 1024))
+        } else {
+            String::new()
+        }
+    }
+
+    // Format the rate columns, if shown, with a leading space.
+    fn format_rate_columns(&self, total_blocks: u64, total_bytes: u64) -> String {
+        if self.show_rates {
+            format!(
+                " {}",
+                report::format_rate_values(total_blocks, total_bytes, self.summarized_span)
+            )
+        } else {
+            String::new()
+        }
+    }
+
+    // Format the time columns, if shown, with a leading space.
+    fn format_time_columns(&self, first_time: Option<u64>, last_time: Option<u64>) -> String {
+        if self.show_times {
+            format!(" {}", report::format_time_values(first_time, last_time))
+        } else {
+            String::new()
+        }
+    }
+
+    // True if the address columns are shown.  They aren't in the focus and
+    // search views, which list functions rather than stack entries.
+    fn showing_addresses(&self) -> bool {
+        self.show_addresses && self.focus.is_none() && self.search.is_none()
+    }
+
+    // Format the address columns, if shown, with a leading space.
+    fn format_address_columns(&self, entry: &rows::StackEntryRow) -> String {
+        if self.showing_addresses() {
+            let modules = self.modules.as_deref().unwrap_or_default();
+            format!(" {}", report::format_address_values(entry, modules))
+        } else {
+            String::new()
+        }
+    }
+
+    // True if the bar column is shown, which it isn't for the focus view or
+    // a search.
+    fn showing_bars(&self) -> bool {
+        self.show_bars && self.focus.is_none() && self.search.is_none()
+    }
+
+    // Format the bar showing a row's share of the total of the sorted
+    // column, if shown.
+    fn format_bar_column(&self, entry: &rows::StackEntryRow) -> String {
+        if self.showing_bars() {
+            let value = rows::bar_value(&entry.summary(), self.sort_order.mode);
+            format!(" {}", report::format_bar(value, self.bar_total))
+        } else {
+            String::new()
+        }
+    }
+
+    // Show or hide the address columns, reading the modules of the trace
+    // when first shown.
+    fn on_toggle_addresses(&mut self) {
+        if self.modules.is_none() {
+            self.modules = Some(self.trace.modules().unwrap_or_default());
+        }
+        self.show_addresses = !self.show_addresses;
+    }
+
+    // The width of the churn, computed, rate and time columns shown, with
+    // their leading spaces.
+    fn optional_columns_width(&self) -> usize {
+        let churn_width = if self.show_churn {
+            self.churn_format.width + 1
+        } else {
+            0
+        };
+        let computed_width: usize = columns::computed_columns()
+            .iter()
+            .map(|column| column.width() + 1)
+            .sum();
+        let rates_width = if self.show_rates {
+            2 * (report::table_value_width() + 1)
+        } else {
+            0
+        };
+        let times_width = if self.show_times { 14 } else { 0 };
+
+        churn_width + computed_width + rates_width + times_width
+    }
+
+    // Format the totals of a function in the focus view.
+    fn format_focus_totals(&self, totals: &focus::FocusTotals) -> String {
+        let value_format = self.value_format;
+        let (held_size, unfreed_blocks) = if self.free_tracking {
+            (
+                value_format.value(totals.held_size, 1024),
+                value_format.value(totals.unfreed_blocks, 1000),
+            )
+        } else {
+            (value_format.header("-"), value_format.header("-"))
+        };
+
+        format!(
+            "{} {} {} {}{}{}{}{}",
+            value_format.value(totals.maximum_size, 1024),
+            held_size,
+            value_format.value(totals.total_blocks, 1000),
+            unfreed_blocks,
+            self.format_churn_column(totals.total_bytes),
+            report::format_computed_values(&totals.summary()),
+            self.format_rate_columns(totals.total_blocks, totals.total_bytes),
+            self.format_time_columns(totals.first_time, totals.last_time),
+        )
+    }
+
+    // Draw the callers and callees of the focused function, scrolled so
+    // that the selected function is shown.
+    fn draw_focus(&self, focus: &focus::FocusView) {
+        let blank_totals =
+            " ".repeat(self.value_format.columns_width() + self.optional_columns_width());
+
+        // Lines of the view, with the index of the function selectable on
+        // each line.
+        let mut lines: Vec<(String, Option<usize>)> = Vec::new();
+        lines.push((
+            format!(
+                "{}   {}",
+                self.format_focus_totals(&focus.totals),
+                focus.function
+            ),
+            None,
+        ));
+        let mut index = 0;
+        for (heading, functions) in [("Callers", &focus.callers), ("Callees", &focus.callees)] {
+            lines.push((format!("{}   {}:", blank_totals, heading), None));
+            if functions.is_empty() {
+                lines.push((format!("{}     (none)", blank_totals), None));
+            }
+            for (function, totals) in functions.iter() {
+                lines.push((
+                    format!("{}     {}", self.format_focus_totals(totals), function),
+                    Some(index),
+                ));
+                index += 1;
+            }
+        }
+
+        let width = self.screen.get_max_x() as usize;
+        let height = self.tree_height() as usize;
+        let selected_line = lines
+            .iter()
+            .position(|(_, index)| *index == Some(self.focus_selected))
+            .unwrap_or(0);
+        let first_line = (selected_line + 1).saturating_sub(height);
+        let top = self.pane_height() + 1;
+        for (row, (line, index)) in lines.iter().skip(first_line).take(height).enumerate() {
+            let str = text::fit(line, width);
+
+            let selected = *index == Some(self.focus_selected);
+            if selected {
+                self.screen.attron(self.palette.selected);
+                self.screen.attron(pancurses::A_BOLD);
+            }
+            self.screen.mv(row as i32 + top, 0);
+            self.screen.printw(str);
+            if selected {
+                self.screen.attroff(pancurses::A_BOLD);
+                self.screen.attroff(self.palette.selected);
+            }
+        }
+    }
+
+    // An unexpected error has occurred while generating the display.
+    // Draw it.
+    fn draw_error(&mut self, err: Box<dyn Error>) {
+        let err_str = format!("{:?}", err);
+        self.screen.mv(self.screen.get_max_y() - 1, 0);
+        self.screen.printw(err_str);
+    }
+
+    // Redraw all components of the user interface.
+    fn draw(&mut self, report_perf: bool) {
+        let start_draw_time = time::Instant::now();
+        self.screen.erase();
+
+        // The previous format is kept if the trace can't be read, with the
+        // error shown in drawing the call tree.
+        let _ = self.update_value_format();
+        self.draw_timeline();
+        if let Some(list) = &self.allocations {
+            self.draw_allocations(list);
+        } else if let Some(histogram) = &self.histogram {
+            self.draw_histogram(histogram);
+        } else if let Some(lifetimes) = &self.lifetimes {
+            self.draw_lifetimes(lifetimes);
+        } else if let Some(lines) = &self.text_lines {
+            self.draw_text_lines(lines);
+        } else if let Some(phases) = &self.phases {
+            self.draw_phases(phases);
+        } else if let Some(threads) = &self.threads {
+            self.draw_threads(threads);
+        } else if self.flamegraph.is_some() {
+            if let Err(err) = self.fit_flamegraph() {
+                self.draw_error(err);
+            }
+            if let Some(graph) = &self.flamegraph {
+                self.draw_flamegraph(graph);
+            }
+        } else if let Some(search) = &self.search {
+            self.draw_stack_header();
+            self.draw_search(search);
+        } else if let Some(focus) = &self.focus {
+            self.draw_stack_header();
+            self.draw_focus(focus);
+        } else {
+            self.draw_stack_header();
+            match self.generate_display_rows() {
+                Ok(()) => {
+                    self.draw_stackentry_rows(&mut self.display_rows.iter());
+                    self.draw_source();
+                    self.draw_detail();
+                }
+                Err(err) => self.draw_error(err),
+            }
+        }
+        self.draw_key_help();
+        if let Some(err) = &self.refresh_error {
+            let width = self.screen.get_max_x() as usize;
+            self.screen.mv(self.screen.get_max_y() - 1, 0);
+            self.screen.attron(self.palette.header);
+            self.screen.printw(text::truncate(
+                &format!("Can't read trace: {}", err),
+                width - 1,
+            ));
+            self.screen.attroff(self.palette.header);
+        }
+        let end_draw_time = time::Instant::now();
+
+        if report_perf {
+            let time_str = format!("draw: {:.2?} ", end_draw_time - start_draw_time);
+            let rows = self.screen.get_max_y();
+            self.screen.mv(rows - 1, 0);
+            self.screen.printw(time_str);
+        }
+
+        // The cursor is shown at the end of a search pattern as it is typed.
+        match &self.search {
+            Some(search) => {
+                let width = self.screen.get_max_x() as usize;
+                let column = std::cmp::min(text::width(&self.search_prompt(search)), width - 1);
+                self.screen.mv(self.screen.get_max_y() - 1, column as i32);
+                pancurses::curs_set(1);
+            }
+            None => {
+                pancurses::curs_set(0);
+            }
+        }
+
+        self.screen.refresh();
+    }
+
+    // Adjust the scroll offset such that the currently selected row is shown.
+    fn scroll_to_selection(&mut self) {
+        let rows = self.tree_height();
+
+        if self.scroll_offset > self.selected_row {
+            self.scroll_offset = self.selected_row;
+        }
+        if self.scroll_offset + rows <= self.selected_row {
+            self.scroll_offset = self.selected_row - rows + 1;
+        }
+    }
+
+    // Respond to a down keypress.
+    fn on_move_down(&mut self) {
+        if self.selected_row < self.scroll_offset + self.display_rows.len() as i64 - 1 {
+            self.selected_row += 1;
+        }
+        self.scroll_to_selection();
+    }
+
+    // Respond to an up keypress.
+    fn on_move_up(&mut self) {
+        if self.selected_row > 0 {
+            self.selected_row -= 1;
+        }
+        self.scroll_to_selection();
+    }
+
+    // Find the nearest row before or after the selected row with a depth
+    // no greater than 'max_depth', returning its index and depth.
+    fn find_row(
+        &mut self,
+        forward: bool,
+        max_depth: usize,
+    ) -> Result<Option<(i64, usize)>, Box<dyn Error>> {
+        let row_limits = self.row_limits();
+        self.tree_cache.set_sort_order(self.sort_order);
+        let mut transaction = trace::Transaction::new(&self.trace)?;
+        let mut start = if forward {
+            self.selected_row as usize + 1
+        } else {
+            self.selected_row as usize
+        };
+        loop {
+            let mut count = SEARCH_CHUNK_ROWS;
+            if !forward {
+                if start == 0 {
+                    return Ok(None);
+                }
+                count = std::cmp::min(count, start);
+                start -= count;
+            }
+            let chunk = rows::iter_stackentry_rows(
+                &mut transaction,
+                &mut self.tree_cache,
+                Some(&self.collapsed),
+                row_limits,
+                start,
+                count,
+            )?;
+            if chunk.is_empty() {
+                return Ok(None);
+            }
+
+            let found = if forward {
+                chunk.iter().position(|row| row.depth <= max_depth)
+            } else {
+                chunk.iter().rposition(|row| row.depth <= max_depth)
+            };
+            if let Some(index) = found {
+                return Ok(Some(((start + index) as i64, chunk[index].depth)));
+            }
+            if forward {
+                start += chunk.len();
+            }
+        }
+    }
+
+    // Select a row found by searching the call tree, if any.
+    fn select_found_row(&mut self, found: Result<Option<(i64, usize)>, Box<dyn Error>>) {
+        match found {
+            Ok(Some((row, _))) => {
+                self.selected_row = row;
+                self.scroll_to_selection();
+            }
+            Ok(None) => {}
+            Err(err) => {
+                self.draw_error(err);
+                self.screen.getch();
+            }
+        }
+    }
+
+    // The depth of the selected row.
+    fn selected_depth(&self) -> Option<usize> {
+        self.display_rows
+            .get((self.selected_row - self.scroll_offset) as usize)
+            .map(|row| row.depth)
+    }
+
+    // Select the parent of the selected row.
+    fn on_move_to_parent(&mut self) {
+        if let Some(depth) = self.selected_depth() {
+            if depth > 0 {
+                let found = self.find_row(false, depth - 1);
+                self.select_found_row(found);
+            }
+        }
+    }
+
+    // Select the next or previous sibling of the selected row, skipping
+    // over the descendents between them.
+    fn on_move_to_sibling(&mut self, forward: bool) {
+        if let Some(depth) = self.selected_depth() {
+            let found = self
+                .find_row(forward, depth)
+                .map(|found| found.filter(|(_, found_depth)| *found_depth == depth));
+            self.select_found_row(found);
+        }
+    }
+
+    // Respond to a left keypress.
+    fn on_move_left(&mut self) {
+        self.column_offset = std::cmp::max(self.column_offset - 8, 0);
+    }
+
+    // Respond to a right keypress.
+    fn on_move_right(&mut self) {
+        self.column_offset += 8;
+    }
+
+    // Respond to a page down keypress.
+    fn on_page_down(&mut self) {
+        let rows = self.tree_height();
+
+        if self.selected_row == self.scroll_offset + rows - 1
+            && self.display_rows.len() as i64 > rows
+        {
+            self.scroll_offset += rows;
+        }
+        self.selected_row = self.scroll_offset + rows - 1;
+
+        match self.generate_display_rows() {
+            Ok(()) => {
+                let display_count = self.display_rows.len() as i64;
+                if self.selected_row > self.scroll_offset + display_count - 1 {
+                    self.selected_row = self.scroll_offset + display_count - 1;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Respond to a page up keypress.
+    fn on_page_up(&mut self) {
+        let rows = self.tree_height();
+
+        if self.selected_row == self.scroll_offset {
+            self.scroll_offset = std::cmp::max(self.scroll_offset - rows, 0);
+        }
+        self.selected_row = self.scroll_offset;
+    }
+
+    // On a home keypress, scroll to the top.
+    fn on_home(&mut self) {
+        self.selected_row = 0;
+        self.scroll_offset = 0;
+    }
+
+    // On an end keypress, scroll to the bottom.
+    fn on_end(&mut self) {
+        let display_rows = self.tree_height();
+        let row_limits = self.row_limits();
+        if let Ok(mut transaction) = trace::Transaction::new(&self.trace) {
+            if let Ok(total_rows) = rows::count_rows(
+                &mut transaction,
+                &mut self.tree_cache,
+                Some(&self.collapsed),
+                row_limits,
+            ) {
+                self.selected_row = total_rows as i64 - 1;
+                self.scroll_offset = std::cmp::max(self.selected_row - display_rows + 1, 0);
+            }
+        }
+    }
+
+    // Collapse or expand the currently selected row.  A row standing for
+    // the frames of a shared object is expanded to show those frames.
+    fn on_toggle_collapse(&mut self) {
+        if let Some(row) = self
+            .display_rows
+            .get((self.selected_row - self.scroll_offset) as usize)
+        {
+            if row.pruned > 0 {
+                return;
+            }
+            if let Some(module) = self.tree_builder.rolled_module(&row.function) {
+                self.tree_builder.expand_module(&module);
+                self.rebuild_tree();
+            } else if self.collapsed.contains(&row.id) {
+                self.collapsed.remove(&row.id);
+            } else {
+                self.collapsed.insert(row.id);
+            }
+        }
+    }
+
+    // Toggle through the sort modes.
+    fn on_next_sort(&mut self) {
+        self.sort_order.mode = match self.sort_order.mode {
+            rows::SortMode::None => rows::SortMode::Bytes,
+            rows::SortMode::Bytes if self.free_tracking => rows::SortMode::Held,
+            rows::SortMode::Bytes => rows::SortMode::Blocks,
+            rows::SortMode::Held => rows::SortMode::Blocks,
+            rows::SortMode::Blocks if self.free_tracking => rows::SortMode::Leaks,
+            rows::SortMode::Blocks | rows::SortMode::Leaks if self.show_churn => {
+                rows::SortMode::Churn
+            }
+            rows::SortMode::Blocks | rows::SortMode::Leaks | rows::SortMode::Churn => {
+                self.next_column_sort(0)
+            }
+            rows::SortMode::Column(index) => self.next_column_sort(index + 1),
+            rows::SortMode::Function => rows::SortMode::None,
+        };
+        self.sort_order.reversed = false;
+    }
+
+    // The sort mode following the built-in columns, sorting by the computed
+    // column at an index, or by function after the last computed column.
+    fn next_column_sort(&self, index: usize) -> rows::SortMode {
+        if index < columns::computed_columns().len() {
+            rows::SortMode::Column(index)
+        } else {
+            rows::SortMode::Function
+        }
+    }
+
+    // Sort by a column, or reverse the order if already sorted by it.
+    fn on_sort_by(&mut self, mode: rows::SortMode) {
+        let needs_free_tracking = mode == rows::SortMode::Held || mode == rows::SortMode::Leaks;
+        if needs_free_tracking && !self.free_tracking {
+            return;
+        }
+
+        // The column sorted by is always shown.
+        if mode == rows::SortMode::Churn {
+            self.show_churn = true;
+        }
+
+        if self.sort_order.mode == mode {
+            self.sort_order.reversed = !self.sort_order.reversed;
+        } else {
+            self.sort_order = rows::SortOrder {
+                mode,
+                reversed: false,
+            };
+        }
+    }
+
+    // The index of the computed column whose header is at an offset from
+    // the end of the built-in columns, if any.
+    fn computed_column_at(&self, x: i32) -> Option<usize> {
+        let mut start = if self.show_churn {
+            self.churn_format.width as i32 + 1
+        } else {
+            0
+        };
+        for (index, column) in columns::computed_columns().iter().enumerate() {
+            let end = start + column.width() as i32;
+            if x >= start && x < end {
+                return Some(index);
+            }
+            start = end + 1;
+        }
+
+        None
+    }
+
+    // The sort mode of the column header at a screen column, if any.
+    fn header_sort_mode(&self, x: i32) -> Option<rows::SortMode> {
+        let optional_width = self.optional_columns_width() as i32;
+        let address_width = if self.showing_addresses() {
+            report::ADDRESS_COLUMNS_WIDTH as i32 + 1
+        } else {
+            0
+        };
+        let bar_width = if self.showing_bars() {
+            report::BAR_WIDTH as i32 + 1
+        } else {
+            0
+        };
+        let column_width = self.value_format.width as i32 + 1;
+        let columns = [
+            rows::SortMode::Bytes,
+            rows::SortMode::Held,
+            rows::SortMode::Blocks,
+            rows::SortMode::Leaks,
+        ];
+        if x < 0 {
+            None
+        } else if x < column_width * 4 {
+            // The space following each column isn't part of its header.
+            if x % column_width == column_width - 1 {
+                None
+            } else {
+                Some(columns[(x / column_width) as usize])
+            }
+        } else if self.show_churn && x < column_width * 4 + self.churn_format.width as i32 {
+            Some(rows::SortMode::Churn)
+        } else if let Some(index) = self.computed_column_at(x - column_width * 4) {
+            Some(rows::SortMode::Column(index))
+        } else if x >= column_width * 4 + 2 + optional_width + address_width + bar_width {
+            Some(rows::SortMode::Function)
+        } else {
+            None
+        }
+    }
+
+    // Respond to a mouse event.  Clicking a column header sorts by that
+    // column, clicking a row selects it, and double clicking a row
+    // collapses or expands it.  The wheel scrolls the selection.
+    fn on_mouse(&mut self) {
+        let event = match pancurses::getmouse() {
+            Ok(event) => event,
+            Err(_) => return,
+        };
+
+        if event.bstate & pancurses::BUTTON4_PRESSED != 0 {
+            for _ in 0..WHEEL_SCROLL_ROWS {
+                self.on_move_up();
+            }
+        } else if event.bstate & pancurses::BUTTON5_PRESSED != 0 {
+            for _ in 0..WHEEL_SCROLL_ROWS {
+                self.on_move_down();
+            }
+        } else if event.bstate & (pancurses::BUTTON1_CLICKED | pancurses::BUTTON1_DOUBLE_CLICKED)
+            != 0
+        {
+            let header_y = self.pane_height();
+            if event.y == header_y {
+                if let Some(mode) = self.header_sort_mode(event.x) {
+                    self.on_sort_by(mode);
+                }
+                return;
+            }
+
+            let row = (event.y - header_y - 1) as i64;
+            if row < 0 || row >= self.tree_height() || row >= self.display_rows.len() as i64 {
+                return;
+            }
+            self.selected_row = self.scroll_offset + row;
+            if event.bstate & pancurses::BUTTON1_DOUBLE_CLICKED != 0 {
+                self.on_toggle_collapse();
+            }
+        }
+    }
+
+    // Read the trace again, adding events written since it was last read
+    // to the summaries.  The totals of traces made with --aggregate are
+    // rewritten in place, so they are summarized again in full.
+    fn refresh(&mut self) -> Result<(), Box<dyn Error>> {
+        self.last_refresh = time::Instant::now();
+        self.tree_cache.clear();
+        if self.trace.has_capability(trace::CAPABILITY_AGGREGATE) {
+            self.trace.clear_summaries()?;
+            self.summary_position = summary::summarize_allocations(
+                &mut self.trace,
+                &mut self.tree_builder,
+                self.time_range,
+                false,
+            )?;
+        } else {
+            self.summary_position = summary::extend_summary(
+                &mut self.trace,
+                &mut self.tree_builder,
+                self.time_range,
+                self.summary_position,
+                false,
+            )?;
+        }
+        self.summarized_span = self.time_range.span(self.trace.duration().unwrap_or(0));
+
+        if self.timeline.is_some() {
+            self.timeline = timeline::Timeline::new(&self.trace)?;
+            self.show_timeline = self.show_timeline && self.timeline.is_some();
+        }
+        self.relayout_flamegraph()?;
+
+        Ok(())
+    }
+
+    // Read the trace again, if following it and it hasn't been read
+    // recently.  Errors are shown on the status line, rather than waiting
+    // for a key, and the trace is read again at the next attempt.
+    fn refresh_if_due(&mut self) {
+        if self.follow && self.last_refresh.elapsed() >= FOLLOW_INTERVAL {
+            self.refresh_error = self.refresh().err().map(|err| err.to_string());
+        }
+    }
+
+    // Respond to a request to read the trace again.
+    fn on_refresh(&mut self) {
+        self.refresh_error = None;
+        if let Err(err) = self.refresh() {
+            self.draw_error(err);
+            self.screen.getch();
+        }
+    }
+
+    // Toggle between abbreviated and exact values in the call tree.
+    fn on_toggle_exact(&mut self) {
+        self.value_style = if self.value_style == report::ValueStyle::Abbreviated {
+            self.exact_style
+        } else {
+            report::ValueStyle::Abbreviated
+        };
+    }
+
+    // Show or hide the timeline pane, computing the timeline when first
+    // shown.
+    fn on_toggle_timeline(&mut self) {
+        if self.timeline.is_none() {
+            if let Ok(timeline) = timeline::Timeline::new(&self.trace) {
+                self.timeline = timeline;
+            }
+        }
+        self.show_timeline = self.timeline.is_some() && !self.show_timeline;
+        self.scroll_to_selection();
+    }
+
+    // Move the cursor of the timeline pane by a number of columns.
+    fn on_move_timeline_cursor(&mut self, columns: i64) {
+        if let Some(timeline) = &self.timeline {
+            let width = self.screen.get_max_x() as usize;
+            let column = timeline.column_at(self.timeline_cursor, width) as i64 + columns;
+            let column = column.clamp(0, width as i64 - 1) as usize;
+            self.timeline_cursor = timeline.column_span(column, width).0;
+        }
+    }
+
+    // Summarize only the events within a span of the trace.
+    fn set_time_range(&mut self, time_range: summary::TimeRange) {
+        self.time_range = time_range;
+        self.summarized_span = time_range.span(self.trace.duration().unwrap_or(0));
+        self.tree_cache.clear();
+        let result = self.trace.clear_summaries().and_then(|()| {
+            summary::summarize_allocations(
+                &mut self.trace,
+                &mut self.tree_builder,
+                time_range,
+                false,
+            )
+        });
+        match result {
+            Ok(position) => self.summary_position = position,
+            Err(err) => {
+                self.draw_error(err);
+                self.screen.getch();
+            }
+        }
+
+        self.selected_row = 0;
+        self.scroll_offset = 0;
+    }
+
+    // Start the summarized span of the trace at the cursor of the timeline.
+    fn on_set_range_start(&mut self) {
+        if let Some(timeline) = &self.timeline {
+            let width = self.screen.get_max_x() as usize;
+            let column = timeline.column_at(self.timeline_cursor, width);
+            let start = timeline.column_span(column, width).0;
+            let end = std::cmp::max(self.time_range.end, start);
+            self.set_time_range(summary::TimeRange { start, end });
+        }
+    }
+
+    // End the summarized span of the trace at the cursor of the timeline.
+    fn on_set_range_end(&mut self) {
+        if let Some(timeline) = &self.timeline {
+            let width = self.screen.get_max_x() as usize;
+            let column = timeline.column_at(self.timeline_cursor, width);
+            let end = timeline.column_span(column, width).1;
+            let start = std::cmp::min(self.time_range.start, end);
+            self.set_time_range(summary::TimeRange { start, end });
+        }
+    }
+
+    // Summarize the whole of the trace again.
+    fn on_clear_range(&mut self) {
+        if self.time_range != summary::TimeRange::all() {
+            self.set_time_range(summary::TimeRange::all());
+        }
+    }
+
+    // The span of the trace from the start of a phase to the start of the
+    // next, or to the end of the trace for the last phase.
+    fn phase_range(phases: &[trace::Phase], index: usize) -> summary::TimeRange {
+        summary::TimeRange {
+            start: phases[index].time,
+            end: phases.get(index + 1).map_or(u64::MAX, |next| next.time),
+        }
+    }
+
+    // List the phases marked in the trace, to choose one to summarize, or
+    // explain how phases are marked if there are none.
+    fn on_show_phases(&mut self) {
+        match self.trace.phases() {
+            Ok(phases) if phases.is_empty() => {
+                self.text_lines = Some(vec![
+                    "No phases were marked in the trace".to_string(),
+                    String::new(),
+                    "Phases are marked by the traced program's calls to a function".to_string(),
+                    "it defines with no effect, allocscope_phase(const char *name),".to_string(),
+                    "with the name of each phase it enters.".to_string(),
+                ]);
+                self.text_offset = 0;
+            }
+            Ok(phases) => {
+                self.phase_selected = phases
+                    .iter()
+                    .rposition(|phase| phase.time <= self.time_range.start)
+                    .unwrap_or(0);
+                self.phases = Some(phases);
+            }
+            Err(err) => {
+                self.draw_error(err);
+                self.screen.getch();
+            }
+        }
+    }
+
+    // Draw the phases marked in the trace, with the start and length of
+    // each.
+    fn draw_phases(&self, phases: &[trace::Phase]) {
+        let width = self.screen.get_max_x() as usize;
+        let top = self.pane_height();
+
+        self.screen.mv(top, 0);
+        self.screen.attron(self.palette.header);
+        self.screen
+            .printw(text::fit(" START     LENGTH   Phase", width));
+        self.screen.attroff(self.palette.header);
+
+        let duration = self.trace.duration().unwrap_or(0);
+        let height = std::cmp::max(self.tree_height(), 1) as usize;
+        let first = (self.phase_selected + 1).saturating_sub(height);
+        for (row, index) in (first..phases.len()).take(height).enumerate() {
+            let range = UIState::phase_range(phases, index);
+            let length = time::Duration::from_nanos(range.span(duration));
+            let line = format!(
+                "{} {:>10}   {}",
+                report::format_time_value(Some(range.start)),
+                format!("{:.2?}", length),
+                phases[index].name
+            );
+
+            let selected = index == self.phase_selected;
+            if selected {
+                self.screen.attron(self.palette.selected);
+                self.screen.attron(pancurses::A_BOLD);
+            }
+            self.screen.mv(top + 1 + row as i32, 0);
+            self.screen.printw(text::fit(&line, width));
+            if selected {
+                self.screen.attroff(pancurses::A_BOLD);
+                self.screen.attroff(self.palette.selected);
+            }
+        }
+    }
+
+    // Handle a key pressed while the phases are listed.  Enter summarizes
+    // the span of the selected phase.
+    fn handle_phases_input(&mut self, input: pancurses::Input) {
+        let count = match &self.phases {
+            Some(phases) => phases.len(),
+            None => return,
+        };
+
+        match input {
+            pancurses::Input::KeyDown if self.phase_selected + 1 < count => {
+                self.phase_selected += 1
+            }
+            pancurses::Input::KeyUp => self.phase_selected = self.phase_selected.saturating_sub(1),
+            pancurses::Input::KeyEnter | pancurses::Input::Character('\n') => {
+                if let Some(phases) = self.phases.take() {
+                    self.set_time_range(UIState::phase_range(&phases, self.phase_selected));
+                }
+            }
+            pancurses::Input::Character('\x1b')
+            | pancurses::Input::KeyBackspace
+            | pancurses::Input::KeyLeft => self.phases = None,
+            pancurses::Input::Character('q') => self.exited = true,
+            _ => (),
+        }
+    }
+
+    // List the threads which generated events, to choose those whose events
+    // are shown, or explain why there are none.
+    fn on_show_threads(&mut self) {
+        match self.trace.threads() {
+            Ok(threads) if threads.is_empty() => {
+                self.text_lines = Some(vec![
+                    "No thread ids were recorded in the trace".to_string(),
+                    String::new(),
+                    "Traces recorded by earlier versions of allocscope-trace".to_string(),
+                    "don't record the thread generating each event.".to_string(),
+                ]);
+                self.text_offset = 0;
+            }
+            Ok(threads) => {
+                self.threads_chosen = self.tree_builder.threads().cloned().unwrap_or_default();
+                self.thread_selected = 0;
+                self.threads = Some(threads);
+            }
+            Err(err) => {
+                self.draw_error(err);
+                self.screen.getch();
+            }
+        }
+    }
+
+    // Draw the threads which generated events, with the number of events
+    // generated by each, marking those chosen.
+    fn draw_threads(&self, threads: &[trace::TraceThread]) {
+        let width = self.screen.get_max_x() as usize;
+        let top = self.pane_height();
+
+        self.screen.mv(top, 0);
+        self.screen.attron(self.palette.header);
+        self.screen
+            .printw(text::fit("        TID     EVENTS   Name", width));
+        self.screen.attroff(self.palette.header);
+
+        let height = std::cmp::max(self.tree_height(), 1) as usize;
+        let first = (self.thread_selected + 1).saturating_sub(height);
+        for (row, thread) in threads.iter().enumerate().skip(first).take(height) {
+            let mark = if self.threads_chosen.contains(&thread.tid) {
+                "[x]"
+            } else {
+                "[ ]"
+            };
+            let line = format!(
+                " {} {:>6} {:>10}   {}",
+                mark,
+                thread.tid,
+                thread.event_count,
+                thread.name.as_deref().unwrap_or("")
+            );
+
+            let selected = row == self.thread_selected;
+            if selected {
+                self.screen.attron(self.palette.selected);
+                self.screen.attron(pancurses::A_BOLD);
+            }
+            self.screen.mv(top + 1 + (row - first) as i32, 0);
+            self.screen.printw(text::fit(&line, width));
+            if selected {
+                self.screen.attroff(pancurses::A_BOLD);
+                self.screen.attroff(self.palette.selected);
+            }
+        }
+    }
+
+    // Handle a key pressed while the threads are listed.  Space chooses or
+    // unchooses the selected thread, and Enter shows only the events of the
+    // threads chosen, or of all threads if none are chosen.
+    fn handle_threads_input(&mut self, input: pancurses::Input) {
+        let threads = match &self.threads {
+            Some(threads) => threads,
+            None => return,
+        };
+
+        match input {
+            pancurses::Input::KeyDown if self.thread_selected + 1 < threads.len() => {
+                self.thread_selected += 1
+            }
+            pancurses::Input::KeyUp => {
+                self.thread_selected = self.thread_selected.saturating_sub(1)
+            }
+            pancurses::Input::Character(' ') => {
+                let tid = threads[self.thread_selected].tid;
+                if !self.threads_chosen.remove(&tid) {
+                    self.threads_chosen.insert(tid);
+                }
+            }
+            pancurses::Input::Character('a') => {
+                if self.threads_chosen.len() == threads.len() {
+                    self.threads_chosen.clear();
+                } else {
+                    self.threads_chosen = threads.iter().map(|thread| thread.tid).collect();
+                }
+            }
+            pancurses::Input::KeyEnter | pancurses::Input::Character('\n') => {
+                let chosen = std::mem::take(&mut self.threads_chosen);
+                let threads = if chosen.is_empty() || chosen.len() == threads.len() {
+                    None
+                } else {
+                    Some(chosen)
+                };
+                self.threads = None;
+                if threads.as_ref() != self.tree_builder.threads() {
+                    self.tree_builder.set_threads(threads);
+                    self.rebuild_tree();
+                }
+            }
+            pancurses::Input::Character('\x1b')
+            | pancurses::Input::KeyBackspace
+            | pancurses::Input::KeyLeft => self.threads = None,
+            pancurses::Input::Character('q') => self.exited = true,
+            _ => (),
+        }
+    }
+
+    // Invert the call tree, or restore its order, and summarize it again.
+    fn on_toggle_inverted(&mut self) {
+        let inverted = !self.tree_builder.is_inverted();
+        self.tree_builder.set_inverted(inverted);
+        self.rebuild_tree();
+    }
+
+    // Remove the frames of recursive calls, or show them as recorded, and
+    // summarize the call tree again.
+    fn on_toggle_recursion(&mut self) {
+        let collapse_recursion = !self.tree_builder.is_collapsing_recursion();
+        self.tree_builder.set_collapse_recursion(collapse_recursion);
+        self.rebuild_tree();
+    }
+
+    // Hide or show the entries below the pruning threshold.
+    fn on_toggle_prune(&mut self) {
+        self.prune = !self.prune;
+        self.selected_row = 0;
+        self.scroll_offset = 0;
+    }
+
+    // True if limiting the call tree to 'depth' levels leaves out entries.
+    fn depth_limit_hides_entries(&mut self, depth: usize) -> bool {
+        let mut transaction = match trace::Transaction::new(&self.trace) {
+            Ok(transaction) => transaction,
+            Err(_) => return true,
+        };
+        let mut limits = self.row_limits();
+        limits.max_depth = Some(depth);
+        let limited_count = rows::count_rows(
+            &mut transaction,
+            &mut self.tree_cache,
+            Some(&self.collapsed),
+            limits,
+        );
+        limits.max_depth = Some(depth + 1);
+        let deeper_count = rows::count_rows(
+            &mut transaction,
+            &mut self.tree_cache,
+            Some(&self.collapsed),
+            limits,
+        );
+
+        !matches!((limited_count, deeper_count), (Ok(limited), Ok(deeper)) if limited == deeper)
+    }
+
+    // Show one level fewer or more of the call tree.  Without a limit, the
+    // first step shallower limits the tree to the level of the selected
+    // row.  The limit is removed once it no longer leaves out any entries.
+    fn on_change_depth(&mut self, deeper: bool) {
+        let selected_depth = self
+            .display_rows
+            .get((self.selected_row - self.scroll_offset) as usize)
+            .map_or(0, |row| row.depth);
+        self.max_depth = match (self.max_depth, deeper) {
+            (None, false) => Some(selected_depth + 1),
+            (None, true) => None,
+            (Some(depth), false) => Some(std::cmp::max(depth - 1, 1)),
+            (Some(depth), true) if self.depth_limit_hides_entries(depth + 1) => Some(depth + 1),
+            (Some(_), true) => None,
+        };
+        self.selected_row = 0;
+        self.scroll_offset = 0;
+    }
+
+    // Roll frames by shared object, or show every frame.
+    fn on_toggle_modules(&mut self) {
+        let modules = if self.tree_builder.is_grouping_modules() {
+            None
+        } else {
+            match self.trace.modules() {
+                Ok(modules) => Some(modules),
+                Err(err) => {
+                    self.draw_error(err);
+                    self.screen.getch();
+                    return;
+                }
+            }
+        };
+        self.tree_builder.set_modules(modules);
+        self.rebuild_tree();
+    }
+
+    // Derive the call tree again after the tree builder has changed, and
+    // summarize it.
+    fn rebuild_tree(&mut self) {
+        self.collapsed.clear();
+        if let Err(err) = self.trace.clear_scratch_tree() {
+            self.draw_error(err);
+            self.screen.getch();
+        }
+        self.set_time_range(self.time_range);
+    }
+
+    // Read a line of text typed on the bottom row of the screen, starting
+    // with 'initial'.  Returns None if escape is pressed.
+    fn prompt(&self, label: &str, initial: &str) -> Option<String> {
+        let mut text = initial.to_string();
+        let width = self.screen.get_max_x() as usize;
+        let height = self.screen.get_max_y();
+        pancurses::curs_set(1);
+
+        let result = loop {
+            let line = format!("{}{}", label, text);
+            self.screen.mv(height - 1, 0);
+            self.screen.printw(text::fit(&line, width));
+            self.screen.mv(
+                height - 1,
+                std::cmp::min(text::width(&line), width - 1) as i32,
+            );
+            self.screen.refresh();
+
+            match self.screen.getch() {
+                Some(pancurses::Input::KeyEnter) | Some(pancurses::Input::Character('\n')) => {
+                    break Some(text)
+                }
+                Some(pancurses::Input::Character('\x1b')) => break None,
+                Some(pancurses::Input::KeyBackspace)
+                | Some(pancurses::Input::Character('\x7f'))
+                | Some(pancurses::Input::Character('\x08')) => {
+                    text.pop();
+                }
+                Some(pancurses::Input::Character(char)) if !char.is_control() => text.push(char),
+                _ => (),
+            }
+        };
+
+        pancurses::curs_set(0);
+        result
+    }
+
+    // Prompt for a regular expression, and show only the subtrees with a
+    // function matching it.  An empty expression removes the filter.
+    fn on_filter(&mut self) {
+        let current = self
+            .tree_builder
+            .filter()
+            .map(|filter| filter.as_str().to_string())
+            .unwrap_or_default();
+        let text = match self.prompt("Filter: ", &current) {
+            Some(text) => text,
+            None => return,
+        };
+
+        let filter = if text.is_empty() {
+            None
+        } else {
+            match Regex::new(&text) {
+                Ok(filter) => Some(filter),
+                Err(err) => {
+                    self.draw_error(err.into());
+                    self.screen.getch();
+                    return;
+                }
+            }
+        };
+        self.tree_builder.set_filter(filter);
+        self.rebuild_tree();
+    }
+
+    // Show the callers and callees of a function.
+    fn focus_function(&mut self, function: &str) {
+        if function.is_empty() {
+            return;
+        }
+
+        let inverted = self.tree_builder.is_inverted();
+        let view = trace::Transaction::new(&self.trace).and_then(|mut transaction| {
+            focus::FocusView::new(&mut transaction, function, inverted)
+        });
+        match view {
+            Ok(view) => {
+                self.focus = Some(view);
+                self.focus_selected = 0;
+            }
+            Err(err) => {
+                self.draw_error(err);
+                self.screen.getch();
             }
         }
     }
 
-    // Toggle through the sort modes.
-    fn on_next_sort(&mut self) {
-        self.sort_mode = match self.sort_mode {
-            rows::SortMode::None => rows::SortMode::Bytes,
-            rows::SortMode::Bytes => rows::SortMode::Blocks,
-            rows::SortMode::Blocks => rows::SortMode::Leaks,
-            rows::SortMode::Leaks => rows::SortMode::None,
+    // Show the callers and callees of the function of the selected row.
+    fn on_focus_selected_row(&mut self) {
+        if let Some(row) = self
+            .display_rows
+            .get((self.selected_row - self.scroll_offset) as usize)
+        {
+            if row.pruned > 0 {
+                return;
+            }
+            let function = row.function.clone();
+            self.focus_function(&function);
+        }
+    }
+
+    // The line on which a search pattern is typed.
+    fn search_prompt(&self, search: &search::FunctionSearch) -> String {
+        format!("Search: {}", search.pattern)
+    }
+
+    // Draw the functions matching a search, best match first, scrolled so
+    // that the selected match is shown.
+    fn draw_search(&self, search: &search::FunctionSearch) {
+        let width = self.screen.get_max_x() as usize;
+        let height = self.tree_height() as usize;
+        let top = self.pane_height() + 1;
+        if search.matches.is_empty() {
+            let blank_totals =
+                " ".repeat(self.value_format.columns_width() + self.optional_columns_width());
+            self.screen.mv(top, 0);
+            self.screen.printw(text::fit(
+                &format!("{}   (no matches)", blank_totals),
+                width,
+            ));
+            return;
+        }
+
+        let first_match = (self.search_selected + 1).saturating_sub(height);
+        for (row, index) in search
+            .matches
+            .iter()
+            .enumerate()
+            .skip(first_match)
+            .take(height)
+        {
+            let function = &search.functions[*index];
+            let line = format!(
+                "{}   {}",
+                self.format_focus_totals(&function.totals),
+                function.function
+            );
+
+            let selected = row == self.search_selected;
+            if selected {
+                self.screen.attron(self.palette.selected);
+                self.screen.attron(pancurses::A_BOLD);
+            }
+            self.screen.mv((row - first_match) as i32 + top, 0);
+            self.screen.printw(text::fit(&line, width));
+            if selected {
+                self.screen.attroff(pancurses::A_BOLD);
+                self.screen.attroff(self.palette.selected);
+            }
+        }
+    }
+
+    // List every function of the call tree, narrowed to those matching a
+    // pattern as it is typed.
+    fn on_search(&mut self) {
+        let search = trace::Transaction::new(&self.trace)
+            .and_then(|mut transaction| search::FunctionSearch::new(&mut transaction));
+        match search {
+            Ok(search) => {
+                self.search = Some(search);
+                self.search_selected = 0;
+            }
+            Err(err) => {
+                self.draw_error(err);
+                self.screen.getch();
+            }
+        }
+    }
+
+    // Find the index of the row of a stack entry, if it is shown.
+    fn find_stackentry_row(
+        &mut self,
+        id: trace::StackEntryId,
+    ) -> Result<Option<i64>, Box<dyn Error>> {
+        let row_limits = self.row_limits();
+        self.tree_cache.set_sort_order(self.sort_order);
+        let mut transaction = trace::Transaction::new(&self.trace)?;
+        let mut start = 0;
+        loop {
+            let chunk = rows::iter_stackentry_rows(
+                &mut transaction,
+                &mut self.tree_cache,
+                Some(&self.collapsed),
+                row_limits,
+                start,
+                SEARCH_CHUNK_ROWS,
+            )?;
+            if chunk.is_empty() {
+                return Ok(None);
+            }
+            if let Some(index) = chunk.iter().position(|row| row.id == id && row.pruned == 0) {
+                return Ok(Some((start + index) as i64));
+            }
+            start += chunk.len();
+        }
+    }
+
+    // Select the row of a stack entry, expanding the entries above it, and
+    // lifting the depth limit and pruning if they hide it.
+    fn select_stackentry(&mut self, id: trace::StackEntryId) -> Result<(), Box<dyn Error>> {
+        let mut transaction = trace::Transaction::new(&self.trace)?;
+        let mut depth = 0;
+        let mut parent = transaction.stackentry(id).and_then(|entry| entry.next);
+        while let Some(parent_id) = parent {
+            self.collapsed.remove(&parent_id);
+            depth += 1;
+            parent = transaction
+                .stackentry(parent_id)
+                .and_then(|entry| entry.next);
+        }
+        drop(transaction);
+        if self.max_depth.is_some_and(|max_depth| max_depth <= depth) {
+            self.max_depth = Some(depth + 1);
+        }
+
+        let mut row = self.find_stackentry_row(id)?;
+        if row.is_none() && self.prune {
+            self.prune = false;
+            row = self.find_stackentry_row(id)?;
+        }
+        if let Some(row) = row {
+            self.selected_row = row;
+            self.scroll_to_selection();
+        }
+
+        Ok(())
+    }
+
+    // Follow the heaviest child of each entry down from 'start', or from
+    // the roots of the call tree, returning the leaf reached.  Children
+    // are weighed by the sorted column, or by peak bytes if the tree isn't
+    // sorted by a value.
+    fn heaviest_leaf(
+        &self,
+        start: Option<trace::StackEntryId>,
+    ) -> Result<Option<trace::StackEntryId>, Box<dyn Error>> {
+        let mode = match self.sort_order.mode {
+            rows::SortMode::None | rows::SortMode::Function => rows::SortMode::Bytes,
+            mode => mode,
+        };
+        let sort_order = rows::SortOrder {
+            mode,
+            reversed: false,
+        };
+
+        let mut transaction = trace::Transaction::new(&self.trace)?;
+        let mut entry = start;
+        loop {
+            let children = match entry {
+                Some(id) => transaction.get_stackentry_children(id)?,
+                None => transaction.root_stackentries()?,
+            };
+            let heaviest =
+                rows::sort_stackentries(&mut transaction, &mut children.into_iter(), sort_order)?
+                    .into_iter()
+                    .next();
+            match heaviest {
+                Some(child) => entry = Some(child.id),
+                None => return Ok(entry),
+            }
+        }
+    }
+
+    // Expand the chain of heaviest children beneath the selected row down
+    // to the leaf at its end, and select the leaf.
+    fn on_expand_heaviest(&mut self) {
+        let start = match self
+            .display_rows
+            .get((self.selected_row - self.scroll_offset) as usize)
+        {
+            Some(row) if row.pruned > 0 => return,
+            Some(row) => Some(row.id),
+            None => None,
+        };
+
+        let result = self.heaviest_leaf(start).and_then(|leaf| match leaf {
+            Some(leaf) => self.select_stackentry(leaf),
+            None => Ok(()),
+        });
+        if let Err(err) = result {
+            self.draw_error(err);
+            self.screen.getch();
+        }
+    }
+
+    // Draw the list of allocations made beneath a stack entry, with a
+    // header in place of the call tree's.
+    fn draw_allocations(&self, list: &allocations::AllocationList) {
+        let width = self.screen.get_max_x() as usize;
+        let top = self.pane_height();
+
+        let unfreed = list
+            .allocations
+            .iter()
+            .filter(|allocation| allocation.freed.is_none())
+            .count();
+        let mut header = format!(
+            "{:>10} {:>12}  {:<18}  {:<16}  {} allocations by {}, {} not freed",
+            "TIME",
+            "BYTES",
+            "ADDRESS",
+            "FREED",
+            list.allocations.len(),
+            list.function,
+            unfreed
+        );
+        header = text::fit(&header, width);
+        self.screen.mv(top, 0);
+        self.screen.attron(self.palette.header);
+        self.screen.printw(header);
+        self.screen.attroff(self.palette.header);
+
+        let height = self.tree_height() as usize;
+        for (row, allocation) in list
+            .allocations
+            .iter()
+            .skip(self.allocations_offset)
+            .take(height)
+            .enumerate()
+        {
+            let freed = match allocation.freed {
+                Some(freed) => format!(
+                    "after {:.2?}",
+                    time::Duration::from_nanos(freed - allocation.time)
+                ),
+                None => "never".to_string(),
+            };
+            let line = format!(
+                "{:>10} {:>12}  0x{:<16x}  {}",
+                format!("{:.2?}", time::Duration::from_nanos(allocation.time)),
+                allocation.size,
+                allocation.address,
+                freed
+            );
+            self.screen.mv(top + 1 + row as i32, 0);
+            self.screen.printw(text::truncate(&line, width));
+        }
+    }
+
+    // Draw a histogram of the block sizes allocated beneath a stack entry,
+    // with a bucket for each power of two.
+    fn draw_histogram(&self, histogram: &allocations::SizeHistogram) {
+        let width = self.screen.get_max_x() as usize;
+        let top = self.pane_height();
+
+        let value_width = report::table_value_width();
+        let size_width = 2 * value_width + 3;
+        let mut header = format!(
+            "{:>size_width$} {:>8} {}  {} allocations by {}, {} distinct sizes",
+            "SIZE",
+            "BLOCKS",
+            report::table_header("BYTES"),
+            histogram.count,
+            histogram.function,
+            histogram.distinct_sizes
+        );
+        if let Some((size, count)) = histogram.common_size {
+            header += &format!(
+                ", most often {} bytes ({:.0}%)",
+                size,
+                count as f64 * 100.0 / histogram.count as f64
+            );
+        }
+        header = text::fit(&header, width);
+        self.screen.mv(top, 0);
+        self.screen.attron(self.palette.header);
+        self.screen.addstr(header);
+        self.screen.attroff(self.palette.header);
+
+        let max_count = histogram
+            .buckets
+            .iter()
+            .map(|bucket| bucket.count)
+            .max()
+            .unwrap_or(0);
+        let bar_width = width.saturating_sub(3 * value_width + 16);
+        let height = self.tree_height() as usize;
+        for (row, bucket) in histogram.buckets.iter().take(height).enumerate() {
+            let bar_length = if max_count > 0 {
+                (bucket.count * bar_width).div_ceil(max_count)
+            } else {
+                0
+            };
+            let line = format!(
+                "{}-{} {:>8} {}  {}",
+                report::format_table_value(bucket.low, 1024),
+                report::format_table_value(bucket.high, 1024),
+                bucket.count,
+                report::format_table_value(bucket.bytes, 1024),
+                "#".repeat(bar_length)
+            );
+            self.screen.mv(top + 1 + row as i32, 0);
+            self.screen.printw(text::truncate(&line, width));
+        }
+    }
+
+    // Draw lines of text, such as the description of the blocks allocated
+    // over an inspected address, with the first line as a header in place
+    // of the call tree's.
+    fn draw_text_lines(&self, lines: &[String]) {
+        let width = self.screen.get_max_x() as usize;
+        let top = self.pane_height();
+
+        let header = text::fit(lines.first().map_or("", |line| line.as_str()), width);
+        self.screen.mv(top, 0);
+        self.screen.attron(self.palette.header);
+        self.screen.printw(header);
+        self.screen.attroff(self.palette.header);
+
+        let height = self.tree_height() as usize;
+        for (row, line) in lines
+            .iter()
+            .skip(1 + self.text_offset)
+            .take(height)
+            .enumerate()
+        {
+            self.screen.mv(top + 1 + row as i32, 0);
+            self.screen.printw(text::truncate(line, width));
+        }
+    }
+
+    // Prompt for an address, and describe the blocks allocated over it.
+    fn on_inspect_address(&mut self) {
+        let text = match self.prompt("Address: ", "") {
+            Some(text) if !text.is_empty() => text,
+            _ => return,
+        };
+
+        let lines = allocations::parse_address(&text)
+            .and_then(|address| allocations::AddressHistory::new(&self.trace, address))
+            .and_then(|history| {
+                report::format_address_history(&self.trace, &history, &mut self.source_cache)
+            });
+        match lines {
+            Ok(lines) => {
+                self.text_lines = Some(lines);
+                self.text_offset = 0;
+            }
+            Err(err) => {
+                self.draw_error(err);
+                self.screen.getch();
+            }
+        }
+    }
+
+    // Describe the trace, along with the filter and span of it viewed.
+    fn on_show_info(&mut self) {
+        let filter = self.tree_builder.filter().map(|filter| filter.as_str());
+        match report::format_trace_info(&self.trace, filter, self.time_range) {
+            Ok(lines) => {
+                self.text_lines = Some(lines);
+                self.text_offset = 0;
+            }
+            Err(err) => {
+                self.draw_error(err);
+                self.screen.getch();
+            }
+        }
+    }
+
+    // The selected box of the flamegraph.
+    fn selected_flame_box(&self) -> Option<&flamegraph::FlameBox> {
+        let (level, index) = self.flame_selected;
+        self.flamegraph.as_ref()?.levels.get(level)?.get(index)
+    }
+
+    // Select the box of a stack entry in the flamegraph, or the top left
+    // box if the entry isn't in it.
+    fn select_flame_entry(&mut self, id: Option<trace::StackEntryId>) {
+        self.flame_selected = (0, 0);
+        if let (Some(graph), Some(id)) = (&self.flamegraph, id) {
+            for (level, boxes) in graph.levels.iter().enumerate() {
+                if let Some(index) = boxes.iter().position(|flame_box| flame_box.id == id) {
+                    self.flame_selected = (level, index);
+                }
+            }
+        }
+        self.scroll_to_flame_selection();
+    }
+
+    // Adjust the levels of the flamegraph scrolled past such that the
+    // selected box is shown.
+    fn scroll_to_flame_selection(&mut self) {
+        let height = std::cmp::max(self.tree_height(), 1) as usize;
+        let (level, _) = self.flame_selected;
+        self.flame_offset = std::cmp::min(self.flame_offset, level);
+        self.flame_offset = std::cmp::max(self.flame_offset, (level + 1).saturating_sub(height));
+    }
+
+    // Lay out the flamegraph across the width of the screen, zoomed into
+    // entries, and select the box of an entry.
+    fn layout_flamegraph(
+        &mut self,
+        zoom: Vec<trace::StackEntryId>,
+        selected: Option<trace::StackEntryId>,
+    ) -> Result<(), Box<dyn Error>> {
+        let columns = self.screen.get_max_x() as usize;
+        let mut transaction = trace::Transaction::new(&self.trace)?;
+        let graph = flamegraph::Flamegraph::new(&mut transaction, zoom, columns)?;
+        drop(transaction);
+        self.flamegraph = Some(graph);
+        self.select_flame_entry(selected);
+
+        Ok(())
+    }
+
+    // Lay out the flamegraph again, keeping its zoom and the selected
+    // entry, as when the trace is summarized again.
+    fn relayout_flamegraph(&mut self) -> Result<(), Box<dyn Error>> {
+        let zoom = match &self.flamegraph {
+            Some(graph) => graph.zoom.clone(),
+            None => return Ok(()),
+        };
+        let selected = self.selected_flame_box().map(|flame_box| flame_box.id);
+        self.layout_flamegraph(zoom, selected)
+    }
+
+    // Lay out the flamegraph again if the screen has been resized.
+    fn fit_flamegraph(&mut self) -> Result<(), Box<dyn Error>> {
+        let columns = self.screen.get_max_x() as usize;
+        match &self.flamegraph {
+            Some(graph) if graph.columns != columns => self.relayout_flamegraph(),
+            _ => Ok(()),
+        }
+    }
+
+    // Show the call tree as a flamegraph, with the selected row's entry
+    // selected.
+    fn on_show_flamegraph(&mut self) {
+        let selected = self
+            .display_rows
+            .get((self.selected_row - self.scroll_offset) as usize)
+            .map(|row| row.id);
+        self.flame_offset = 0;
+        if let Err(err) = self.layout_flamegraph(Vec::new(), selected) {
+            self.flamegraph = None;
+            self.draw_error(err);
+            self.screen.getch();
+        }
+    }
+
+    // Zoom the flamegraph into the selected box, or out of the innermost
+    // entry zoomed into, selecting it.
+    fn on_zoom_flamegraph(&mut self, zoom_in: bool) {
+        let mut zoom = match &self.flamegraph {
+            Some(graph) => graph.zoom.clone(),
+            None => return,
+        };
+        let selected = if zoom_in {
+            match self.selected_flame_box() {
+                Some(flame_box) if zoom.last() != Some(&flame_box.id) => {
+                    zoom.push(flame_box.id);
+                    Some(flame_box.id)
+                }
+                _ => return,
+            }
+        } else {
+            zoom.pop()
+        };
+
+        self.flame_offset = 0;
+        if let Err(err) = self.layout_flamegraph(zoom, selected) {
+            self.draw_error(err);
+            self.screen.getch();
+        }
+    }
+
+    // Draw the flamegraph, with a level of boxes on each row beneath a
+    // header describing the selected box.
+    fn draw_flamegraph(&self, graph: &flamegraph::Flamegraph) {
+        let width = self.screen.get_max_x() as usize;
+        let top = self.pane_height();
+
+        let mut header = "FLAMEGRAPH of peak bytes".to_string();
+        if let Some(flame_box) = self.selected_flame_box() {
+            header += &format!(
+                "  {} {} ({:.1}%)",
+                flame_box.function,
+                report::format_table_value(flame_box.bytes, 1024).trim_start(),
+                flame_box.bytes as f64 * 100.0 / std::cmp::max(graph.total, 1) as f64
+            );
+        }
+        self.screen.mv(top, 0);
+        self.screen.attron(self.palette.header);
+        self.screen.printw(text::fit(&header, width));
+        self.screen.attroff(self.palette.header);
+
+        let height = self.tree_height() as usize;
+        for (row, (level, boxes)) in graph
+            .levels
+            .iter()
+            .enumerate()
+            .skip(self.flame_offset)
+            .take(height)
+            .enumerate()
+        {
+            for (index, flame_box) in boxes.iter().enumerate() {
+                if flame_box.column >= width {
+                    continue;
+                }
+                let box_width = std::cmp::min(flame_box.width, width - flame_box.column);
+                let label = format!(
+                    "{}|",
+                    text::fit(&flame_box.function, box_width.saturating_sub(1))
+                );
+
+                let selected = self.flame_selected == (level, index);
+                if selected {
+                    self.screen.attron(self.palette.selected);
+                    self.screen.attron(pancurses::A_BOLD);
+                }
+                self.screen
+                    .mv(top + 1 + row as i32, flame_box.column as i32);
+                self.screen.printw(text::truncate(&label, box_width));
+                if selected {
+                    self.screen.attroff(pancurses::A_BOLD);
+                    self.screen.attroff(self.palette.selected);
+                }
+            }
+        }
+    }
+
+    // Draw the allocations made beneath a stack entry counted by how long
+    // their blocks lived, with a bar for the bytes of each bucket.
+    fn draw_lifetimes(&self, lifetimes: &allocations::LifetimeHistogram) {
+        let width = self.screen.get_max_x() as usize;
+        let top = self.pane_height();
+
+        let header = format!(
+            "{:>8} {:>8} {}  {} allocations by {}, by how long they lived",
+            "LIFETIME",
+            "BLOCKS",
+            report::table_header("BYTES"),
+            lifetimes.count,
+            lifetimes.function
+        );
+        self.screen.mv(top, 0);
+        self.screen.attron(self.palette.header);
+        self.screen.addstr(text::fit(&header, width));
+        self.screen.attroff(self.palette.header);
+
+        let counts = &lifetimes.counts;
+        let max_bytes = counts.bytes.iter().copied().max().unwrap_or(0);
+        let bar_width = width.saturating_sub(report::table_value_width() + 21);
+        let height = self.tree_height() as usize;
+        for (row, label) in allocations::LIFETIME_LABELS.iter().enumerate().take(height) {
+            let bar_length = if max_bytes > 0 {
+                (counts.bytes[row] * bar_width as u64).div_ceil(max_bytes) as usize
+            } else {
+                0
+            };
+            let line = format!(
+                "{:>8} {:>8} {}  {}",
+                label,
+                counts.blocks[row],
+                report::format_table_value(counts.bytes[row], 1024),
+                "#".repeat(bar_length)
+            );
+            self.screen.mv(top + 1 + row as i32, 0);
+            self.screen.printw(text::truncate(&line, width));
+        }
+    }
+
+    // Gather the allocations made beneath the selected row, reporting any
+    // error in doing so.
+    fn selected_allocations(&mut self) -> Option<allocations::AllocationList> {
+        let row = match self
+            .display_rows
+            .get((self.selected_row - self.scroll_offset) as usize)
+        {
+            Some(row) if row.pruned == 0 => row,
+            _ => return None,
+        };
+
+        match allocations::AllocationList::new(
+            &self.trace,
+            &mut self.tree_builder,
+            row.id,
+            &row.function,
+            self.time_range,
+        ) {
+            Ok(list) => Some(list),
+            Err(err) => {
+                self.draw_error(err);
+                self.screen.getch();
+                None
+            }
+        }
+    }
+
+    // List the allocations made beneath the selected row.
+    fn on_list_allocations(&mut self) {
+        if let Some(list) = self.selected_allocations() {
+            self.allocations = Some(list);
+            self.allocations_offset = 0;
+        }
+    }
+
+    // Show the distribution of block sizes allocated beneath the selected
+    // row.
+    fn on_show_histogram(&mut self) {
+        if let Some(list) = self.selected_allocations() {
+            self.histogram = Some(allocations::SizeHistogram::new(&list));
+        }
+    }
+
+    // Show the callstacks which freed the blocks allocated beneath the
+    // selected row.
+    fn on_show_freed_by(&mut self) {
+        let list = match self.selected_allocations() {
+            Some(list) => list,
+            None => return,
+        };
+        let freed_by = allocations::FreedBy::new(&list);
+        match report::format_freed_by(&self.trace, &freed_by, &mut self.source_cache) {
+            Ok(lines) => {
+                self.text_lines = Some(lines);
+                self.text_offset = 0;
+            }
+            Err(err) => {
+                self.draw_error(err);
+                self.screen.getch();
+            }
+        }
+    }
+
+    // Show the chains of reallocations of the blocks allocated beneath the
+    // selected row.
+    fn on_show_realloc_chains(&mut self) {
+        let row = match self
+            .display_rows
+            .get((self.selected_row - self.scroll_offset) as usize)
+        {
+            Some(row) if row.pruned == 0 => row,
+            _ => return,
+        };
+
+        match allocations::ReallocChains::new(
+            &self.trace,
+            &mut self.tree_builder,
+            row.id,
+            &row.function,
+            self.time_range,
+        ) {
+            Ok(chains) => {
+                self.text_lines = Some(report::format_realloc_chains(&chains));
+                self.text_offset = 0;
+            }
+            Err(err) => {
+                self.draw_error(err);
+                self.screen.getch();
+            }
+        }
+    }
+
+    // Count the allocations made beneath the selected row by lifetime.
+    fn on_show_lifetimes(&mut self) {
+        if let Some(list) = self.selected_allocations() {
+            self.lifetimes = Some(allocations::LifetimeHistogram::new(&list));
+        }
+    }
+
+    // Handle a key pressed while the histogram of block sizes, or of
+    // lifetimes, is shown.
+    fn handle_histogram_input(&mut self, input: pancurses::Input) {
+        match input {
+            pancurses::Input::Character('\x1b')
+            | pancurses::Input::KeyBackspace
+            | pancurses::Input::KeyLeft => {
+                self.histogram = None;
+                self.lifetimes = None;
+            }
+            pancurses::Input::Character('q') => self.exited = true,
+            _ => {}
+        }
+    }
+
+    // Handle a key pressed while the list of allocations is shown.
+    fn handle_allocations_input(&mut self, input: pancurses::Input) {
+        let count = match &self.allocations {
+            Some(list) => list.allocations.len(),
+            None => return,
+        };
+        let page = std::cmp::max(self.tree_height(), 1) as usize;
+        let last_page = count.saturating_sub(page);
+
+        self.allocations_offset = match input {
+            pancurses::Input::KeyDown => self.allocations_offset + 1,
+            pancurses::Input::KeyUp => self.allocations_offset.saturating_sub(1),
+            pancurses::Input::KeyNPage => self.allocations_offset + page,
+            pancurses::Input::KeyPPage => self.allocations_offset.saturating_sub(page),
+            pancurses::Input::KeyHome => 0,
+            pancurses::Input::KeyEnd => last_page,
+            pancurses::Input::Character('\x1b')
+            | pancurses::Input::KeyBackspace
+            | pancurses::Input::KeyLeft => {
+                self.allocations = None;
+                0
+            }
+            pancurses::Input::Character('q') => {
+                self.exited = true;
+                self.allocations_offset
+            }
+            _ => self.allocations_offset,
+        };
+        self.allocations_offset = std::cmp::min(self.allocations_offset, last_page);
+    }
+
+    // Handle a key pressed while lines of text are shown.
+    fn handle_text_input(&mut self, input: pancurses::Input) {
+        let count = match &self.text_lines {
+            Some(lines) => lines.len().saturating_sub(1),
+            None => return,
+        };
+        let page = std::cmp::max(self.tree_height(), 1) as usize;
+        let last_page = count.saturating_sub(page);
+
+        self.text_offset = match input {
+            pancurses::Input::KeyDown => self.text_offset + 1,
+            pancurses::Input::KeyUp => self.text_offset.saturating_sub(1),
+            pancurses::Input::KeyNPage => self.text_offset + page,
+            pancurses::Input::KeyPPage => self.text_offset.saturating_sub(page),
+            pancurses::Input::KeyHome => 0,
+            pancurses::Input::KeyEnd => last_page,
+            pancurses::Input::Character('\x1b')
+            | pancurses::Input::KeyBackspace
+            | pancurses::Input::KeyLeft => {
+                self.text_lines = None;
+                0
+            }
+            pancurses::Input::Character('q') => {
+                self.exited = true;
+                self.text_offset
+            }
+            _ => self.text_offset,
+        };
+        self.text_offset = std::cmp::min(self.text_offset, last_page);
+    }
+
+    // Handle a key pressed while the flamegraph is shown.  The arrow keys
+    // move to the parent, widest child, or neighbors of the selected box.
+    fn handle_flamegraph_input(&mut self, input: pancurses::Input) {
+        let graph = match &self.flamegraph {
+            Some(graph) => graph,
+            None => return,
+        };
+        let (level, index) = self.flame_selected;
+        let level_len = graph.levels.get(level).map_or(0, |boxes| boxes.len());
+
+        match input {
+            pancurses::Input::KeyUp => {
+                let parent = graph
+                    .levels
+                    .get(level)
+                    .and_then(|boxes| boxes.get(index))
+                    .and_then(|flame_box| flame_box.parent);
+                if let Some(parent) = parent {
+                    self.flame_selected = (level - 1, parent);
+                }
+            }
+            pancurses::Input::KeyDown => {
+                if let Some(child) = graph.first_child(level, index) {
+                    self.flame_selected = (level + 1, child);
+                }
+            }
+            pancurses::Input::KeyLeft if index > 0 => self.flame_selected = (level, index - 1),
+            pancurses::Input::KeyRight if index + 1 < level_len => {
+                self.flame_selected = (level, index + 1)
+            }
+            pancurses::Input::KeyEnter | pancurses::Input::Character('\n') => {
+                self.on_zoom_flamegraph(true)
+            }
+            pancurses::Input::Character('g') => {
+                let selected = self.selected_flame_box().map(|flame_box| flame_box.id);
+                self.flamegraph = None;
+                if let Some(id) = selected {
+                    if let Err(err) = self.select_stackentry(id) {
+                        self.draw_error(err);
+                        self.screen.getch();
+                    }
+                }
+            }
+            pancurses::Input::Character('\x1b') | pancurses::Input::KeyBackspace => {
+                if graph.zoom.is_empty() {
+                    self.flamegraph = None;
+                } else {
+                    self.on_zoom_flamegraph(false);
+                }
+            }
+            pancurses::Input::Character('q') => self.exited = true,
+            _ => (),
+        }
+        self.scroll_to_flame_selection();
+    }
+
+    // Handle a key pressed while the focus view is shown.
+    fn handle_focus_input(&mut self, input: pancurses::Input) {
+        let focus = match &self.focus {
+            Some(focus) => focus,
+            None => return,
+        };
+        let count = focus.callers.len() + focus.callees.len();
+
+        match input {
+            pancurses::Input::KeyDown if self.focus_selected + 1 < count => {
+                self.focus_selected += 1
+            }
+            pancurses::Input::KeyUp => self.focus_selected = self.focus_selected.saturating_sub(1),
+            pancurses::Input::KeyEnter | pancurses::Input::Character('\n') => {
+                let selected = focus
+                    .callers
+                    .iter()
+                    .chain(focus.callees.iter())
+                    .nth(self.focus_selected)
+                    .map(|(function, _)| function.clone());
+                if let Some(function) = selected {
+                    self.focus_function(&function);
+                }
+            }
+            pancurses::Input::Character('\x1b')
+            | pancurses::Input::KeyBackspace
+            | pancurses::Input::KeyLeft => self.focus = None,
+            pancurses::Input::Character('q') => self.exited = true,
+            _ => (),
+        }
+    }
+
+    // Handle a key pressed while searching for a function.  Typed
+    // characters narrow the matches, and Enter selects the chosen match in
+    // the call tree.
+    fn handle_search_input(&mut self, input: pancurses::Input) {
+        let search = match &mut self.search {
+            Some(search) => search,
+            None => return,
+        };
+
+        match input {
+            pancurses::Input::KeyDown if self.search_selected + 1 < search.matches.len() => {
+                self.search_selected += 1
+            }
+            pancurses::Input::KeyUp => {
+                self.search_selected = self.search_selected.saturating_sub(1)
+            }
+            pancurses::Input::KeyEnter | pancurses::Input::Character('\n') => {
+                let selected = search
+                    .matches
+                    .get(self.search_selected)
+                    .map(|index| search.functions[*index].stackentry_id);
+                self.search = None;
+                if let Some(id) = selected {
+                    if let Err(err) = self.select_stackentry(id) {
+                        self.draw_error(err);
+                        self.screen.getch();
+                    }
+                }
+            }
+            pancurses::Input::Character('\x1b') | pancurses::Input::KeyLeft => self.search = None,
+            pancurses::Input::KeyBackspace
+            | pancurses::Input::Character('\x7f')
+            | pancurses::Input::Character('\x08') => {
+                let mut pattern = search.pattern.clone();
+                pattern.pop();
+                search.set_pattern(&pattern);
+                self.search_selected = 0;
+            }
+            pancurses::Input::Character(c) if !c.is_control() => {
+                let pattern = format!("{}{}", search.pattern, c);
+                search.set_pattern(&pattern);
+                self.search_selected = 0;
+            }
+            _ => (),
         }
     }
 
     // Handle the next key pressed.
     fn handle_input(&mut self) {
         if let Some(c) = self.screen.getch() {
+            if self.allocations.is_some() {
+                self.handle_allocations_input(c);
+                return;
+            }
+            if self.histogram.is_some() || self.lifetimes.is_some() {
+                self.handle_histogram_input(c);
+                return;
+            }
+            if self.text_lines.is_some() {
+                self.handle_text_input(c);
+                return;
+            }
+            if self.phases.is_some() {
+                self.handle_phases_input(c);
+                return;
+            }
+            if self.threads.is_some() {
+                self.handle_threads_input(c);
+                return;
+            }
+            if self.flamegraph.is_some() {
+                self.handle_flamegraph_input(c);
+                return;
+            }
+            if self.search.is_some() {
+                self.handle_search_input(c);
+                return;
+            }
+            if self.focus.is_some() {
+                self.handle_focus_input(c);
+                return;
+            }
+
             match c {
+                pancurses::Input::KeyEnter | pancurses::Input::Character('\n') => {
+                    self.on_focus_selected_row()
+                }
                 pancurses::Input::Character(' ') => self.on_toggle_collapse(),
+                pancurses::Input::Character('+') | pancurses::Input::Character('=') => {
+                    self.on_change_depth(true)
+                }
+                pancurses::Input::Character('-') => self.on_change_depth(false),
+                pancurses::Input::Character('/') => self.on_search(),
+                pancurses::Input::Character('1') => self.on_sort_by(rows::SortMode::Bytes),
+                pancurses::Input::Character('2') => self.on_sort_by(rows::SortMode::Held),
+                pancurses::Input::Character('3') => self.on_sort_by(rows::SortMode::Blocks),
+                pancurses::Input::Character('4') => self.on_sort_by(rows::SortMode::Leaks),
+                pancurses::Input::Character('5') => self.on_sort_by(rows::SortMode::Function),
+                pancurses::Input::Character('6') => self.on_sort_by(rows::SortMode::Churn),
+                pancurses::Input::Character(key @ '7'..='9') => {
+                    let index = key as usize - '7' as usize;
+                    if index < columns::computed_columns().len() {
+                        self.on_sort_by(rows::SortMode::Column(index))
+                    }
+                }
+                pancurses::Input::Character('a') => self.on_list_allocations(),
+                pancurses::Input::Character('A') => self.on_inspect_address(),
+                pancurses::Input::Character('b') => self.show_bars = !self.show_bars,
+                pancurses::Input::Character('c') => self.on_toggle_source(),
+                pancurses::Input::Character('C') => self.show_churn = !self.show_churn,
+                pancurses::Input::Character('d') => self.on_toggle_detail(),
+                pancurses::Input::Character('e') => self.on_toggle_exact(),
+                pancurses::Input::Character('f') => self.on_filter(),
+                pancurses::Input::Character('F') => self.on_show_freed_by(),
+                pancurses::Input::Character('g') => self.on_show_flamegraph(),
+                pancurses::Input::Character('G') => self.on_show_realloc_chains(),
+                pancurses::Input::Character('h') => self.on_move_to_parent(),
+                pancurses::Input::Character('H') => self.on_expand_heaviest(),
+                pancurses::Input::Character('i') => self.on_toggle_inverted(),
+                pancurses::Input::Character('I') => self.on_show_info(),
+                pancurses::Input::Character('J') => self.on_move_to_sibling(true),
+                pancurses::Input::Character('K') => self.on_move_to_sibling(false),
+                pancurses::Input::Character('l') => self.on_show_lifetimes(),
+                pancurses::Input::Character('m') => self.on_toggle_modules(),
+                pancurses::Input::Character('o') => self.on_open_source(),
+                pancurses::Input::Character('p') => self.on_toggle_prune(),
+                pancurses::Input::Character('P') => self.on_show_phases(),
+                pancurses::Input::Character('w') => self.on_show_threads(),
                 pancurses::Input::Character('q') => self.exited = true,
+                pancurses::Input::Character('r') => self.show_rates = !self.show_rates,
+                pancurses::Input::Character('R') => self.on_refresh(),
+                pancurses::Input::Character('s') => self.on_show_histogram(),
+                pancurses::Input::Character('t') => self.on_toggle_timeline(),
+                pancurses::Input::Character('T') => self.show_times = !self.show_times,
+                pancurses::Input::Character('u') => self.on_toggle_recursion(),
+                pancurses::Input::Character('<') if self.show_timeline => {
+                    self.on_move_timeline_cursor(-1)
+                }
+                pancurses::Input::Character('>') if self.show_timeline => {
+                    self.on_move_timeline_cursor(1)
+                }
+                pancurses::Input::Character('[') if self.show_timeline => self.on_set_range_start(),
+                pancurses::Input::Character(']') if self.show_timeline => self.on_set_range_end(),
+                pancurses::Input::Character('x') => self.on_clear_range(),
+                pancurses::Input::Character('X') => self.on_toggle_addresses(),
                 pancurses::Input::KeyDown => self.on_move_down(),
                 pancurses::Input::KeyUp => self.on_move_up(),
                 pancurses::Input::KeyLeft => self.on_move_left(),
@@ -393,6 +3411,8 @@ impl UIState {
                 pancurses::Input::KeyHome => self.on_home(),
                 pancurses::Input::KeyEnd => self.on_end(),
                 pancurses::Input::KeyF5 => self.on_next_sort(),
+                pancurses::Input::KeyMouse => self.on_mouse(),
+                pancurses::Input::KeyF6 => self.sort_order.reversed = !self.sort_order.reversed,
                 _ => (),
             }
         }
@@ -400,14 +3420,76 @@ impl UIState {
 }
 
 // The main loop of the curses user interface.
-pub fn main_loop(trace: trace::Trace, report_perf: bool) {
+pub fn main_loop(
+    trace: trace::Trace,
+    tree_builder: transform::TreeBuilder,
+    args: &commandline::CommandLineArguments,
+    summary_position: summary::SummaryPosition,
+    view_state: Option<viewstate::ViewState>,
+) {
+    // The locale is taken from the environment, so that function names
+    // which aren't ASCII are drawn as they are.
+    unsafe {
+        libc::setlocale(libc::LC_ALL, c"".as_ptr());
+    }
     let screen = pancurses::initscr();
-    let mut ui = UIState::new(trace, screen);
+    let mut ui = UIState::new(
+        trace,
+        screen,
+        tree_builder,
+        args.time_range,
+        args.rates,
+        args.prune,
+        args.theme,
+    );
+    ui.summary_position = summary_position;
+    ui.max_depth = args.max_depth;
+    ui.show_times = args.times;
+    ui.show_churn = args.churn;
+    ui.show_bars = args.bars;
+    if args.addresses {
+        ui.on_toggle_addresses();
+    }
+    ui.value_style = args.value_style;
+    ui.source_cache = source::SourceCache::new(args.source_map.clone());
+    if args.value_style != report::ValueStyle::Abbreviated {
+        ui.exact_style = args.value_style;
+    }
+    if let Some(view_state) = view_state {
+        ui.restore_view_state(view_state);
+    }
+    // A sort order given on the commandline overrides the saved one.
+    if let Some(mode) = args.sort_mode {
+        ui.sort_order = rows::SortOrder {
+            mode,
+            reversed: false,
+        };
+    }
+
+    // When following a trace, waiting for input times out so that the
+    // trace can be read again.
+    if args.follow {
+        ui.follow = true;
+        ui.screen.timeout(FOLLOW_INTERVAL.as_millis() as i32);
+    }
 
     while !ui.exited {
-        ui.draw(report_perf);
+        ui.draw(args.report_perf);
         ui.handle_input();
+        ui.refresh_if_due();
     }
 
     pancurses::endwin();
+
+    // The state is saved on a best effort basis, as the trace may be in a
+    // directory which can't be written.  Traces which aren't local files
+    // have nowhere to save it.
+    if let Some(atrace_filename) = &args.atrace_filename {
+        if fetch::is_remote(atrace_filename) {
+            return;
+        }
+        if let Ok(view_state) = ui.view_state() {
+            let _ = view_state.save(atrace_filename);
+        }
+    }
 }
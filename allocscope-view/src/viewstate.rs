@@ -0,0 +1,159 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::columns;
+use crate::rows;
+use crate::trace;
+use std::error::Error;
+
+// The state of the curses UI for a trace, saved to a file alongside the
+// trace when the UI exits, so that it can be restored when the trace is
+// viewed again.
+#[derive(Clone, Debug, Default)]
+pub struct ViewState {
+    // The sort order of the call tree.
+    pub sort_order: Option<rows::SortOrder>,
+
+    // The index of the selected row.
+    pub selected_row: i64,
+
+    // The number of rows scrolled past.
+    pub scroll_offset: i64,
+
+    // The filter applied to the call tree, if any.
+    pub filter: Option<String>,
+
+    // The collapsed stack entries, each as the labels of the frames from
+    // the root, so that they can be found again even if the call tree is
+    // built differently.
+    pub collapsed: Vec<Vec<String>>,
+}
+
+// The label identifying the frame of a stack entry among its siblings.
+fn entry_label(transaction: &mut trace::Transaction, entry: &trace::StackEntry) -> Option<String> {
+    let location = transaction.location(entry.location)?;
+    match location.function {
+        Some(function) if !function.is_empty() => {
+            Some(format!("{}+0x{:x}", function, location.offset.unwrap_or(0)))
+        }
+        _ => Some(format!("0x{:x}", location.address)),
+    }
+}
+
+// The labels of the frames from the root of the call tree to a stack
+// entry.
+pub fn stackentry_path(
+    transaction: &mut trace::Transaction,
+    id: trace::StackEntryId,
+) -> Option<Vec<String>> {
+    let mut path = Vec::new();
+    let mut next = Some(id);
+    while let Some(entry_id) = next {
+        let entry = transaction.stackentry(entry_id)?;
+        path.push(entry_label(transaction, &entry)?);
+        next = entry.next;
+    }
+    path.reverse();
+
+    Some(path)
+}
+
+// Find the stack entry at the end of a path of frame labels from the root
+// of the call tree, if it is in the tree.
+pub fn find_stackentry(
+    transaction: &mut trace::Transaction,
+    path: &[String],
+) -> Result<Option<trace::StackEntryId>, Box<dyn Error>> {
+    let mut found: Option<trace::StackEntryId> = None;
+    for label in path {
+        let siblings = match found {
+            Some(parent) => transaction.get_stackentry_children(parent)?,
+            None => transaction.root_stackentries()?,
+        };
+        let mut matched = None;
+        for sibling in siblings {
+            if entry_label(transaction, &sibling).as_ref() == Some(label) {
+                matched = Some(sibling.id);
+                break;
+            }
+        }
+        match matched {
+            Some(id) => found = Some(id),
+            None => return Ok(None),
+        }
+    }
+
+    Ok(found)
+}
+
+impl ViewState {
+    // The file to which the state of the UI for a trace is saved.
+    pub fn filename(atrace_filename: &str) -> String {
+        format!("{}.view", atrace_filename)
+    }
+
+    // Read the saved state for a trace, if there is one.  Lines which
+    // can't be understood are ignored, so that a damaged file restores
+    // what it can.
+    pub fn load(atrace_filename: &str) -> Option<ViewState> {
+        let contents = std::fs::read_to_string(ViewState::filename(atrace_filename)).ok()?;
+
+        let mut state = ViewState::default();
+        let mut sort_mode = None;
+        let mut reversed = false;
+        for line in contents.lines() {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "sort" => {
+                    sort_mode = rows::SortMode::parse(value, columns::computed_columns()).ok()
+                }
+                "reversed" => reversed = value == "true",
+                "selected" => state.selected_row = value.parse().unwrap_or(0),
+                "scroll" => state.scroll_offset = value.parse().unwrap_or(0),
+                "filter" => state.filter = Some(value.to_string()),
+                "collapsed" => state
+                    .collapsed
+                    .push(value.split('\t').map(|label| label.to_string()).collect()),
+                _ => {}
+            }
+        }
+        state.sort_order = sort_mode.map(|mode| rows::SortOrder { mode, reversed });
+
+        Some(state)
+    }
+
+    // Save the state for a trace.
+    pub fn save(&self, atrace_filename: &str) -> Result<(), Box<dyn Error>> {
+        let mut contents = String::new();
+        if let Some(sort_order) = self.sort_order {
+            contents += &format!("sort {}\n", sort_order.mode.name());
+            contents += &format!("reversed {}\n", sort_order.reversed);
+        }
+        contents += &format!("selected {}\n", self.selected_row);
+        contents += &format!("scroll {}\n", self.scroll_offset);
+        if let Some(filter) = &self.filter {
+            contents += &format!("filter {}\n", filter);
+        }
+        for path in self.collapsed.iter() {
+            contents += &format!("collapsed {}\n", path.join("\t"));
+        }
+        std::fs::write(ViewState::filename(atrace_filename), contents)?;
+
+        Ok(())
+    }
+}
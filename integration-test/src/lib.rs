@@ -26,6 +26,9 @@ pub struct ReportLine {
     // The peak bytes allocated by that stack entry and its children.
     pub bytes: String,
 
+    // The bytes still allocated at the end of the trace.
+    pub held: String,
+
     // The total number of blocks allocated.
     pub blocks: String,
 
@@ -83,16 +86,37 @@ pub fn compile_source(filename: &str) -> Result<String, Box<dyn Error>> {
     Ok(binary_path)
 }
 
+// Run the version of allocscope-trace under test on a binary, with extra
+// command line arguments, writing the given trace file.  Return the exit
+// code of the tracer.
+pub fn run_trace(
+    command: &str,
+    trace_path: &str,
+    args: &[&str],
+) -> Result<Option<i32>, Box<dyn Error>> {
+    let trace_status = process::Command::new(std::env::var("TEST_ALLOCSCOPE_TRACE")?)
+        .args(args)
+        .args(["-o", trace_path, command])
+        .spawn()?
+        .wait()?;
+
+    Ok(trace_status.code())
+}
+
 // Given a string representing a binary to trace, use the version of
 // allocscope-trace under test to generate a trace file.
 pub fn perform_trace(command: &str) -> Result<String, Box<dyn Error>> {
+    perform_trace_with_args(command, &[])
+}
+
+// Generate a trace file for a binary, passing extra command line arguments
+// to allocscope-trace.
+pub fn perform_trace_with_args(command: &str, args: &[&str]) -> Result<String, Box<dyn Error>> {
     let trace_path = format!("{}.atrace", command);
 
-    let trace_status = process::Command::new(std::env::var("TEST_ALLOCSCOPE_TRACE")?)
-        .args(["-o", &trace_path, &command])
-        .spawn()?
-        .wait()?;
-    assert_eq!(trace_status.code(), Some(0));
+    let mut trace_args = vec!["--force"];
+    trace_args.extend_from_slice(args);
+    assert_eq!(run_trace(command, &trace_path, &trace_args)?, Some(0));
 
     Ok(trace_path)
 }
@@ -100,21 +124,29 @@ pub fn perform_trace(command: &str) -> Result<String, Box<dyn Error>> {
 // Given a line of text output from allocscope-view, parse the text into a
 // ReportLine struct.
 pub fn parse_report_line(line: &str) -> Result<ReportLine, Box<dyn Error>> {
-    let re = Regex::new(r"([0-9A-Za-z]+) +([0-9A-Za-z]+) +([0-9A-Za-z]+) ([-+| ]+)(.+)")?;
+    let re = Regex::new(
+        r"([0-9A-Za-z]+) +([-0-9A-Za-z]+) +([0-9A-Za-z]+) +([-0-9A-Za-z]+) ([-+| ]+)(.+)",
+    )?;
     let caps = re.captures(line).ok_or("no captures")?;
 
     let bytes = caps.get(1).ok_or("missing bytes")?.as_str().to_string();
-    let blocks = caps.get(2).ok_or("missing blocks")?.as_str().to_string();
-    let leaks = caps.get(3).ok_or("missing leaks")?.as_str().to_string();
-    let tree = caps.get(4).ok_or("missing tree")?.as_str().to_string();
+    let held = caps
+        .get(2)
+        .ok_or("missing held bytes")?
+        .as_str()
+        .to_string();
+    let blocks = caps.get(3).ok_or("missing blocks")?.as_str().to_string();
+    let leaks = caps.get(4).ok_or("missing leaks")?.as_str().to_string();
+    let tree = caps.get(5).ok_or("missing tree")?.as_str().to_string();
     let name = caps
-        .get(5)
+        .get(6)
         .ok_or("missing function name")?
         .as_str()
         .to_string();
     let line = ReportLine {
         blocks,
         bytes,
+        held,
         leaks,
         tree,
         name,
@@ -126,7 +158,17 @@ pub fn parse_report_line(line: &str) -> Result<ReportLine, Box<dyn Error>> {
 // Given the filename of a trace file, return a vector of ReportLines
 // representing the output of the version of allocscope-view under test.
 pub fn view_trace(atrace_path: &str) -> Result<Vec<ReportLine>, Box<dyn Error>> {
+    view_trace_with_args(atrace_path, &[])
+}
+
+// Report on a trace file, passing extra command line arguments to
+// allocscope-view.
+pub fn view_trace_with_args(
+    atrace_path: &str,
+    args: &[&str],
+) -> Result<Vec<ReportLine>, Box<dyn Error>> {
     let output = process::Command::new(std::env::var("TEST_ALLOCSCOPE_VIEW")?)
+        .args(args)
         .arg(&atrace_path)
         .output()?;
     assert_eq!(output.status.code(), Some(0));
@@ -139,7 +181,7 @@ pub fn view_trace(atrace_path: &str) -> Result<Vec<ReportLine>, Box<dyn Error>>
             if line.len() > 0 {
                 report_lines.push(parse_report_line(line)?);
             }
-        } else if line.find("BYTES BLOCK LEAKS") == Some(0) {
+        } else if line.find("BYTES  HELD BLOCK LEAKS") == Some(0) {
             found_table = true;
         }
     }
@@ -175,9 +217,18 @@ pub fn find_top_leaf_index(trace: &Vec<ReportLine>) -> Option<usize> {
 // Build a source file and perform a trace on the resulting binary.  Return
 // the output of that trace report as a vector of ReportLine structs.
 pub fn build_and_trace(source_filename: &str) -> Result<Vec<ReportLine>, Box<dyn Error>> {
+    build_and_trace_with_args(source_filename, &[])
+}
+
+// Build a source file and trace the resulting binary, passing extra command
+// line arguments to allocscope-trace.  Return the output of the report.
+pub fn build_and_trace_with_args(
+    source_filename: &str,
+    args: &[&str],
+) -> Result<Vec<ReportLine>, Box<dyn Error>> {
     let binary_path = compile_source(source_filename)?;
 
-    let trace_result = perform_trace(&binary_path);
+    let trace_result = perform_trace_with_args(&binary_path, args);
     std::fs::remove_file(&binary_path)?;
     let trace_path = trace_result?;
 
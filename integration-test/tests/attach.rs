@@ -51,7 +51,13 @@ fn test_attach() -> Result<(), Box<dyn Error>> {
 
     let trace_path = format!("{}.atrace", binary_path);
     let mut trace_process = process::Command::new(std::env::var("TEST_ALLOCSCOPE_TRACE")?)
-        .args(["-o", &trace_path, "-p", &format!("{}", forever_pid)])
+        .args([
+            "--force",
+            "-o",
+            &trace_path,
+            "-p",
+            &format!("{}", forever_pid),
+        ])
         .spawn()?;
     let trace_pid = trace_process.id();
 
@@ -33,6 +33,38 @@ fn test_basic_trace() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+// Trace the allocation loop without recording frees.  Each iteration reuses
+// the address freed by the last, which must not stop the report.
+#[test]
+fn test_no_free_tracking() -> Result<(), Box<dyn Error>> {
+    let trace = integration_test::build_and_trace_with_args("loop.c", &["--no-free-tracking"])?;
+
+    let leaf_ix = integration_test::find_top_leaf_index(&trace).ok_or("no top leaf")?;
+    let leaf = &trace[leaf_ix];
+    assert_eq!(leaf.blocks, "1024");
+    assert_eq!(leaf.held, "-");
+    assert_eq!(leaf.leaks, "-");
+    assert!(leaf.name.contains("malloc"));
+
+    Ok(())
+}
+
+// Trace the allocation loop recording only the totals of each callstack.
+// The report of those totals should match that of the recorded events.
+#[test]
+fn test_aggregate() -> Result<(), Box<dyn Error>> {
+    let trace = integration_test::build_and_trace_with_args("loop.c", &["--aggregate"])?;
+
+    let leaf_ix = integration_test::find_top_leaf_index(&trace).ok_or("no top leaf")?;
+    let leaf = &trace[leaf_ix];
+    assert_eq!(leaf.bytes, "1024k");
+    assert_eq!(leaf.blocks, "1024");
+    assert_eq!(leaf.leaks, "0");
+    assert!(leaf.name.contains("malloc"));
+
+    Ok(())
+}
+
 // Trace a program which uses realloc for allocation.
 #[test]
 fn test_realloc() -> Result<(), Box<dyn Error>> {
@@ -0,0 +1,96 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use integration_test;
+use rusqlite;
+use std::error::Error;
+use std::process;
+
+// Write a trace file in the format recorded by the first release of
+// allocscope-trace, which lacks sessions, capabilities and the later
+// columns of the event table, and records event times as text.  A function
+// 'work' allocates three blocks through malloc, freeing the first two.
+fn write_baseline_trace(atrace_path: &str) -> Result<(), Box<dyn Error>> {
+    let connection = rusqlite::Connection::open(atrace_path)?;
+    connection.execute_batch(
+        "CREATE TABLE trace (
+            version TEXT NOT NULL,
+            time TEXT NOT NULL
+        );
+        CREATE TABLE event (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            time TEXT NOT NULL,
+            allocation BOOLEAN NOT NULL,
+            address INTEGER NOT NULL,
+            size INTEGER,
+            callstack INTEGER
+        );
+        CREATE TABLE stackentry (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            location INTEGER NOT NULL,
+            next INTEGER
+        );
+        CREATE TABLE location (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            address INTEGER NOT NULL,
+            function TEXT,
+            offset INTEGER
+        );
+        INSERT INTO trace (version, time) VALUES ('0.1.0', datetime('now'));
+        INSERT INTO location (id, address, function, offset) VALUES
+            (1, 4096, 'main', 16),
+            (2, 8192, 'work', 32),
+            (3, 12288, 'malloc', 0),
+            (4, 16384, 'free', 0);
+        INSERT INTO stackentry (id, location, next) VALUES
+            (1, 1, NULL),
+            (2, 2, 1),
+            (3, 3, 2),
+            (4, 4, 2);
+        INSERT INTO event (time, allocation, address, size, callstack) VALUES
+            (datetime('now'), TRUE, 65536, 1024, 3),
+            (datetime('now'), FALSE, 65536, NULL, 4),
+            (datetime('now'), TRUE, 65536, 2048, 3),
+            (datetime('now'), FALSE, 65536, NULL, 4),
+            (datetime('now'), TRUE, 131072, 4096, 3);",
+    )?;
+
+    Ok(())
+}
+
+// Report on a trace recorded by the first release of the tracer.
+#[test]
+fn test_baseline_trace() -> Result<(), Box<dyn Error>> {
+    let trace_path = format!("/tmp/baseline-{}.atrace", process::id());
+    let _ = std::fs::remove_file(&trace_path);
+
+    let write_result = write_baseline_trace(&trace_path);
+    let view_result = write_result.and_then(|_| integration_test::view_trace(&trace_path));
+    std::fs::remove_file(&trace_path)?;
+    let trace = view_result?;
+
+    let leaf_ix = integration_test::find_top_leaf_index(&trace).ok_or("no top leaf")?;
+    let leaf = &trace[leaf_ix];
+    assert_eq!(leaf.bytes, "4096");
+    assert_eq!(leaf.blocks, "3");
+    assert_eq!(leaf.leaks, "1");
+    assert_eq!(leaf.tree.len(), 6);
+    assert!(leaf.name.contains("malloc"));
+
+    Ok(())
+}
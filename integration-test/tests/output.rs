@@ -0,0 +1,85 @@
+/*
+    allocscope  -  a memory tracking tool
+    Copyright (C) 2023  Matt Kimball
+
+    This program is free software: you can redistribute it and/or modify it
+    under the terms of the GNU General Public License as published by the
+    Free Software Foundation, either version 3 of the License, or (at your
+    option) any later version.
+
+    This program is distributed in the hope that it will be useful, but
+    WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+    or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License
+    for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use integration_test;
+use std::error::Error;
+
+// Return the number of blocks allocated by the top leaf of a trace report,
+// passing extra command line arguments to allocscope-view.
+fn leaf_blocks(atrace_path: &str, args: &[&str]) -> Result<String, Box<dyn Error>> {
+    let trace = integration_test::view_trace_with_args(atrace_path, args)?;
+    let leaf_ix = integration_test::find_top_leaf_index(&trace).ok_or("no top leaf")?;
+
+    Ok(trace[leaf_ix].blocks.clone())
+}
+
+// Trace one program, then try to record a trace of another to the same
+// file.  The existing trace must be kept unless --force or --backup is
+// given, and --backup must move it aside rather than replace it.
+#[test]
+fn test_existing_output() -> Result<(), Box<dyn Error>> {
+    let loop_path = integration_test::compile_source("loop.c")?;
+    let realloc_path = integration_test::compile_source("realloc.c")?;
+    let trace_path = integration_test::perform_trace(&loop_path)?;
+    let backup_path = format!("{}.1", trace_path);
+
+    let refused_code = integration_test::run_trace(&realloc_path, &trace_path, &[])?;
+    let refused_blocks = leaf_blocks(&trace_path, &[])?;
+
+    let backup_code = integration_test::run_trace(&realloc_path, &trace_path, &["--backup"])?;
+    let new_blocks = leaf_blocks(&trace_path, &[])?;
+    let backup_blocks = leaf_blocks(&backup_path, &[])?;
+
+    std::fs::remove_file(&loop_path)?;
+    std::fs::remove_file(&realloc_path)?;
+    std::fs::remove_file(&trace_path)?;
+    std::fs::remove_file(&backup_path)?;
+
+    assert_ne!(refused_code, Some(0));
+    assert_eq!(refused_blocks, "1024");
+    assert_eq!(backup_code, Some(0));
+    assert_eq!(new_blocks, "20");
+    assert_eq!(backup_blocks, "1024");
+
+    Ok(())
+}
+
+// Trace two programs to the same file with --append.  Each is a session,
+// the most recent of which is viewed unless --session selects another.
+#[test]
+fn test_append_sessions() -> Result<(), Box<dyn Error>> {
+    let loop_path = integration_test::compile_source("loop.c")?;
+    let realloc_path = integration_test::compile_source("realloc.c")?;
+    let trace_path = integration_test::perform_trace(&loop_path)?;
+
+    let append_code = integration_test::run_trace(&realloc_path, &trace_path, &["--append"])?;
+    let latest_blocks = leaf_blocks(&trace_path, &[])?;
+    let first_blocks = leaf_blocks(&trace_path, &["--session", "1"])?;
+    let second_blocks = leaf_blocks(&trace_path, &["--session", "2"])?;
+
+    std::fs::remove_file(&loop_path)?;
+    std::fs::remove_file(&realloc_path)?;
+    std::fs::remove_file(&trace_path)?;
+
+    assert_eq!(append_code, Some(0));
+    assert_eq!(latest_blocks, "20");
+    assert_eq!(first_blocks, "1024");
+    assert_eq!(second_blocks, "20");
+
+    Ok(())
+}